@@ -14,8 +14,26 @@
 // limitations under the License.
 //
 
+use sha2::{Digest, Sha256};
+
+const PROTO_PATHS: &[&str] = &["../proto/crypto/crypto.proto"];
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    micro_rpc_build::compile(&["../proto/crypto/crypto.proto"], &["../proto"], Default::default());
+    micro_rpc_build::compile(PROTO_PATHS, &["../proto"], Default::default());
+
+    // Record a digest of the schema that the generated code was built from, so
+    // that stale generated code can be detected.
+    let mut hasher = Sha256::new();
+    for proto_path in PROTO_PATHS {
+        println!("cargo:rerun-if-changed={}", proto_path);
+        hasher.update(std::fs::read(proto_path)?);
+    }
+    let schema_digest: [u8; 32] = hasher.finalize().into();
+    let out_dir = std::env::var("OUT_DIR")?;
+    std::fs::write(
+        format!("{}/schema_digest.rs", out_dir),
+        format!("pub const SCHEMA_DIGEST: [u8; 32] = {:?};\n", schema_digest),
+    )?;
 
     Ok(())
 }
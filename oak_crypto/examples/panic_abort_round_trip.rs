@@ -0,0 +1,65 @@
+//
+// Copyright 2024 The Project Oak Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Request/response round trip for checking the crate in enclave builds, which
+//! use `panic = "abort"`:
+//!
+//! ```shell
+//! cargo run --package=oak_crypto --example=panic_abort_round_trip --profile=release-lto
+//! ```
+//!
+//! The crate doesn't catch panics, and zeroizes secrets in `Drop`
+//! implementations, which run on normal scope exit with either panic strategy.
+//! With `panic = "abort"` a panic terminates the process without running
+//! destructors, so secrets that are alive at the time of the panic aren't
+//! zeroized and only go away with the process memory.
+
+use oak_crypto::{
+    encryption_key::generate_encryption_key_pair,
+    encryptor::{ClientEncryptor, ServerEncryptor},
+};
+
+const REQUEST: &[u8] = b"Request";
+const RESPONSE: &[u8] = b"Response";
+const ASSOCIATED_DATA: &[u8] = b"Associated data";
+
+fn main() -> anyhow::Result<()> {
+    if !cfg!(panic = "abort") {
+        anyhow::bail!("example must be built with panic = \"abort\", e.g. --profile=release-lto");
+    }
+
+    let (encryption_key, public_key) = generate_encryption_key_pair();
+    let mut client_encryptor = ClientEncryptor::create(&public_key)?;
+    let encrypted_request = client_encryptor.encrypt(REQUEST, ASSOCIATED_DATA)?;
+    let (server_encryptor, request, request_associated_data) =
+        ServerEncryptor::decrypt(&encrypted_request, &encryption_key)?;
+    anyhow::ensure!(request == REQUEST, "request doesn't match");
+    anyhow::ensure!(
+        request_associated_data == ASSOCIATED_DATA,
+        "request associated data doesn't match"
+    );
+
+    let encrypted_response = server_encryptor.encrypt(RESPONSE, ASSOCIATED_DATA)?;
+    let (response, response_associated_data) = client_encryptor.decrypt(&encrypted_response)?;
+    anyhow::ensure!(response == RESPONSE, "response doesn't match");
+    anyhow::ensure!(
+        response_associated_data == ASSOCIATED_DATA,
+        "response associated data doesn't match"
+    );
+
+    println!("round trip with panic = \"abort\" succeeded");
+    Ok(())
+}
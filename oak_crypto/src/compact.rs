@@ -0,0 +1,224 @@
+//
+// Copyright 2024 The Project Oak Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Compact binary envelope for encrypted messages, for embedders that can't use
+//! protobuf.
+//!
+//! The envelope is a [postcard](https://docs.rs/postcard) serialized struct that
+//! starts with [`COMPACT_FORMAT_VERSION`], followed by the optional
+//! encapsulated public key, the nonce, the ciphertext and the associated data.
+//! It is a stable alternative to the protobuf encoding, and is a few bytes
+//! smaller because fixed-size fields don't carry tags or lengths.
+//!
+//! Version bytes are below 8, so they can never start a valid protobuf encoding
+//! of the message protos (they would denote field number 0), and recipients can
+//! accept both formats on a per-message basis with [`decode_any_request`] and
+//! [`decode_any_response`]. Versions outside of
+//! [`PROTOCOL_VERSION_RANGE`](crate::PROTOCOL_VERSION_RANGE) are rejected with
+//! a [`CryptoError::UnsupportedProtocolVersion`] error before the rest of the
+//! envelope is decoded.
+
+use alloc::{format, vec::Vec};
+
+use anyhow::Context;
+use prost::Message;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::CryptoError,
+    hpke::{aead::AeadNonce, deserialize_nonce, SUITE_LENGTHS},
+    proto::oak::crypto::v1::{AeadEncryptedMessage, EncryptedRequest, EncryptedResponse},
+};
+
+/// Version of the compact envelopes produced by this crate, which is the
+/// highest supported protocol version.
+pub const COMPACT_FORMAT_VERSION: u8 = *crate::PROTOCOL_VERSION_RANGE.end();
+
+// Higher versions couldn't be told apart from protobuf encodings.
+static_assertions::const_assert!(COMPACT_FORMAT_VERSION < 8);
+
+/// Version byte of a compact envelope, which can only hold supported protocol
+/// versions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "u8", into = "u8")]
+pub struct EnvelopeVersion(u8);
+
+impl EnvelopeVersion {
+    /// Version of the envelopes produced by this crate.
+    pub const CURRENT: Self = Self(COMPACT_FORMAT_VERSION);
+}
+
+impl TryFrom<u8> for EnvelopeVersion {
+    type Error = anyhow::Error;
+
+    fn try_from(value: u8) -> anyhow::Result<Self> {
+        if !crate::supports_version(value) {
+            return Err(CryptoError::UnsupportedProtocolVersion.context(format!(
+                "unsupported compact format version {}, supported versions are {:?}",
+                value,
+                crate::PROTOCOL_VERSION_RANGE
+            )));
+        }
+        Ok(Self(value))
+    }
+}
+
+impl From<EnvelopeVersion> for u8 {
+    fn from(version: EnvelopeVersion) -> Self {
+        version.0
+    }
+}
+
+type EncapsulatedPublicKey = [u8; SUITE_LENGTHS.nenc];
+
+#[derive(Serialize, Deserialize)]
+struct CompactRequest<'a> {
+    version: EnvelopeVersion,
+    serialized_encapsulated_public_key: Option<EncapsulatedPublicKey>,
+    nonce: AeadNonce,
+    ciphertext: &'a [u8],
+    associated_data: &'a [u8],
+}
+
+#[derive(Serialize, Deserialize)]
+struct CompactResponse<'a> {
+    version: EnvelopeVersion,
+    nonce: AeadNonce,
+    ciphertext: &'a [u8],
+    associated_data: &'a [u8],
+}
+
+/// Encodes an [`EncryptedRequest`] as a compact envelope.
+pub fn encode_request(encrypted_request: &EncryptedRequest) -> anyhow::Result<Vec<u8>> {
+    let encrypted_message = encrypted_request
+        .encrypted_message
+        .as_ref()
+        .context("request doesn't contain encrypted message")?;
+    let serialized_encapsulated_public_key = encrypted_request
+        .serialized_encapsulated_public_key
+        .as_deref()
+        .map(|key| {
+            EncapsulatedPublicKey::try_from(key).map_err(|_| {
+                CryptoError::MalformedInput
+                    .context(format!("invalid encapsulated public key length: {}", key.len()))
+            })
+        })
+        .transpose()?;
+    postcard::to_allocvec(&CompactRequest {
+        version: EnvelopeVersion::CURRENT,
+        serialized_encapsulated_public_key,
+        nonce: deserialize_nonce(&encrypted_message.nonce)?,
+        ciphertext: &encrypted_message.ciphertext,
+        associated_data: &encrypted_message.associated_data,
+    })
+    .map_err(|error| {
+        CryptoError::MalformedInput.context(format!("couldn't encode request: {}", error))
+    })
+}
+
+/// Decodes a compact envelope into an [`EncryptedRequest`].
+pub fn decode_request(encoded_request: &[u8]) -> anyhow::Result<EncryptedRequest> {
+    let request: CompactRequest = decode(encoded_request).context("couldn't decode request")?;
+    Ok(EncryptedRequest {
+        encrypted_message: Some(AeadEncryptedMessage {
+            ciphertext: request.ciphertext.to_vec(),
+            associated_data: request.associated_data.to_vec(),
+            nonce: request.nonce.to_vec(),
+        }),
+        serialized_encapsulated_public_key: request
+            .serialized_encapsulated_public_key
+            .map(|key| key.to_vec()),
+    })
+}
+
+/// Encodes an [`EncryptedResponse`] as a compact envelope.
+pub fn encode_response(encrypted_response: &EncryptedResponse) -> anyhow::Result<Vec<u8>> {
+    let encrypted_message = encrypted_response
+        .encrypted_message
+        .as_ref()
+        .context("response doesn't contain encrypted message")?;
+    postcard::to_allocvec(&CompactResponse {
+        version: EnvelopeVersion::CURRENT,
+        nonce: deserialize_nonce(&encrypted_message.nonce)?,
+        ciphertext: &encrypted_message.ciphertext,
+        associated_data: &encrypted_message.associated_data,
+    })
+    .map_err(|error| {
+        CryptoError::MalformedInput.context(format!("couldn't encode response: {}", error))
+    })
+}
+
+/// Decodes a compact envelope into an [`EncryptedResponse`].
+pub fn decode_response(encoded_response: &[u8]) -> anyhow::Result<EncryptedResponse> {
+    let response: CompactResponse = decode(encoded_response).context("couldn't decode response")?;
+    Ok(EncryptedResponse {
+        encrypted_message: Some(AeadEncryptedMessage {
+            ciphertext: response.ciphertext.to_vec(),
+            associated_data: response.associated_data.to_vec(),
+            nonce: response.nonce.to_vec(),
+        }),
+    })
+}
+
+/// Decodes an [`EncryptedRequest`] that is encoded either as a compact envelope
+/// or as a protobuf message.
+pub fn decode_any_request(encoded_request: &[u8]) -> anyhow::Result<EncryptedRequest> {
+    if is_compact(encoded_request) {
+        decode_request(encoded_request)
+    } else {
+        EncryptedRequest::decode(encoded_request).map_err(|error| {
+            CryptoError::MalformedInput.context(format!("couldn't decode request proto: {}", error))
+        })
+    }
+}
+
+/// Decodes an [`EncryptedResponse`] that is encoded either as a compact
+/// envelope or as a protobuf message.
+pub fn decode_any_response(encoded_response: &[u8]) -> anyhow::Result<EncryptedResponse> {
+    if is_compact(encoded_response) {
+        decode_response(encoded_response)
+    } else {
+        EncryptedResponse::decode(encoded_response).map_err(|error| {
+            CryptoError::MalformedInput
+                .context(format!("couldn't decode response proto: {}", error))
+        })
+    }
+}
+
+/// Returns whether the message starts with a version byte, i.e. a byte that
+/// would denote field number 0 in a protobuf encoding. Messages of unsupported
+/// versions are treated as compact envelopes too, so that they are rejected
+/// for their version.
+fn is_compact(encoded_message: &[u8]) -> bool {
+    encoded_message.first().is_some_and(|byte| byte >> 3 == 0)
+}
+
+fn decode<'a, T: Deserialize<'a>>(encoded_message: &'a [u8]) -> anyhow::Result<T> {
+    // Checked separately so that the error says which version was found, which
+    // doesn't survive the postcard error.
+    let version = encoded_message
+        .first()
+        .ok_or_else(|| CryptoError::MalformedInput.context("compact envelope is empty"))?;
+    EnvelopeVersion::try_from(*version)?;
+    let (message, remainder) = postcard::take_from_bytes(encoded_message).map_err(|error| {
+        CryptoError::MalformedInput.context(format!("invalid compact envelope: {}", error))
+    })?;
+    if !remainder.is_empty() {
+        return Err(CryptoError::MalformedInput
+            .context(format!("compact envelope has {} trailing bytes", remainder.len())));
+    }
+    Ok(message)
+}
@@ -0,0 +1,155 @@
+//
+// Copyright 2024 The Project Oak Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Session continuity, which lets a client detect that a re-established session
+//! targets a recipient key that wasn't endorsed by the recipient of its
+//! previous session, e.g. because the key directory was compromised.
+//!
+//! Both peers of a session derive the same [`ContinuitySecret`] as
+//! `HKDF-SHA256(salt = CONTINUITY_SECRET_LABEL, ikm = request_key ||
+//! response_key)`, where the session keys are the HPKE exporter outputs. The
+//! holder of the previous recipient key endorses the recipient public key
+//! `pkR` of the next session with a [`ContinuityToken`], computed as
+//! `HMAC-SHA256(continuity_secret, CONTINUITY_TOKEN_LABEL || pkR)`:
+//!
+//! - If the key wasn't rotated, the recipient mints the token for its own
+//!   public key.
+//! - If the key was rotated, the holder of the old key mints the token for the
+//!   new public key, and hands it off to the holder of the new key.
+//!
+//! The client re-establishes the session with
+//! [`ClientEncryptor::reestablish_with_continuity`], and the recipient sends
+//! the token in the associated data of its first response, with
+//! [`ServerEncryptor::encrypt_with_continuity`]. The token can only be computed
+//! with the continuity secret of the previous session, so a substituted key
+//! fails the check with [`CryptoError::ContinuityBroken`].
+//!
+//! [`ClientEncryptor::reestablish_with_continuity`]: crate::encryptor::ClientEncryptor::reestablish_with_continuity
+//! [`ServerEncryptor::encrypt_with_continuity`]: crate::encryptor::ServerEncryptor::encrypt_with_continuity
+//! [`CryptoError::ContinuityBroken`]: crate::error::CryptoError::ContinuityBroken
+
+use alloc::vec::Vec;
+
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::{
+    hpke::aead::AeadKey,
+    labels::{CONTINUITY_SECRET_LABEL, CONTINUITY_TOKEN_LABEL},
+};
+
+/// Size of a [`ContinuitySecret`].
+pub const CONTINUITY_SECRET_SIZE_BYTES: usize = 32;
+/// Size of a [`ContinuityToken`].
+pub const CONTINUITY_TOKEN_SIZE_BYTES: usize = 32;
+
+/// Secret of a session that both peers derive, which endorses the recipient
+/// key of the next session. It has to be stored securely until the session is
+/// re-established, and is zeroized when dropped.
+pub struct ContinuitySecret([u8; CONTINUITY_SECRET_SIZE_BYTES]);
+
+impl ContinuitySecret {
+    /// Restores a continuity secret that was stored with
+    /// [`ContinuitySecret::as_bytes`].
+    pub fn new(secret: [u8; CONTINUITY_SECRET_SIZE_BYTES]) -> Self {
+        Self(secret)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; CONTINUITY_SECRET_SIZE_BYTES] {
+        &self.0
+    }
+
+    /// Mints the token that endorses `serialized_recipient_public_key` as the
+    /// recipient key of the next session.
+    pub fn hand_off_token(&self, serialized_recipient_public_key: &[u8]) -> ContinuityToken {
+        ContinuityToken(
+            self.token_mac(serialized_recipient_public_key).finalize().into_bytes().into(),
+        )
+    }
+
+    fn token_mac(&self, serialized_recipient_public_key: &[u8]) -> Hmac<Sha256> {
+        let mut mac =
+            <Hmac<Sha256> as Mac>::new_from_slice(&self.0).expect("HMAC accepts keys of any size");
+        mac.update(CONTINUITY_TOKEN_LABEL);
+        mac.update(serialized_recipient_public_key);
+        mac
+    }
+}
+
+impl Drop for ContinuitySecret {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+/// Endorsement of the recipient key of a re-established session, see
+/// [`ContinuitySecret::hand_off_token`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ContinuityToken([u8; CONTINUITY_TOKEN_SIZE_BYTES]);
+
+impl ContinuityToken {
+    pub fn new(token: [u8; CONTINUITY_TOKEN_SIZE_BYTES]) -> Self {
+        Self(token)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; CONTINUITY_TOKEN_SIZE_BYTES] {
+        &self.0
+    }
+}
+
+/// Token that the client of a re-established session expects in the first
+/// response, returned by
+/// [`ClientEncryptor::reestablish_with_continuity`](crate::encryptor::ClientEncryptor::reestablish_with_continuity).
+pub struct ContinuityCheck {
+    previous_secret: ContinuitySecret,
+    serialized_recipient_public_key: Vec<u8>,
+}
+
+impl ContinuityCheck {
+    pub(crate) fn new(
+        previous_secret: &ContinuitySecret,
+        serialized_recipient_public_key: &[u8],
+    ) -> Self {
+        Self {
+            previous_secret: ContinuitySecret::new(*previous_secret.as_bytes()),
+            serialized_recipient_public_key: serialized_recipient_public_key.to_vec(),
+        }
+    }
+
+    /// Returns whether `token` endorses the recipient key, comparing in
+    /// constant time.
+    pub(crate) fn verify(&self, token: &[u8]) -> bool {
+        self.previous_secret
+            .token_mac(&self.serialized_recipient_public_key)
+            .verify_slice(token)
+            .is_ok()
+    }
+}
+
+/// Derives the continuity secret of a session from its keys.
+pub(crate) fn derive_continuity_secret(
+    request_key: &AeadKey,
+    response_key: &AeadKey,
+) -> ContinuitySecret {
+    let ikm = Zeroizing::new([&request_key.as_bytes()[..], &response_key.as_bytes()[..]].concat());
+    let mut secret = [0u8; CONTINUITY_SECRET_SIZE_BYTES];
+    Hkdf::<Sha256>::new(Some(CONTINUITY_SECRET_LABEL), &ikm)
+        .expand(&[], &mut secret)
+        .expect("continuity secret size is a valid HKDF-SHA256 output length");
+    ContinuitySecret(secret)
+}
@@ -0,0 +1,331 @@
+//
+// Copyright 2024 The Project Oak Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Proof-of-work challenges that a server can demand from clients before
+//! setting up a session, so that it degrades gracefully under load instead of
+//! spending a key decapsulation on every initial request.
+//!
+//! Challenges are stateless: each challenge carries its issue time and is
+//! authenticated with a server secret, so a solution can be verified without
+//! remembering which challenges were issued. A challenge is bound to a client
+//! hint, which for session setup is the encapsulated public key of the initial
+//! request, so that a solution can't be reused for other sessions.
+
+use alloc::format;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+use crate::{
+    error::CryptoError,
+    labels::SETUP_CHALLENGE_LABEL,
+    util::wire::{WireReader, WireWriter},
+};
+
+const SETUP_CHALLENGE_TAG_SIZE_BYTES: usize = 32;
+
+/// Size of a serialized [`SetupChallenge`].
+pub const SETUP_CHALLENGE_SIZE_BYTES: usize = 8 + 1 + SETUP_CHALLENGE_TAG_SIZE_BYTES;
+
+/// Size of a serialized [`SetupSolution`].
+pub const SETUP_SOLUTION_SIZE_BYTES: usize = SETUP_CHALLENGE_SIZE_BYTES + 8;
+
+/// Maximum difficulty of a [`SetupChallenge`]. Solving a challenge of this
+/// difficulty takes about 2^32 SHA-256 evaluations, i.e. minutes on a single
+/// core, so anything harder would lock out legitimate clients rather than slow
+/// down attackers. Clients reject harder challenges, so that a server can't
+/// make them spin forever.
+pub const MAX_SETUP_DIFFICULTY: u8 = 32;
+
+/// Proof-of-work challenge issued by a server. Solving it takes about
+/// `2^difficulty` SHA-256 evaluations.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SetupChallenge {
+    /// Time in milliseconds since the Unix epoch at which the challenge was
+    /// issued.
+    issued_at_millis: u64,
+    /// Number of leading zero bits required in the solution hash.
+    difficulty: u8,
+    /// HMAC of the other fields and the client hint under the server secret.
+    tag: [u8; SETUP_CHALLENGE_TAG_SIZE_BYTES],
+}
+
+impl SetupChallenge {
+    /// Issues a challenge of `difficulty` for the client identified by
+    /// `client_hint`, authenticated with `server_secret`. Fails if `difficulty`
+    /// is above [`MAX_SETUP_DIFFICULTY`].
+    pub fn issue(
+        server_secret: &[u8],
+        difficulty: u8,
+        client_hint: &[u8],
+        current_time_millis: u64,
+    ) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            difficulty <= MAX_SETUP_DIFFICULTY,
+            "difficulty {} is above the maximum of {}",
+            difficulty,
+            MAX_SETUP_DIFFICULTY
+        );
+        let tag = challenge_mac(server_secret, current_time_millis, difficulty, client_hint)
+            .finalize()
+            .into_bytes()
+            .into();
+        Ok(Self { issued_at_millis: current_time_millis, difficulty, tag })
+    }
+
+    pub fn issued_at_millis(&self) -> u64 {
+        self.issued_at_millis
+    }
+
+    pub fn difficulty(&self) -> u8 {
+        self.difficulty
+    }
+
+    /// Solves the challenge by searching for a counter for which the solution
+    /// hash has enough leading zero bits.
+    pub fn solve(&self) -> SetupSolution {
+        let mut counter = 0u64;
+        while leading_zero_bits(&solution_hash(&self.tag, counter)) < u32::from(self.difficulty) {
+            counter += 1;
+        }
+        SetupSolution { challenge: self.clone(), counter }
+    }
+
+    /// Verifies that `solution` solves a challenge that was issued with
+    /// `server_secret` for `client_hint`, that the challenge is at least
+    /// `required_difficulty` and that it was issued less than `validity_ms`
+    /// milliseconds ago. Fails if `required_difficulty` is above
+    /// [`MAX_SETUP_DIFFICULTY`], since no challenge could meet it.
+    pub fn verify(
+        server_secret: &[u8],
+        client_hint: &[u8],
+        solution: &SetupSolution,
+        required_difficulty: u8,
+        validity_ms: u64,
+        current_time_millis: u64,
+    ) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            required_difficulty <= MAX_SETUP_DIFFICULTY,
+            "required difficulty {} is above the maximum of {}",
+            required_difficulty,
+            MAX_SETUP_DIFFICULTY
+        );
+        let challenge = &solution.challenge;
+        challenge_mac(server_secret, challenge.issued_at_millis, challenge.difficulty, client_hint)
+            .verify_slice(&challenge.tag)
+            .map_err(|_| {
+                CryptoError::SetupChallengeFailure
+                    .context("challenge wasn't issued by this server for this client")
+            })?;
+        let age_ms = current_time_millis.saturating_sub(challenge.issued_at_millis);
+        if age_ms >= validity_ms {
+            return Err(CryptoError::SetupChallengeFailure.context(format!(
+                "challenge issued at {} has expired, current time is {}",
+                challenge.issued_at_millis, current_time_millis
+            )));
+        }
+        if challenge.difficulty < required_difficulty {
+            return Err(CryptoError::SetupChallengeFailure.context(format!(
+                "challenge difficulty {} is below the required difficulty {}",
+                challenge.difficulty, required_difficulty
+            )));
+        }
+        if leading_zero_bits(&solution_hash(&challenge.tag, solution.counter))
+            < u32::from(challenge.difficulty)
+        {
+            return Err(CryptoError::SetupChallengeFailure.context("challenge isn't solved"));
+        }
+        Ok(())
+    }
+
+    pub fn serialize(&self) -> [u8; SETUP_CHALLENGE_SIZE_BYTES] {
+        let mut serialized = [0u8; SETUP_CHALLENGE_SIZE_BYTES];
+        let mut writer = WireWriter::new(&mut serialized);
+        writer
+            .put_u64(self.issued_at_millis)
+            .and_then(|()| writer.put_u8(self.difficulty))
+            .and_then(|()| writer.put_bytes(&self.tag))
+            .expect("setup challenge size is fixed");
+        serialized
+    }
+
+    pub fn deserialize(serialized: &[u8]) -> anyhow::Result<Self> {
+        if serialized.len() != SETUP_CHALLENGE_SIZE_BYTES {
+            return Err(CryptoError::MalformedInput.context(format!(
+                "invalid setup challenge length, expected {} bytes, got {}",
+                SETUP_CHALLENGE_SIZE_BYTES,
+                serialized.len()
+            )));
+        }
+        let mut reader = WireReader::new(serialized);
+        let issued_at_millis = reader.get_u64()?;
+        let difficulty = reader.get_u8()?;
+        if difficulty > MAX_SETUP_DIFFICULTY {
+            return Err(CryptoError::MalformedInput.context(format!(
+                "setup challenge difficulty {} is above the maximum of {}",
+                difficulty, MAX_SETUP_DIFFICULTY
+            )));
+        }
+        Ok(Self { issued_at_millis, difficulty, tag: reader.get_array()? })
+    }
+}
+
+/// Solution of a [`SetupChallenge`], which the client sends along with its
+/// initial request.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SetupSolution {
+    challenge: SetupChallenge,
+    counter: u64,
+}
+
+impl SetupSolution {
+    pub fn challenge(&self) -> &SetupChallenge {
+        &self.challenge
+    }
+
+    pub fn serialize(&self) -> [u8; SETUP_SOLUTION_SIZE_BYTES] {
+        let mut serialized = [0u8; SETUP_SOLUTION_SIZE_BYTES];
+        let mut writer = WireWriter::new(&mut serialized);
+        writer
+            .put_bytes(&self.challenge.serialize())
+            .and_then(|()| writer.put_u64(self.counter))
+            .expect("setup solution size is fixed");
+        serialized
+    }
+
+    pub fn deserialize(serialized: &[u8]) -> anyhow::Result<Self> {
+        if serialized.len() != SETUP_SOLUTION_SIZE_BYTES {
+            return Err(CryptoError::MalformedInput.context(format!(
+                "invalid setup solution length, expected {} bytes, got {}",
+                SETUP_SOLUTION_SIZE_BYTES,
+                serialized.len()
+            )));
+        }
+        let mut reader = WireReader::new(serialized);
+        Ok(Self {
+            challenge: SetupChallenge::deserialize(reader.get_bytes(SETUP_CHALLENGE_SIZE_BYTES)?)?,
+            counter: reader.get_u64()?,
+        })
+    }
+}
+
+/// Server-side gate that decides whether a session can be set up. The
+/// difficulty can be changed at runtime, e.g. when the server detects an
+/// attack, and a difficulty of zero lets all setups through.
+///
+/// Solutions can be replayed for the same client hint until the challenge
+/// expires, since the gate keeps no state. A replayed initial request only
+/// results in a session that the attacker can't use.
+pub struct SetupGate {
+    server_secret: Zeroizing<alloc::vec::Vec<u8>>,
+    difficulty: AtomicU8,
+    validity_ms: u64,
+}
+
+impl SetupGate {
+    /// Creates a gate that issues challenges authenticated with
+    /// `server_secret` and valid for `validity_ms` milliseconds. The gate is
+    /// open until a difficulty is set.
+    pub fn new(server_secret: &[u8], validity_ms: u64) -> Self {
+        Self {
+            server_secret: Zeroizing::new(server_secret.to_vec()),
+            difficulty: AtomicU8::new(0),
+            validity_ms,
+        }
+    }
+
+    pub fn difficulty(&self) -> u8 {
+        self.difficulty.load(Ordering::Relaxed)
+    }
+
+    /// Sets the difficulty of new challenges, clamped to
+    /// [`MAX_SETUP_DIFFICULTY`]. Solutions of earlier challenges are only
+    /// accepted if they are at least as difficult.
+    pub fn set_difficulty(&self, difficulty: u8) {
+        self.difficulty.store(difficulty.min(MAX_SETUP_DIFFICULTY), Ordering::Relaxed);
+    }
+
+    /// Issues a challenge of the current difficulty for `client_hint`.
+    pub fn issue(&self, client_hint: &[u8], current_time_millis: u64) -> SetupChallenge {
+        SetupChallenge::issue(
+            &self.server_secret,
+            self.difficulty(),
+            client_hint,
+            current_time_millis,
+        )
+        .expect("gate difficulty is clamped to the maximum")
+    }
+
+    /// Checks that a session can be set up for `client_hint`. A solution is
+    /// only required if the difficulty is not zero.
+    pub fn check(
+        &self,
+        client_hint: &[u8],
+        solution: Option<&SetupSolution>,
+        current_time_millis: u64,
+    ) -> anyhow::Result<()> {
+        let difficulty = self.difficulty();
+        if difficulty == 0 {
+            return Ok(());
+        }
+        let solution = solution.ok_or_else(|| {
+            CryptoError::SetupChallengeFailure.context("setup requires a solved challenge")
+        })?;
+        SetupChallenge::verify(
+            &self.server_secret,
+            client_hint,
+            solution,
+            difficulty,
+            self.validity_ms,
+            current_time_millis,
+        )
+    }
+}
+
+fn challenge_mac(
+    server_secret: &[u8],
+    issued_at_millis: u64,
+    difficulty: u8,
+    client_hint: &[u8],
+) -> Hmac<Sha256> {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(server_secret)
+        .expect("HMAC accepts keys of any size");
+    mac.update(SETUP_CHALLENGE_LABEL);
+    mac.update(&issued_at_millis.to_be_bytes());
+    mac.update(&[difficulty]);
+    mac.update(client_hint);
+    mac
+}
+
+fn solution_hash(tag: &[u8; SETUP_CHALLENGE_TAG_SIZE_BYTES], counter: u64) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(tag);
+    hasher.update(counter.to_be_bytes());
+    hasher.finalize().into()
+}
+
+fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in hash {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
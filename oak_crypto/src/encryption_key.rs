@@ -14,21 +14,45 @@
 // limitations under the License.
 //
 
-use alloc::{boxed::Box, vec::Vec};
+#[cfg(feature = "response-direction")]
+use alloc::boxed::Box;
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    format,
+    vec::Vec,
+};
 
 use anyhow::Context;
+#[cfg(feature = "response-direction")]
 use async_trait::async_trait;
+use hkdf::Hkdf;
+use rand_core::{OsRng, RngCore};
+use sha2::Sha256;
 use zeroize::{Zeroize, Zeroizing};
 
+#[cfg(feature = "response-direction")]
 use crate::{
     encryptor::ClientEncryptor,
     hpke::{
-        generate_kem_key_pair, setup_base_recipient, Deserializable, PrivateKey, RecipientContext,
-        Serializable, OAK_HPKE_INFO,
+        recipient_bound_info, setup_base_recipient, setup_base_recipient_shared_keys,
+        setup_psk_recipient, RecipientContext, SharedKeys,
     },
+    labels::OAK_HPKE_INFO,
     proto::oak::crypto::v1::EncryptedRequest,
     EMPTY_ASSOCIATED_DATA,
 };
+use crate::{
+    error::CryptoError,
+    hpke::{
+        can_decapsulate, derive_key_pair, generate_kem_key_pair, kem_public_key,
+        setup_request_only_recipient, Deserializable, HpkeMode, PrivateKey, PublicKey,
+        RequestOnlyRecipientContext, Serializable,
+    },
+    labels::{
+        BATCH_KEY_DERIVATION_SALT, LOCAL_SECRET_DERIVATION_SALT, OAK_HPKE_REQUEST_ONLY_INFO,
+        TENANT_KEY_DERIVATION_SALT,
+    },
+};
 
 /// Generates a random encryption key pair and returns an instance of the
 /// `EncryptionKey` and a NIST P-256 SEC1 encoded point public key.
@@ -38,27 +62,297 @@ pub fn generate_encryption_key_pair() -> (EncryptionKey, Vec<u8>) {
     (EncryptionKey::new(private_key), public_key.to_bytes().to_vec())
 }
 
+/// Size of the input keying material passed to `DeriveKeyPair` for each key in
+/// a batch.
+const BATCH_IKM_SIZE_BYTES: usize = 32;
+/// Minimum size of the seed used for deterministic key generation.
+const MIN_SEED_SIZE_BYTES: usize = 32;
+/// Maximum size of a secret derived with
+/// [`EncryptionKey::derive_local_secret`], which is the maximum output length
+/// of HKDF-SHA256.
+pub const MAX_LOCAL_SECRET_SIZE_BYTES: usize = 255 * 32;
+/// Size of a private key serialized with [`EncryptionKey::serialize`], which is
+/// `Nsk` of DHKEM(X25519, HKDF-SHA256).
+pub const PRIVATE_KEY_SIZE_BYTES: usize = 32;
+
+/// Generates `count` random encryption key pairs, drawing all the required
+/// entropy from the OS with a single call.
+///
+/// Intended for provisioning many recipients, test fleets and load generation,
+/// where key generation is the dominant setup cost. Every key is derived from
+/// its own 32 bytes of entropy, so keys are as independent as keys from
+/// [`generate_encryption_key_pair`]. Fails if the entropy for `count` keys
+/// doesn't fit into memory.
+pub fn generate_encryption_key_pairs(
+    count: usize,
+) -> anyhow::Result<Vec<(EncryptionKey, Vec<u8>)>> {
+    let ikm_size = count
+        .checked_mul(BATCH_IKM_SIZE_BYTES)
+        .ok_or_else(|| anyhow::anyhow!("too many key pairs requested: {}", count))?;
+    let mut ikm = Zeroizing::new(alloc::vec![0u8; ikm_size]);
+    OsRng.fill_bytes(&mut ikm);
+    Ok(ikm.chunks_exact(BATCH_IKM_SIZE_BYTES).map(encryption_key_pair_from_ikm).collect())
+}
+
+/// Deterministically derives `count` encryption key pairs from `seed`, so that
+/// golden tests can use a reproducible fleet of keys. `seed` must contain at
+/// least 32 bytes of entropy.
+///
+/// Input keying material for the key with index `i` is computed as
+/// `HKDF-SHA256(salt = BATCH_KEY_DERIVATION_SALT, ikm = seed, info =
+/// u64_be(i))`.
+pub fn derive_encryption_key_pairs_from_seed(
+    seed: &[u8],
+    count: usize,
+) -> anyhow::Result<Vec<(EncryptionKey, Vec<u8>)>> {
+    anyhow::ensure!(
+        seed.len() >= MIN_SEED_SIZE_BYTES,
+        "seed is too short, expected at least {} bytes, got {}",
+        MIN_SEED_SIZE_BYTES,
+        seed.len()
+    );
+    let hkdf = Hkdf::<Sha256>::new(Some(BATCH_KEY_DERIVATION_SALT), seed);
+    Ok((0..count as u64)
+        .map(|index| {
+            let mut ikm = Zeroizing::new([0u8; BATCH_IKM_SIZE_BYTES]);
+            hkdf.expand(&index.to_be_bytes(), ikm.as_mut())
+                .expect("batch ikm size is a valid HKDF-SHA256 output length");
+            encryption_key_pair_from_ikm(ikm.as_ref())
+        })
+        .collect())
+}
+
+fn encryption_key_pair_from_ikm(ikm: &[u8]) -> (EncryptionKey, Vec<u8>) {
+    let (private_key, public_key) = derive_key_pair(ikm);
+    (EncryptionKey::new(private_key), public_key.to_bytes().to_vec())
+}
+
+/// Usage policy of an [`EncryptionKey`] that is checked before the key is used
+/// to decapsulate a session key.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyPolicy {
+    /// Time in milliseconds since the Unix epoch after which the key must not
+    /// be used. `None` means that the key doesn't expire.
+    pub not_after: Option<u64>,
+    /// HPKE modes the key can be used with.
+    pub allowed_modes: Vec<HpkeMode>,
+}
+
+/// Tolerance for clock differences between the recipient and the rest of the
+/// system, which is applied to time-based key policy checks.
+///
+/// The leeway is applied symmetrically: a time that is within `leeway_ms` of a
+/// validity boundary on either side is treated as being inside the validity
+/// window. The default policy has zero leeway.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ClockSkewPolicy {
+    pub leeway_ms: u64,
+}
+
+impl KeyPolicy {
+    /// Checks that the key can be used in `mode` at `current_time_millis`.
+    /// Expiring keys can only be used if the current time is provided.
+    fn check(
+        &self,
+        mode: HpkeMode,
+        current_time_millis: Option<u64>,
+        clock_skew_policy: ClockSkewPolicy,
+    ) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.allowed_modes.contains(&mode),
+            "key policy doesn't allow using the key in {:?} mode",
+            mode
+        );
+        if let Some(not_after) = self.not_after {
+            let current_time_millis = current_time_millis
+                .context("key policy has an expiration time, but current time wasn't provided")?;
+            anyhow::ensure!(
+                current_time_millis.saturating_sub(clock_skew_policy.leeway_ms) <= not_after,
+                "key expired at {}, current time is {} (leeway {} ms)",
+                not_after,
+                current_time_millis,
+                clock_skew_policy.leeway_ms
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Private key of a server, which is used to decapsulate the session keys of
+/// client requests.
+///
+/// A key is immutable once constructed, and all operations only read it, so a
+/// single key can be shared between threads that set up sessions concurrently.
 pub struct EncryptionKey {
     private_key: PrivateKey,
+    policy: Option<KeyPolicy>,
 }
 
+static_assertions::assert_impl_all!(EncryptionKey: Send, Sync);
+
 impl EncryptionKey {
     pub fn new(private_key: PrivateKey) -> Self {
-        Self { private_key }
+        Self { private_key, policy: None }
+    }
+
+    /// Deterministically derives an encryption key pair from `seed` with
+    /// `DeriveKeyPair`, e.g. for test environments whose recipients have to
+    /// present a stable public key. Returns the key and the serialized public
+    /// key. `seed` must contain at least 32 bytes of entropy, and anyone who
+    /// knows it can derive the private key.
+    /// <https://www.rfc-editor.org/rfc/rfc9180.html#name-derivekeypair>
+    pub fn from_seed(seed: &[u8]) -> anyhow::Result<(Self, Vec<u8>)> {
+        anyhow::ensure!(
+            seed.len() >= MIN_SEED_SIZE_BYTES,
+            "seed is too short, expected at least {} bytes, got {}",
+            MIN_SEED_SIZE_BYTES,
+            seed.len()
+        );
+        Ok(encryption_key_pair_from_ikm(seed))
+    }
+
+    /// Attaches a usage policy to the key. Keys with an expiration time have to
+    /// be used via [`EncryptionKey::at_time`].
+    pub fn with_policy(mut self, policy: KeyPolicy) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    pub fn policy(&self) -> Option<&KeyPolicy> {
+        self.policy.as_ref()
+    }
+
+    /// Returns the public key of the key pair, e.g. to compare keys without
+    /// serializing them.
+    pub fn public_key(&self) -> PublicKey {
+        kem_public_key(&self.private_key)
+    }
+
+    /// Returns the serialized public key of the key pair, as returned by
+    /// [`generate_encryption_key_pair`].
+    pub fn serialized_public_key(&self) -> Vec<u8> {
+        self.public_key().to_bytes().to_vec()
+    }
+
+    /// Returns a key handle that checks the key policy against the
+    /// caller-provided `current_time_millis` (milliseconds since the Unix
+    /// epoch). The handle offers all operations that use the key, so that keys
+    /// with an expiration time can be used with any of them.
+    pub fn at_time(&self, current_time_millis: u64) -> TimedEncryptionKey<'_> {
+        TimedEncryptionKey {
+            encryption_key: self,
+            current_time_millis: Some(current_time_millis),
+            clock_skew_policy: ClockSkewPolicy::default(),
+        }
+    }
+
+    /// Returns a key handle without a current time, whose policy checks fail
+    /// for keys with an expiration time.
+    fn untimed(&self) -> TimedEncryptionKey<'_> {
+        TimedEncryptionKey {
+            encryption_key: self,
+            current_time_millis: None,
+            clock_skew_policy: ClockSkewPolicy::default(),
+        }
+    }
+
+    /// Returns a key handle that only accepts sessions created with
+    /// [`ClientEncryptor::create_with_recipient_identity`], which authenticate
+    /// the public key of this key in the HPKE info. A client that encrypted to
+    /// this key while binding a different identity can't set up a session.
+    /// Keys with an expiration time have to be bound with
+    /// [`TimedEncryptionKey::bind_recipient_identity`].
+    #[cfg(feature = "response-direction")]
+    pub fn bind_recipient_identity(&self) -> RecipientBoundEncryptionKey<'_> {
+        self.untimed().bind_recipient_identity()
+    }
+
+    /// Returns a key handle that only accepts sessions created with
+    /// [`ClientEncryptor::create_with_psk`] and the same `psk` and `psk_id`,
+    /// which are authenticated by the HPKE key schedule in PSK mode. Requests
+    /// of clients that used a different PSK, or base mode, fail to decrypt.
+    /// Keys with an expiration time have to use
+    /// [`TimedEncryptionKey::with_psk`].
+    #[cfg(feature = "response-direction")]
+    pub fn with_psk<'a>(&'a self, psk: &'a [u8], psk_id: &'a [u8]) -> PskEncryptionKey<'a> {
+        self.untimed().with_psk(psk, psk_id)
+    }
+
+    /// Returns whether a session could be set up with the encapsulated public
+    /// key, by only running the KEM decapsulation, as a cheap pre-filter before
+    /// decrypting a large request. Keys whose policy doesn't allow setting up
+    /// sessions return `false`.
+    ///
+    /// This rejects malformed and low-order keys, but X25519 decapsulation
+    /// succeeds for any other point, so it can't tell whether the key was
+    /// encapsulated for this key pair. Only decrypting the request can.
+    pub fn can_decap(&self, encapsulated_public_key: &[u8]) -> bool {
+        self.untimed().can_decap(encapsulated_public_key)
+    }
+
+    /// Derives [`SharedKeys`] from the encapsulated public key, for callers
+    /// that implement their own record layer.
+    #[cfg(feature = "response-direction")]
+    pub fn derive_shared_keys(&self, encapsulated_public_key: &[u8]) -> anyhow::Result<SharedKeys> {
+        self.untimed().derive_shared_keys(encapsulated_public_key)
+    }
+
+    /// Generates a recipient context for a session in which only requests are
+    /// sent, see [`crate::encryptor::RequestOnlyServerEncryptor`].
+    pub fn generate_request_only_recipient_context(
+        &self,
+        encapsulated_public_key: &[u8],
+    ) -> anyhow::Result<RequestOnlyRecipientContext> {
+        self.untimed().generate_request_only_recipient_context(encapsulated_public_key)
     }
 
+    /// Serializes the private key with `SerializePrivateKey`, i.e. as the 32
+    /// bytes of the X25519 scalar, e.g. for persisting it in sealed storage.
+    /// The key policy isn't serialized.
+    /// <https://www.rfc-editor.org/rfc/rfc9180.html#name-serializeprivatekey-and-de>
     pub fn serialize(self) -> Vec<u8> {
         self.private_key.to_bytes().to_vec()
     }
 
+    /// Restores a key serialized with [`EncryptionKey::serialize`], which has
+    /// the same public key as the original. Fails with a
+    /// [`CryptoError::MalformedInput`] error if `serialized_private_key` isn't
+    /// [`PRIVATE_KEY_SIZE_BYTES`] long. `serialized_private_key` is zeroized
+    /// either way.
     pub fn deserialize(serialized_private_key: &mut [u8]) -> anyhow::Result<Self> {
-        let private_key = PrivateKey::from_bytes(serialized_private_key)
-            .map_err(|error| anyhow::anyhow!("couldn't deserialize private key: {}", error))?;
+        let private_key = PrivateKey::from_bytes(serialized_private_key).map_err(|error| {
+            CryptoError::MalformedInput.context_with_source(
+                format!(
+                    "couldn't deserialize private key, expected {} bytes, got {}",
+                    PRIVATE_KEY_SIZE_BYTES,
+                    serialized_private_key.len()
+                ),
+                error,
+            )
+        });
         serialized_private_key.zeroize();
-        Ok(Self { private_key })
+        Ok(Self { private_key: private_key?, policy: None })
+    }
+
+    /// Same as [`EncryptionKey::deserialize`], but also checks that
+    /// `serialized_public_key` is the public key of the deserialized private
+    /// key, e.g. for key stores that persist both halves of a key pair.
+    /// Returns [`CryptoError::KeyMismatch`] if it isn't.
+    pub fn from_private_and_public(
+        serialized_private_key: &mut [u8],
+        serialized_public_key: &[u8],
+    ) -> anyhow::Result<Self> {
+        let encryption_key = Self::deserialize(serialized_private_key)?;
+        if encryption_key.serialized_public_key() != serialized_public_key {
+            return Err(
+                CryptoError::KeyMismatch.context("public key doesn't match the private key")
+            );
+        }
+        Ok(encryption_key)
     }
 
     /// Returns the private key encrypted with the `peer_public_key`.
+    #[cfg(feature = "response-direction")]
     pub fn encrypted_private_key(
         &self,
         peer_public_key: &[u8],
@@ -68,11 +362,140 @@ impl EncryptionKey {
         client_encryptor
             .encrypt(&Zeroizing::new(self.private_key.to_bytes()), EMPTY_ASSOCIATED_DATA)
     }
+
+    /// Derives a `length`-byte secret for `purpose` from the private key, e.g.
+    /// a key for sealing local storage, so that services don't have to manage
+    /// a second secret. Each purpose gets an independent secret, which is
+    /// zeroized when dropped.
+    ///
+    /// Purposes aren't registered with the labels of this crate, since they
+    /// can't collide with them: the salt is used by no other derivation, so
+    /// the HKDF pseudorandom key of local secrets differs from every key this
+    /// crate derives, and a purpose equal to one of its labels still gets an
+    /// independent secret. Callers only have to keep their own purposes
+    /// distinct.
+    ///
+    /// The secret is computed as `HKDF-SHA256(salt =
+    /// LOCAL_SECRET_DERIVATION_SALT, ikm = private_key, info = purpose)`, so it
+    /// is stable for as long as the key pair is: a key restored with
+    /// [`EncryptionKey::deserialize`] or from a wrapped private key derives the
+    /// same secrets, and rotating the key pair rotates all of them. Data sealed
+    /// with a derived secret has to be re-sealed before the old key pair is
+    /// destroyed.
+    pub fn derive_local_secret(
+        &self,
+        purpose: &'static str,
+        length: usize,
+    ) -> anyhow::Result<Zeroizing<Vec<u8>>> {
+        anyhow::ensure!(!purpose.is_empty(), "local secret purpose must not be empty");
+        anyhow::ensure!(length > 0, "local secret length must not be zero");
+        let private_key = Zeroizing::new(self.private_key.to_bytes());
+        let mut secret = Zeroizing::new(alloc::vec![0u8; length]);
+        Hkdf::<Sha256>::new(Some(LOCAL_SECRET_DERIVATION_SALT), &private_key)
+            .expand(purpose.as_bytes(), &mut secret)
+            .map_err(|_| {
+                anyhow::anyhow!(
+                    "local secret is too long, expected at most {} bytes, got {}",
+                    MAX_LOCAL_SECRET_SIZE_BYTES,
+                    length
+                )
+            })?;
+        Ok(secret)
+    }
+}
+
+/// Minimum size of the master secret used for deriving tenant keys.
+const MIN_MASTER_IKM_SIZE_BYTES: usize = 32;
+/// Size of the input keying material passed to `DeriveKeyPair` for each tenant.
+const TENANT_IKM_SIZE_BYTES: usize = 32;
+/// Default number of derived tenant keys kept by [`RecipientKeyDeriver`].
+pub const DEFAULT_TENANT_KEY_CACHE_CAPACITY: usize = 64;
+
+/// Derives per-tenant encryption keys from a single master secret, so that a
+/// server hosting multiple tenants doesn't need to store a private key for each
+/// of them.
+///
+/// Tenant input keying material is computed as
+/// `HKDF-SHA256(salt = TENANT_KEY_DERIVATION_SALT, ikm = master_ikm, info =
+/// tenant_id)` and then turned into a key pair using `DeriveKeyPair` from
+/// RFC9180. <https://www.rfc-editor.org/rfc/rfc9180.html#name-derivekeypair>
+pub struct RecipientKeyDeriver {
+    master_ikm: Zeroizing<Vec<u8>>,
+    cache_capacity: usize,
+    cache: BTreeMap<Vec<u8>, EncryptionKey>,
+    /// Tenant IDs in the order of their last use, least recently used first.
+    cache_order: VecDeque<Vec<u8>>,
+}
+
+impl RecipientKeyDeriver {
+    /// Creates a key deriver with [`DEFAULT_TENANT_KEY_CACHE_CAPACITY`].
+    /// `master_ikm` must contain at least 32 bytes of entropy.
+    pub fn new(master_ikm: &[u8]) -> anyhow::Result<Self> {
+        Self::with_cache_capacity(master_ikm, DEFAULT_TENANT_KEY_CACHE_CAPACITY)
+    }
+
+    /// Creates a key deriver that keeps at most `cache_capacity` derived tenant
+    /// keys.
+    pub fn with_cache_capacity(master_ikm: &[u8], cache_capacity: usize) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            master_ikm.len() >= MIN_MASTER_IKM_SIZE_BYTES,
+            "master secret is too short, expected at least {} bytes, got {}",
+            MIN_MASTER_IKM_SIZE_BYTES,
+            master_ikm.len()
+        );
+        anyhow::ensure!(cache_capacity > 0, "tenant key cache capacity must be non-zero");
+        Ok(Self {
+            master_ikm: Zeroizing::new(master_ikm.to_vec()),
+            cache_capacity,
+            cache: BTreeMap::new(),
+            cache_order: VecDeque::new(),
+        })
+    }
+
+    /// Derives the encryption key for `tenant_id` and returns it together with
+    /// the corresponding serialized public key.
+    pub fn derive_tenant_key_pair(&self, tenant_id: &[u8]) -> (EncryptionKey, Vec<u8>) {
+        let mut tenant_ikm = Zeroizing::new([0u8; TENANT_IKM_SIZE_BYTES]);
+        Hkdf::<Sha256>::new(Some(TENANT_KEY_DERIVATION_SALT), &self.master_ikm)
+            .expand(tenant_id, tenant_ikm.as_mut())
+            .expect("tenant ikm size is a valid HKDF-SHA256 output length");
+        let (private_key, public_key) = derive_key_pair(tenant_ikm.as_ref());
+        (EncryptionKey::new(private_key), public_key.to_bytes().to_vec())
+    }
+
+    /// Returns the encryption key for `tenant_id`, deriving it if it isn't
+    /// cached. The least recently used key is evicted once the cache is full.
+    pub fn provider_for_tenant(&mut self, tenant_id: &[u8]) -> &EncryptionKey {
+        if let Some(position) = self.cache_order.iter().position(|id| id == tenant_id) {
+            let id = self.cache_order.remove(position).expect("position is in range");
+            self.cache_order.push_back(id);
+        } else {
+            if self.cache.len() >= self.cache_capacity {
+                if let Some(evicted) = self.cache_order.pop_front() {
+                    self.cache.remove(&evicted);
+                }
+            }
+            let (encryption_key, _) = self.derive_tenant_key_pair(tenant_id);
+            self.cache.insert(tenant_id.to_vec(), encryption_key);
+            self.cache_order.push_back(tenant_id.to_vec());
+        }
+        self.cache.get(tenant_id).expect("tenant key was just cached")
+    }
+
+    #[cfg(all(test, feature = "response-direction"))]
+    pub(crate) fn is_cached(&self, tenant_id: &[u8]) -> bool {
+        self.cache.contains_key(tenant_id)
+    }
 }
 
 /// Exposes the ability to derive a session key from the provided encapsulated
 /// private key, using a private key that has been endorsed in the Attestation
 /// Evidence.
+///
+/// The returned context owns the session keys, so sessions never borrow the
+/// handle or the underlying [`EncryptionKey`], which can be dropped or rotated
+/// while sessions are still in use.
+#[cfg(feature = "response-direction")]
 pub trait EncryptionKeyHandle {
     fn generate_recipient_context(
         &self,
@@ -80,16 +503,182 @@ pub trait EncryptionKeyHandle {
     ) -> anyhow::Result<RecipientContext>;
 }
 
+#[cfg(feature = "response-direction")]
 impl EncryptionKeyHandle for EncryptionKey {
     fn generate_recipient_context(
         &self,
         encapsulated_public_key: &[u8],
     ) -> anyhow::Result<RecipientContext> {
-        setup_base_recipient(encapsulated_public_key, &self.private_key, OAK_HPKE_INFO)
-            .context("couldn't generate recipient crypto context")
+        self.untimed().generate_recipient_context(encapsulated_public_key)
+    }
+}
+
+/// Exposes the ability to derive the request key of a session in which only
+/// requests are sent, see
+/// [`RequestOnlyServerEncryptor`](crate::encryptor::RequestOnlyServerEncryptor).
+pub trait RequestOnlyEncryptionKeyHandle {
+    fn generate_request_only_recipient_context(
+        &self,
+        encapsulated_public_key: &[u8],
+    ) -> anyhow::Result<RequestOnlyRecipientContext>;
+}
+
+impl RequestOnlyEncryptionKeyHandle for EncryptionKey {
+    fn generate_request_only_recipient_context(
+        &self,
+        encapsulated_public_key: &[u8],
+    ) -> anyhow::Result<RequestOnlyRecipientContext> {
+        self.untimed().generate_request_only_recipient_context(encapsulated_public_key)
+    }
+}
+
+/// [`EncryptionKey`] handle that enforces the key policy at a given time,
+/// created with [`EncryptionKey::at_time`].
+#[derive(Clone, Copy)]
+pub struct TimedEncryptionKey<'a> {
+    encryption_key: &'a EncryptionKey,
+    /// `None` for the handles that [`EncryptionKey`] uses internally.
+    current_time_millis: Option<u64>,
+    clock_skew_policy: ClockSkewPolicy,
+}
+
+impl<'a> TimedEncryptionKey<'a> {
+    /// Sets the tolerance for clock skew used when checking the key policy.
+    pub fn with_clock_skew_policy(mut self, clock_skew_policy: ClockSkewPolicy) -> Self {
+        self.clock_skew_policy = clock_skew_policy;
+        self
+    }
+
+    /// Same as [`EncryptionKey::bind_recipient_identity`], checking the key
+    /// policy at the time of this handle.
+    #[cfg(feature = "response-direction")]
+    pub fn bind_recipient_identity(self) -> RecipientBoundEncryptionKey<'a> {
+        let info = recipient_bound_info(&self.encryption_key.serialized_public_key());
+        RecipientBoundEncryptionKey { key: self, info }
+    }
+
+    /// Same as [`EncryptionKey::with_psk`], checking the key policy at the time
+    /// of this handle.
+    #[cfg(feature = "response-direction")]
+    pub fn with_psk(self, psk: &'a [u8], psk_id: &'a [u8]) -> PskEncryptionKey<'a> {
+        PskEncryptionKey { key: self, psk, psk_id }
+    }
+
+    /// Same as [`EncryptionKey::can_decap`], checking the key policy at the
+    /// time of this handle.
+    pub fn can_decap(&self, encapsulated_public_key: &[u8]) -> bool {
+        self.check_policy(HpkeMode::Base).is_ok()
+            && can_decapsulate(encapsulated_public_key, &self.encryption_key.private_key)
+    }
+
+    /// Same as [`EncryptionKey::derive_shared_keys`], checking the key policy
+    /// at the time of this handle.
+    #[cfg(feature = "response-direction")]
+    pub fn derive_shared_keys(&self, encapsulated_public_key: &[u8]) -> anyhow::Result<SharedKeys> {
+        self.check_policy(HpkeMode::Base)?;
+        setup_base_recipient_shared_keys(encapsulated_public_key, &self.encryption_key.private_key)
+            .context("couldn't derive shared keys")
+    }
+
+    fn check_policy(&self, mode: HpkeMode) -> anyhow::Result<()> {
+        if let Some(policy) = &self.encryption_key.policy {
+            policy
+                .check(mode, self.current_time_millis, self.clock_skew_policy)
+                .context("key policy check failed")?;
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "response-direction")]
+    fn generate_recipient_context_inner(
+        &self,
+        encapsulated_public_key: &[u8],
+        info: &[u8],
+        psk: Option<(&[u8], &[u8])>,
+    ) -> anyhow::Result<RecipientContext> {
+        let private_key = &self.encryption_key.private_key;
+        match psk {
+            Some((psk, psk_id)) => {
+                self.check_policy(HpkeMode::Psk)?;
+                setup_psk_recipient(encapsulated_public_key, private_key, psk, psk_id, info)
+            }
+            None => {
+                self.check_policy(HpkeMode::Base)?;
+                setup_base_recipient(encapsulated_public_key, private_key, info)
+            }
+        }
+        .context("couldn't generate recipient crypto context")
+    }
+}
+
+#[cfg(feature = "response-direction")]
+impl EncryptionKeyHandle for TimedEncryptionKey<'_> {
+    fn generate_recipient_context(
+        &self,
+        encapsulated_public_key: &[u8],
+    ) -> anyhow::Result<RecipientContext> {
+        self.generate_recipient_context_inner(encapsulated_public_key, OAK_HPKE_INFO, None)
+    }
+}
+
+impl RequestOnlyEncryptionKeyHandle for TimedEncryptionKey<'_> {
+    fn generate_request_only_recipient_context(
+        &self,
+        encapsulated_public_key: &[u8],
+    ) -> anyhow::Result<RequestOnlyRecipientContext> {
+        self.check_policy(HpkeMode::Base)?;
+        setup_request_only_recipient(
+            encapsulated_public_key,
+            &self.encryption_key.private_key,
+            OAK_HPKE_REQUEST_ONLY_INFO,
+        )
+        .context("couldn't generate recipient crypto context")
+    }
+}
+
+/// [`EncryptionKey`] handle for sessions that bind the recipient public key,
+/// created with [`EncryptionKey::bind_recipient_identity`] or
+/// [`TimedEncryptionKey::bind_recipient_identity`].
+#[cfg(feature = "response-direction")]
+pub struct RecipientBoundEncryptionKey<'a> {
+    key: TimedEncryptionKey<'a>,
+    info: Vec<u8>,
+}
+
+#[cfg(feature = "response-direction")]
+impl EncryptionKeyHandle for RecipientBoundEncryptionKey<'_> {
+    fn generate_recipient_context(
+        &self,
+        encapsulated_public_key: &[u8],
+    ) -> anyhow::Result<RecipientContext> {
+        self.key.generate_recipient_context_inner(encapsulated_public_key, &self.info, None)
+    }
+}
+
+/// [`EncryptionKey`] handle for sessions in HPKE PSK mode, created with
+/// [`EncryptionKey::with_psk`] or [`TimedEncryptionKey::with_psk`].
+#[cfg(feature = "response-direction")]
+pub struct PskEncryptionKey<'a> {
+    key: TimedEncryptionKey<'a>,
+    psk: &'a [u8],
+    psk_id: &'a [u8],
+}
+
+#[cfg(feature = "response-direction")]
+impl EncryptionKeyHandle for PskEncryptionKey<'_> {
+    fn generate_recipient_context(
+        &self,
+        encapsulated_public_key: &[u8],
+    ) -> anyhow::Result<RecipientContext> {
+        self.key.generate_recipient_context_inner(
+            encapsulated_public_key,
+            OAK_HPKE_INFO,
+            Some((self.psk, self.psk_id)),
+        )
     }
 }
 
+#[cfg(feature = "response-direction")]
 #[async_trait]
 pub trait AsyncEncryptionKeyHandle {
     async fn generate_recipient_context(
@@ -98,6 +687,7 @@ pub trait AsyncEncryptionKeyHandle {
     ) -> anyhow::Result<RecipientContext>;
 }
 
+#[cfg(feature = "response-direction")]
 #[async_trait]
 impl AsyncEncryptionKeyHandle for EncryptionKey {
     async fn generate_recipient_context(
@@ -19,18 +19,327 @@
 //! <https://www.rfc-editor.org/rfc/rfc9180.html#name-bidirectional-encryption>
 
 use alloc::vec::Vec;
+#[cfg(feature = "response-direction")]
+use alloc::{borrow::Cow, format};
+#[cfg(feature = "response-direction")]
+use core::marker::PhantomData;
 
 use anyhow::Context;
+#[cfg(feature = "response-direction")]
+use prost::Message;
+#[cfg(feature = "response-direction")]
+use sha2::{Digest, Sha256};
+#[cfg(feature = "response-direction")]
+use zeroize::{Zeroize, Zeroizing};
 
+#[cfg(feature = "response-direction")]
 use crate::{
+    continuity::{ContinuityCheck, ContinuitySecret, ContinuityToken, CONTINUITY_TOKEN_SIZE_BYTES},
+    dos::{SetupGate, SetupSolution},
     encryption_key::{AsyncEncryptionKeyHandle, EncryptionKeyHandle},
     hpke::{
-        deserialize_nonce, generate_random_nonce, setup_base_sender, RecipientContext,
-        SenderContext, OAK_HPKE_INFO,
+        aead::{AeadKey, AeadNonce},
+        recipient_bound_info, setup_base_sender, setup_base_sender_from_shared_secret,
+        setup_psk_sender, RecipientContext, SenderContext,
     },
-    proto::oak::crypto::v1::{AeadEncryptedMessage, EncryptedRequest, EncryptedResponse},
+    labels::{
+        CONTINUITY_RESPONSE_LABEL, HASHED_AAD_LABEL, KEEPALIVE_LABEL, MESSAGE_TYPE_LABEL,
+        OAK_HPKE_INFO, RETURN_PATH_LABEL, ROUTING_HEADER_LABEL, SESSION_TOKEN_LABEL,
+        SIMPLEX_STREAM_LABEL,
+    },
+    proto::oak::crypto::v1::{EncryptedResponse, SessionKeys},
+    transfer::{TransferManifest, TransferReceiver, TransferSender},
+    util::{
+        wire::{self, WireReader},
+        LENGTH_PREFIX_SIZE_BYTES,
+    },
+};
+use crate::{
+    encryption_key::RequestOnlyEncryptionKeyHandle,
+    error::CryptoError,
+    hpke::{
+        deserialize_nonce, generate_random_nonce, setup_request_only_sender,
+        RequestOnlyRecipientContext, RequestOnlySenderContext, SUITE_LENGTHS,
+    },
+    labels::OAK_HPKE_REQUEST_ONLY_INFO,
+    proto::oak::crypto::v1::{AeadEncryptedMessage, EncryptedRequest},
 };
 
+/// Decodes authenticated associated data returned by `decrypt` with `decode`
+/// and checks that it is equal to `expected_associated_data`. Returns the
+/// decoded associated data.
+pub fn verify_associated_data<T, F>(
+    authenticated_associated_data: &[u8],
+    decode: F,
+    expected_associated_data: &T,
+) -> anyhow::Result<T>
+where
+    T: PartialEq,
+    F: FnOnce(&[u8]) -> anyhow::Result<T>,
+{
+    let associated_data =
+        decode(authenticated_associated_data).context("couldn't decode associated data")?;
+    anyhow::ensure!(
+        associated_data == *expected_associated_data,
+        "associated data doesn't match the expected value"
+    );
+    Ok(associated_data)
+}
+
+/// Policy for the associated data of received messages, which is checked after
+/// the message has been authenticated. Prevents accepting associated data that
+/// the peer merely echoed back, e.g. from a previous message.
+#[derive(Clone, Debug)]
+pub enum AadPolicy {
+    /// Associated data must be empty.
+    RequireEmpty,
+    /// Associated data must be equal to the given value.
+    RequireExact(Vec<u8>),
+    /// Associated data must start with the given value.
+    RequirePrefix(Vec<u8>),
+    /// Associated data must be accepted by the given function.
+    Validator(fn(&[u8]) -> bool),
+}
+
+impl AadPolicy {
+    /// Checks that `associated_data` satisfies the policy. Violations are
+    /// reported as [`CryptoError::AssociatedDataPolicyViolation`].
+    pub fn check(&self, associated_data: &[u8]) -> anyhow::Result<()> {
+        let (satisfied, description) = match self {
+            AadPolicy::RequireEmpty => (associated_data.is_empty(), "associated data isn't empty"),
+            AadPolicy::RequireExact(expected) => (
+                associated_data == expected.as_slice(),
+                "associated data doesn't match the expected value",
+            ),
+            AadPolicy::RequirePrefix(prefix) => (
+                associated_data.starts_with(prefix),
+                "associated data doesn't start with the expected prefix",
+            ),
+            AadPolicy::Validator(validate) => {
+                (validate(associated_data), "associated data was rejected by the validator")
+            }
+        };
+        if !satisfied {
+            return Err(CryptoError::AssociatedDataPolicyViolation.context(description));
+        }
+        Ok(())
+    }
+}
+
+/// How a session authenticates associated data. It is a type parameter of the
+/// encryptors rather than a field, so that it doesn't grow them.
+#[cfg(feature = "response-direction")]
+pub trait AadMode {
+    /// Whether the SHA-256 digest of the associated data is authenticated
+    /// instead of the associated data itself.
+    const HASHED: bool;
+}
+
+/// Associated data is authenticated as is, which is the default.
+#[cfg(feature = "response-direction")]
+pub struct PlainAad;
+
+#[cfg(feature = "response-direction")]
+impl AadMode for PlainAad {
+    const HASHED: bool = false;
+}
+
+/// The SHA-256 digest of the associated data is authenticated, see
+/// [`ClientEncryptor::with_hashed_aad`].
+#[cfg(feature = "response-direction")]
+pub struct HashedAad;
+
+#[cfg(feature = "response-direction")]
+impl AadMode for HashedAad {
+    const HASHED: bool = true;
+}
+
+/// Returns the associated data that is authenticated with AEAD, which is
+/// `HASHED_AAD_LABEL || SHA256(associated_data)` if `hashed` is set. The label
+/// keeps a digest that is sent as plain associated data from being accepted as
+/// a hashed message, and the other way round.
+#[cfg(feature = "response-direction")]
+fn aead_associated_data(associated_data: &[u8], hashed: bool) -> Cow<'_, [u8]> {
+    if hashed {
+        Cow::Owned([HASHED_AAD_LABEL, Sha256::digest(associated_data).as_slice()].concat())
+    } else {
+        Cow::Borrowed(associated_data)
+    }
+}
+
+/// Maximum size of a routing header, see
+/// [`ClientEncryptor::encrypt_with_header`].
+pub const MAX_ROUTING_HEADER_SIZE_BYTES: usize = 256;
+
+/// Combines a routing header with the caller associated data as
+/// `ROUTING_HEADER_LABEL || header_len || header || associated_data`, where
+/// `header_len` is a 4-byte big-endian integer. Every framing of the associated
+/// data starts with its own label, so that a message framed for one API is
+/// rejected by the others.
+#[cfg(feature = "response-direction")]
+fn combine_routing_header(header: &[u8], associated_data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    if header.len() > MAX_ROUTING_HEADER_SIZE_BYTES {
+        return Err(CryptoError::MalformedInput.context(format!(
+            "routing header is too long, expected at most {} bytes, got {}",
+            MAX_ROUTING_HEADER_SIZE_BYTES,
+            header.len()
+        )));
+    }
+    let header_length = u32::try_from(header.len()).expect("routing header size is checked");
+    let mut combined_associated_data = Vec::with_capacity(
+        ROUTING_HEADER_LABEL.len()
+            + LENGTH_PREFIX_SIZE_BYTES
+            + header.len()
+            + associated_data.len(),
+    );
+    combined_associated_data.extend_from_slice(ROUTING_HEADER_LABEL);
+    wire::put_u32(&mut combined_associated_data, header_length);
+    combined_associated_data.extend_from_slice(header);
+    combined_associated_data.extend_from_slice(associated_data);
+    Ok(combined_associated_data)
+}
+
+/// Splits associated data combined by [`combine_routing_header`] into the
+/// routing header and the caller associated data.
+#[cfg(feature = "response-direction")]
+fn split_routing_header(combined_associated_data: &[u8]) -> anyhow::Result<(&[u8], &[u8])> {
+    let framed_associated_data =
+        combined_associated_data.strip_prefix(ROUTING_HEADER_LABEL).ok_or_else(|| {
+            CryptoError::MalformedInput.context("associated data has no routing header")
+        })?;
+    let mut reader = WireReader::new(framed_associated_data);
+    let header_length = reader.get_u32().context("associated data has no routing header")?;
+    let header_length = usize::try_from(header_length).unwrap_or(usize::MAX);
+    if header_length > MAX_ROUTING_HEADER_SIZE_BYTES {
+        return Err(CryptoError::MalformedInput.context(format!(
+            "invalid routing header length {}, expected at most {} bytes",
+            header_length, MAX_ROUTING_HEADER_SIZE_BYTES
+        )));
+    }
+    let header = reader.get_bytes(header_length).context("truncated routing header")?;
+    Ok((header, reader.remaining()))
+}
+
+/// Reads the routing header of a request encrypted with
+/// [`ClientEncryptor::encrypt_with_header`], without decrypting the request.
+///
+/// The header is only authenticated when the request is decrypted with
+/// [`ServerEncryptor::decrypt_with_header`], so intermediaries must not treat
+/// it as trusted.
+#[cfg(feature = "response-direction")]
+pub fn read_routing_header(encrypted_request: &EncryptedRequest) -> anyhow::Result<&[u8]> {
+    let encrypted_message = encrypted_request
+        .encrypted_message
+        .as_ref()
+        .context("request doesn't contain encrypted message")?;
+    let (header, _) = split_routing_header(&encrypted_message.associated_data)?;
+    Ok(header)
+}
+
+/// Size of the message type tag, see [`ClientEncryptor::encrypt_typed`].
+pub const MESSAGE_TYPE_SIZE_BYTES: usize = 8;
+
+/// Combines a message type tag with the caller associated data as
+/// `MESSAGE_TYPE_LABEL || msg_type || associated_data`, where `msg_type` is a
+/// big-endian integer.
+#[cfg(feature = "response-direction")]
+fn combine_message_type(msg_type: u64, associated_data: &[u8]) -> Vec<u8> {
+    let mut combined_associated_data = Vec::with_capacity(
+        MESSAGE_TYPE_LABEL.len() + MESSAGE_TYPE_SIZE_BYTES + associated_data.len(),
+    );
+    combined_associated_data.extend_from_slice(MESSAGE_TYPE_LABEL);
+    wire::put_u64(&mut combined_associated_data, msg_type);
+    combined_associated_data.extend_from_slice(associated_data);
+    combined_associated_data
+}
+
+/// Checks that associated data combined by [`combine_message_type`] has the
+/// `expected_msg_type` tag, and returns the caller associated data.
+#[cfg(feature = "response-direction")]
+fn check_message_type(
+    combined_associated_data: &[u8],
+    expected_msg_type: u64,
+) -> anyhow::Result<&[u8]> {
+    let framed_associated_data =
+        combined_associated_data.strip_prefix(MESSAGE_TYPE_LABEL).ok_or_else(|| {
+            CryptoError::MalformedInput.context("associated data has no message type")
+        })?;
+    let mut reader = WireReader::new(framed_associated_data);
+    let msg_type = reader.get_u64().context("associated data has no message type")?;
+    if msg_type != expected_msg_type {
+        return Err(CryptoError::AssociatedDataPolicyViolation.context(format!(
+            "unexpected message type {}, expected {}",
+            msg_type, expected_msg_type
+        )));
+    }
+    Ok(reader.remaining())
+}
+
+/// Combines a continuity token with the caller associated data as
+/// `CONTINUITY_RESPONSE_LABEL || token || associated_data`.
+#[cfg(feature = "response-direction")]
+fn combine_continuity_token(token: &ContinuityToken, associated_data: &[u8]) -> Vec<u8> {
+    [CONTINUITY_RESPONSE_LABEL, &token.as_bytes()[..], associated_data].concat()
+}
+
+/// Checks that associated data combined by [`combine_continuity_token`]
+/// carries a token that satisfies `check`, and returns the caller associated
+/// data.
+#[cfg(feature = "response-direction")]
+fn check_continuity_token<'a>(
+    combined_associated_data: &'a [u8],
+    check: &ContinuityCheck,
+) -> anyhow::Result<&'a [u8]> {
+    let framed_associated_data = combined_associated_data
+        .strip_prefix(CONTINUITY_RESPONSE_LABEL)
+        .filter(|framed_associated_data| {
+            framed_associated_data.len() >= CONTINUITY_TOKEN_SIZE_BYTES
+        })
+        .ok_or_else(|| CryptoError::ContinuityBroken.context("response has no continuity token"))?;
+    let (token, associated_data) = framed_associated_data.split_at(CONTINUITY_TOKEN_SIZE_BYTES);
+    if !check.verify(token) {
+        return Err(CryptoError::ContinuityBroken
+            .context("continuity token doesn't endorse the recipient public key"));
+    }
+    Ok(associated_data)
+}
+
+/// Request decrypted with [`ServerEncryptor::decrypt_with_header`].
+#[cfg(feature = "response-direction")]
+#[derive(Debug)]
+pub struct DecryptedRequest {
+    /// Authenticated routing header.
+    pub header: Vec<u8>,
+    pub plaintext: Vec<u8>,
+    /// Authenticated associated data provided by the caller, without the
+    /// routing header.
+    pub associated_data: Vec<u8>,
+}
+
+/// Minimum size of a combined initial request message produced by
+/// [`ClientEncryptor::encrypt_combined`].
+pub const MIN_COMBINED_MESSAGE_SIZE_BYTES: usize =
+    SUITE_LENGTHS.nenc + SUITE_LENGTHS.nn + SUITE_LENGTHS.nt;
+
+/// Maximum number of messages that can be encrypted with a single session key.
+/// AES-GCM with random 96-bit nonces must not be used for more than 2^32
+/// messages per key.
+/// <https://nvlpubs.nist.gov/nistpubs/Legacy/SP/nistspecialpublication800-38d.pdf>
+pub const MAX_MESSAGES_PER_SESSION: u64 = 1 << 32;
+
+/// Fields of a request message encrypted with
+/// [`ClientEncryptor::encrypt_into`], whose ciphertext was written into a
+/// caller-provided buffer.
+#[cfg(feature = "response-direction")]
+#[derive(Debug)]
+pub struct EncryptedRequestHeader {
+    /// Encapsulated public key, only set for the initial request of a session.
+    pub serialized_encapsulated_public_key: Option<Vec<u8>>,
+    pub nonce: [u8; SUITE_LENGTHS.nn],
+    /// Number of ciphertext bytes written into the output buffer.
+    pub ciphertext_size: usize,
+}
+
 /// Encryptor object for encrypting client requests that will be sent to the
 /// server and decrypting server responses that are received by the client. Each
 /// Encryptor object corresponds to a single crypto session between the client
@@ -39,13 +348,22 @@ use crate::{
 /// Sequence numbers for requests and responses are incremented separately,
 /// meaning that there could be multiple responses per request and multiple
 /// requests per response.
-pub struct ClientEncryptor {
+#[cfg(feature = "response-direction")]
+pub struct ClientEncryptor<M = PlainAad> {
     /// Encapsulated public key needed to establish a symmetric session key.
     /// Only sent in the initial request message of the session.
     serialized_encapsulated_public_key: Option<Vec<u8>>,
     sender_context: SenderContext,
+    /// Buffer for plaintexts that are only exposed inside
+    /// [`ClientEncryptor::decrypt_scoped`]. It is reused across calls and is
+    /// zeroized after each of them.
+    scoped_plaintext: Zeroizing<Vec<u8>>,
+    /// Number of requests encrypted in this session.
+    encrypted_requests: u64,
+    aad_mode: PhantomData<fn() -> M>,
 }
 
+#[cfg(feature = "response-direction")]
 impl ClientEncryptor {
     /// Creates an HPKE crypto context by generating an new ephemeral key pair.
     /// The `serialized_server_public_key` must be a NIST P-256 SEC1 encoded
@@ -54,10 +372,152 @@ impl ClientEncryptor {
         let (serialized_encapsulated_public_key, sender_context) =
             setup_base_sender(serialized_server_public_key, OAK_HPKE_INFO)
                 .context("couldn't create sender crypto context")?;
-        Ok(Self {
-            serialized_encapsulated_public_key: Some(serialized_encapsulated_public_key.to_vec()),
+        Ok(Self::from_sender_context(
+            Some(serialized_encapsulated_public_key.to_vec()),
             sender_context,
-        })
+            0,
+        ))
+    }
+
+    /// Same as [`ClientEncryptor::create`], but also authenticates
+    /// `serialized_server_public_key` in the HPKE info, so that the session
+    /// can only be set up by a server that binds the same public key as its
+    /// identity with
+    /// [`EncryptionKey::bind_recipient_identity`](crate::encryption_key::EncryptionKey::bind_recipient_identity).
+    /// This prevents unknown key-share attacks at the application level.
+    pub fn create_with_recipient_identity(
+        serialized_server_public_key: &[u8],
+    ) -> anyhow::Result<Self> {
+        let (serialized_encapsulated_public_key, sender_context) = setup_base_sender(
+            serialized_server_public_key,
+            &recipient_bound_info(serialized_server_public_key),
+        )
+        .context("couldn't create sender crypto context")?;
+        Ok(Self::from_sender_context(
+            Some(serialized_encapsulated_public_key.to_vec()),
+            sender_context,
+            0,
+        ))
+    }
+
+    /// Same as [`ClientEncryptor::create`], but sets up the session in HPKE PSK
+    /// mode, so that the key schedule also authenticates `psk`, identified by
+    /// `psk_id`. The server has to decrypt the first request with
+    /// [`EncryptionKey::with_psk`](crate::encryption_key::EncryptionKey::with_psk)
+    /// and the same PSK and PSK ID. `psk` must be between
+    /// [`MIN_PSK_SIZE_BYTES`](crate::hpke::MIN_PSK_SIZE_BYTES) and
+    /// [`MAX_PSK_SIZE_BYTES`](crate::hpke::MAX_PSK_SIZE_BYTES) long, and
+    /// `psk_id` must be non-empty and at most
+    /// [`MAX_PSK_ID_SIZE_BYTES`](crate::hpke::MAX_PSK_ID_SIZE_BYTES) long.
+    pub fn create_with_psk(
+        serialized_server_public_key: &[u8],
+        psk: &[u8],
+        psk_id: &[u8],
+    ) -> anyhow::Result<Self> {
+        let (serialized_encapsulated_public_key, sender_context) =
+            setup_psk_sender(serialized_server_public_key, psk, psk_id, OAK_HPKE_INFO)
+                .context("couldn't create sender crypto context")?;
+        Ok(Self::from_sender_context(
+            Some(serialized_encapsulated_public_key.to_vec()),
+            sender_context,
+            0,
+        ))
+    }
+
+    /// Re-establishes a session that continues the session with
+    /// `previous_secret`, which was returned by
+    /// [`ClientEncryptor::continuity_secret`].
+    ///
+    /// The first response of the new session has to be decrypted with
+    /// [`ClientEncryptor::decrypt_with_continuity`] and the returned
+    /// [`ContinuityCheck`], which fails with [`CryptoError::ContinuityBroken`]
+    /// unless `serialized_server_public_key` was endorsed by the recipient of
+    /// the previous session. See [`crate::continuity`].
+    pub fn reestablish_with_continuity(
+        serialized_server_public_key: &[u8],
+        previous_secret: &ContinuitySecret,
+    ) -> anyhow::Result<(Self, ContinuityCheck)> {
+        let client_encryptor = Self::create(serialized_server_public_key)?;
+        Ok((client_encryptor, ContinuityCheck::new(previous_secret, serialized_server_public_key)))
+    }
+
+    /// Creates an HPKE crypto context from a KEM encapsulation that was
+    /// performed outside of this crate, e.g. by a hardware security module.
+    /// Only the HPKE key schedule is run in-crate.
+    ///
+    /// The caller is trusted to provide a `shared_secret` and the corresponding
+    /// `serialized_encapsulated_public_key` that were produced by `Encap` of
+    /// DHKEM(X25519, HKDF-SHA256) for the intended server public key, using a
+    /// fresh ephemeral key pair. None of this can be checked here: a wrong
+    /// shared secret only surfaces as a decryption failure on the server, and
+    /// reusing an encapsulation across sessions reuses session keys.
+    /// <https://www.rfc-editor.org/rfc/rfc9180.html#name-dh-based-kem-dhkem>
+    pub fn create_from_encapsulation(
+        serialized_encapsulated_public_key: &[u8],
+        shared_secret: &[u8],
+    ) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            serialized_encapsulated_public_key.len() == SUITE_LENGTHS.nenc,
+            "incorrect encapsulated public key size, expected {}, got {}",
+            SUITE_LENGTHS.nenc,
+            serialized_encapsulated_public_key.len()
+        );
+        let sender_context = setup_base_sender_from_shared_secret(shared_secret, OAK_HPKE_INFO)
+            .context("couldn't create sender crypto context")?;
+        Ok(Self::from_sender_context(
+            Some(serialized_encapsulated_public_key.to_vec()),
+            sender_context,
+            0,
+        ))
+    }
+
+    /// Makes the session authenticate the labeled SHA-256 digest of the
+    /// associated data of requests and responses instead of the associated
+    /// data itself, which saves AEAD processing for large associated data.
+    /// Messages still carry the full associated data. The server has to use
+    /// [`ServerEncryptor::decrypt_with_hashed_aad`].
+    ///
+    /// This relies on the collision resistance of SHA-256: associated data is
+    /// only bound to a message through its digest, so two values with the same
+    /// digest would be indistinguishable.
+    pub fn with_hashed_aad(self) -> ClientEncryptor<HashedAad> {
+        ClientEncryptor::from_sender_context(
+            self.serialized_encapsulated_public_key,
+            self.sender_context,
+            self.encrypted_requests,
+        )
+    }
+
+    /// Returns the size of the initial request that
+    /// [`ClientEncryptor::encrypt_combined`] produces for a plaintext of
+    /// `plaintext_len` bytes, so that send buffers can be allocated exactly.
+    /// This includes the nonce, which is sent between the encapsulated public
+    /// key and the ciphertext.
+    pub const fn first_message_len(plaintext_len: usize) -> usize {
+        MIN_COMBINED_MESSAGE_SIZE_BYTES + plaintext_len
+    }
+}
+
+#[cfg(feature = "response-direction")]
+impl<M: AadMode> ClientEncryptor<M> {
+    fn from_sender_context(
+        serialized_encapsulated_public_key: Option<Vec<u8>>,
+        sender_context: SenderContext,
+        encrypted_requests: u64,
+    ) -> Self {
+        Self {
+            serialized_encapsulated_public_key,
+            sender_context,
+            scoped_plaintext: Zeroizing::new(Vec::new()),
+            encrypted_requests,
+            aad_mode: PhantomData,
+        }
+    }
+
+    /// Returns the number of requests that can still be encrypted in this
+    /// session before a new session has to be created.
+    pub fn messages_remaining(&self) -> u64 {
+        MAX_MESSAGES_PER_SESSION - self.encrypted_requests
     }
 
     /// Encrypts `plaintext` and authenticates `associated_data` using AEAD.
@@ -68,11 +528,7 @@ impl ClientEncryptor {
         plaintext: &[u8],
         associated_data: &[u8],
     ) -> anyhow::Result<EncryptedRequest> {
-        let nonce = generate_random_nonce();
-        let ciphertext = self
-            .sender_context
-            .seal(&nonce, plaintext, associated_data)
-            .context("couldn't encrypt request")?;
+        let (nonce, ciphertext) = self.seal_request(plaintext, associated_data)?;
 
         Ok(EncryptedRequest {
             encrypted_message: Some(AeadEncryptedMessage {
@@ -84,9 +540,310 @@ impl ClientEncryptor {
             serialized_encapsulated_public_key: self.serialized_encapsulated_public_key.take(),
         })
     }
+}
+
+// Framed and combined requests are only opened by servers with plain associated
+// data, so they can't be sent by sessions with hashed associated data.
+#[cfg(feature = "response-direction")]
+impl ClientEncryptor {
+    /// Same as [`ClientEncryptor::encrypt`], but also attaches a routing
+    /// `header`, e.g. a tenant id or priority, that is sent in clear and can be
+    /// read with [`read_routing_header`] without any keys. The header is
+    /// authenticated together with `associated_data`, so tampering with it is
+    /// detected by the server. The header can be at most
+    /// [`MAX_ROUTING_HEADER_SIZE_BYTES`] long.
+    ///
+    /// The associated data field of the request carries both, so the server has
+    /// to use [`ServerEncryptor::decrypt_with_header`].
+    pub fn encrypt_with_header(
+        &mut self,
+        header: &[u8],
+        plaintext: &[u8],
+        associated_data: &[u8],
+    ) -> anyhow::Result<EncryptedRequest> {
+        let combined_associated_data = combine_routing_header(header, associated_data)?;
+        self.encrypt(plaintext, &combined_associated_data)
+    }
+
+    /// Same as [`ClientEncryptor::encrypt_with_header`], but for transports
+    /// that carry the routing `header` themselves, e.g. as gateway metadata.
+    /// Returns the header, which has to be sent in clear next to the request,
+    /// and the request, whose associated data field only carries
+    /// `associated_data`.
+    ///
+    /// The header is still authenticated, so the server has to pass the
+    /// received header to [`ServerEncryptor::open_with_header`], which fails
+    /// if it was tampered with.
+    pub fn seal_with_header(
+        &mut self,
+        header: &[u8],
+        plaintext: &[u8],
+        associated_data: &[u8],
+    ) -> anyhow::Result<(Vec<u8>, EncryptedRequest)> {
+        let combined_associated_data = combine_routing_header(header, associated_data)?;
+        let (nonce, ciphertext) = self.seal_request(plaintext, &combined_associated_data)?;
+        let encrypted_request = EncryptedRequest {
+            encrypted_message: Some(AeadEncryptedMessage {
+                nonce: nonce.to_vec(),
+                ciphertext,
+                associated_data: associated_data.to_vec(),
+            }),
+            serialized_encapsulated_public_key: self.serialized_encapsulated_public_key.take(),
+        };
+        Ok((header.to_vec(), encrypted_request))
+    }
+
+    /// Encrypts `plaintext` and authenticates `msg_type` together with
+    /// `associated_data`, so that a message of one type can't be passed off as
+    /// a message of another type.
+    ///
+    /// The associated data field of the request carries both, so the server has
+    /// to use [`ServerEncryptor::decrypt_typed`].
+    pub fn encrypt_typed(
+        &mut self,
+        msg_type: u64,
+        plaintext: &[u8],
+        associated_data: &[u8],
+    ) -> anyhow::Result<EncryptedRequest> {
+        self.encrypt(plaintext, &combine_message_type(msg_type, associated_data))
+    }
+
+    /// Encrypts `plaintext` and authenticates `associated_data` using AEAD, and
+    /// returns the initial request message as a single contiguous buffer of
+    /// `encapsulated_public_key || nonce || ciphertext`, for transports that
+    /// only carry one opaque byte field per message.
+    ///
+    /// The associated data is not included in the buffer and must be passed to
+    /// [`ServerEncryptor::decrypt_combined`] separately. Only the initial
+    /// request of a session can be combined.
+    pub fn encrypt_combined(
+        &mut self,
+        plaintext: &[u8],
+        associated_data: &[u8],
+    ) -> anyhow::Result<Vec<u8>> {
+        anyhow::ensure!(
+            self.serialized_encapsulated_public_key.is_some(),
+            "combined messages can only be used for the initial request of a session"
+        );
+        let (nonce, ciphertext) = self.seal_request(plaintext, associated_data)?;
+        let serialized_encapsulated_public_key = self
+            .serialized_encapsulated_public_key
+            .take()
+            .expect("encapsulated public key was checked above");
+        Ok([serialized_encapsulated_public_key.as_slice(), &nonce, &ciphertext].concat())
+    }
+}
+
+#[cfg(feature = "response-direction")]
+impl ClientEncryptor {
+    /// Encrypts `plaintext` as the initial request of a new session, for
+    /// senders that don't keep state between sending a request and handling
+    /// its response. Returns the request, laid out as in
+    /// [`ClientEncryptor::encrypt_combined`], and a token that carries the
+    /// session keys encrypted with `token_key`.
+    ///
+    /// The session can be resumed from the token with
+    /// [`ClientEncryptor::resume_from_token`], e.g. by another instance of the
+    /// service. Anyone holding the token and `token_key` can decrypt the
+    /// responses of the session.
+    pub fn seal_stateless(
+        serialized_server_public_key: &[u8],
+        plaintext: &[u8],
+        associated_data: &[u8],
+        token_key: &[u8; SUITE_LENGTHS.nk],
+    ) -> anyhow::Result<(Vec<u8>, Vec<u8>)> {
+        let mut encryptor = Self::create(serialized_server_public_key)?;
+        let request = encryptor.encrypt_combined(plaintext, associated_data)?;
+        Ok((request, encryptor.session_token(token_key, SESSION_TOKEN_LABEL)?))
+    }
+
+    /// Same as [`ClientEncryptor::seal_stateless`], but binds the token to
+    /// `epoch`, which the caller has to increase for every token it stores.
+    /// Tokens are resumed with
+    /// [`ClientEncryptor::resume_from_token_with_epoch`], which rejects tokens
+    /// of old epochs, so that an older token can't be restored by rolling back
+    /// the storage.
+    pub fn seal_stateless_with_epoch(
+        serialized_server_public_key: &[u8],
+        plaintext: &[u8],
+        associated_data: &[u8],
+        token_key: &[u8; SUITE_LENGTHS.nk],
+        epoch: u64,
+    ) -> anyhow::Result<(Vec<u8>, Vec<u8>)> {
+        let mut encryptor = Self::create(serialized_server_public_key)?;
+        let request = encryptor.encrypt_combined(plaintext, associated_data)?;
+        let token = encryptor.session_token(token_key, &epoch_token_associated_data(epoch))?;
+        Ok((request, [epoch.to_be_bytes().as_slice(), &token].concat()))
+    }
+
+    /// Resumes a session from a token returned by
+    /// [`ClientEncryptor::seal_stateless`]. The initial request has already
+    /// been sent, so the returned encryptor can decrypt responses and encrypt
+    /// follow-up requests.
+    pub fn resume_from_token(
+        token: &[u8],
+        token_key: &[u8; SUITE_LENGTHS.nk],
+    ) -> anyhow::Result<Self> {
+        Self::resume(token, token_key, SESSION_TOKEN_LABEL)
+    }
+
+    /// Resumes a session from a token returned by
+    /// [`ClientEncryptor::seal_stateless_with_epoch`]. Fails with
+    /// [`CryptoError::StaleEpoch`] if the epoch of the token isn't greater
+    /// than `last_seen_epoch`. Returns the encryptor and the epoch of the
+    /// token, which the caller has to persist as the last seen epoch.
+    pub fn resume_from_token_with_epoch(
+        token: &[u8],
+        token_key: &[u8; SUITE_LENGTHS.nk],
+        last_seen_epoch: u64,
+    ) -> anyhow::Result<(Self, u64)> {
+        let mut reader = WireReader::new(token);
+        let epoch = reader.get_u64().context("couldn't read session token epoch")?;
+        // The epoch is authenticated by the token, so it is only compared once
+        // the token has been decrypted.
+        let encryptor =
+            Self::resume(reader.remaining(), token_key, &epoch_token_associated_data(epoch))?;
+        if epoch <= last_seen_epoch {
+            return Err(CryptoError::StaleEpoch.context(format!(
+                "session token of epoch {} isn't newer than the last seen epoch {}",
+                epoch, last_seen_epoch
+            )));
+        }
+        Ok((encryptor, epoch))
+    }
+
+    /// Encrypts the session keys with `token_key`, authenticating
+    /// `associated_data`.
+    fn session_token(
+        &self,
+        token_key: &[u8; SUITE_LENGTHS.nk],
+        associated_data: &[u8],
+    ) -> anyhow::Result<Vec<u8>> {
+        let session_keys = self.sender_context.serialize();
+        let nonce = generate_random_nonce();
+        let ciphertext = crate::hpke::aead::encrypt(
+            &AeadKey::new(*token_key),
+            &nonce,
+            &session_keys,
+            associated_data,
+        )
+        .context("couldn't encrypt session token")?;
+        Ok([nonce.as_slice(), &ciphertext].concat())
+    }
+
+    fn resume(
+        token: &[u8],
+        token_key: &[u8; SUITE_LENGTHS.nk],
+        associated_data: &[u8],
+    ) -> anyhow::Result<Self> {
+        if token.len() < SUITE_LENGTHS.nn {
+            return Err(CryptoError::MalformedInput.context(format!(
+                "session token is too short, expected at least {} bytes, got {}",
+                SUITE_LENGTHS.nn,
+                token.len()
+            )));
+        }
+        let (nonce, ciphertext) = token.split_at(SUITE_LENGTHS.nn);
+        let nonce = deserialize_nonce(nonce).context("couldn't deserialize nonce")?;
+        let session_keys = Zeroizing::new(
+            crate::hpke::aead::decrypt(
+                &AeadKey::new(*token_key),
+                &nonce,
+                ciphertext,
+                associated_data,
+            )
+            .context("couldn't decrypt session token")?,
+        );
+        let session_keys = SessionKeys::decode(session_keys.as_slice()).map_err(|error| {
+            CryptoError::MalformedInput.context(format!("couldn't decode session keys: {}", error))
+        })?;
+        let sender_context = SenderContext::deserialize(session_keys)
+            .context("couldn't restore sender crypto context")?;
+        Ok(Self::from_sender_context(None, sender_context, 1))
+    }
+
+    /// Encrypts `plaintext` as the initial request of a new session, for
+    /// store-and-forward transports on which the sender can be offline by the
+    /// time the response is generated. Returns the request, laid out as in
+    /// [`ClientEncryptor::encrypt_combined`], and a return path that carries
+    /// only the response key, encrypted with `storage_key`.
+    ///
+    /// The response is decrypted with a [`ResponseDecryptor`] loaded from the
+    /// return path. Unlike the token of [`ClientEncryptor::seal_stateless`],
+    /// the return path can't be used to decrypt or forge requests.
+    pub fn create_offline_exchange(
+        serialized_server_public_key: &[u8],
+        plaintext: &[u8],
+        associated_data: &[u8],
+        storage_key: &[u8; SUITE_LENGTHS.nk],
+    ) -> anyhow::Result<(Vec<u8>, Vec<u8>)> {
+        let mut encryptor = Self::create(serialized_server_public_key)?;
+        let request = encryptor.encrypt_combined(plaintext, associated_data)?;
+        let nonce = generate_random_nonce();
+        let ciphertext = crate::hpke::aead::encrypt(
+            &AeadKey::new(*storage_key),
+            &nonce,
+            encryptor.sender_context.response_key()?.as_bytes(),
+            RETURN_PATH_LABEL,
+        )
+        .context("couldn't encrypt return path")?;
+        Ok((request, [nonce.as_slice(), &ciphertext].concat()))
+    }
+}
+
+#[cfg(feature = "response-direction")]
+impl<M: AadMode> ClientEncryptor<M> {
+    /// Encrypts `plaintext` and authenticates `associated_data` using AEAD, and
+    /// writes the ciphertext into the beginning of `output` without allocating
+    /// it. Returns the remaining fields of the request message, which the
+    /// caller has to send along with the ciphertext.
+    ///
+    /// `output` must be at least `plaintext.len()` plus the authentication tag
+    /// size long, otherwise an error is returned and the session state is left
+    /// unchanged.
+    pub fn encrypt_into(
+        &mut self,
+        plaintext: &[u8],
+        associated_data: &[u8],
+        output: &mut [u8],
+    ) -> anyhow::Result<EncryptedRequestHeader> {
+        self.check_messages_remaining()?;
+        let nonce = generate_random_nonce();
+        let ciphertext_size = self
+            .sender_context
+            .seal_into(&nonce, plaintext, &aead_associated_data(associated_data, M::HASHED), output)
+            .context("couldn't encrypt request")?;
+        self.encrypted_requests += 1;
+        Ok(EncryptedRequestHeader {
+            // Encapsulated public key is only sent in the initial request message of the session.
+            serialized_encapsulated_public_key: self.serialized_encapsulated_public_key.take(),
+            nonce,
+            ciphertext_size,
+        })
+    }
+
+    /// Same as [`ClientEncryptor::encrypt`], but returns the request encoded as
+    /// a compact envelope.
+    #[cfg(feature = "compact")]
+    pub fn encrypt_to_compact(
+        &mut self,
+        plaintext: &[u8],
+        associated_data: &[u8],
+    ) -> anyhow::Result<Vec<u8>> {
+        crate::compact::encode_request(&self.encrypt(plaintext, associated_data)?)
+    }
+
+    /// Same as [`ClientEncryptor::decrypt`], but accepts a response encoded
+    /// either as a compact envelope or as a protobuf message.
+    #[cfg(feature = "compact")]
+    pub fn decrypt_any(&self, encoded_response: &[u8]) -> anyhow::Result<(Vec<u8>, Vec<u8>)> {
+        self.decrypt(&crate::compact::decode_any_response(encoded_response)?)
+    }
 
     /// Decrypts a [`EncryptedResponse`] proto message using AEAD.
-    /// Returns a response message plaintext and associated data.
+    /// Returns a response message plaintext and associated data. The returned
+    /// associated data is exactly the data that was authenticated, and can be
+    /// checked with [`verify_associated_data`].
     /// <https://datatracker.ietf.org/doc/html/rfc5116>
     pub fn decrypt(
         &self,
@@ -101,10 +858,237 @@ impl ClientEncryptor {
 
         let plaintext = self
             .sender_context
-            .open(&nonce, &encrypted_message.ciphertext, &encrypted_message.associated_data)
+            .open(
+                &nonce,
+                &encrypted_message.ciphertext,
+                &aead_associated_data(&encrypted_message.associated_data, M::HASHED),
+            )
             .context("couldn't decrypt response")?;
         Ok((plaintext, encrypted_message.associated_data.to_vec()))
     }
+
+    /// Same as [`ClientEncryptor::decrypt`], but also checks that the
+    /// authenticated associated data satisfies `policy`. The plaintext is
+    /// zeroized if it doesn't.
+    pub fn decrypt_with_policy(
+        &self,
+        encrypted_response: &EncryptedResponse,
+        policy: &AadPolicy,
+    ) -> anyhow::Result<(Vec<u8>, Vec<u8>)> {
+        let (mut plaintext, associated_data) = self.decrypt(encrypted_response)?;
+        if let Err(error) = policy.check(&associated_data) {
+            plaintext.zeroize();
+            return Err(error.context("response associated data violates the policy"));
+        }
+        Ok((plaintext, associated_data))
+    }
+
+    /// Decrypts a [`EncryptedResponse`] proto message produced by
+    /// [`ServerEncryptor::encrypt_typed`], and checks that its authenticated
+    /// message type is `expected_msg_type`. Returns the message plaintext and
+    /// the associated data without the message type. The plaintext is zeroized
+    /// if the message type doesn't match.
+    pub fn decrypt_typed(
+        &self,
+        encrypted_response: &EncryptedResponse,
+        expected_msg_type: u64,
+    ) -> anyhow::Result<(Vec<u8>, Vec<u8>)> {
+        let (mut plaintext, combined_associated_data) = self.decrypt(encrypted_response)?;
+        match check_message_type(&combined_associated_data, expected_msg_type) {
+            Ok(associated_data) => Ok((plaintext, associated_data.to_vec())),
+            Err(error) => {
+                plaintext.zeroize();
+                Err(error)
+            }
+        }
+    }
+
+    /// Decrypts the first response of a session re-established with
+    /// [`ClientEncryptor::reestablish_with_continuity`], produced by
+    /// [`ServerEncryptor::encrypt_with_continuity`], and checks its continuity
+    /// token. Returns the message plaintext and the associated data without the
+    /// token. The plaintext is zeroized if the check fails.
+    pub fn decrypt_with_continuity(
+        &self,
+        encrypted_response: &EncryptedResponse,
+        check: &ContinuityCheck,
+    ) -> anyhow::Result<(Vec<u8>, Vec<u8>)> {
+        let (mut plaintext, combined_associated_data) = self.decrypt(encrypted_response)?;
+        match check_continuity_token(&combined_associated_data, check) {
+            Ok(associated_data) => Ok((plaintext, associated_data.to_vec())),
+            Err(error) => {
+                plaintext.zeroize();
+                Err(error)
+            }
+        }
+    }
+
+    /// Decrypts a [`EncryptedResponse`] proto message using AEAD and passes the
+    /// plaintext to `f`, returning its result.
+    ///
+    /// The plaintext is only accessible inside `f`, and is zeroized as soon as
+    /// `f` returns. The plaintext buffer is reused across calls to avoid
+    /// allocations.
+    pub fn decrypt_scoped<R, F: FnOnce(&[u8]) -> R>(
+        &mut self,
+        encrypted_response: &EncryptedResponse,
+        f: F,
+    ) -> anyhow::Result<R> {
+        let encrypted_message = encrypted_response
+            .encrypted_message
+            .as_ref()
+            .context("response doesn't contain encrypted message")?;
+        let nonce =
+            deserialize_nonce(&encrypted_message.nonce).context("couldn't deserialize nonce")?;
+
+        self.sender_context
+            .open_into(
+                &nonce,
+                &encrypted_message.ciphertext,
+                &aead_associated_data(&encrypted_message.associated_data, M::HASHED),
+                &mut self.scoped_plaintext,
+            )
+            .context("couldn't decrypt response")?;
+        let result = f(&self.scoped_plaintext);
+        self.scoped_plaintext.zeroize();
+        Ok(result)
+    }
+
+    fn seal_request(
+        &mut self,
+        plaintext: &[u8],
+        associated_data: &[u8],
+    ) -> anyhow::Result<(AeadNonce, Vec<u8>)> {
+        self.check_messages_remaining()?;
+        let nonce = generate_random_nonce();
+        let ciphertext = self
+            .sender_context
+            .seal(&nonce, plaintext, &aead_associated_data(associated_data, M::HASHED))
+            .context("couldn't encrypt request")?;
+        self.encrypted_requests += 1;
+        Ok((nonce, ciphertext))
+    }
+
+    /// Discards the response path of the session by zeroizing the response key
+    /// immediately, for callers that only send requests and want the key wiped
+    /// before the encryptor goes out of scope. Requests can still be encrypted,
+    /// but all responses are rejected afterwards. The key is zeroized on drop
+    /// either way.
+    pub fn drop_response(&mut self) {
+        self.sender_context.discard_response_key();
+    }
+
+    /// Returns the number of bytes of secret material held by the session,
+    /// i.e. the session keys and the capacity of the buffer used by
+    /// [`ClientEncryptor::decrypt_scoped`], without exposing their contents.
+    /// All of it is zeroized when the encryptor is dropped.
+    pub fn secret_material_len(&self) -> usize {
+        self.sender_context.secret_material_len() + self.scoped_plaintext.capacity()
+    }
+
+    /// Returns the continuity secret of the session, which has to be stored to
+    /// re-establish the session later with
+    /// [`ClientEncryptor::reestablish_with_continuity`]. Fails if the response
+    /// key was discarded with [`ClientEncryptor::drop_response`].
+    pub fn continuity_secret(&self) -> anyhow::Result<ContinuitySecret> {
+        self.sender_context.continuity_secret()
+    }
+
+    /// Turns the encryptor into a [`SimplexReceiver`] for messages pushed by
+    /// the server with a [`SimplexPusher`]. The session has to be established
+    /// by sending at least one request first.
+    pub fn into_simplex_receiver(self) -> SimplexReceiver {
+        SimplexReceiver {
+            sender_context: self.sender_context,
+            hashed_associated_data: M::HASHED,
+            received_messages: 0,
+        }
+    }
+
+    fn check_messages_remaining(&self) -> anyhow::Result<()> {
+        if self.messages_remaining() == 0 {
+            return Err(CryptoError::NonceExhaustion
+                .context("session has reached the maximum number of messages"));
+        }
+        Ok(())
+    }
+
+    #[cfg(test)]
+    pub(crate) fn set_encrypted_requests(&mut self, encrypted_requests: u64) {
+        self.encrypted_requests = encrypted_requests;
+    }
+
+    #[cfg(test)]
+    pub(crate) fn scoped_plaintext_buffer(&self) -> &Vec<u8> {
+        &self.scoped_plaintext
+    }
+}
+
+/// Size of a return path created by
+/// [`ClientEncryptor::create_offline_exchange`].
+#[cfg(feature = "response-direction")]
+pub const RETURN_PATH_SIZE_BYTES: usize = SUITE_LENGTHS.nn + SUITE_LENGTHS.nk + SUITE_LENGTHS.nt;
+
+/// Decryptor object for the responses of a session that was created with
+/// [`ClientEncryptor::create_offline_exchange`]. It only holds the response
+/// key, which is zeroized when the decryptor is dropped.
+#[cfg(feature = "response-direction")]
+pub struct ResponseDecryptor {
+    response_key: AeadKey,
+}
+
+#[cfg(feature = "response-direction")]
+impl ResponseDecryptor {
+    /// Loads the response key from a return path that was encrypted with
+    /// `storage_key`.
+    pub fn from_return_path(
+        return_path: &[u8],
+        storage_key: &[u8; SUITE_LENGTHS.nk],
+    ) -> anyhow::Result<Self> {
+        if return_path.len() != RETURN_PATH_SIZE_BYTES {
+            return Err(CryptoError::MalformedInput.context(format!(
+                "incorrect return path size, expected {}, got {}",
+                RETURN_PATH_SIZE_BYTES,
+                return_path.len()
+            )));
+        }
+        let (nonce, ciphertext) = return_path.split_at(SUITE_LENGTHS.nn);
+        let nonce = deserialize_nonce(nonce).context("couldn't deserialize nonce")?;
+        let response_key = Zeroizing::new(
+            crate::hpke::aead::decrypt(
+                &AeadKey::new(*storage_key),
+                &nonce,
+                ciphertext,
+                RETURN_PATH_LABEL,
+            )
+            .context("couldn't decrypt return path")?,
+        );
+        let response_key: [u8; SUITE_LENGTHS.nk] =
+            response_key.as_slice().try_into().expect("return path size was checked above");
+        Ok(Self { response_key: AeadKey::new(response_key) })
+    }
+
+    /// Decrypts a [`EncryptedResponse`] proto message using AEAD. Returns the
+    /// message plaintext and associated data.
+    pub fn decrypt(
+        &self,
+        encrypted_response: &EncryptedResponse,
+    ) -> anyhow::Result<(Vec<u8>, Vec<u8>)> {
+        let encrypted_message = encrypted_response
+            .encrypted_message
+            .as_ref()
+            .context("response doesn't contain encrypted message")?;
+        let nonce =
+            deserialize_nonce(&encrypted_message.nonce).context("couldn't deserialize nonce")?;
+        let plaintext = crate::hpke::aead::decrypt(
+            &self.response_key,
+            &nonce,
+            &encrypted_message.ciphertext,
+            &encrypted_message.associated_data,
+        )
+        .context("couldn't decrypt response")?;
+        Ok((plaintext, encrypted_message.associated_data.to_vec()))
+    }
 }
 
 /// Encryptor object for decrypting client requests that are received by the
@@ -115,28 +1099,145 @@ impl ClientEncryptor {
 /// Sequence numbers for requests and responses are incremented separately,
 /// meaning that there could be multiple responses per request and multiple
 /// requests per response.
-pub struct ServerEncryptor {
+///
+/// The encryptor owns the keys of its session, and only zeroizes them when it
+/// is dropped. It doesn't depend on the [`EncryptionKeyHandle`] it was set up
+/// with, which can be dropped first.
+#[cfg(feature = "response-direction")]
+pub struct ServerEncryptor<M = PlainAad> {
     recipient_context: RecipientContext,
+    aad_mode: PhantomData<fn() -> M>,
 }
 
+#[cfg(feature = "response-direction")]
 impl ServerEncryptor {
     /// Decrypts a [`EncryptedRequest`] proto message using AEAD.
     /// Returns a response encryptor, the message plaintext and associated data.
+    /// The returned associated data is exactly the data that was authenticated,
+    /// and can be checked with [`verify_associated_data`].
     /// <https://datatracker.ietf.org/doc/html/rfc5116>
     pub fn decrypt<E: EncryptionKeyHandle + ?Sized>(
         encrypted_request: &EncryptedRequest,
         encryption_key_handle: &E,
+    ) -> anyhow::Result<(Self, Vec<u8>, Vec<u8>)> {
+        let encryptor = Self::setup(encrypted_request, encryption_key_handle)?;
+        let (plaintext, associated_data) = encryptor.decrypt_inner(encrypted_request)?;
+        Ok((encryptor, plaintext, associated_data))
+    }
+
+    /// Same as [`ServerEncryptor::decrypt`], but for sessions created with
+    /// [`ClientEncryptor::with_hashed_aad`], in which the SHA-256 digest of the
+    /// associated data is authenticated. The returned encryptor authenticates
+    /// response associated data the same way.
+    pub fn decrypt_with_hashed_aad<E: EncryptionKeyHandle + ?Sized>(
+        encrypted_request: &EncryptedRequest,
+        encryption_key_handle: &E,
+    ) -> anyhow::Result<(ServerEncryptor<HashedAad>, Vec<u8>, Vec<u8>)> {
+        let encryptor = ServerEncryptor::setup(encrypted_request, encryption_key_handle)?;
+        let (plaintext, associated_data) = encryptor.decrypt_inner(encrypted_request)?;
+        Ok((encryptor, plaintext, associated_data))
+    }
+
+    /// Same as [`ServerEncryptor::decrypt`], but also checks that the
+    /// authenticated associated data satisfies `policy`. The plaintext is
+    /// zeroized if it doesn't.
+    pub fn decrypt_with_policy<E: EncryptionKeyHandle + ?Sized>(
+        encrypted_request: &EncryptedRequest,
+        encryption_key_handle: &E,
+        policy: &AadPolicy,
+    ) -> anyhow::Result<(Self, Vec<u8>, Vec<u8>)> {
+        let (encryptor, mut plaintext, associated_data) =
+            Self::decrypt(encrypted_request, encryption_key_handle)?;
+        if let Err(error) = policy.check(&associated_data) {
+            plaintext.zeroize();
+            return Err(error.context("request associated data violates the policy"));
+        }
+        Ok((encryptor, plaintext, associated_data))
+    }
+
+    /// Same as [`ServerEncryptor::decrypt`], but first checks `solution`
+    /// against `gate`, with the encapsulated public key as the client hint.
+    /// Requests without a valid solution are refused before the session key is
+    /// decapsulated.
+    pub fn decrypt_gated<E: EncryptionKeyHandle + ?Sized>(
+        encrypted_request: &EncryptedRequest,
+        encryption_key_handle: &E,
+        gate: &SetupGate,
+        solution: Option<&SetupSolution>,
+        current_time_millis: u64,
     ) -> anyhow::Result<(Self, Vec<u8>, Vec<u8>)> {
         let serialized_encapsulated_public_key = encrypted_request
             .serialized_encapsulated_public_key
             .as_ref()
             .context("initial request message doesn't contain encapsulated public key")?;
-        let recipient_context = encryption_key_handle
-            .generate_recipient_context(serialized_encapsulated_public_key)
-            .context("couldn't generate recipient crypto context")?;
-        let encryptor = Self { recipient_context };
-        let (plaintext, associated_data) = encryptor.decrypt_inner(encrypted_request)?;
-        Ok((encryptor, plaintext, associated_data))
+        gate.check(serialized_encapsulated_public_key, solution, current_time_millis)
+            .context("session setup was refused")?;
+        Self::decrypt(encrypted_request, encryption_key_handle)
+    }
+
+    /// Decrypts a [`EncryptedRequest`] proto message produced by
+    /// [`ClientEncryptor::encrypt_with_header`]. Returns a response encryptor
+    /// and the decrypted request, whose routing header has been authenticated.
+    ///
+    /// An [`AadPolicy`] only applies to the associated data provided by the
+    /// caller, so it has to be checked against
+    /// [`DecryptedRequest::associated_data`], not with
+    /// [`ServerEncryptor::decrypt_with_policy`].
+    pub fn decrypt_with_header<E: EncryptionKeyHandle + ?Sized>(
+        encrypted_request: &EncryptedRequest,
+        encryption_key_handle: &E,
+    ) -> anyhow::Result<(Self, DecryptedRequest)> {
+        let (encryptor, plaintext, combined_associated_data) =
+            Self::decrypt(encrypted_request, encryption_key_handle)?;
+        let (header, associated_data) = split_routing_header(&combined_associated_data)?;
+        let decrypted_request = DecryptedRequest {
+            header: header.to_vec(),
+            plaintext,
+            associated_data: associated_data.to_vec(),
+        };
+        Ok((encryptor, decrypted_request))
+    }
+
+    /// Decrypts a [`EncryptedRequest`] proto message produced by
+    /// [`ClientEncryptor::seal_with_header`], authenticating the routing
+    /// `header` that was received next to it. Returns a response encryptor, the
+    /// message plaintext and the associated data, which doesn't include the
+    /// header.
+    pub fn open_with_header<E: EncryptionKeyHandle + ?Sized>(
+        header: &[u8],
+        encrypted_request: &EncryptedRequest,
+        encryption_key_handle: &E,
+    ) -> anyhow::Result<(Self, Vec<u8>, Vec<u8>)> {
+        let encryptor = Self::setup(encrypted_request, encryption_key_handle)?;
+        let encrypted_message = encrypted_request
+            .encrypted_message
+            .as_ref()
+            .context("request doesn't contain encrypted message")?;
+        let combined_associated_data =
+            combine_routing_header(header, &encrypted_message.associated_data)?;
+        let plaintext = encryptor.open_request(encrypted_message, &combined_associated_data)?;
+        Ok((encryptor, plaintext, encrypted_message.associated_data.to_vec()))
+    }
+
+    /// Decrypts a [`EncryptedRequest`] proto message produced by
+    /// [`ClientEncryptor::encrypt_typed`], and checks that its authenticated
+    /// message type is `expected_msg_type`. Returns a response encryptor, the
+    /// message plaintext and the associated data without the message type. The
+    /// plaintext is zeroized if the message type doesn't match.
+    pub fn decrypt_typed<E: EncryptionKeyHandle + ?Sized>(
+        encrypted_request: &EncryptedRequest,
+        encryption_key_handle: &E,
+        expected_msg_type: u64,
+    ) -> anyhow::Result<(Self, Vec<u8>, Vec<u8>)> {
+        let (encryptor, mut plaintext, combined_associated_data) =
+            Self::decrypt(encrypted_request, encryption_key_handle)?;
+        match check_message_type(&combined_associated_data, expected_msg_type) {
+            Ok(associated_data) => Ok((encryptor, plaintext, associated_data.to_vec())),
+            Err(error) => {
+                plaintext.zeroize();
+                Err(error)
+            }
+        }
     }
 
     /// Decrypts a [`EncryptedRequest`] proto message using AEAD.
@@ -156,13 +1257,108 @@ impl ServerEncryptor {
             .generate_recipient_context(serialized_encapsulated_public_key)
             .await
             .context("couldn't generate recipient crypto context")?;
-        let encryptor = Self { recipient_context };
+        let encryptor = Self { recipient_context, aad_mode: PhantomData };
         let (plaintext, associated_data) = encryptor.decrypt_inner(encrypted_request)?;
         Ok((encryptor, plaintext, associated_data))
     }
 
+    /// Same as [`ServerEncryptor::decrypt`], but accepts a request encoded
+    /// either as a compact envelope or as a protobuf message.
+    #[cfg(feature = "compact")]
+    pub fn decrypt_any<E: EncryptionKeyHandle + ?Sized>(
+        encoded_request: &[u8],
+        encryption_key_handle: &E,
+    ) -> anyhow::Result<(Self, Vec<u8>, Vec<u8>)> {
+        Self::decrypt(&crate::compact::decode_any_request(encoded_request)?, encryption_key_handle)
+    }
+
+    /// Decrypts an initial request message produced by
+    /// [`ClientEncryptor::encrypt_combined`], authenticating `associated_data`.
+    /// Returns a response encryptor and the message plaintext.
+    pub fn decrypt_combined<E: EncryptionKeyHandle + ?Sized>(
+        combined_message: &[u8],
+        associated_data: &[u8],
+        encryption_key_handle: &E,
+    ) -> anyhow::Result<(Self, Vec<u8>)> {
+        if combined_message.len() < MIN_COMBINED_MESSAGE_SIZE_BYTES {
+            return Err(CryptoError::MalformedInput.context(format!(
+                "combined message is too short, expected at least {} bytes, got {}",
+                MIN_COMBINED_MESSAGE_SIZE_BYTES,
+                combined_message.len()
+            )));
+        }
+        let (serialized_encapsulated_public_key, encrypted_message) =
+            combined_message.split_at(SUITE_LENGTHS.nenc);
+        let (nonce, ciphertext) = encrypted_message.split_at(SUITE_LENGTHS.nn);
+
+        let recipient_context = encryption_key_handle
+            .generate_recipient_context(serialized_encapsulated_public_key)
+            .context("couldn't generate recipient crypto context")?;
+        let nonce = deserialize_nonce(nonce).context("couldn't deserialize nonce")?;
+        let plaintext = recipient_context
+            .open(&nonce, ciphertext, associated_data)
+            .context("couldn't decrypt request")?;
+        Ok((Self { recipient_context, aad_mode: PhantomData }, plaintext))
+    }
+
+    /// Decrypts a [`EncryptedRequest`] proto message using AEAD and passes the
+    /// plaintext to `f`. Returns a response encryptor and the result of `f`.
+    ///
+    /// The plaintext is only accessible inside `f`, and is zeroized as soon as
+    /// `f` returns.
+    pub fn decrypt_scoped<E: EncryptionKeyHandle + ?Sized, R, F: FnOnce(&[u8]) -> R>(
+        encrypted_request: &EncryptedRequest,
+        encryption_key_handle: &E,
+        f: F,
+    ) -> anyhow::Result<(Self, R)> {
+        let serialized_encapsulated_public_key = encrypted_request
+            .serialized_encapsulated_public_key
+            .as_ref()
+            .context("initial request message doesn't contain encapsulated public key")?;
+        let recipient_context = encryption_key_handle
+            .generate_recipient_context(serialized_encapsulated_public_key)
+            .context("couldn't generate recipient crypto context")?;
+        let encrypted_message = encrypted_request
+            .encrypted_message
+            .as_ref()
+            .context("request doesn't contain encrypted message")?;
+        let nonce =
+            deserialize_nonce(&encrypted_message.nonce).context("couldn't deserialize nonce")?;
+
+        let mut plaintext = Zeroizing::new(Vec::new());
+        recipient_context
+            .open_into(
+                &nonce,
+                &encrypted_message.ciphertext,
+                &encrypted_message.associated_data,
+                &mut plaintext,
+            )
+            .context("couldn't decrypt request")?;
+        let result = f(&plaintext);
+        Ok((Self { recipient_context, aad_mode: PhantomData }, result))
+    }
+
     pub fn new(recipient_context: RecipientContext) -> Self {
-        Self { recipient_context }
+        Self { recipient_context, aad_mode: PhantomData }
+    }
+}
+
+#[cfg(feature = "response-direction")]
+impl<M: AadMode> ServerEncryptor<M> {
+    /// Sets up the session of the initial request `encrypted_request`, without
+    /// decrypting it.
+    fn setup<E: EncryptionKeyHandle + ?Sized>(
+        encrypted_request: &EncryptedRequest,
+        encryption_key_handle: &E,
+    ) -> anyhow::Result<Self> {
+        let serialized_encapsulated_public_key = encrypted_request
+            .serialized_encapsulated_public_key
+            .as_ref()
+            .context("initial request message doesn't contain encapsulated public key")?;
+        let recipient_context = encryption_key_handle
+            .generate_recipient_context(serialized_encapsulated_public_key)
+            .context("couldn't generate recipient crypto context")?;
+        Ok(Self { recipient_context, aad_mode: PhantomData })
     }
 
     fn decrypt_inner(
@@ -173,19 +1369,39 @@ impl ServerEncryptor {
             .encrypted_message
             .as_ref()
             .context("request doesn't contain encrypted message")?;
+        let plaintext = self.open_request(encrypted_message, &encrypted_message.associated_data)?;
+        Ok((plaintext, encrypted_message.associated_data.to_vec()))
+    }
+
+    /// Decrypts `encrypted_message`, authenticating `associated_data` instead
+    /// of its associated data field.
+    fn open_request(
+        &self,
+        encrypted_message: &AeadEncryptedMessage,
+        associated_data: &[u8],
+    ) -> anyhow::Result<Vec<u8>> {
         let nonce =
             deserialize_nonce(&encrypted_message.nonce).context("couldn't deserialize nonce")?;
-
-        let plaintext = self
-            .recipient_context
-            .open(&nonce, &encrypted_message.ciphertext, &encrypted_message.associated_data)
-            .context("couldn't decrypt request")?;
-        Ok((plaintext, encrypted_message.associated_data.to_vec()))
+        self.recipient_context
+            .open(
+                &nonce,
+                &encrypted_message.ciphertext,
+                &aead_associated_data(associated_data, M::HASHED),
+            )
+            .context("couldn't decrypt request")
     }
 
     /// Encrypts `plaintext` and authenticates `associated_data` using AEAD.
     /// Returns a [`EncryptedResponse`] proto message.
     /// <https://datatracker.ietf.org/doc/html/rfc5116>
+    ///
+    /// The response doesn't depend on any later state of the session, so it can
+    /// be sealed right after decrypting the request (e.g. for an ack that is
+    /// known in advance) and sent once the request has been processed.
+    ///
+    /// The encryptor is consumed, so a session has at most one response, and
+    /// its random nonce is never reused. Sessions that send more messages to
+    /// the client have to turn into a [`SimplexPusher`] instead.
     pub fn encrypt(
         self,
         plaintext: &[u8],
@@ -194,7 +1410,7 @@ impl ServerEncryptor {
         let nonce = generate_random_nonce();
         let ciphertext = self
             .recipient_context
-            .seal(&nonce, plaintext, associated_data)
+            .seal(&nonce, plaintext, &aead_associated_data(associated_data, M::HASHED))
             .context("couldn't encrypt response")?;
 
         Ok(EncryptedResponse {
@@ -205,4 +1421,383 @@ impl ServerEncryptor {
             }),
         })
     }
+
+    /// Same as [`ServerEncryptor::encrypt`], but also authenticates `msg_type`,
+    /// see [`ClientEncryptor::encrypt_typed`]. The client has to use
+    /// [`ClientEncryptor::decrypt_typed`].
+    pub fn encrypt_typed(
+        self,
+        msg_type: u64,
+        plaintext: &[u8],
+        associated_data: &[u8],
+    ) -> anyhow::Result<EncryptedResponse> {
+        self.encrypt(plaintext, &combine_message_type(msg_type, associated_data))
+    }
+
+    /// Same as [`ServerEncryptor::encrypt`], but also authenticates `token`,
+    /// for the first response of a session re-established with
+    /// [`ClientEncryptor::reestablish_with_continuity`]. The token is minted
+    /// with [`ContinuitySecret::hand_off_token`] by the recipient of the
+    /// previous session. The client has to use
+    /// [`ClientEncryptor::decrypt_with_continuity`].
+    pub fn encrypt_with_continuity(
+        self,
+        token: &ContinuityToken,
+        plaintext: &[u8],
+        associated_data: &[u8],
+    ) -> anyhow::Result<EncryptedResponse> {
+        self.encrypt(plaintext, &combine_continuity_token(token, associated_data))
+    }
+
+    /// Same as [`ServerEncryptor::encrypt`], but returns the response encoded
+    /// as a compact envelope.
+    #[cfg(feature = "compact")]
+    pub fn encrypt_to_compact(
+        self,
+        plaintext: &[u8],
+        associated_data: &[u8],
+    ) -> anyhow::Result<Vec<u8>> {
+        crate::compact::encode_response(&self.encrypt(plaintext, associated_data)?)
+    }
+
+    /// Returns the number of bytes of secret material held by the session,
+    /// i.e. the session keys, without exposing their contents.
+    pub fn secret_material_len(&self) -> usize {
+        self.recipient_context.secret_material_len()
+    }
+
+    /// Returns the continuity secret of the session, which the server has to
+    /// store to endorse the recipient key of the next session with
+    /// [`ContinuitySecret::hand_off_token`].
+    pub fn continuity_secret(&self) -> ContinuitySecret {
+        self.recipient_context.continuity_secret()
+    }
+
+    /// Turns the encryptor into a [`SimplexPusher`] that can send many
+    /// responses without intervening requests, e.g. for server push.
+    pub fn into_simplex_pusher(self) -> SimplexPusher {
+        SimplexPusher {
+            recipient_context: self.recipient_context,
+            hashed_associated_data: M::HASHED,
+            pushed_messages: 0,
+        }
+    }
+}
+
+/// Returns the associated data of a session token of `epoch`.
+#[cfg(feature = "response-direction")]
+fn epoch_token_associated_data(epoch: u64) -> Vec<u8> {
+    [SESSION_TOKEN_LABEL, &epoch.to_be_bytes()].concat()
+}
+
+/// Returns the AEAD associated data of the pushed message with the given
+/// `sequence_number`, which is `SIMPLEX_STREAM_LABEL || I2OSP(sequence_number,
+/// 8) || associated_data`.
+#[cfg(feature = "response-direction")]
+pub(crate) fn simplex_associated_data(
+    sequence_number: u64,
+    associated_data: &[u8],
+    hashed: bool,
+) -> Vec<u8> {
+    let associated_data = aead_associated_data(associated_data, hashed);
+    let mut simplex_associated_data =
+        Vec::with_capacity(SIMPLEX_STREAM_LABEL.len() + 8 + associated_data.len());
+    simplex_associated_data.extend_from_slice(SIMPLEX_STREAM_LABEL);
+    wire::put_u64(&mut simplex_associated_data, sequence_number);
+    simplex_associated_data.extend_from_slice(&associated_data);
+    simplex_associated_data
+}
+
+/// Encryptor object for pushing a stream of messages from the server to the
+/// client of a session, created with [`ServerEncryptor::into_simplex_pusher`].
+///
+/// Messages are sealed with the response key and random nonces, and their
+/// sequence numbers are authenticated as part of the associated data, so the
+/// [`SimplexReceiver`] only accepts them in the order in which they were
+/// pushed. Nonces are random rather than derived from the sequence number,
+/// because the session keys of a server can exist more than once, e.g. if the
+/// initial request is replayed, and two pushers must never seal different
+/// messages under the same key and nonce.
+#[cfg(feature = "response-direction")]
+pub struct SimplexPusher {
+    recipient_context: RecipientContext,
+    hashed_associated_data: bool,
+    /// Number of messages pushed in this session.
+    pushed_messages: u64,
+}
+
+#[cfg(feature = "response-direction")]
+impl SimplexPusher {
+    /// Encrypts `plaintext` and authenticates `associated_data` using AEAD as
+    /// the next message of the stream. Returns a [`EncryptedResponse`] proto
+    /// message.
+    pub fn push(
+        &mut self,
+        plaintext: &[u8],
+        associated_data: &[u8],
+    ) -> anyhow::Result<EncryptedResponse> {
+        anyhow::ensure!(
+            associated_data != KEEPALIVE_LABEL,
+            "associated data is reserved for keepalive messages"
+        );
+        self.push_message(plaintext, associated_data)
+    }
+
+    /// Seals a keepalive message, i.e. an empty message whose associated data
+    /// marks it as a keepalive, so that idle streams can be kept open. It takes
+    /// the next position of the stream like any other message, and the
+    /// receiver can tell it apart from data messages with
+    /// [`SimplexReceiver::is_keepalive`].
+    pub fn seal_keepalive(&mut self) -> anyhow::Result<EncryptedResponse> {
+        self.push_message(&[], KEEPALIVE_LABEL)
+    }
+
+    fn push_message(
+        &mut self,
+        plaintext: &[u8],
+        associated_data: &[u8],
+    ) -> anyhow::Result<EncryptedResponse> {
+        if self.pushed_messages == MAX_MESSAGES_PER_SESSION {
+            return Err(CryptoError::NonceExhaustion
+                .context("session has reached the maximum number of messages"));
+        }
+        let nonce = generate_random_nonce();
+        let ciphertext = self
+            .recipient_context
+            .seal(
+                &nonce,
+                plaintext,
+                &simplex_associated_data(
+                    self.pushed_messages,
+                    associated_data,
+                    self.hashed_associated_data,
+                ),
+            )
+            .context("couldn't encrypt pushed message")?;
+        self.pushed_messages += 1;
+
+        Ok(EncryptedResponse {
+            encrypted_message: Some(AeadEncryptedMessage {
+                nonce: nonce.to_vec(),
+                ciphertext,
+                associated_data: associated_data.to_vec(),
+            }),
+        })
+    }
+
+    /// Returns the number of messages that can still be pushed in this
+    /// session.
+    pub fn messages_remaining(&self) -> u64 {
+        MAX_MESSAGES_PER_SESSION - self.pushed_messages
+    }
+
+    /// Returns the number of bytes of secret material held by the session,
+    /// i.e. the session keys, without exposing their contents.
+    pub fn secret_material_len(&self) -> usize {
+        self.recipient_context.secret_material_len()
+    }
+
+    /// Turns the pusher into the sender of a resumable transfer of the chunks
+    /// described by `manifest`, which takes the remaining positions of the
+    /// stream. Returns the sender and the encrypted manifest, which has to be
+    /// pushed before the chunks.
+    pub fn into_transfer_sender(
+        self,
+        manifest: TransferManifest,
+    ) -> anyhow::Result<(TransferSender, EncryptedResponse)> {
+        TransferSender::create(self.recipient_context, self.pushed_messages, manifest)
+    }
+}
+
+/// Decryptor object for the messages of a [`SimplexPusher`], created with
+/// [`ClientEncryptor::into_simplex_receiver`].
+#[cfg(feature = "response-direction")]
+pub struct SimplexReceiver {
+    sender_context: SenderContext,
+    hashed_associated_data: bool,
+    /// Number of messages received in this session.
+    received_messages: u64,
+}
+
+#[cfg(feature = "response-direction")]
+impl SimplexReceiver {
+    /// Decrypts the next pushed [`EncryptedResponse`] proto message of the
+    /// stream. Returns the message plaintext and associated data.
+    ///
+    /// Messages that were reordered, replayed or follow a dropped message fail
+    /// authentication, and don't advance the stream.
+    pub fn receive(
+        &mut self,
+        encrypted_response: &EncryptedResponse,
+    ) -> anyhow::Result<(Vec<u8>, Vec<u8>)> {
+        let encrypted_message = encrypted_response
+            .encrypted_message
+            .as_ref()
+            .context("response doesn't contain encrypted message")?;
+
+        let nonce =
+            deserialize_nonce(&encrypted_message.nonce).context("couldn't deserialize nonce")?;
+        let plaintext = self
+            .sender_context
+            .open(
+                &nonce,
+                &encrypted_message.ciphertext,
+                &simplex_associated_data(
+                    self.received_messages,
+                    &encrypted_message.associated_data,
+                    self.hashed_associated_data,
+                ),
+            )
+            .context("couldn't decrypt pushed message")?;
+        self.received_messages += 1;
+        Ok((plaintext, encrypted_message.associated_data.to_vec()))
+    }
+
+    /// Returns whether the authenticated associated data returned by
+    /// [`SimplexReceiver::receive`] belongs to a keepalive message, which
+    /// doesn't carry application data.
+    pub fn is_keepalive(associated_data: &[u8]) -> bool {
+        associated_data == KEEPALIVE_LABEL
+    }
+
+    /// Returns the number of bytes of secret material held by the session,
+    /// i.e. the session keys, without exposing their contents.
+    pub fn secret_material_len(&self) -> usize {
+        self.sender_context.secret_material_len()
+    }
+
+    /// Turns the receiver into the receiver of a resumable transfer, whose
+    /// manifest is the next pushed message of the stream.
+    pub fn into_transfer_receiver(
+        self,
+        encrypted_manifest: &EncryptedResponse,
+    ) -> anyhow::Result<TransferReceiver> {
+        TransferReceiver::create(self.sender_context, self.received_messages, encrypted_manifest)
+    }
+}
+
+/// Encryptor object for sessions in which the client only sends requests and
+/// never receives responses, e.g. telemetry ingestion. Only the request key is
+/// derived, so the session keys take half the memory of a [`ClientEncryptor`].
+///
+/// The direction of the session is bound into the HPKE info, so its requests
+/// can only be decrypted by a [`RequestOnlyServerEncryptor`].
+pub struct RequestOnlyClientEncryptor {
+    /// Encapsulated public key needed to establish a symmetric session key.
+    /// Only sent in the initial request message of the session.
+    serialized_encapsulated_public_key: Option<Vec<u8>>,
+    sender_context: RequestOnlySenderContext,
+    /// Number of requests encrypted in this session.
+    encrypted_requests: u64,
+}
+
+impl RequestOnlyClientEncryptor {
+    /// Creates an HPKE crypto context by generating an new ephemeral key pair.
+    pub fn create(serialized_server_public_key: &[u8]) -> anyhow::Result<Self> {
+        let (serialized_encapsulated_public_key, sender_context) =
+            setup_request_only_sender(serialized_server_public_key, OAK_HPKE_REQUEST_ONLY_INFO)
+                .context("couldn't create sender crypto context")?;
+        Ok(Self {
+            serialized_encapsulated_public_key: Some(serialized_encapsulated_public_key),
+            sender_context,
+            encrypted_requests: 0,
+        })
+    }
+
+    /// Returns the number of requests that can still be encrypted in this
+    /// session before a new session has to be created.
+    pub fn messages_remaining(&self) -> u64 {
+        MAX_MESSAGES_PER_SESSION - self.encrypted_requests
+    }
+
+    /// Returns the number of bytes of secret material held by the session,
+    /// i.e. the request key, without exposing its contents.
+    pub fn secret_material_len(&self) -> usize {
+        self.sender_context.secret_material_len()
+    }
+
+    /// Encrypts `plaintext` and authenticates `associated_data` using AEAD.
+    /// Returns a [`EncryptedRequest`] proto message.
+    /// <https://datatracker.ietf.org/doc/html/rfc5116>
+    pub fn encrypt(
+        &mut self,
+        plaintext: &[u8],
+        associated_data: &[u8],
+    ) -> anyhow::Result<EncryptedRequest> {
+        if self.messages_remaining() == 0 {
+            return Err(CryptoError::NonceExhaustion
+                .context("session has reached the maximum number of messages"));
+        }
+        let nonce = generate_random_nonce();
+        let ciphertext = self
+            .sender_context
+            .seal(&nonce, plaintext, associated_data)
+            .context("couldn't encrypt request")?;
+        self.encrypted_requests += 1;
+
+        Ok(EncryptedRequest {
+            encrypted_message: Some(AeadEncryptedMessage {
+                nonce: nonce.to_vec(),
+                ciphertext,
+                associated_data: associated_data.to_vec(),
+            }),
+            // Encapsulated public key is only sent in the initial request message of the session.
+            serialized_encapsulated_public_key: self.serialized_encapsulated_public_key.take(),
+        })
+    }
+}
+
+/// Encryptor object for decrypting the requests of a session created by a
+/// [`RequestOnlyClientEncryptor`]. It can't encrypt responses.
+pub struct RequestOnlyServerEncryptor {
+    recipient_context: RequestOnlyRecipientContext,
+}
+
+impl RequestOnlyServerEncryptor {
+    /// Decrypts the initial [`EncryptedRequest`] proto message of a session.
+    /// Returns an encryptor for the following requests of the session, the
+    /// message plaintext and associated data.
+    /// <https://datatracker.ietf.org/doc/html/rfc5116>
+    pub fn decrypt<E: RequestOnlyEncryptionKeyHandle + ?Sized>(
+        encrypted_request: &EncryptedRequest,
+        encryption_key_handle: &E,
+    ) -> anyhow::Result<(Self, Vec<u8>, Vec<u8>)> {
+        let serialized_encapsulated_public_key = encrypted_request
+            .serialized_encapsulated_public_key
+            .as_ref()
+            .context("initial request message doesn't contain encapsulated public key")?;
+        let recipient_context = encryption_key_handle
+            .generate_request_only_recipient_context(serialized_encapsulated_public_key)
+            .context("couldn't generate recipient crypto context")?;
+        let encryptor = Self { recipient_context };
+        let (plaintext, associated_data) = encryptor.decrypt_next(encrypted_request)?;
+        Ok((encryptor, plaintext, associated_data))
+    }
+
+    /// Decrypts a following [`EncryptedRequest`] proto message of the session.
+    /// Returns the message plaintext and associated data.
+    /// <https://datatracker.ietf.org/doc/html/rfc5116>
+    pub fn decrypt_next(
+        &self,
+        encrypted_request: &EncryptedRequest,
+    ) -> anyhow::Result<(Vec<u8>, Vec<u8>)> {
+        let encrypted_message = encrypted_request
+            .encrypted_message
+            .as_ref()
+            .context("request doesn't contain encrypted message")?;
+        let nonce =
+            deserialize_nonce(&encrypted_message.nonce).context("couldn't deserialize nonce")?;
+
+        let plaintext = self
+            .recipient_context
+            .open(&nonce, &encrypted_message.ciphertext, &encrypted_message.associated_data)
+            .context("couldn't decrypt request")?;
+        Ok((plaintext, encrypted_message.associated_data.to_vec()))
+    }
+
+    /// Returns the number of bytes of secret material held by the session,
+    /// i.e. the request key, without exposing its contents.
+    pub fn secret_material_len(&self) -> usize {
+        self.recipient_context.secret_material_len()
+    }
 }
@@ -0,0 +1,150 @@
+//
+// Copyright 2024 The Project Oak Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Typed views of the encrypted message protos, whose fields are checked once
+//! on conversion instead of at every use. Conversions from protos fail with a
+//! [`CryptoError::MalformedInput`] error that names the offending field.
+//!
+//! The checks only cover the shape of a message. Whether the ciphertext
+//! authenticates is only known once it is decrypted.
+
+use alloc::{format, vec::Vec};
+
+use crate::{
+    error::CryptoError,
+    hpke::SUITE_LENGTHS,
+    proto::oak::crypto::v1::{AeadEncryptedMessage, EncryptedRequest, EncryptedResponse},
+};
+
+/// Encrypted message with a nonce of the correct size and a ciphertext that is
+/// at least as long as the authentication tag.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EncryptedMessage {
+    pub nonce: [u8; SUITE_LENGTHS.nn],
+    pub ciphertext: Vec<u8>,
+    pub associated_data: Vec<u8>,
+}
+
+/// Typed [`EncryptedRequest`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RequestEnvelope {
+    /// Encapsulated public key, only set for the initial request of a session.
+    pub encapsulated_public_key: Option<[u8; SUITE_LENGTHS.nenc]>,
+    pub encrypted_message: EncryptedMessage,
+}
+
+/// Typed [`EncryptedResponse`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ResponseEnvelope {
+    pub encrypted_message: EncryptedMessage,
+}
+
+impl EncryptedMessage {
+    /// Checks the fields of `message`, which is found at `field` in the
+    /// enclosing message.
+    fn from_proto(message: AeadEncryptedMessage, field: &str) -> anyhow::Result<Self> {
+        let nonce = message.nonce.as_slice().try_into().map_err(|_| {
+            CryptoError::MalformedInput.context(format!(
+                "{}.nonce has {} bytes, expected {}",
+                field,
+                message.nonce.len(),
+                SUITE_LENGTHS.nn
+            ))
+        })?;
+        if message.ciphertext.len() < SUITE_LENGTHS.nt {
+            return Err(CryptoError::MalformedInput.context(format!(
+                "{}.ciphertext has {} bytes, expected at least {}",
+                field,
+                message.ciphertext.len(),
+                SUITE_LENGTHS.nt
+            )));
+        }
+        Ok(Self { nonce, ciphertext: message.ciphertext, associated_data: message.associated_data })
+    }
+}
+
+impl From<EncryptedMessage> for AeadEncryptedMessage {
+    fn from(message: EncryptedMessage) -> Self {
+        Self {
+            ciphertext: message.ciphertext,
+            associated_data: message.associated_data,
+            nonce: message.nonce.to_vec(),
+        }
+    }
+}
+
+impl TryFrom<EncryptedRequest> for RequestEnvelope {
+    type Error = anyhow::Error;
+
+    fn try_from(request: EncryptedRequest) -> anyhow::Result<Self> {
+        let encapsulated_public_key = request
+            .serialized_encapsulated_public_key
+            .map(|key| {
+                key.as_slice().try_into().map_err(|_| {
+                    CryptoError::MalformedInput.context(format!(
+                        "EncryptedRequest.serialized_encapsulated_public_key has {} bytes, \
+                         expected {}",
+                        key.len(),
+                        SUITE_LENGTHS.nenc
+                    ))
+                })
+            })
+            .transpose()?;
+        let encrypted_message = request.encrypted_message.ok_or_else(|| {
+            CryptoError::MalformedInput.context("EncryptedRequest.encrypted_message is missing")
+        })?;
+        Ok(Self {
+            encapsulated_public_key,
+            encrypted_message: EncryptedMessage::from_proto(
+                encrypted_message,
+                "EncryptedRequest.encrypted_message",
+            )?,
+        })
+    }
+}
+
+impl From<RequestEnvelope> for EncryptedRequest {
+    fn from(envelope: RequestEnvelope) -> Self {
+        Self {
+            encrypted_message: Some(envelope.encrypted_message.into()),
+            serialized_encapsulated_public_key: envelope
+                .encapsulated_public_key
+                .map(|key| key.to_vec()),
+        }
+    }
+}
+
+impl TryFrom<EncryptedResponse> for ResponseEnvelope {
+    type Error = anyhow::Error;
+
+    fn try_from(response: EncryptedResponse) -> anyhow::Result<Self> {
+        let encrypted_message = response.encrypted_message.ok_or_else(|| {
+            CryptoError::MalformedInput.context("EncryptedResponse.encrypted_message is missing")
+        })?;
+        Ok(Self {
+            encrypted_message: EncryptedMessage::from_proto(
+                encrypted_message,
+                "EncryptedResponse.encrypted_message",
+            )?,
+        })
+    }
+}
+
+impl From<ResponseEnvelope> for EncryptedResponse {
+    fn from(envelope: ResponseEnvelope) -> Self {
+        Self { encrypted_message: Some(envelope.encrypted_message.into()) }
+    }
+}
@@ -0,0 +1,174 @@
+//
+// Copyright 2024 The Project Oak Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Error classes that callers of this crate may need to act on.
+
+use core::fmt;
+
+/// Class of an error returned by this crate.
+///
+/// Functions in this crate return [`anyhow::Error`]. Errors that belong to one
+/// of these classes carry a [`CryptoError`] in their chain, which can be
+/// retrieved with `error.downcast_ref::<CryptoError>()`. If the failure was
+/// caused by an error of an underlying library, e.g. the EC or AEAD
+/// implementation, that error is the `source()` of the [`CryptoError`] in the
+/// chain, and the root cause of the whole error. Inspecting the chain requires
+/// the `std` feature of `anyhow`.
+///
+/// With the `std` feature of this crate, the root cause is the library error
+/// itself and can be downcast to its type, e.g. `aes_gcm::Error`. Without it,
+/// the library errors don't implement `Error`, so only their message is kept.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CryptoError {
+    /// The message couldn't be authenticated, i.e. it was tampered with or was
+    /// encrypted with a different key. Retrying with the same input won't help.
+    AuthenticationFailure,
+    /// The input is malformed, e.g. a field has an incorrect size.
+    MalformedInput,
+    /// The session has encrypted the maximum number of messages, and a new
+    /// session has to be created.
+    NonceExhaustion,
+    /// The message was authenticated, but its associated data doesn't satisfy
+    /// the [`AadPolicy`](crate::encryptor::AadPolicy) of the recipient.
+    AssociatedDataPolicyViolation,
+    /// The private and public halves of a key pair don't belong together, e.g.
+    /// because a key store is corrupted.
+    KeyMismatch,
+    /// The recipient requires a solved setup challenge before setting up a
+    /// session, and the request doesn't carry a valid, unexpired solution. The
+    /// client has to solve a new challenge.
+    SetupChallengeFailure,
+    /// The random number generator failed a health check, so keys generated
+    /// from it could be weak. See [`crate::util::entropy_health_check`].
+    EntropySuspect,
+    /// The first response of a re-established session doesn't prove that the
+    /// recipient key was endorsed by the recipient of the previous session, so
+    /// the key may have been substituted. See [`crate::continuity`].
+    ContinuityBroken,
+    /// The message or the peer uses a protocol version outside of
+    /// [`PROTOCOL_VERSION_RANGE`](crate::PROTOCOL_VERSION_RANGE).
+    UnsupportedProtocolVersion,
+    /// A buffer for the plaintext or ciphertext of a message couldn't be
+    /// allocated. Only buffers of the size of a message are allocated fallibly,
+    /// i.e. by the functions that encrypt or decrypt a message into a new
+    /// buffer, and the session remains usable. Functions that write into a
+    /// caller-provided buffer, like
+    /// [`ClientEncryptor::encrypt_into`](crate::encryptor::ClientEncryptor::encrypt_into),
+    /// don't allocate it at all.
+    OutOfMemory,
+    /// The session token belongs to an epoch that isn't newer than the last
+    /// seen one, e.g. because the token storage was rolled back. See
+    /// [`ClientEncryptor::resume_from_token_with_epoch`](crate::encryptor::ClientEncryptor::resume_from_token_with_epoch).
+    StaleEpoch,
+}
+
+impl CryptoError {
+    pub fn is_authentication_failure(&self) -> bool {
+        *self == CryptoError::AuthenticationFailure
+    }
+
+    pub fn is_malformed_input(&self) -> bool {
+        *self == CryptoError::MalformedInput
+    }
+
+    pub fn is_nonce_exhaustion(&self) -> bool {
+        *self == CryptoError::NonceExhaustion
+    }
+
+    pub fn is_associated_data_policy_violation(&self) -> bool {
+        *self == CryptoError::AssociatedDataPolicyViolation
+    }
+
+    pub fn is_key_mismatch(&self) -> bool {
+        *self == CryptoError::KeyMismatch
+    }
+
+    pub fn is_setup_challenge_failure(&self) -> bool {
+        *self == CryptoError::SetupChallengeFailure
+    }
+
+    pub fn is_entropy_suspect(&self) -> bool {
+        *self == CryptoError::EntropySuspect
+    }
+
+    pub fn is_continuity_broken(&self) -> bool {
+        *self == CryptoError::ContinuityBroken
+    }
+
+    pub fn is_unsupported_protocol_version(&self) -> bool {
+        *self == CryptoError::UnsupportedProtocolVersion
+    }
+
+    pub fn is_out_of_memory(&self) -> bool {
+        *self == CryptoError::OutOfMemory
+    }
+
+    pub fn is_stale_epoch(&self) -> bool {
+        *self == CryptoError::StaleEpoch
+    }
+
+    /// Returns an [`anyhow::Error`] of this class, described by `context`.
+    pub(crate) fn context<C>(self, context: C) -> anyhow::Error
+    where
+        C: fmt::Display + Send + Sync + 'static,
+    {
+        anyhow::Error::msg(self).context(context)
+    }
+
+    /// Same as [`CryptoError::context`], but keeps `source`, the error of an
+    /// underlying library that caused the failure, as the source of this
+    /// class.
+    #[cfg(feature = "std")]
+    pub(crate) fn context_with_source<C, S>(self, context: C, source: S) -> anyhow::Error
+    where
+        C: fmt::Display + Send + Sync + 'static,
+        S: core::error::Error + Send + Sync + 'static,
+    {
+        anyhow::Error::new(source).context(self).context(context)
+    }
+
+    /// Same as [`CryptoError::context`], but keeps the message of `source`, the
+    /// error of an underlying library that caused the failure, as the source of
+    /// this class.
+    #[cfg(not(feature = "std"))]
+    pub(crate) fn context_with_source<C, S>(self, context: C, source: S) -> anyhow::Error
+    where
+        C: fmt::Display + Send + Sync + 'static,
+        S: fmt::Display + fmt::Debug + Send + Sync + 'static,
+    {
+        anyhow::Error::msg(source).context(self).context(context)
+    }
+}
+
+impl fmt::Display for CryptoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CryptoError::AuthenticationFailure => write!(f, "authentication failure"),
+            CryptoError::MalformedInput => write!(f, "malformed input"),
+            CryptoError::NonceExhaustion => write!(f, "nonce exhaustion"),
+            CryptoError::AssociatedDataPolicyViolation => {
+                write!(f, "associated data policy violation")
+            }
+            CryptoError::KeyMismatch => write!(f, "key mismatch"),
+            CryptoError::SetupChallengeFailure => write!(f, "setup challenge failure"),
+            CryptoError::EntropySuspect => write!(f, "entropy suspect"),
+            CryptoError::ContinuityBroken => write!(f, "continuity broken"),
+            CryptoError::UnsupportedProtocolVersion => write!(f, "unsupported protocol version"),
+            CryptoError::OutOfMemory => write!(f, "out of memory"),
+            CryptoError::StaleEpoch => write!(f, "stale epoch"),
+        }
+    }
+}
@@ -0,0 +1,626 @@
+//
+// Copyright 2024 The Project Oak Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Recorded conversations that are replayed on every build, in two kinds:
+//!
+//! - Interoperability fixtures in `testdata/fixtures/`, shared with the other
+//!   Oak client implementations, which carry the recipient private key.
+//! - Compatibility vectors in `testdata/compat_vectors/`, recorded by earlier
+//!   versions of this crate, so that data archived by them stays decryptable.
+//!   They carry a seed for the recipient key.
+//!
+//! Both are JSON files with the transcript of a scripted conversation, in which
+//! each exchange is an initial request and the response to it. Binary fields
+//! are base64-encoded (standard alphabet, with padding). A fixture looks like:
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "suite": { "kem_id": 32, "kdf_id": 1, "aead": "Aes256Gcm" },
+//!   "schema_digest": "<hex SHA-256 of the .proto sources>",
+//!   "recipient_private_key": "<base64>",
+//!   "recipient_public_key": "<base64>",
+//!   "exchanges": [
+//!     {
+//!       "request": "<base64>",
+//!       "request_associated_data": "<base64>",
+//!       "encrypted_request": "<base64>",
+//!       "response": "<base64>",
+//!       "response_associated_data": "<base64>",
+//!       "encrypted_response": "<base64>",
+//!       "channel_binding_token": "<base64, optional>"
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! Envelopes of fixtures are serialized `EncryptedRequest` and
+//! `EncryptedResponse` protos. A compatibility vector is named
+//! `<format>-<suite>.json`, where `<suite>` is the hex
+//! [`WireSuiteId`](crate::hpke::WireSuiteId) of the suite, and has the same
+//! exchanges under a `recipient_seed`. Its envelope format is one of:
+//!
+//! - `proto`: serialized protos, as in fixtures.
+//! - `compact`: compact envelopes, only replayed with the `compact` feature.
+//! - `combined`: requests from
+//!   [`ClientEncryptor::encrypt_combined`](crate::encryptor::ClientEncryptor::encrypt_combined),
+//!   whose associated data is carried separately, and proto responses.
+//!
+//! Replaying fails on any change to key derivation, labels, nonce handling or
+//! envelope layout, or if a proto envelope doesn't re-encode to the same bytes,
+//! e.g. because it has fields this crate doesn't know. Recordings are never
+//! re-recorded to make a replay pass: such a change has to be reverted, or
+//! released as a new version of the protocol. The only exception is the schema
+//! digest of fixtures, which fails their replay after any change to the
+//! .proto sources, so that the other implementations pick up the new schema.
+//! Once the compatibility vectors show that the change keeps archived data
+//! decryptable, the fixtures are re-recorded with:
+//!
+//! ```shell
+//! cargo test --package=oak_crypto -- --ignored regenerate_fixtures
+//! ```
+//!
+//! Compatibility vectors are only added, for new suites and envelope formats.
+//! Vectors that are missing are recorded with:
+//!
+//! ```shell
+//! cargo test --package=oak_crypto --features=compact -- --ignored add_compat_vectors
+//! ```
+
+use std::{
+    format,
+    path::{Path, PathBuf},
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use anyhow::Context;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use prost::Message;
+use rand_core::{OsRng, RngCore};
+use serde_json::{json, Value};
+
+use crate::{
+    encryption_key::{generate_encryption_key_pair, EncryptionKey},
+    encryptor::{ClientEncryptor, ServerEncryptor},
+    hpke::{
+        aead::{decrypt, AeadKey},
+        deserialize_nonce, SuiteId, WireSuiteId, DEFAULT_SUITE, SUITE_LENGTHS,
+    },
+    proto::oak::crypto::v1::{AeadEncryptedMessage, EncryptedRequest, EncryptedResponse},
+};
+
+const FIXTURE_FORMAT_VERSION: u64 = 1;
+const FIXTURES_PATH: &str = "testdata/fixtures";
+const COMPAT_VECTORS_PATH: &str = "testdata/compat_vectors";
+const FIXTURE_EXTENSION: &str = "json";
+const COMPAT_VECTOR_SEED_SIZE_BYTES: usize = 32;
+
+/// Envelope formats of compatibility vectors that this build can record and
+/// replay.
+const COMPAT_VECTOR_FORMATS: &[&str] = &[
+    "proto",
+    #[cfg(feature = "compact")]
+    "compact",
+    "combined",
+];
+
+/// Compatibility vectors that have to be present, one for each envelope format
+/// of each suite that sessions can use.
+const REQUIRED_COMPAT_VECTORS: &[&str] = &["proto-0001", "compact-0001", "combined-0001"];
+
+/// Single exchange of a scripted conversation.
+pub(crate) struct ScriptedExchange<'a> {
+    pub request: &'a [u8],
+    pub request_associated_data: &'a [u8],
+    pub response: &'a [u8],
+    pub response_associated_data: &'a [u8],
+}
+
+/// Conversations that all implementations have agreed to check in, by fixture
+/// name.
+const AGREED_FIXTURES: &[(&str, &[ScriptedExchange])] = &[
+    (
+        "conversation",
+        &[
+            ScriptedExchange {
+                request: b"Hello",
+                request_associated_data: b"Request associated data",
+                response: b"Hello back",
+                response_associated_data: b"Response associated data",
+            },
+            ScriptedExchange {
+                request: b"Second request",
+                request_associated_data: b"",
+                response: b"Second response",
+                response_associated_data: b"",
+            },
+        ],
+    ),
+    (
+        "empty_messages",
+        &[ScriptedExchange {
+            request: b"",
+            request_associated_data: b"",
+            response: b"",
+            response_associated_data: b"",
+        }],
+    ),
+];
+
+/// Conversation of every compatibility vector.
+const COMPAT_VECTOR_SCRIPT: &[ScriptedExchange] = &[
+    ScriptedExchange {
+        request: b"Hello",
+        request_associated_data: b"Request associated data",
+        response: b"Hello back",
+        response_associated_data: b"Response associated data",
+    },
+    ScriptedExchange {
+        request: b"",
+        request_associated_data: b"",
+        response: b"",
+        response_associated_data: b"",
+    },
+];
+
+/// Plays the client side of `script` against a freshly generated recipient key
+/// pair, and writes the resulting fixture to `path`.
+pub(crate) fn record_as_sender(script: &[ScriptedExchange], path: &Path) -> anyhow::Result<()> {
+    let (encryption_key, public_key) = generate_encryption_key_pair();
+    let private_key = encryption_key.serialize();
+    let encryption_key = EncryptionKey::deserialize(&mut private_key.clone())?;
+
+    let fixture = json!({
+        "version": FIXTURE_FORMAT_VERSION,
+        "suite": suite_json(),
+        "schema_digest": hex::encode(crate::schema_digest()),
+        "recipient_private_key": BASE64.encode(&private_key),
+        "recipient_public_key": BASE64.encode(&public_key),
+        "exchanges": record_script("proto", &encryption_key, &public_key, script)?,
+    });
+    write_json(&fixture, path)
+}
+
+/// Plays the server side of the fixture at `path`: decrypts every request with
+/// the recipient key, and checks that the plaintexts and associated data match
+/// the transcript and that every response decrypts with the session keys.
+pub(crate) fn replay_as_recipient(path: &Path) -> anyhow::Result<()> {
+    let fixture = read_json(path)?;
+    let version = fixture["version"].as_u64().context("fixture has no version")?;
+    anyhow::ensure!(
+        version == FIXTURE_FORMAT_VERSION,
+        "unsupported fixture version {}, expected {}",
+        version,
+        FIXTURE_FORMAT_VERSION
+    );
+    anyhow::ensure!(
+        fixture["suite"] == suite_json(),
+        "fixture suite {} isn't supported, expected {}",
+        fixture["suite"],
+        suite_json()
+    );
+    let schema_digest = string_field(&fixture, "schema_digest")?;
+    anyhow::ensure!(
+        schema_digest == hex::encode(crate::schema_digest()),
+        "fixture was recorded against a different schema, regenerate the fixtures"
+    );
+
+    let public_key = base64_field(&fixture, "recipient_public_key")?;
+    let encryption_key = EncryptionKey::from_private_and_public(
+        &mut base64_field(&fixture, "recipient_private_key")?,
+        &public_key,
+    )?;
+    replay_exchanges("proto", &encryption_key, &fixture)
+}
+
+/// Records the compatibility vector of `format` for the default suite at
+/// `path`, with a fresh seed for the recipient key.
+fn record_compat_vector(format: &str, path: &Path) -> anyhow::Result<()> {
+    let mut seed = [0u8; COMPAT_VECTOR_SEED_SIZE_BYTES];
+    OsRng.fill_bytes(&mut seed);
+    let (encryption_key, public_key) = EncryptionKey::from_seed(&seed)?;
+    let vector = json!({
+        "format": format,
+        "suite": u16::from(WireSuiteId::try_from(DEFAULT_SUITE)?),
+        "recorded_by": format!("oak_crypto {}", env!("CARGO_PKG_VERSION")),
+        "recipient_seed": BASE64.encode(seed),
+        "recipient_public_key": BASE64.encode(&public_key),
+        "exchanges": record_script(format, &encryption_key, &public_key, COMPAT_VECTOR_SCRIPT)?,
+    });
+    write_json(&vector, path)
+}
+
+/// Replays the compatibility vector at `path` with the recipient key derived
+/// from its seed.
+fn replay_compat_vector(path: &Path) -> anyhow::Result<()> {
+    let vector = read_json(path)?;
+    let format = string_field(&vector, "format")?;
+    let wire_id = vector["suite"].as_u64().context("missing suite")?;
+    let wire_id =
+        u16::try_from(wire_id).map_err(|_| anyhow::anyhow!("suite {} is out of range", wire_id))?;
+    let suite = SuiteId::from(WireSuiteId::try_from(wire_id)?);
+    // Sessions can only be created with the default suite.
+    anyhow::ensure!(suite == DEFAULT_SUITE, "no session API for suite {:?}", suite);
+
+    let (encryption_key, public_key) =
+        EncryptionKey::from_seed(&base64_field(&vector, "recipient_seed")?)?;
+    anyhow::ensure!(
+        public_key == base64_field(&vector, "recipient_public_key")?,
+        "recipient public key derived from the seed doesn't match"
+    );
+    replay_exchanges(format, &encryption_key, &vector)
+}
+
+/// Plays both sides of every exchange of `script` in a new session, with
+/// envelopes in `format`, and returns the transcript.
+fn record_script(
+    format: &str,
+    encryption_key: &EncryptionKey,
+    public_key: &[u8],
+    script: &[ScriptedExchange],
+) -> anyhow::Result<Vec<Value>> {
+    script
+        .iter()
+        .map(|exchange| {
+            let mut client_encryptor = ClientEncryptor::create(public_key)?;
+            let encoded_request = encode_request(
+                format,
+                &mut client_encryptor,
+                exchange.request,
+                exchange.request_associated_data,
+            )?;
+            let encrypted_request =
+                decode_request(format, &encoded_request, exchange.request_associated_data)?;
+            let (server_encryptor, request, _) =
+                ServerEncryptor::decrypt(&encrypted_request, encryption_key)?;
+            anyhow::ensure!(request == exchange.request, "recorded request doesn't round trip");
+            let channel_binding_token = encryption_key
+                .derive_shared_keys(
+                    encrypted_request
+                        .serialized_encapsulated_public_key
+                        .as_ref()
+                        .context("request doesn't contain encapsulated public key")?,
+                )?
+                .channel_binding_token();
+
+            let encrypted_response =
+                server_encryptor.encrypt(exchange.response, exchange.response_associated_data)?;
+            let (response, _) = client_encryptor.decrypt(&encrypted_response)?;
+            anyhow::ensure!(response == exchange.response, "recorded response doesn't round trip");
+            Ok(json!({
+                "request": BASE64.encode(exchange.request),
+                "request_associated_data": BASE64.encode(exchange.request_associated_data),
+                "encrypted_request": BASE64.encode(&encoded_request),
+                "response": BASE64.encode(exchange.response),
+                "response_associated_data": BASE64.encode(exchange.response_associated_data),
+                "encrypted_response": BASE64.encode(encode_response(format, &encrypted_response)?),
+                "channel_binding_token": BASE64.encode(channel_binding_token),
+            }))
+        })
+        .collect()
+}
+
+fn replay_exchanges(
+    format: &str,
+    encryption_key: &EncryptionKey,
+    recording: &Value,
+) -> anyhow::Result<()> {
+    let exchanges = recording["exchanges"].as_array().context("recording has no exchanges")?;
+    anyhow::ensure!(!exchanges.is_empty(), "recording has no exchanges");
+    for (index, exchange) in exchanges.iter().enumerate() {
+        replay_exchange(format, encryption_key, exchange)
+            .with_context(|| format!("exchange {} failed", index))?;
+    }
+    Ok(())
+}
+
+fn replay_exchange(
+    format: &str,
+    encryption_key: &EncryptionKey,
+    exchange: &Value,
+) -> anyhow::Result<()> {
+    let request_associated_data = base64_field(exchange, "request_associated_data")?;
+    let encrypted_request = decode_request(
+        format,
+        &base64_field(exchange, "encrypted_request")?,
+        &request_associated_data,
+    )?;
+    let (_, request, decrypted_request_associated_data) =
+        ServerEncryptor::decrypt(&encrypted_request, encryption_key)?;
+    anyhow::ensure!(request == base64_field(exchange, "request")?, "request doesn't match");
+    anyhow::ensure!(
+        decrypted_request_associated_data == request_associated_data,
+        "request associated data doesn't match"
+    );
+
+    let shared_keys = encryption_key.derive_shared_keys(
+        encrypted_request
+            .serialized_encapsulated_public_key
+            .as_ref()
+            .context("request doesn't contain encapsulated public key")?,
+    )?;
+    if exchange.get("channel_binding_token").is_some() {
+        anyhow::ensure!(
+            shared_keys.channel_binding_token().as_slice()
+                == base64_field(exchange, "channel_binding_token")?,
+            "channel binding token doesn't match"
+        );
+    }
+
+    // A recipient can't open its own responses, so they are checked with the
+    // response key of the session.
+    let encrypted_message =
+        decode_response(format, &base64_field(exchange, "encrypted_response")?)?
+            .encrypted_message
+            .context("response doesn't contain encrypted message")?;
+    let response_key = AeadKey::new(
+        shared_keys.response_key().try_into().expect("response key has the AEAD key size"),
+    );
+    let response = decrypt(
+        &response_key,
+        &deserialize_nonce(&encrypted_message.nonce)?,
+        &encrypted_message.ciphertext,
+        &encrypted_message.associated_data,
+    )?;
+    anyhow::ensure!(response == base64_field(exchange, "response")?, "response doesn't match");
+    anyhow::ensure!(
+        encrypted_message.associated_data == base64_field(exchange, "response_associated_data")?,
+        "response associated data doesn't match"
+    );
+    Ok(())
+}
+
+/// Encrypts the initial request of the session of `client_encryptor` in
+/// `format`.
+fn encode_request(
+    format: &str,
+    client_encryptor: &mut ClientEncryptor,
+    request: &[u8],
+    associated_data: &[u8],
+) -> anyhow::Result<Vec<u8>> {
+    match format {
+        "proto" => Ok(client_encryptor.encrypt(request, associated_data)?.encode_to_vec()),
+        #[cfg(feature = "compact")]
+        "compact" => client_encryptor.encrypt_to_compact(request, associated_data),
+        "combined" => client_encryptor.encrypt_combined(request, associated_data),
+        _ => anyhow::bail!("unknown envelope format {}", format),
+    }
+}
+
+/// Decodes a request in `format`. Combined requests don't carry their
+/// associated data, so it is passed separately.
+fn decode_request(
+    format: &str,
+    encoded_request: &[u8],
+    associated_data: &[u8],
+) -> anyhow::Result<EncryptedRequest> {
+    match format {
+        "proto" => {
+            let encrypted_request = EncryptedRequest::decode(encoded_request)
+                .map_err(|error| anyhow::anyhow!("couldn't decode request proto: {}", error))?;
+            anyhow::ensure!(
+                encrypted_request.encode_to_vec() == encoded_request,
+                "request doesn't re-encode to the same bytes"
+            );
+            Ok(encrypted_request)
+        }
+        #[cfg(feature = "compact")]
+        "compact" => crate::compact::decode_request(encoded_request),
+        "combined" => {
+            anyhow::ensure!(
+                encoded_request.len() >= SUITE_LENGTHS.nenc + SUITE_LENGTHS.nn,
+                "combined request is too short"
+            );
+            let (serialized_encapsulated_public_key, encrypted_message) =
+                encoded_request.split_at(SUITE_LENGTHS.nenc);
+            let (nonce, ciphertext) = encrypted_message.split_at(SUITE_LENGTHS.nn);
+            Ok(EncryptedRequest {
+                encrypted_message: Some(AeadEncryptedMessage {
+                    ciphertext: ciphertext.to_vec(),
+                    associated_data: associated_data.to_vec(),
+                    nonce: nonce.to_vec(),
+                }),
+                serialized_encapsulated_public_key: Some(
+                    serialized_encapsulated_public_key.to_vec(),
+                ),
+            })
+        }
+        _ => anyhow::bail!("unknown envelope format {}", format),
+    }
+}
+
+/// Encodes a response in `format`. Combined sessions respond with protos.
+fn encode_response(
+    format: &str,
+    encrypted_response: &EncryptedResponse,
+) -> anyhow::Result<Vec<u8>> {
+    match format {
+        #[cfg(feature = "compact")]
+        "compact" => crate::compact::encode_response(encrypted_response),
+        _ => Ok(encrypted_response.encode_to_vec()),
+    }
+}
+
+fn decode_response(format: &str, encoded_response: &[u8]) -> anyhow::Result<EncryptedResponse> {
+    match format {
+        #[cfg(feature = "compact")]
+        "compact" => crate::compact::decode_response(encoded_response),
+        _ => {
+            let encrypted_response = EncryptedResponse::decode(encoded_response)
+                .map_err(|error| anyhow::anyhow!("couldn't decode response proto: {}", error))?;
+            anyhow::ensure!(
+                encrypted_response.encode_to_vec() == encoded_response,
+                "response doesn't re-encode to the same bytes"
+            );
+            Ok(encrypted_response)
+        }
+    }
+}
+
+fn suite_json() -> Value {
+    json!({
+        "kem_id": DEFAULT_SUITE.kem_id,
+        "kdf_id": DEFAULT_SUITE.kdf_id,
+        "aead": format!("{:?}", DEFAULT_SUITE.aead),
+    })
+}
+
+fn read_json(path: &Path) -> anyhow::Result<Value> {
+    let serialized = std::fs::read_to_string(path)
+        .map_err(|error| anyhow::anyhow!("couldn't read {}: {}", path.display(), error))?;
+    serde_json::from_str(&serialized)
+        .map_err(|error| anyhow::anyhow!("couldn't parse {}: {}", path.display(), error))
+}
+
+fn write_json(value: &Value, path: &Path) -> anyhow::Result<()> {
+    let serialized = serde_json::to_string_pretty(value)
+        .map_err(|error| anyhow::anyhow!("couldn't serialize {}: {}", path.display(), error))?;
+    std::fs::write(path, format!("{}\n", serialized))
+        .map_err(|error| anyhow::anyhow!("couldn't write {}: {}", path.display(), error))
+}
+
+fn string_field<'a>(value: &'a Value, name: &str) -> anyhow::Result<&'a str> {
+    value[name].as_str().with_context(|| format!("missing string field {}", name))
+}
+
+fn base64_field(value: &Value, name: &str) -> anyhow::Result<Vec<u8>> {
+    BASE64
+        .decode(string_field(value, name)?)
+        .map_err(|error| anyhow::anyhow!("couldn't decode base64 field {}: {}", name, error))
+}
+
+fn fixtures_path() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join(FIXTURES_PATH)
+}
+
+fn fixture_path(name: &str) -> PathBuf {
+    fixtures_path().join(name).with_extension(FIXTURE_EXTENSION)
+}
+
+fn compat_vectors_path() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join(COMPAT_VECTORS_PATH)
+}
+
+fn compat_vector_path(name: &str) -> PathBuf {
+    compat_vectors_path().join(name).with_extension(FIXTURE_EXTENSION)
+}
+
+/// Returns the sorted paths of the files in `directory`.
+fn recording_paths(directory: &Path) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(directory)
+        .expect("couldn't read recordings directory")
+        .map(|entry| entry.expect("couldn't read directory entry").path())
+        .collect();
+    paths.sort();
+    paths
+}
+
+#[test]
+fn test_replay_fixtures() {
+    let paths = recording_paths(&fixtures_path());
+    for (name, _) in AGREED_FIXTURES {
+        assert!(paths.contains(&fixture_path(name)), "agreed fixture {} is missing", name);
+    }
+
+    let failures: Vec<String> = paths
+        .iter()
+        .filter_map(|path| {
+            replay_as_recipient(path).err().map(|error| format!("{}: {:#}", path.display(), error))
+        })
+        .collect();
+    assert!(failures.is_empty(), "fixtures failed:\n{}", failures.join("\n"));
+}
+
+#[test]
+fn test_record_and_replay_fixture() {
+    let path = std::env::temp_dir().join(format!("oak_crypto_fixture_{}.json", std::process::id()));
+    for (name, script) in AGREED_FIXTURES {
+        record_as_sender(script, &path).expect("couldn't record fixture");
+        let result = replay_as_recipient(&path);
+        assert!(result.is_ok(), "{}: {:#}", name, result.unwrap_err());
+    }
+
+    // Tampering with a recorded envelope is caught.
+    let mut fixture = read_json(&path).expect("couldn't read fixture");
+    let mut response =
+        BASE64.decode(fixture["exchanges"][0]["response"].as_str().unwrap()).unwrap();
+    response.push(0);
+    fixture["exchanges"][0]["response"] = Value::String(BASE64.encode(response));
+    std::fs::write(&path, fixture.to_string()).expect("couldn't write fixture");
+    assert!(replay_as_recipient(&path).is_err());
+    std::fs::remove_file(&path).expect("couldn't remove fixture");
+}
+
+#[test]
+fn test_compat_vectors() {
+    let paths = recording_paths(&compat_vectors_path());
+    for name in REQUIRED_COMPAT_VECTORS {
+        assert!(
+            paths.contains(&compat_vector_path(name)),
+            "compatibility vector {} is missing",
+            name
+        );
+    }
+
+    let failures: Vec<String> = paths
+        .iter()
+        .filter(|path| {
+            let name = path.file_name().unwrap().to_string_lossy().to_string();
+            COMPAT_VECTOR_FORMATS.iter().any(|format| name.starts_with(&format!("{}-", format)))
+        })
+        .filter_map(|path| {
+            replay_compat_vector(path).err().map(|error| format!("{}: {:#}", path.display(), error))
+        })
+        .collect();
+    assert!(failures.is_empty(), "compatibility vectors failed:\n{}", failures.join("\n"));
+}
+
+#[test]
+fn test_record_and_replay_compat_vector() {
+    for format in COMPAT_VECTOR_FORMATS {
+        let path = std::env::temp_dir().join(format!(
+            "oak_crypto_compat_vector_{}_{}.json",
+            format,
+            std::process::id()
+        ));
+        record_compat_vector(format, &path).expect("couldn't record compatibility vector");
+        let result = replay_compat_vector(&path);
+        assert!(result.is_ok(), "{}: {:#}", format, result.unwrap_err());
+        std::fs::remove_file(&path).expect("couldn't remove compatibility vector");
+    }
+}
+
+#[test]
+#[ignore = "only run to regenerate the checked-in fixtures"]
+fn regenerate_fixtures() {
+    for (name, script) in AGREED_FIXTURES {
+        record_as_sender(script, &fixture_path(name)).expect("couldn't record fixture");
+    }
+}
+
+#[test]
+#[ignore = "only run to add compatibility vectors for new suites and formats"]
+fn add_compat_vectors() {
+    let wire_id = u16::from(WireSuiteId::try_from(DEFAULT_SUITE).expect("no default wire id"));
+    for format in COMPAT_VECTOR_FORMATS {
+        // Existing vectors are never overwritten.
+        let path = compat_vector_path(&format!("{}-{:04x}", format, wire_id));
+        if !path.exists() {
+            record_compat_vector(format, &path).expect("couldn't record compatibility vector");
+        }
+    }
+}
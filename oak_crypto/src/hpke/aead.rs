@@ -17,25 +17,89 @@
 //! Implementation of Authenticated Encryption with Associated Data (AEAD).
 //! <https://datatracker.ietf.org/doc/html/rfc5116>
 
-use alloc::vec::Vec;
+use alloc::{format, vec::Vec};
+use core::fmt;
 
 use aes_gcm::{
-    aead::{Aead, Payload},
-    Aes256Gcm, Key, KeyInit,
+    aead::{AeadInPlace, KeyInit},
+    Aes256Gcm, Key,
 };
 use anyhow::anyhow;
+use zeroize::Zeroize;
+
+use crate::{error::CryptoError, hpke::SUITE_LENGTHS};
 
 /// Represents `N_k` from RFC9180.
 /// <https://www.rfc-editor.org/rfc/rfc9180.html#name-cryptographic-dependencies>
-pub(crate) const AEAD_ALGORITHM_KEY_SIZE_BYTES: usize = 32;
+pub(crate) const AEAD_ALGORITHM_KEY_SIZE_BYTES: usize = SUITE_LENGTHS.nk;
 /// Represents `N_n` from RFC9180.
 /// <https://www.rfc-editor.org/rfc/rfc9180.html#name-cryptographic-dependencies>
-pub(crate) const AEAD_NONCE_SIZE_BYTES: usize = 12;
-/// Convenience type for representing an AEAD key.
-pub(crate) type AeadKey = [u8; AEAD_ALGORITHM_KEY_SIZE_BYTES];
+pub(crate) const AEAD_NONCE_SIZE_BYTES: usize = SUITE_LENGTHS.nn;
+/// Represents `N_t` from RFC9180.
+/// <https://www.rfc-editor.org/rfc/rfc9180.html#name-cryptographic-dependencies>
+pub(crate) const AEAD_TAG_SIZE_BYTES: usize = SUITE_LENGTHS.nt;
 /// Convenience type for representing an AEAD nonce.
 pub(crate) type AeadNonce = [u8; AEAD_NONCE_SIZE_BYTES];
 
+/// Identifies the AEAD algorithm of a [`SuiteId`](crate::hpke::SuiteId).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum AeadId {
+    Aes256Gcm,
+}
+
+/// AEAD key that is zeroized when dropped and doesn't reveal its value in
+/// `Debug` output.
+#[repr(transparent)]
+pub(crate) struct AeadKey([u8; AEAD_ALGORITHM_KEY_SIZE_BYTES]);
+
+impl AeadKey {
+    #[cfg(feature = "response-direction")]
+    pub(crate) fn new(key: [u8; AEAD_ALGORITHM_KEY_SIZE_BYTES]) -> Self {
+        Self(key)
+    }
+
+    pub(crate) fn as_bytes(&self) -> &[u8; AEAD_ALGORITHM_KEY_SIZE_BYTES] {
+        &self.0
+    }
+
+    pub(crate) fn as_mut_bytes(&mut self) -> &mut [u8; AEAD_ALGORITHM_KEY_SIZE_BYTES] {
+        &mut self.0
+    }
+
+    /// Returns whether all bytes of the key are zero, without branching on the
+    /// key value.
+    pub(crate) fn is_zero(&self) -> bool {
+        self.0.iter().fold(0u8, |accumulator, byte| accumulator | byte) == 0
+    }
+}
+
+impl Default for AeadKey {
+    fn default() -> Self {
+        Self([0u8; AEAD_ALGORITHM_KEY_SIZE_BYTES])
+    }
+}
+
+impl Zeroize for AeadKey {
+    fn zeroize(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+// Destructors don't run if a panic aborts the process, so with `panic =
+// "abort"` keys that are alive at the time of a panic aren't zeroized.
+impl Drop for AeadKey {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+impl fmt::Debug for AeadKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("AeadKey(<redacted>)")
+    }
+}
+
 /// Encrypts `plaintext` with associated data using AES-GCM encryption scheme.
 /// Note: the corresponding associated data is NOT encrypted.
 pub(crate) fn encrypt(
@@ -44,12 +108,46 @@ pub(crate) fn encrypt(
     plaintext: &[u8],
     associated_data: &[u8],
 ) -> anyhow::Result<Vec<u8>> {
-    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(secret_key));
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(secret_key.as_bytes()));
+    let mut buffer = allocate_buffer(plaintext.len() + AEAD_TAG_SIZE_BYTES)?;
+    buffer.extend_from_slice(plaintext);
 
-    // Encrypt message.
+    // Encrypt message in place and append the authentication tag.
     cipher
-        .encrypt(nonce.into(), Payload { msg: plaintext, aad: associated_data })
-        .map_err(|error| anyhow!("couldn't encrypt data: {}", error))
+        .encrypt_in_place(nonce.into(), associated_data, &mut buffer)
+        .map_err(|error| anyhow!("couldn't encrypt data: {}", error))?;
+    Ok(buffer)
+}
+
+/// Encrypts `plaintext` with associated data using AES-GCM encryption scheme,
+/// and writes the ciphertext into the beginning of `output`. Returns the number
+/// of bytes written. `output` is left untouched if it is too small.
+#[cfg(feature = "response-direction")]
+pub(crate) fn encrypt_into(
+    secret_key: &AeadKey,
+    nonce: &AeadNonce,
+    plaintext: &[u8],
+    associated_data: &[u8],
+    output: &mut [u8],
+) -> anyhow::Result<usize> {
+    let ciphertext_size = plaintext.len() + AEAD_TAG_SIZE_BYTES;
+    if output.len() < ciphertext_size {
+        return Err(CryptoError::MalformedInput.context(format!(
+            "output buffer is too small, expected at least {} bytes, got {}",
+            ciphertext_size,
+            output.len()
+        )));
+    }
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(secret_key.as_bytes()));
+
+    // Encrypt message in place and append the authentication tag.
+    let (buffer, tag_buffer) = output[..ciphertext_size].split_at_mut(plaintext.len());
+    buffer.copy_from_slice(plaintext);
+    let tag = cipher
+        .encrypt_in_place_detached(nonce.into(), associated_data, buffer)
+        .map_err(|error| anyhow!("couldn't encrypt data: {}", error))?;
+    tag_buffer.copy_from_slice(&tag);
+    Ok(ciphertext_size)
 }
 
 /// Decrypts `ciphertext` and authenticates `associated_data` using AES-GCM
@@ -60,10 +158,68 @@ pub(crate) fn decrypt(
     ciphertext: &[u8],
     associated_data: &[u8],
 ) -> anyhow::Result<Vec<u8>> {
-    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(secret_key));
+    check_ciphertext_size(ciphertext)?;
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(secret_key.as_bytes()));
+    let mut buffer = allocate_buffer(ciphertext.len())?;
+    buffer.extend_from_slice(ciphertext);
 
     // Decrypt message.
-    cipher
-        .decrypt(nonce.into(), Payload { msg: ciphertext, aad: associated_data })
-        .map_err(|error| anyhow!("couldn't decrypt data: {}", error))
+    cipher.decrypt_in_place(nonce.into(), associated_data, &mut buffer).map_err(|error| {
+        buffer.zeroize();
+        CryptoError::AuthenticationFailure.context_with_source("couldn't decrypt data", error)
+    })?;
+    Ok(buffer)
+}
+
+/// Decrypts `ciphertext` into `buffer` and authenticates `associated_data`
+/// using AES-GCM encryption scheme. The previous contents of `buffer` are
+/// replaced, and `buffer` is zeroized if decryption fails.
+#[cfg(feature = "response-direction")]
+pub(crate) fn decrypt_into(
+    secret_key: &AeadKey,
+    nonce: &AeadNonce,
+    ciphertext: &[u8],
+    associated_data: &[u8],
+    buffer: &mut Vec<u8>,
+) -> anyhow::Result<()> {
+    check_ciphertext_size(ciphertext)?;
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(secret_key.as_bytes()));
+
+    // Make sure that plaintext is not left behind in memory if the buffer has to
+    // be reallocated.
+    buffer.zeroize();
+    buffer.try_reserve(ciphertext.len()).map_err(|_| out_of_memory(ciphertext.len()))?;
+    buffer.extend_from_slice(ciphertext);
+
+    // Decrypt message.
+    cipher.decrypt_in_place(nonce.into(), associated_data, buffer).map_err(|error| {
+        buffer.zeroize();
+        CryptoError::AuthenticationFailure.context_with_source("couldn't decrypt data", error)
+    })
+}
+
+/// Allocates an empty buffer for `capacity` bytes, without aborting if the
+/// allocation fails, since messages can be large.
+fn allocate_buffer(capacity: usize) -> anyhow::Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    buffer.try_reserve_exact(capacity).map_err(|_| out_of_memory(capacity))?;
+    Ok(buffer)
+}
+
+fn out_of_memory(requested: usize) -> anyhow::Error {
+    CryptoError::OutOfMemory.context(format!("couldn't allocate a buffer of {} bytes", requested))
+}
+
+/// Checks that `ciphertext` is long enough to contain an authentication tag of
+/// the configured suite, so that messages sealed with a suite that uses a
+/// different tag size are rejected with a clear error.
+fn check_ciphertext_size(ciphertext: &[u8]) -> anyhow::Result<()> {
+    if ciphertext.len() < AEAD_TAG_SIZE_BYTES {
+        return Err(CryptoError::MalformedInput.context(format!(
+            "invalid ciphertext length, expected at least {} bytes for the authentication tag, got {}",
+            AEAD_TAG_SIZE_BYTES,
+            ciphertext.len()
+        )));
+    }
+    Ok(())
 }
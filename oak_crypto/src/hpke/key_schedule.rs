@@ -0,0 +1,100 @@
+//
+// Copyright 2024 The Project Oak Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! In-crate implementation of the RFC9180 key schedule in base mode, for
+//! sessions where the KEM encapsulation is performed outside of this crate.
+//! <https://www.rfc-editor.org/rfc/rfc9180.html#name-creating-the-encryption-con>
+
+use hkdf::{Hkdf, HkdfExtract};
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+use crate::hpke::{suite_id_bytes, SUITE_LENGTHS};
+
+/// Version label prepended to all labeled KDF inputs.
+const HPKE_VERSION_LABEL: &[u8] = b"HPKE-v1";
+/// `mode_base` from RFC9180.
+const MODE_BASE: u8 = 0x00;
+
+/// Exporter secret of an HPKE context, from which session keys are exported.
+pub(crate) struct ExporterSecret(Zeroizing<[u8; SUITE_LENGTHS.nh]>);
+
+impl ExporterSecret {
+    /// Runs `KeySchedule(mode_base, shared_secret, info, "", "")` and returns
+    /// the resulting exporter secret.
+    pub(crate) fn from_shared_secret(shared_secret: &[u8], info: &[u8]) -> Self {
+        let suite_id = suite_id_bytes();
+        let psk_id_hash = labeled_extract(&suite_id, b"", b"psk_id_hash", b"");
+        let info_hash = labeled_extract(&suite_id, b"", b"info_hash", info);
+        let secret = labeled_extract(&suite_id, shared_secret, b"secret", b"");
+
+        let mut exporter_secret = Zeroizing::new([0u8; SUITE_LENGTHS.nh]);
+        labeled_expand(
+            &suite_id,
+            secret.as_ref(),
+            b"exp",
+            &[&[MODE_BASE], psk_id_hash.as_ref(), info_hash.as_ref()],
+            exporter_secret.as_mut(),
+        )
+        .expect("exporter secret size is a valid HKDF-SHA256 output length");
+        Self(exporter_secret)
+    }
+
+    /// Implements `Context.Export(exporter_context, L)`, where `L` is the size
+    /// of `output`.
+    pub(crate) fn export(
+        &self,
+        exporter_context: &[u8],
+        output: &mut [u8],
+    ) -> Result<(), hpke::HpkeError> {
+        labeled_expand(&suite_id_bytes(), self.0.as_ref(), b"sec", &[exporter_context], output)
+    }
+}
+
+/// `LabeledExtract(salt, label, ikm)` from RFC9180.
+fn labeled_extract(
+    suite_id: &[u8],
+    salt: &[u8],
+    label: &[u8],
+    ikm: &[u8],
+) -> Zeroizing<[u8; SUITE_LENGTHS.nh]> {
+    let mut extract = HkdfExtract::<Sha256>::new(Some(salt));
+    extract.input_ikm(HPKE_VERSION_LABEL);
+    extract.input_ikm(suite_id);
+    extract.input_ikm(label);
+    extract.input_ikm(ikm);
+    let (prk, _) = extract.finalize();
+    Zeroizing::new(prk.into())
+}
+
+/// `LabeledExpand(prk, label, info, L)` from RFC9180, where `L` is the size of
+/// `output` and `info` is the concatenation of `info_parts`.
+fn labeled_expand(
+    suite_id: &[u8],
+    prk: &[u8],
+    label: &[u8],
+    info_parts: &[&[u8]],
+    output: &mut [u8],
+) -> Result<(), hpke::HpkeError> {
+    let output_size: u16 =
+        output.len().try_into().map_err(|_| hpke::HpkeError::KdfOutputTooLong)?;
+    let hkdf = Hkdf::<Sha256>::from_prk(prk).map_err(|_| hpke::HpkeError::ValidationError)?;
+    let output_size_bytes = output_size.to_be_bytes();
+    let mut info: alloc::vec::Vec<&[u8]> =
+        alloc::vec![&output_size_bytes, HPKE_VERSION_LABEL, suite_id, label];
+    info.extend_from_slice(info_parts);
+    hkdf.expand_multi_info(&info, output).map_err(|_| hpke::HpkeError::KdfOutputTooLong)
+}
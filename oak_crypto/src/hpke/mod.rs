@@ -15,19 +15,44 @@
 //
 
 pub(crate) mod aead;
+#[cfg(feature = "response-direction")]
+mod key_schedule;
 
-use alloc::vec::Vec;
+use alloc::{format, vec::Vec};
 
 use anyhow::{anyhow, Context};
+#[cfg(feature = "response-direction")]
+use hpke::PskBundle;
 use hpke::{
-    aead::AesGcm256, kdf::HkdfSha256, kem::X25519HkdfSha256, Kem as KemTrait, OpModeR, OpModeS,
+    aead::{Aead as AeadTrait, AeadCtxR, AeadCtxS, AesGcm256},
+    kdf::{HkdfSha256, Kdf as KdfTrait},
+    kem::X25519HkdfSha256,
+    Kem as KemTrait, OpModeR, OpModeS,
 };
 pub use hpke::{Deserializable, Serializable};
+#[cfg(feature = "response-direction")]
+use prost::Message;
 use rand_core::{OsRng, RngCore};
+#[cfg(feature = "response-direction")]
+use zeroize::{Zeroize, Zeroizing};
 
+pub use crate::hpke::aead::AeadId;
+#[cfg(feature = "response-direction")]
 use crate::{
-    hpke::aead::{AeadKey, AeadNonce, AEAD_ALGORITHM_KEY_SIZE_BYTES, AEAD_NONCE_SIZE_BYTES},
+    continuity::{derive_continuity_secret, ContinuitySecret},
+    hpke::{aead::AEAD_ALGORITHM_KEY_SIZE_BYTES, key_schedule::ExporterSecret},
+    labels::{
+        CHANNEL_BINDING_TOKEN_LABEL, OAK_HPKE_INFO, OAK_HPKE_RECIPIENT_BOUND_INFO,
+        REQUEST_BASE_NONCE_LABEL, RESPONSE_BASE_NONCE_LABEL, RESPONSE_KEY_LABEL,
+    },
     proto::oak::crypto::v1::SessionKeys,
+    transfer::{derive_transfer_id, TRANSFER_ID_SIZE_BYTES},
+};
+use crate::{
+    error::CryptoError,
+    hpke::aead::{AeadKey, AeadNonce, AEAD_NONCE_SIZE_BYTES},
+    labels::REQUEST_KEY_LABEL,
+    util::check_entropy_if_required,
 };
 
 type Aead = AesGcm256;
@@ -37,30 +62,207 @@ pub type PrivateKey = <Kem as KemTrait>::PrivateKey;
 pub type PublicKey = <Kem as KemTrait>::PublicKey;
 pub(crate) type EncappedKey = <Kem as KemTrait>::EncappedKey;
 
-/// Info string used by Hybrid Public Key Encryption;
-pub(crate) const OAK_HPKE_INFO: &[u8] = b"Oak Hybrid Public Key Encryption v1";
+/// Sizes in bytes of the values used by the HPKE cipher suite, named as in
+/// RFC9180. <https://www.rfc-editor.org/rfc/rfc9180.html#name-algorithm-identifiers>
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct SuiteLengths {
+    /// `Nk`: size of an AEAD key.
+    pub nk: usize,
+    /// `Nn`: size of an AEAD nonce.
+    pub nn: usize,
+    /// `Nt`: size of an AEAD authentication tag.
+    pub nt: usize,
+    /// `Nh`: output size of the KDF `Extract` function.
+    pub nh: usize,
+    /// `Npk`: size of a serialized KEM public key.
+    pub npk: usize,
+    /// `Nenc`: size of a serialized encapsulated public key.
+    pub nenc: usize,
+    /// `Nsecret`: size of a KEM shared secret.
+    pub nsecret: usize,
+}
+
+/// Sizes used by DHKEM(X25519, HKDF-SHA256), HKDF-SHA256 and AES-256-GCM.
+pub const SUITE_LENGTHS: SuiteLengths =
+    SuiteLengths { nk: 32, nn: 12, nt: 16, nh: 32, npk: 32, nenc: 32, nsecret: 32 };
+
+/// Cipher suite of a session, identified by the HPKE identifiers of its KEM and
+/// KDF, and by its AEAD algorithm.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct SuiteId {
+    pub kem_id: u16,
+    pub kdf_id: u16,
+    pub aead: AeadId,
+}
+
+/// Suite used by all sessions.
+pub const DEFAULT_SUITE: SuiteId =
+    SuiteId { kem_id: Kem::KEM_ID, kdf_id: Kdf::KDF_ID, aead: AeadId::Aes256Gcm };
+
+const SUPPORTED_SUITES: &[SuiteId] = &[DEFAULT_SUITE];
+
+/// Returns all suites that sessions can use, with the default suite first.
+pub fn supported_suites() -> &'static [SuiteId] {
+    SUPPORTED_SUITES
+}
+
+/// Wire identifiers of the supported suites. Identifiers are never reused for
+/// another suite, and 0x0002 is reserved for AES-256-GCM-SIV.
+const WIRE_SUITE_IDS: &[(u16, SuiteId)] = &[(0x0001, DEFAULT_SUITE)];
+
+/// Identifier of a supported [`SuiteId`] in wire formats, e.g. when suites are
+/// negotiated out of band. Unknown identifiers are rejected when they are
+/// converted from the raw integer, instead of when a session is set up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct WireSuiteId(u16);
+
+impl TryFrom<u16> for WireSuiteId {
+    type Error = anyhow::Error;
+
+    fn try_from(value: u16) -> anyhow::Result<Self> {
+        WIRE_SUITE_IDS
+            .iter()
+            .find(|(wire_id, _)| *wire_id == value)
+            .map(|(wire_id, _)| Self(*wire_id))
+            .ok_or_else(|| {
+                CryptoError::MalformedInput.context(format!("unknown wire suite id {:#06x}", value))
+            })
+    }
+}
+
+impl TryFrom<SuiteId> for WireSuiteId {
+    type Error = anyhow::Error;
+
+    fn try_from(suite: SuiteId) -> anyhow::Result<Self> {
+        WIRE_SUITE_IDS
+            .iter()
+            .find(|(_, wire_suite)| *wire_suite == suite)
+            .map(|(wire_id, _)| Self(*wire_id))
+            .ok_or_else(|| {
+                CryptoError::MalformedInput.context(format!("unsupported suite {:?}", suite))
+            })
+    }
+}
+
+impl From<WireSuiteId> for u16 {
+    fn from(wire_id: WireSuiteId) -> Self {
+        wire_id.0
+    }
+}
+
+impl From<WireSuiteId> for SuiteId {
+    fn from(wire_id: WireSuiteId) -> Self {
+        WIRE_SUITE_IDS
+            .iter()
+            .find(|(value, _)| *value == wire_id.0)
+            .map(|(_, suite)| *suite)
+            .expect("wire suite ids are only constructed for supported suites")
+    }
+}
+
+/// Size of the channel binding token of a session.
+pub const CHANNEL_BINDING_TOKEN_SIZE_BYTES: usize = 32;
+
+/// HPKE modes of operation.
+/// <https://www.rfc-editor.org/rfc/rfc9180.html#name-hybrid-public-key-encryption>
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HpkeMode {
+    Base,
+    /// The key schedule additionally authenticates a pre-shared key.
+    /// <https://www.rfc-editor.org/rfc/rfc9180.html#name-authentication-using-a-pre->
+    Psk,
+}
+
+/// Minimum size of a pre-shared key, which must contain at least 32 bytes of
+/// entropy. <https://www.rfc-editor.org/rfc/rfc9180.html#name-pre-shared-key-recommendati>
+pub const MIN_PSK_SIZE_BYTES: usize = 32;
+
+/// Maximum size of a pre-shared key. RFC 9180 only bounds it by the input
+/// limit of the KDF, so this keeps callers from passing arbitrarily large
+/// buffers to the key schedule.
+pub const MAX_PSK_SIZE_BYTES: usize = 256;
+
+/// Maximum size of a pre-shared key identifier, bounded for the same reason as
+/// [`MAX_PSK_SIZE_BYTES`].
+pub const MAX_PSK_ID_SIZE_BYTES: usize = 256;
+
+/// Returns the HPKE `suite_id` used in the key schedule, which is defined as
+/// `"HPKE" || I2OSP(kem_id, 2) || I2OSP(kdf_id, 2) || I2OSP(aead_id, 2)`.
+/// <https://www.rfc-editor.org/rfc/rfc9180.html#name-creating-the-encryption-con>
+pub fn suite_id_bytes() -> Vec<u8> {
+    let mut suite_id = b"HPKE".to_vec();
+    suite_id.extend_from_slice(&Kem::KEM_ID.to_be_bytes());
+    suite_id.extend_from_slice(&Kdf::KDF_ID.to_be_bytes());
+    suite_id.extend_from_slice(&Aead::AEAD_ID.to_be_bytes());
+    suite_id
+}
 
 pub(crate) fn generate_kem_key_pair() -> (PrivateKey, PublicKey) {
     Kem::gen_keypair(&mut OsRng)
 }
 
+/// Deterministically derives a KEM key pair from the input keying material,
+/// as `DeriveKeyPair(ikm)` from RFC9180. This is the derivation used by all
+/// seeded key generation in this crate, so it can be used to reproduce those
+/// keys, e.g. for deterministic provisioning or test vectors.
+/// <https://www.rfc-editor.org/rfc/rfc9180.html#name-derivekeypair>
+pub fn derive_key_pair(ikm: &[u8]) -> (PrivateKey, PublicKey) {
+    Kem::derive_keypair(ikm)
+}
+
+/// Computes the KEM public key that corresponds to `private_key`.
+pub(crate) fn kem_public_key(private_key: &PrivateKey) -> PublicKey {
+    Kem::sk_to_pk(private_key)
+}
+
+/// Returns whether `serialized_encapsulated_public_key` can be decapsulated
+/// with `private_key`, without deriving any session keys.
+pub(crate) fn can_decapsulate(
+    serialized_encapsulated_public_key: &[u8],
+    private_key: &PrivateKey,
+) -> bool {
+    EncappedKey::from_bytes(serialized_encapsulated_public_key)
+        .and_then(|encapped_key| Kem::decap(private_key, None, &encapped_key))
+        .is_ok()
+}
+
 /// Sets up an HPKE sender by generating an ephemeral keypair (and serializing
 /// the corresponding public key) and creating a sender context.
 /// <https://www.rfc-editor.org/rfc/rfc9180.html#name-encryption-to-a-public-key>
+#[cfg(feature = "response-direction")]
 pub(crate) fn setup_base_sender(
     serialized_recipient_public_key: &[u8],
     info: &[u8],
 ) -> anyhow::Result<(Vec<u8>, SenderContext)> {
-    let recipient_public_key = PublicKey::from_bytes(serialized_recipient_public_key)
-        .map_err(|error| anyhow!("couldn't deserialize recipient public key: {}", error))?;
+    setup_sender(&OpModeS::Base, serialized_recipient_public_key, info)
+}
 
-    let (encapsulated_public_key, sender_context) = hpke::setup_sender::<Aead, Kdf, Kem, _>(
-        &OpModeS::Base,
-        &recipient_public_key,
-        info,
-        &mut OsRng,
-    )
-    .map_err(|error| anyhow!("couldn't create sender context: {}", error))?;
+/// Sets up an HPKE sender in PSK mode, in which the key schedule also
+/// authenticates `psk`, identified by `psk_id`. Only a recipient that knows the
+/// same PSK can decrypt the requests of the session.
+/// <https://www.rfc-editor.org/rfc/rfc9180.html#name-authentication-using-a-pre->
+#[cfg(feature = "response-direction")]
+pub(crate) fn setup_psk_sender(
+    serialized_recipient_public_key: &[u8],
+    psk: &[u8],
+    psk_id: &[u8],
+    info: &[u8],
+) -> anyhow::Result<(Vec<u8>, SenderContext)> {
+    verify_psk_inputs(psk, psk_id)?;
+    setup_sender(&OpModeS::Psk(PskBundle { psk, psk_id }), serialized_recipient_public_key, info)
+}
+
+#[cfg(feature = "response-direction")]
+fn setup_sender(
+    mode: &OpModeS<Kem>,
+    serialized_recipient_public_key: &[u8],
+    info: &[u8],
+) -> anyhow::Result<(Vec<u8>, SenderContext)> {
+    let (serialized_encapsulated_public_key, sender_context) =
+        setup_hpke_sender(mode, serialized_recipient_public_key, info)?;
 
     // Derive request key and nonce.
     // This is a deviation from the HPKE RFC, because we are deriving both session
@@ -68,37 +270,86 @@ pub(crate) fn setup_base_sender(
     // request key be directly derived from the shared secret. This is required
     // to be able to share session keys between the Kernel and the Application
     // via RPC. <https://www.rfc-editor.org/rfc/rfc9180.html#name-encryption-and-decryption>
-    let mut request_key = [0u8; AEAD_ALGORITHM_KEY_SIZE_BYTES];
-    sender_context
-        .export(b"request_key", &mut request_key)
-        .map_err(|error| anyhow!("couldn't export request key: {}", error))?;
+    let request_key =
+        export_aead_key(|label, key| sender_context.export(label, key), REQUEST_KEY_LABEL)
+            .context("couldn't export request key")?;
 
     // Derive response key and nonce.
-    let mut response_key = [0u8; AEAD_ALGORITHM_KEY_SIZE_BYTES];
-    sender_context
-        .export(b"response_key", &mut response_key)
-        .map_err(|error| anyhow!("couldn't export response key: {}", error))?;
+    let response_key =
+        export_aead_key(|label, key| sender_context.export(label, key), RESPONSE_KEY_LABEL)
+            .context("couldn't export response key")?;
 
-    Ok((encapsulated_public_key.to_bytes().to_vec(), SenderContext { request_key, response_key }))
+    Ok((serialized_encapsulated_public_key, SenderContext { request_key, response_key }))
+}
+
+/// Sets up an HPKE sender from the `shared_secret` of a KEM encapsulation that
+/// was performed outside of this crate. Only the key schedule is run in-crate.
+/// <https://www.rfc-editor.org/rfc/rfc9180.html#name-creating-the-encryption-con>
+#[cfg(feature = "response-direction")]
+pub(crate) fn setup_base_sender_from_shared_secret(
+    shared_secret: &[u8],
+    info: &[u8],
+) -> anyhow::Result<SenderContext> {
+    anyhow::ensure!(
+        shared_secret.len() == SUITE_LENGTHS.nsecret,
+        "incorrect shared secret size, expected {}, got {}",
+        SUITE_LENGTHS.nsecret,
+        shared_secret.len()
+    );
+    let exporter_secret = ExporterSecret::from_shared_secret(shared_secret, info);
+    let request_key =
+        export_aead_key(|label, key| exporter_secret.export(label, key), REQUEST_KEY_LABEL)
+            .context("couldn't export request key")?;
+    let response_key =
+        export_aead_key(|label, key| exporter_secret.export(label, key), RESPONSE_KEY_LABEL)
+            .context("couldn't export response key")?;
+    Ok(SenderContext { request_key, response_key })
 }
 
 /// Sets up an HPKE recipient by creating a recipient context.
 /// <https://www.rfc-editor.org/rfc/rfc9180.html#name-encryption-to-a-public-key>
+#[cfg(feature = "response-direction")]
 pub(crate) fn setup_base_recipient(
     serialized_encapsulated_public_key: &[u8],
     recipient_private_key: &PrivateKey,
     info: &[u8],
 ) -> anyhow::Result<RecipientContext> {
-    let encapsulated_public_key = EncappedKey::from_bytes(serialized_encapsulated_public_key)
-        .map_err(|error| anyhow!("couldn't deserialize the encapsulated public key: {}", error))?;
+    setup_recipient(&OpModeR::Base, serialized_encapsulated_public_key, recipient_private_key, info)
+}
 
-    let recipient_context = hpke::setup_receiver::<Aead, Kdf, Kem>(
-        &OpModeR::Base,
+/// Sets up an HPKE recipient in PSK mode. Requests of a sender that used a
+/// different PSK or PSK ID fail to decrypt.
+/// <https://www.rfc-editor.org/rfc/rfc9180.html#name-authentication-using-a-pre->
+#[cfg(feature = "response-direction")]
+pub(crate) fn setup_psk_recipient(
+    serialized_encapsulated_public_key: &[u8],
+    recipient_private_key: &PrivateKey,
+    psk: &[u8],
+    psk_id: &[u8],
+    info: &[u8],
+) -> anyhow::Result<RecipientContext> {
+    verify_psk_inputs(psk, psk_id)?;
+    setup_recipient(
+        &OpModeR::Psk(PskBundle { psk, psk_id }),
+        serialized_encapsulated_public_key,
         recipient_private_key,
-        &encapsulated_public_key,
         info,
     )
-    .map_err(|error| anyhow!("couldn't create recipient context: {}", error))?;
+}
+
+#[cfg(feature = "response-direction")]
+fn setup_recipient(
+    mode: &OpModeR<Kem>,
+    serialized_encapsulated_public_key: &[u8],
+    recipient_private_key: &PrivateKey,
+    info: &[u8],
+) -> anyhow::Result<RecipientContext> {
+    let recipient_context = setup_hpke_recipient(
+        mode,
+        serialized_encapsulated_public_key,
+        recipient_private_key,
+        info,
+    )?;
 
     // Derive request key and nonce.
     // This is a deviation from the HPKE RFC, because we are deriving both session
@@ -106,25 +357,265 @@ pub(crate) fn setup_base_recipient(
     // request key be directly derived from the shared secret. This is required
     // to be able to share session keys between the Kernel and the Application
     // via RPC. <https://www.rfc-editor.org/rfc/rfc9180.html#name-encryption-and-decryption>
-    let mut request_key = [0u8; AEAD_ALGORITHM_KEY_SIZE_BYTES];
-    recipient_context
-        .export(b"request_key", &mut request_key)
-        .map_err(|error| anyhow!("couldn't export request key: {}", error))?;
+    let request_key =
+        export_aead_key(|label, key| recipient_context.export(label, key), REQUEST_KEY_LABEL)
+            .context("couldn't export request key")?;
 
     // Derive response key and nonce.
-    let mut response_key = [0u8; AEAD_ALGORITHM_KEY_SIZE_BYTES];
-    recipient_context
-        .export(b"response_key", &mut response_key)
-        .map_err(|error| anyhow!("couldn't export response key: {}", error))?;
+    let response_key =
+        export_aead_key(|label, key| recipient_context.export(label, key), RESPONSE_KEY_LABEL)
+            .context("couldn't export response key")?;
 
     Ok(RecipientContext { request_key, response_key })
 }
 
+/// Checks the PSK inputs as in `VerifyPSKInputs`: both the PSK and the PSK ID
+/// must be provided, the PSK must be long enough to contain 32 bytes of
+/// entropy, and neither may exceed its maximum size.
+/// <https://www.rfc-editor.org/rfc/rfc9180.html#name-creating-the-encryption-con>
+#[cfg(feature = "response-direction")]
+fn verify_psk_inputs(psk: &[u8], psk_id: &[u8]) -> anyhow::Result<()> {
+    if psk.len() < MIN_PSK_SIZE_BYTES {
+        return Err(CryptoError::MalformedInput.context(format!(
+            "PSK is too short, expected at least {} bytes, got {}",
+            MIN_PSK_SIZE_BYTES,
+            psk.len()
+        )));
+    }
+    if psk.len() > MAX_PSK_SIZE_BYTES {
+        return Err(CryptoError::MalformedInput.context(format!(
+            "PSK is too long, expected at most {} bytes, got {}",
+            MAX_PSK_SIZE_BYTES,
+            psk.len()
+        )));
+    }
+    if psk_id.is_empty() {
+        return Err(CryptoError::MalformedInput.context("PSK ID is empty"));
+    }
+    if psk_id.len() > MAX_PSK_ID_SIZE_BYTES {
+        return Err(CryptoError::MalformedInput.context(format!(
+            "PSK ID is too long, expected at most {} bytes, got {}",
+            MAX_PSK_ID_SIZE_BYTES,
+            psk_id.len()
+        )));
+    }
+    Ok(())
+}
+
+/// Returns the HPKE info of sessions that authenticate the recipient public key
+/// in addition to the KEM context, so that both peers have to agree on the
+/// identity of the recipient. Public keys have a fixed size, so the info is
+/// unambiguous.
+#[cfg(feature = "response-direction")]
+pub(crate) fn recipient_bound_info(serialized_recipient_public_key: &[u8]) -> Vec<u8> {
+    [OAK_HPKE_RECIPIENT_BOUND_INFO, serialized_recipient_public_key].concat()
+}
+
+/// Sets up an HPKE sender for a session in which only requests are sent. Only
+/// the request key is derived.
+/// <https://www.rfc-editor.org/rfc/rfc9180.html#name-encryption-to-a-public-key>
+pub(crate) fn setup_request_only_sender(
+    serialized_recipient_public_key: &[u8],
+    info: &[u8],
+) -> anyhow::Result<(Vec<u8>, RequestOnlySenderContext)> {
+    let (serialized_encapsulated_public_key, sender_context) =
+        setup_hpke_sender(&OpModeS::Base, serialized_recipient_public_key, info)?;
+    let request_key =
+        export_aead_key(|label, key| sender_context.export(label, key), REQUEST_KEY_LABEL)
+            .context("couldn't export request key")?;
+    Ok((serialized_encapsulated_public_key, RequestOnlySenderContext { request_key }))
+}
+
+/// Sets up an HPKE recipient for a session in which only requests are sent.
+/// Only the request key is derived.
+/// <https://www.rfc-editor.org/rfc/rfc9180.html#name-encryption-to-a-public-key>
+pub(crate) fn setup_request_only_recipient(
+    serialized_encapsulated_public_key: &[u8],
+    recipient_private_key: &PrivateKey,
+    info: &[u8],
+) -> anyhow::Result<RequestOnlyRecipientContext> {
+    let recipient_context = setup_hpke_recipient(
+        &OpModeR::Base,
+        serialized_encapsulated_public_key,
+        recipient_private_key,
+        info,
+    )?;
+    let request_key =
+        export_aead_key(|label, key| recipient_context.export(label, key), REQUEST_KEY_LABEL)
+            .context("couldn't export request key")?;
+    Ok(RequestOnlyRecipientContext { request_key })
+}
+
+/// Derives an AEAD key using an HPKE exporter `export` with `label`, and checks
+/// that the derived key is usable.
+pub(crate) fn export_aead_key<F>(export: F, label: &[u8]) -> anyhow::Result<AeadKey>
+where
+    F: FnOnce(&[u8], &mut [u8]) -> Result<(), hpke::HpkeError>,
+{
+    let mut key = AeadKey::default();
+    export(label, key.as_mut_bytes()).map_err(|error| anyhow!("couldn't export key: {}", error))?;
+    // An all-zero key is astronomically unlikely, but would indicate a broken key
+    // derivation.
+    anyhow::ensure!(!key.is_zero(), "key derivation failed: derived key is all zeros");
+    Ok(key)
+}
+
+/// Creates an HPKE sender context for the recipient public key. Returns the
+/// serialized encapsulated public key and the context.
+fn setup_hpke_sender(
+    mode: &OpModeS<Kem>,
+    serialized_recipient_public_key: &[u8],
+    info: &[u8],
+) -> anyhow::Result<(Vec<u8>, AeadCtxS<Aead, Kdf, Kem>)> {
+    let recipient_public_key = PublicKey::from_bytes(serialized_recipient_public_key)
+        .map_err(|error| anyhow!("couldn't deserialize recipient public key: {}", error))?;
+    check_entropy_if_required()?;
+
+    let (encapsulated_public_key, sender_context) =
+        hpke::setup_sender::<Aead, Kdf, Kem, _>(mode, &recipient_public_key, info, &mut OsRng)
+            .map_err(|error| anyhow!("couldn't create sender context: {}", error))?;
+    Ok((encapsulated_public_key.to_bytes().to_vec(), sender_context))
+}
+
+/// Creates an HPKE recipient context from the serialized encapsulated public
+/// key.
+fn setup_hpke_recipient(
+    mode: &OpModeR<Kem>,
+    serialized_encapsulated_public_key: &[u8],
+    recipient_private_key: &PrivateKey,
+    info: &[u8],
+) -> anyhow::Result<AeadCtxR<Aead, Kdf, Kem>> {
+    let encapsulated_public_key = EncappedKey::from_bytes(serialized_encapsulated_public_key)
+        .map_err(|error| {
+            CryptoError::MalformedInput
+                .context_with_source("couldn't deserialize the encapsulated public key", error)
+        })?;
+
+    hpke::setup_receiver::<Aead, Kdf, Kem>(
+        mode,
+        recipient_private_key,
+        &encapsulated_public_key,
+        info,
+    )
+    .map_err(|error| anyhow!("couldn't create recipient context: {}", error))
+}
+
+/// Sets up an HPKE sender and returns the serialized encapsulated public key
+/// together with the derived [`SharedKeys`].
+/// <https://www.rfc-editor.org/rfc/rfc9180.html#name-encryption-to-a-public-key>
+#[cfg(feature = "response-direction")]
+pub fn setup_base_sender_shared_keys(
+    serialized_recipient_public_key: &[u8],
+) -> anyhow::Result<(Vec<u8>, SharedKeys)> {
+    let (serialized_encapsulated_public_key, sender_context) =
+        setup_hpke_sender(&OpModeS::Base, serialized_recipient_public_key, OAK_HPKE_INFO)?;
+    let shared_keys = SharedKeys::export(|label, output| sender_context.export(label, output))?;
+    Ok((serialized_encapsulated_public_key, shared_keys))
+}
+
+/// Sets up an HPKE recipient and returns the derived [`SharedKeys`].
+/// <https://www.rfc-editor.org/rfc/rfc9180.html#name-encryption-to-a-public-key>
+#[cfg(feature = "response-direction")]
+pub(crate) fn setup_base_recipient_shared_keys(
+    serialized_encapsulated_public_key: &[u8],
+    recipient_private_key: &PrivateKey,
+) -> anyhow::Result<SharedKeys> {
+    let recipient_context = setup_hpke_recipient(
+        &OpModeR::Base,
+        serialized_encapsulated_public_key,
+        recipient_private_key,
+        OAK_HPKE_INFO,
+    )?;
+    SharedKeys::export(|label, output| recipient_context.export(label, output))
+}
+
+/// Request and response keys and base nonces of an HPKE session, for callers
+/// that implement their own record layer on top of the KEM and the key schedule
+/// of this crate. The keys are the same ones that are used by
+/// [`crate::encryptor`].
+///
+/// Callers are responsible for nonce management: a nonce must never be used
+/// twice with the same key. One way to achieve this is to XOR the base nonce
+/// with a per-direction message sequence number, as in `ComputeNonce`.
+/// <https://www.rfc-editor.org/rfc/rfc9180.html#name-encryption-and-decryption>
+#[cfg(feature = "response-direction")]
+pub struct SharedKeys {
+    request_key: AeadKey,
+    response_key: AeadKey,
+    request_base_nonce: AeadNonce,
+    response_base_nonce: AeadNonce,
+    channel_binding_token: [u8; CHANNEL_BINDING_TOKEN_SIZE_BYTES],
+}
+
+#[cfg(feature = "response-direction")]
+impl SharedKeys {
+    fn export<F>(export: F) -> anyhow::Result<Self>
+    where
+        F: Fn(&[u8], &mut [u8]) -> Result<(), hpke::HpkeError>,
+    {
+        let mut shared_keys = Self {
+            request_key: export_aead_key(&export, REQUEST_KEY_LABEL)
+                .context("couldn't export request key")?,
+            response_key: export_aead_key(&export, RESPONSE_KEY_LABEL)
+                .context("couldn't export response key")?,
+            request_base_nonce: AeadNonce::default(),
+            response_base_nonce: AeadNonce::default(),
+            channel_binding_token: [0u8; CHANNEL_BINDING_TOKEN_SIZE_BYTES],
+        };
+        export(REQUEST_BASE_NONCE_LABEL, &mut shared_keys.request_base_nonce)
+            .map_err(|error| anyhow!("couldn't export request base nonce: {}", error))?;
+        export(RESPONSE_BASE_NONCE_LABEL, &mut shared_keys.response_base_nonce)
+            .map_err(|error| anyhow!("couldn't export response base nonce: {}", error))?;
+        export(CHANNEL_BINDING_TOKEN_LABEL, &mut shared_keys.channel_binding_token)
+            .map_err(|error| anyhow!("couldn't export channel binding token: {}", error))?;
+        Ok(shared_keys)
+    }
+
+    /// AEAD key for encrypting/decrypting client requests.
+    pub fn request_key(&self) -> &[u8] {
+        self.request_key.as_bytes()
+    }
+
+    /// AEAD key for encrypting/decrypting server responses.
+    pub fn response_key(&self) -> &[u8] {
+        self.response_key.as_bytes()
+    }
+
+    /// Base nonce for client requests.
+    pub fn request_base_nonce(&self) -> &[u8] {
+        &self.request_base_nonce
+    }
+
+    /// Base nonce for server responses.
+    pub fn response_base_nonce(&self) -> &[u8] {
+        &self.response_base_nonce
+    }
+
+    /// Token that identifies the session, for binding an application-layer
+    /// authentication protocol to it. It is exported with a dedicated label, so
+    /// it doesn't reveal anything about the session keys, and both peers
+    /// compute the same token.
+    pub fn channel_binding_token(&self) -> [u8; CHANNEL_BINDING_TOKEN_SIZE_BYTES] {
+        self.channel_binding_token
+    }
+}
+
+#[cfg(feature = "response-direction")]
+impl Drop for SharedKeys {
+    fn drop(&mut self) {
+        self.request_base_nonce.zeroize();
+        self.response_base_nonce.zeroize();
+        self.channel_binding_token.zeroize();
+    }
+}
+
+#[cfg(feature = "response-direction")]
 pub struct SenderContext {
     request_key: AeadKey,
     response_key: AeadKey,
 }
 
+#[cfg(feature = "response-direction")]
 impl SenderContext {
     /// Encrypts request message with associated data using AEAD.
     /// <https://www.rfc-editor.org/rfc/rfc9180.html#name-encryption-and-decryption>
@@ -140,6 +631,25 @@ impl SenderContext {
         Ok(ciphertext)
     }
 
+    /// Same as [`SenderContext::seal`], but writes the ciphertext into
+    /// `output` and returns the number of bytes written.
+    pub(crate) fn seal_into(
+        &self,
+        nonce: &AeadNonce,
+        plaintext: &[u8],
+        associated_data: &[u8],
+        output: &mut [u8],
+    ) -> anyhow::Result<usize> {
+        crate::hpke::aead::encrypt_into(
+            &self.request_key,
+            nonce,
+            plaintext,
+            associated_data,
+            output,
+        )
+        .context("couldn't encrypt request message")
+    }
+
     /// Decrypts response message and validates associated data using AEAD as
     /// part of bidirectional communication.
     /// <https://www.rfc-editor.org/rfc/rfc9180.html#name-bidirectional-encryption>
@@ -149,18 +659,119 @@ impl SenderContext {
         ciphertext: &[u8],
         associated_data: &[u8],
     ) -> anyhow::Result<Vec<u8>> {
+        self.check_response_key()?;
         let plaintext =
             crate::hpke::aead::decrypt(&self.response_key, nonce, ciphertext, associated_data)
                 .context("couldn't decrypt response message")?;
         Ok(plaintext)
     }
+
+    /// Decrypts response message into `buffer` and validates associated data
+    /// using AEAD as part of bidirectional communication.
+    /// <https://www.rfc-editor.org/rfc/rfc9180.html#name-bidirectional-encryption>
+    pub(crate) fn open_into(
+        &self,
+        nonce: &AeadNonce,
+        ciphertext: &[u8],
+        associated_data: &[u8],
+        buffer: &mut Vec<u8>,
+    ) -> anyhow::Result<()> {
+        self.check_response_key()?;
+        crate::hpke::aead::decrypt_into(
+            &self.response_key,
+            nonce,
+            ciphertext,
+            associated_data,
+            buffer,
+        )
+        .context("couldn't decrypt response message")
+    }
+
+    /// Returns the response key, e.g. for handing off the response direction
+    /// of the session.
+    pub(crate) fn response_key(&self) -> anyhow::Result<&AeadKey> {
+        self.check_response_key()?;
+        Ok(&self.response_key)
+    }
+
+    /// Zeroizes the response key immediately, instead of when the context is
+    /// dropped. Responses can't be decrypted afterwards.
+    pub(crate) fn discard_response_key(&mut self) {
+        self.response_key.zeroize();
+    }
+
+    /// Returns whether the response key has been discarded.
+    #[cfg(test)]
+    pub(crate) fn is_response_key_discarded(&self) -> bool {
+        self.response_key.is_zero()
+    }
+
+    // A discarded response key is all zeros, which anyone could use to forge
+    // responses, so it has to be rejected explicitly.
+    fn check_response_key(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(!self.response_key.is_zero(), "response key has been discarded");
+        Ok(())
+    }
+
+    /// Returns the number of bytes of key material held by the context. A
+    /// discarded response key is still counted, since its zeroized buffer is
+    /// only freed when the context is dropped.
+    pub(crate) fn secret_material_len(&self) -> usize {
+        2 * core::mem::size_of::<AeadKey>()
+    }
+
+    /// Derives the continuity secret of the session, which needs the response
+    /// key.
+    pub(crate) fn continuity_secret(&self) -> anyhow::Result<ContinuitySecret> {
+        self.check_response_key()?;
+        Ok(derive_continuity_secret(&self.request_key, &self.response_key))
+    }
+
+    /// Derives the id of the transfer whose manifest is the message at
+    /// `first_sequence_number` of the stream, which needs the response key.
+    pub(crate) fn transfer_id(
+        &self,
+        first_sequence_number: u64,
+    ) -> anyhow::Result<[u8; TRANSFER_ID_SIZE_BYTES]> {
+        self.check_response_key()?;
+        Ok(derive_transfer_id(&self.request_key, &self.response_key, first_sequence_number))
+    }
+
+    /// Serializes the session keys into an encoded `SessionKeys` message, so
+    /// that the context can be restored with [`SenderContext::deserialize`].
+    /// The key fields of the message are zeroized once it is encoded.
+    pub(crate) fn serialize(&self) -> Zeroizing<Vec<u8>> {
+        let mut session_keys = SessionKeys {
+            request_key: self.request_key.as_bytes().to_vec(),
+            response_key: self.response_key.as_bytes().to_vec(),
+        };
+        let serialized = Zeroizing::new(session_keys.encode_to_vec());
+        session_keys.request_key.zeroize();
+        session_keys.response_key.zeroize();
+        serialized
+    }
+
+    pub(crate) fn deserialize(context: SessionKeys) -> anyhow::Result<Self> {
+        let (request_key, response_key) = deserialize_session_keys(context)?;
+        Ok(Self { request_key, response_key })
+    }
+
+    /// Returns whether this context derived the same request and response keys
+    /// as `recipient_context`, without exposing the keys.
+    #[cfg(test)]
+    pub(crate) fn has_same_keys_as(&self, recipient_context: &RecipientContext) -> bool {
+        self.request_key.as_bytes() == recipient_context.request_key.as_bytes()
+            && self.response_key.as_bytes() == recipient_context.response_key.as_bytes()
+    }
 }
 
+#[cfg(feature = "response-direction")]
 pub struct RecipientContext {
     request_key: AeadKey,
     response_key: AeadKey,
 }
 
+#[cfg(feature = "response-direction")]
 impl RecipientContext {
     /// Decrypts request message and validates associated data using AEAD.
     /// <https://www.rfc-editor.org/rfc/rfc9180.html#name-encryption-and-decryption>
@@ -176,6 +787,26 @@ impl RecipientContext {
         Ok(plaintext)
     }
 
+    /// Decrypts request message into `buffer` and validates associated data
+    /// using AEAD.
+    /// <https://www.rfc-editor.org/rfc/rfc9180.html#name-encryption-and-decryption>
+    pub(crate) fn open_into(
+        &self,
+        nonce: &AeadNonce,
+        ciphertext: &[u8],
+        associated_data: &[u8],
+        buffer: &mut Vec<u8>,
+    ) -> anyhow::Result<()> {
+        crate::hpke::aead::decrypt_into(
+            &self.request_key,
+            nonce,
+            ciphertext,
+            associated_data,
+            buffer,
+        )
+        .context("couldn't decrypt request message")
+    }
+
     /// Encrypts response message with associated data using AEAD as part of
     /// bidirectional communication.
     /// <https://www.rfc-editor.org/rfc/rfc9180.html#name-bidirectional-encryption>
@@ -194,29 +825,102 @@ impl RecipientContext {
     /// Serializes recipient context into a `SessionKeys` Protobuf message.
     pub fn serialize(self) -> anyhow::Result<SessionKeys> {
         Ok(SessionKeys {
-            request_key: self.request_key.to_vec(),
-            response_key: self.response_key.to_vec(),
+            request_key: self.request_key.as_bytes().to_vec(),
+            response_key: self.response_key.as_bytes().to_vec(),
         })
     }
 
+    /// Returns the number of bytes of key material held by the context.
+    pub(crate) fn secret_material_len(&self) -> usize {
+        2 * core::mem::size_of::<AeadKey>()
+    }
+
+    /// Derives the continuity secret of the session.
+    pub(crate) fn continuity_secret(&self) -> ContinuitySecret {
+        derive_continuity_secret(&self.request_key, &self.response_key)
+    }
+
+    /// Derives the id of the transfer whose manifest is the message at
+    /// `first_sequence_number` of the stream.
+    pub(crate) fn transfer_id(&self, first_sequence_number: u64) -> [u8; TRANSFER_ID_SIZE_BYTES] {
+        derive_transfer_id(&self.request_key, &self.response_key, first_sequence_number)
+    }
+
     /// Deserializes recipient context from a `SessionKeys` Protobuf message.
     pub fn deserialize(context: SessionKeys) -> anyhow::Result<Self> {
-        Ok(Self {
-            request_key: context.request_key.try_into().map_err(|v: Vec<u8>| {
-                anyhow!(
-                    "incorrect request key size, expected {}, got {}",
-                    AEAD_ALGORITHM_KEY_SIZE_BYTES,
-                    v.len()
-                )
-            })?,
-            response_key: context.response_key.try_into().map_err(|v: Vec<u8>| {
-                anyhow!(
-                    "incorrect response key size, expected {}, got {}",
-                    AEAD_ALGORITHM_KEY_SIZE_BYTES,
-                    v.len()
-                )
-            })?,
-        })
+        let (request_key, response_key) = deserialize_session_keys(context)?;
+        Ok(Self { request_key, response_key })
+    }
+}
+
+#[cfg(feature = "response-direction")]
+fn deserialize_session_keys(mut context: SessionKeys) -> anyhow::Result<(AeadKey, AeadKey)> {
+    // The keys are copied out of the message, so that its key fields can be
+    // zeroized whether or not they are valid.
+    let request_key = context.request_key.as_slice().try_into().map(AeadKey::new).map_err(|_| {
+        anyhow!(
+            "incorrect request key size, expected {}, got {}",
+            AEAD_ALGORITHM_KEY_SIZE_BYTES,
+            context.request_key.len()
+        )
+    });
+    let response_key = context.response_key.as_slice().try_into().map(AeadKey::new).map_err(|_| {
+        anyhow!(
+            "incorrect response key size, expected {}, got {}",
+            AEAD_ALGORITHM_KEY_SIZE_BYTES,
+            context.response_key.len()
+        )
+    });
+    context.request_key.zeroize();
+    context.response_key.zeroize();
+    Ok((request_key?, response_key?))
+}
+
+/// Sender context of a session in which only requests are sent.
+pub struct RequestOnlySenderContext {
+    request_key: AeadKey,
+}
+
+impl RequestOnlySenderContext {
+    /// Encrypts request message with associated data using AEAD.
+    /// <https://www.rfc-editor.org/rfc/rfc9180.html#name-encryption-and-decryption>
+    pub(crate) fn seal(
+        &self,
+        nonce: &AeadNonce,
+        plaintext: &[u8],
+        associated_data: &[u8],
+    ) -> anyhow::Result<Vec<u8>> {
+        crate::hpke::aead::encrypt(&self.request_key, nonce, plaintext, associated_data)
+            .context("couldn't encrypt request message")
+    }
+
+    /// Returns the number of bytes of key material held by the context.
+    pub(crate) fn secret_material_len(&self) -> usize {
+        core::mem::size_of::<AeadKey>()
+    }
+}
+
+/// Recipient context of a session in which only requests are sent.
+pub struct RequestOnlyRecipientContext {
+    request_key: AeadKey,
+}
+
+impl RequestOnlyRecipientContext {
+    /// Decrypts request message and validates associated data using AEAD.
+    /// <https://www.rfc-editor.org/rfc/rfc9180.html#name-encryption-and-decryption>
+    pub(crate) fn open(
+        &self,
+        nonce: &AeadNonce,
+        ciphertext: &[u8],
+        associated_data: &[u8],
+    ) -> anyhow::Result<Vec<u8>> {
+        crate::hpke::aead::decrypt(&self.request_key, nonce, ciphertext, associated_data)
+            .context("couldn't decrypt request message")
+    }
+
+    /// Returns the number of bytes of key material held by the context.
+    pub(crate) fn secret_material_len(&self) -> usize {
+        core::mem::size_of::<AeadKey>()
     }
 }
 
@@ -229,6 +933,10 @@ pub(crate) fn generate_random_nonce() -> AeadNonce {
 
 pub(crate) fn deserialize_nonce(nonce: &[u8]) -> anyhow::Result<AeadNonce> {
     nonce.try_into().map_err(|_| {
-        anyhow!("incorrect nonce size, expected {}, found {}", AEAD_NONCE_SIZE_BYTES, nonce.len())
+        CryptoError::MalformedInput.context(format!(
+            "incorrect nonce size, expected {}, found {}",
+            AEAD_NONCE_SIZE_BYTES,
+            nonce.len()
+        ))
     })
 }
@@ -0,0 +1,255 @@
+//
+// Copyright 2024 The Project Oak Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! QUIC-style key updates for transports that implement their own record layer
+//! on top of [`SharedKeys`].
+//! <https://www.rfc-editor.org/rfc/rfc9001.html#name-key-update>
+//!
+//! The keys of the first generation are the exported session keys. The keys
+//! of each following generation are derived from the keys of the current one
+//! as `HKDF-SHA256(salt = KEY_UPDATE_LABEL, ikm = key, info = label)`, where
+//! `label` is the exporter label of the key. Messages carry the key phase,
+//! which is the lowest bit of the generation, next to the ciphertext.
+//!
+//! Either peer can initiate a key update with
+//! [`KeyUpdateSchedule::key_update`]. The other peer follows when it opens the
+//! first message in the new phase. Keys of the previous generation are retained
+//! until the next update, so that delayed messages can still be opened. As in
+//! QUIC, a peer can only initiate another update once it opened a message in
+//! the current phase.
+//!
+//! Callers remain responsible for nonce management, see [`SharedKeys`]. Base
+//! nonces aren't updated, so nonces have to keep advancing across key updates,
+//! as packet numbers do in QUIC.
+
+use alloc::{format, vec::Vec};
+
+use anyhow::Context;
+use hkdf::Hkdf;
+use sha2::Sha256;
+
+use crate::{
+    error::CryptoError,
+    hpke::{
+        aead::{AeadKey, AEAD_ALGORITHM_KEY_SIZE_BYTES},
+        deserialize_nonce, SharedKeys,
+    },
+    labels::{KEY_UPDATE_LABEL, REQUEST_KEY_LABEL, RESPONSE_KEY_LABEL},
+};
+
+/// Phase of the keys that a message was sealed with, i.e. the lowest bit of the
+/// key generation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyPhase(bool);
+
+impl KeyPhase {
+    pub fn new(bit: bool) -> Self {
+        Self(bit)
+    }
+
+    pub fn bit(&self) -> bool {
+        self.0
+    }
+}
+
+/// Request and response keys of a key generation.
+pub struct DirectionalKeys {
+    request_key: AeadKey,
+    response_key: AeadKey,
+}
+
+impl DirectionalKeys {
+    /// AEAD key for encrypting/decrypting client requests.
+    pub fn request_key(&self) -> &[u8] {
+        self.request_key.as_bytes()
+    }
+
+    /// AEAD key for encrypting/decrypting server responses.
+    pub fn response_key(&self) -> &[u8] {
+        self.response_key.as_bytes()
+    }
+
+    /// Derives the keys of the next generation.
+    fn next(&self) -> Self {
+        Self {
+            request_key: next_key(&self.request_key, REQUEST_KEY_LABEL),
+            response_key: next_key(&self.response_key, RESPONSE_KEY_LABEL),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Role {
+    Client,
+    Server,
+}
+
+/// Key update schedule of one peer of a session.
+pub struct KeyUpdateSchedule {
+    role: Role,
+    /// Number of key updates so far.
+    generation: u64,
+    current: DirectionalKeys,
+    /// Keys of the next generation, which are derived in advance so that
+    /// opening a message of the next phase doesn't take longer.
+    next: DirectionalKeys,
+    previous: Option<DirectionalKeys>,
+    /// Whether a message in the current phase was opened, which confirms that
+    /// the peer uses the current keys.
+    confirmed: bool,
+}
+
+impl KeyUpdateSchedule {
+    /// Creates the key update schedule of the client of a session, which seals
+    /// requests and opens responses.
+    pub fn for_client(shared_keys: &SharedKeys) -> anyhow::Result<Self> {
+        Self::new(Role::Client, shared_keys)
+    }
+
+    /// Creates the key update schedule of the server of a session, which seals
+    /// responses and opens requests.
+    pub fn for_server(shared_keys: &SharedKeys) -> anyhow::Result<Self> {
+        Self::new(Role::Server, shared_keys)
+    }
+
+    fn new(role: Role, shared_keys: &SharedKeys) -> anyhow::Result<Self> {
+        let current = DirectionalKeys {
+            request_key: aead_key(shared_keys.request_key())?,
+            response_key: aead_key(shared_keys.response_key())?,
+        };
+        Ok(Self {
+            role,
+            generation: 0,
+            next: current.next(),
+            current,
+            previous: None,
+            confirmed: true,
+        })
+    }
+
+    /// Returns the phase of the current keys.
+    pub fn phase(&self) -> KeyPhase {
+        KeyPhase(self.generation & 1 == 1)
+    }
+
+    pub fn current_keys(&self) -> &DirectionalKeys {
+        &self.current
+    }
+
+    /// Initiates a key update. Returns the phase and the keys of the new
+    /// generation. Fails if the peer didn't follow the previous update yet.
+    pub fn key_update(&mut self) -> anyhow::Result<(KeyPhase, &DirectionalKeys)> {
+        anyhow::ensure!(
+            self.confirmed,
+            "no message was opened in phase {}, so the peer didn't follow the previous key update",
+            self.phase().bit() as u8
+        );
+        self.advance();
+        self.confirmed = false;
+        Ok((self.phase(), &self.current))
+    }
+
+    /// Encrypts `plaintext` and authenticates `associated_data` with the
+    /// current keys. Returns the key phase, which has to be sent along with the
+    /// ciphertext.
+    pub fn seal(
+        &self,
+        nonce: &[u8],
+        plaintext: &[u8],
+        associated_data: &[u8],
+    ) -> anyhow::Result<(KeyPhase, Vec<u8>)> {
+        let nonce = deserialize_nonce(nonce).context("couldn't deserialize nonce")?;
+        let ciphertext = crate::hpke::aead::encrypt(
+            self.seal_key(&self.current),
+            &nonce,
+            plaintext,
+            associated_data,
+        )
+        .context("couldn't encrypt message")?;
+        Ok((self.phase(), ciphertext))
+    }
+
+    /// Decrypts a message that was sealed by the peer in `phase`. Messages of
+    /// the other phase are opened with the previous keys if they are retained,
+    /// or else with the next keys, in which case the schedule follows the key
+    /// update of the peer.
+    pub fn open(
+        &mut self,
+        phase: KeyPhase,
+        nonce: &[u8],
+        ciphertext: &[u8],
+        associated_data: &[u8],
+    ) -> anyhow::Result<Vec<u8>> {
+        let nonce = deserialize_nonce(nonce).context("couldn't deserialize nonce")?;
+        let open = |keys: &DirectionalKeys| {
+            crate::hpke::aead::decrypt(self.open_key(keys), &nonce, ciphertext, associated_data)
+                .context("couldn't decrypt message")
+        };
+
+        if phase == self.phase() {
+            let plaintext = open(&self.current)?;
+            self.confirmed = true;
+            return Ok(plaintext);
+        }
+        if let Some(plaintext) = self.previous.as_ref().and_then(|keys| open(keys).ok()) {
+            return Ok(plaintext);
+        }
+        let plaintext = open(&self.next)?;
+        self.advance();
+        self.confirmed = true;
+        Ok(plaintext)
+    }
+
+    fn advance(&mut self) {
+        let next = self.next.next();
+        self.previous =
+            Some(core::mem::replace(&mut self.current, core::mem::replace(&mut self.next, next)));
+        self.generation += 1;
+    }
+
+    fn seal_key<'a>(&self, keys: &'a DirectionalKeys) -> &'a AeadKey {
+        match self.role {
+            Role::Client => &keys.request_key,
+            Role::Server => &keys.response_key,
+        }
+    }
+
+    fn open_key<'a>(&self, keys: &'a DirectionalKeys) -> &'a AeadKey {
+        match self.role {
+            Role::Client => &keys.response_key,
+            Role::Server => &keys.request_key,
+        }
+    }
+}
+
+fn aead_key(key: &[u8]) -> anyhow::Result<AeadKey> {
+    let key: [u8; AEAD_ALGORITHM_KEY_SIZE_BYTES] = key.try_into().map_err(|_| {
+        CryptoError::MalformedInput.context(format!(
+            "incorrect key size, expected {}, got {}",
+            AEAD_ALGORITHM_KEY_SIZE_BYTES,
+            key.len()
+        ))
+    })?;
+    Ok(AeadKey::new(key))
+}
+
+fn next_key(key: &AeadKey, label: &[u8]) -> AeadKey {
+    let mut next_key = AeadKey::default();
+    Hkdf::<Sha256>::new(Some(KEY_UPDATE_LABEL), key.as_bytes())
+        .expand(label, next_key.as_mut_bytes())
+        .expect("AEAD key size is a valid HKDF-SHA256 output length");
+    next_key
+}
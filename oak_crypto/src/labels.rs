@@ -0,0 +1,126 @@
+//
+// Copyright 2024 The Project Oak Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Labels used for domain separation of all key derivations in this crate.
+//!
+//! Every label must be declared here and listed in [`ALL_LABELS`], so that
+//! tests can check that no two derivations share a label. New labels must start
+//! with [`LABEL_PREFIX`].
+
+/// Prefix of all labels, except for the legacy exporter labels that predate
+/// this rule and can't be changed without breaking the wire format.
+#[cfg(all(test, feature = "response-direction"))]
+pub(crate) const LABEL_PREFIX: &[u8] = b"Oak ";
+
+/// HPKE info string of bidirectional sessions.
+pub(crate) const OAK_HPKE_INFO: &[u8] = b"Oak Hybrid Public Key Encryption v1";
+/// HPKE info string of request-only sessions. It binds the direction of the
+/// session into the key schedule, so that both peers have to agree on it.
+pub(crate) const OAK_HPKE_REQUEST_ONLY_INFO: &[u8] =
+    b"Oak Hybrid Public Key Encryption v1 request-only";
+/// HPKE info string of bidirectional sessions that bind the recipient public
+/// key, which is appended to it.
+pub(crate) const OAK_HPKE_RECIPIENT_BOUND_INFO: &[u8] =
+    b"Oak Hybrid Public Key Encryption v1 recipient-bound";
+
+/// HPKE exporter label of the request key.
+pub(crate) const REQUEST_KEY_LABEL: &[u8] = b"request_key";
+/// HPKE exporter label of the response key.
+pub(crate) const RESPONSE_KEY_LABEL: &[u8] = b"response_key";
+/// HPKE exporter label of the request base nonce.
+pub(crate) const REQUEST_BASE_NONCE_LABEL: &[u8] = b"request_base_nonce";
+/// HPKE exporter label of the response base nonce.
+pub(crate) const RESPONSE_BASE_NONCE_LABEL: &[u8] = b"response_base_nonce";
+
+/// HPKE exporter label of the channel binding token.
+pub(crate) const CHANNEL_BINDING_TOKEN_LABEL: &[u8] = b"Oak channel binding token v1";
+
+/// Associated data of session tokens, see
+/// [`ClientEncryptor::seal_stateless`](crate::encryptor::ClientEncryptor::seal_stateless).
+pub(crate) const SESSION_TOKEN_LABEL: &[u8] = b"Oak session token v1";
+
+/// HMAC domain separator of proof-of-work challenges for session setup.
+pub(crate) const SETUP_CHALLENGE_LABEL: &[u8] = b"Oak setup challenge v1";
+
+/// HKDF salt for deriving the continuity secret of a session.
+pub(crate) const CONTINUITY_SECRET_LABEL: &[u8] = b"Oak session continuity secret v1";
+/// HMAC domain separator of continuity tokens.
+pub(crate) const CONTINUITY_TOKEN_LABEL: &[u8] = b"Oak session continuity token v1";
+/// HKDF salt for deriving the id of a resumable transfer.
+pub(crate) const TRANSFER_ID_LABEL: &[u8] = b"Oak transfer id v1";
+/// HKDF salt for deriving the keys of the next generation in a key update.
+pub(crate) const KEY_UPDATE_LABEL: &[u8] = b"Oak key update v1";
+/// Prefix of the AEAD associated data of the messages of a simplex stream,
+/// which binds their position in the stream.
+pub(crate) const SIMPLEX_STREAM_LABEL: &[u8] = b"Oak simplex stream v1";
+/// Associated data of keepalive messages of a simplex stream.
+pub(crate) const KEEPALIVE_LABEL: &[u8] = b"Oak keepalive v1";
+/// Associated data of the encrypted response key in a return path.
+pub(crate) const RETURN_PATH_LABEL: &[u8] = b"Oak return path v1";
+/// Prefix of the associated data of requests with a routing header.
+pub(crate) const ROUTING_HEADER_LABEL: &[u8] = b"Oak routing header v1";
+/// Prefix of the associated data of messages with a message type.
+pub(crate) const MESSAGE_TYPE_LABEL: &[u8] = b"Oak message type v1";
+/// Prefix of the associated data of responses with a continuity token.
+pub(crate) const CONTINUITY_RESPONSE_LABEL: &[u8] = b"Oak continuity response v1";
+/// Prefix of the SHA-256 digest of the associated data in sessions that
+/// authenticate hashed associated data.
+pub(crate) const HASHED_AAD_LABEL: &[u8] = b"Oak hashed associated data v1";
+/// Prefix of the AEAD associated data of request frames, which binds their
+/// position in the stream of frames.
+pub(crate) const REQUEST_FRAME_LABEL: &[u8] = b"Oak request frame v1";
+
+/// HKDF salt for deriving tenant keys from a master secret.
+pub(crate) const TENANT_KEY_DERIVATION_SALT: &[u8] = b"Oak HPKE tenant key derivation v1";
+/// HKDF salt for deriving batches of keys from a seed.
+pub(crate) const BATCH_KEY_DERIVATION_SALT: &[u8] = b"Oak HPKE batch key derivation v1";
+/// HKDF salt for deriving local secrets from a private key, which separates
+/// the purposes of callers from all derivations of this crate.
+pub(crate) const LOCAL_SECRET_DERIVATION_SALT: &[u8] = b"Oak local secret derivation v1";
+
+/// Exporter labels that don't start with [`LABEL_PREFIX`].
+#[cfg(all(test, feature = "response-direction"))]
+pub(crate) const LEGACY_LABELS: &[&[u8]] =
+    &[REQUEST_KEY_LABEL, RESPONSE_KEY_LABEL, REQUEST_BASE_NONCE_LABEL, RESPONSE_BASE_NONCE_LABEL];
+
+/// All labels declared in this module, with their names.
+pub(crate) const ALL_LABELS: &[(&str, &[u8])] = &[
+    ("OAK_HPKE_INFO", OAK_HPKE_INFO),
+    ("OAK_HPKE_REQUEST_ONLY_INFO", OAK_HPKE_REQUEST_ONLY_INFO),
+    ("OAK_HPKE_RECIPIENT_BOUND_INFO", OAK_HPKE_RECIPIENT_BOUND_INFO),
+    ("REQUEST_KEY_LABEL", REQUEST_KEY_LABEL),
+    ("RESPONSE_KEY_LABEL", RESPONSE_KEY_LABEL),
+    ("REQUEST_BASE_NONCE_LABEL", REQUEST_BASE_NONCE_LABEL),
+    ("RESPONSE_BASE_NONCE_LABEL", RESPONSE_BASE_NONCE_LABEL),
+    ("CHANNEL_BINDING_TOKEN_LABEL", CHANNEL_BINDING_TOKEN_LABEL),
+    ("SESSION_TOKEN_LABEL", SESSION_TOKEN_LABEL),
+    ("SETUP_CHALLENGE_LABEL", SETUP_CHALLENGE_LABEL),
+    ("CONTINUITY_SECRET_LABEL", CONTINUITY_SECRET_LABEL),
+    ("CONTINUITY_TOKEN_LABEL", CONTINUITY_TOKEN_LABEL),
+    ("TRANSFER_ID_LABEL", TRANSFER_ID_LABEL),
+    ("KEY_UPDATE_LABEL", KEY_UPDATE_LABEL),
+    ("SIMPLEX_STREAM_LABEL", SIMPLEX_STREAM_LABEL),
+    ("KEEPALIVE_LABEL", KEEPALIVE_LABEL),
+    ("RETURN_PATH_LABEL", RETURN_PATH_LABEL),
+    ("ROUTING_HEADER_LABEL", ROUTING_HEADER_LABEL),
+    ("MESSAGE_TYPE_LABEL", MESSAGE_TYPE_LABEL),
+    ("CONTINUITY_RESPONSE_LABEL", CONTINUITY_RESPONSE_LABEL),
+    ("HASHED_AAD_LABEL", HASHED_AAD_LABEL),
+    ("REQUEST_FRAME_LABEL", REQUEST_FRAME_LABEL),
+    ("TENANT_KEY_DERIVATION_SALT", TENANT_KEY_DERIVATION_SALT),
+    ("BATCH_KEY_DERIVATION_SALT", BATCH_KEY_DERIVATION_SALT),
+    ("LOCAL_SECRET_DERIVATION_SALT", LOCAL_SECRET_DERIVATION_SALT),
+];
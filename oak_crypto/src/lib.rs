@@ -23,6 +23,10 @@ extern crate static_assertions;
 extern crate std;
 
 pub mod proto {
+    // SHA-256 digest of the .proto sources that the generated code was built
+    // from.
+    include!(concat!(env!("OUT_DIR"), "/schema_digest.rs"));
+
     pub mod oak {
         pub mod crypto {
             pub mod v1 {
@@ -33,13 +37,68 @@ pub mod proto {
     }
 }
 
+#[cfg(feature = "compact")]
+pub mod compact;
+#[cfg(feature = "response-direction")]
+pub mod continuity;
+pub mod dos;
 pub mod encryption_key;
 pub mod encryptor;
+pub mod envelope;
+pub mod error;
+#[cfg(all(test, feature = "response-direction"))]
+mod fixtures;
 pub mod hpke;
+#[cfg(feature = "response-direction")]
+pub mod key_update;
+mod labels;
 pub mod noise_handshake;
+#[cfg(feature = "serde")]
+mod proto_serde;
+pub mod protocol_description;
+#[cfg(all(test, feature = "response-direction"))]
+mod protocol_model;
 pub mod signer;
-#[cfg(test)]
+#[cfg(all(test, feature = "response-direction"))]
 mod tests;
+#[cfg(feature = "response-direction")]
+pub mod transfer;
+pub mod util;
 pub mod verifier;
 
+pub use hpke::supported_suites;
+pub use protocol_description::protocol_description;
+
+/// Returns the SHA-256 digest of the .proto sources that the message types in
+/// [`proto`] were generated from.
+pub fn schema_digest() -> [u8; 32] {
+    proto::SCHEMA_DIGEST
+}
+
 pub const EMPTY_ASSOCIATED_DATA: &[u8] = b"";
+
+/// Versions of the protocol that this build speaks. Messages are produced with
+/// the highest version, and messages of any version in the range are accepted.
+/// The version is carried in the version byte of compact envelopes.
+pub const PROTOCOL_VERSION_RANGE: core::ops::RangeInclusive<u8> = 1..=1;
+
+/// Returns whether `version` is in [`PROTOCOL_VERSION_RANGE`].
+pub fn supports_version(version: u8) -> bool {
+    PROTOCOL_VERSION_RANGE.contains(&version)
+}
+
+/// Returns the highest protocol version that this build and a peer that speaks
+/// `peer_versions` have in common. Fails with
+/// [`CryptoError::UnsupportedProtocolVersion`](error::CryptoError::UnsupportedProtocolVersion)
+/// if the ranges don't overlap.
+pub fn negotiate_version(peer_versions: core::ops::RangeInclusive<u8>) -> anyhow::Result<u8> {
+    let highest = (*peer_versions.end()).min(*PROTOCOL_VERSION_RANGE.end());
+    if !peer_versions.contains(&highest) || !supports_version(highest) {
+        return Err(error::CryptoError::UnsupportedProtocolVersion.context(alloc::format!(
+            "peer speaks protocol versions {:?}, supported versions are {:?}",
+            peer_versions,
+            PROTOCOL_VERSION_RANGE
+        )));
+    }
+    Ok(highest)
+}
@@ -0,0 +1,172 @@
+//
+// Copyright 2024 The Project Oak Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Optional [`serde`] support for the encrypted message protos, for services
+//! that use JSON or CBOR transports instead of protobuf.
+//!
+//! Field names and the encoding of bytes fields follow the proto3 JSON mapping:
+//! bytes are serialized as standard base64 strings by human-readable formats
+//! (e.g. JSON), and as byte strings by binary formats (e.g. CBOR).
+//! <https://protobuf.dev/programming-guides/proto3/#json>
+
+use alloc::{string::String, vec::Vec};
+use core::fmt;
+
+use base64::{prelude::BASE64_STANDARD, Engine as _};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::proto::oak::crypto::v1::{AeadEncryptedMessage, EncryptedRequest, EncryptedResponse};
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "AeadEncryptedMessage", rename_all = "camelCase")]
+struct AeadEncryptedMessageDef {
+    #[serde(default, with = "bytes")]
+    ciphertext: Vec<u8>,
+    #[serde(default, with = "bytes")]
+    associated_data: Vec<u8>,
+    #[serde(default, with = "bytes")]
+    nonce: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "EncryptedRequest", rename_all = "camelCase")]
+struct EncryptedRequestDef {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    encrypted_message: Option<AeadEncryptedMessage>,
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "optional_bytes")]
+    serialized_encapsulated_public_key: Option<Vec<u8>>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "EncryptedResponse", rename_all = "camelCase")]
+struct EncryptedResponseDef {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    encrypted_message: Option<AeadEncryptedMessage>,
+}
+
+/// Implements [`Serialize`] and [`Deserialize`] for a generated proto type
+/// using the corresponding remote definition.
+macro_rules! impl_serde {
+    ($type:ty, $definition:ty) => {
+        impl Serialize for $type {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                <$definition>::serialize(self, serializer)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $type {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                <$definition>::deserialize(deserializer)
+            }
+        }
+    };
+}
+
+impl_serde!(AeadEncryptedMessage, AeadEncryptedMessageDef);
+impl_serde!(EncryptedRequest, EncryptedRequestDef);
+impl_serde!(EncryptedResponse, EncryptedResponseDef);
+
+/// Serializes bytes fields as base64 strings in human-readable formats and as
+/// byte strings otherwise.
+mod bytes {
+    use super::*;
+
+    pub(super) fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&BASE64_STANDARD.encode(bytes))
+        } else {
+            serializer.serialize_bytes(bytes)
+        }
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<u8>, D::Error> {
+        if deserializer.is_human_readable() {
+            let encoded = String::deserialize(deserializer)?;
+            BASE64_STANDARD.decode(encoded).map_err(de::Error::custom)
+        } else {
+            deserializer.deserialize_byte_buf(BytesVisitor)
+        }
+    }
+
+    /// Upper bound of the capacity that is preallocated for a sequence of
+    /// bytes, so that a length declared by untrusted input can't exhaust
+    /// memory before the bytes are read. Same bound as serde's
+    /// `size_hint::cautious`.
+    const MAX_PREALLOCATED_BYTES: usize = 4096;
+
+    struct BytesVisitor;
+
+    impl<'de> de::Visitor<'de> for BytesVisitor {
+        type Value = Vec<u8>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a byte string")
+        }
+
+        fn visit_bytes<E: de::Error>(self, value: &[u8]) -> Result<Self::Value, E> {
+            Ok(value.to_vec())
+        }
+
+        fn visit_byte_buf<E: de::Error>(self, value: Vec<u8>) -> Result<Self::Value, E> {
+            Ok(value)
+        }
+
+        fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            let mut bytes =
+                Vec::with_capacity(seq.size_hint().unwrap_or(0).min(MAX_PREALLOCATED_BYTES));
+            while let Some(byte) = seq.next_element()? {
+                bytes.push(byte);
+            }
+            Ok(bytes)
+        }
+    }
+}
+
+/// Same as [`bytes`], for optional bytes fields.
+mod optional_bytes {
+    use super::*;
+
+    struct Bytes<'a>(&'a [u8]);
+
+    impl Serialize for Bytes<'_> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            bytes::serialize(self.0, serializer)
+        }
+    }
+
+    struct BytesBuf(Vec<u8>);
+
+    impl<'de> Deserialize<'de> for BytesBuf {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            bytes::deserialize(deserializer).map(BytesBuf)
+        }
+    }
+
+    pub(super) fn serialize<S: Serializer>(
+        bytes: &Option<Vec<u8>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        bytes.as_deref().map(Bytes).serialize(serializer)
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Vec<u8>>, D::Error> {
+        Ok(Option::<BytesBuf>::deserialize(deserializer)?.map(|bytes| bytes.0))
+    }
+}
@@ -0,0 +1,123 @@
+//
+// Copyright 2024 The Project Oak Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Machine-readable description of the protocol implemented by this crate,
+//! e.g. for generating the protocol specification. The description is built
+//! from the same constants that the implementation uses, so it can't drift from
+//! the code.
+
+use alloc::{string::String, vec::Vec};
+use core::ops::RangeInclusive;
+
+use crate::{
+    dos::{MAX_SETUP_DIFFICULTY, SETUP_CHALLENGE_SIZE_BYTES, SETUP_SOLUTION_SIZE_BYTES},
+    encryptor::{
+        MAX_MESSAGES_PER_SESSION, MAX_ROUTING_HEADER_SIZE_BYTES, MIN_COMBINED_MESSAGE_SIZE_BYTES,
+    },
+    hpke::{
+        supported_suites, SuiteId, SuiteLengths, WireSuiteId, CHANNEL_BINDING_TOKEN_SIZE_BYTES,
+        SUITE_LENGTHS,
+    },
+    labels::ALL_LABELS,
+    util::LENGTH_PREFIX_SIZE_BYTES,
+};
+
+/// Description of the protocol, see [`protocol_description`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ProtocolDescription {
+    /// Supported cipher suites, with the default suite first.
+    pub suites: Vec<SuiteDescription>,
+    /// HPKE info strings, exporter labels, salts and other domain separators.
+    pub labels: Vec<LabelDescription>,
+    pub limits: ProtocolLimits,
+    /// Supported protocol versions, see [`crate::PROTOCOL_VERSION_RANGE`].
+    pub protocol_versions: RangeInclusive<u8>,
+    /// Version byte of the compact envelope, if the `compact` feature is
+    /// enabled.
+    pub compact_format_version: Option<u8>,
+    /// Hex-encoded SHA-256 digest of the protobuf schema of the envelope, see
+    /// [`crate::schema_digest`].
+    pub schema_digest: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct SuiteDescription {
+    pub id: SuiteId,
+    pub wire_id: WireSuiteId,
+    pub lengths: SuiteLengths,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct LabelDescription {
+    /// Name of the constant that holds the label.
+    pub name: &'static str,
+    pub value: &'static str,
+}
+
+/// Sizes and limits of messages and sessions.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ProtocolLimits {
+    pub max_messages_per_session: u64,
+    pub min_combined_message_size_bytes: usize,
+    pub max_routing_header_size_bytes: usize,
+    pub length_prefix_size_bytes: usize,
+    pub channel_binding_token_size_bytes: usize,
+    pub setup_challenge_size_bytes: usize,
+    pub setup_solution_size_bytes: usize,
+    pub max_setup_difficulty: u8,
+}
+
+/// Returns a description of the protocol implemented by this crate.
+pub fn protocol_description() -> ProtocolDescription {
+    ProtocolDescription {
+        suites: supported_suites()
+            .iter()
+            .map(|suite| SuiteDescription {
+                id: *suite,
+                wire_id: WireSuiteId::try_from(*suite).expect("supported suites have wire ids"),
+                lengths: SUITE_LENGTHS,
+            })
+            .collect(),
+        labels: ALL_LABELS
+            .iter()
+            .map(|(name, label)| LabelDescription { name, value: label_str(label) })
+            .collect(),
+        limits: ProtocolLimits {
+            max_messages_per_session: MAX_MESSAGES_PER_SESSION,
+            min_combined_message_size_bytes: MIN_COMBINED_MESSAGE_SIZE_BYTES,
+            max_routing_header_size_bytes: MAX_ROUTING_HEADER_SIZE_BYTES,
+            length_prefix_size_bytes: LENGTH_PREFIX_SIZE_BYTES,
+            channel_binding_token_size_bytes: CHANNEL_BINDING_TOKEN_SIZE_BYTES,
+            setup_challenge_size_bytes: SETUP_CHALLENGE_SIZE_BYTES,
+            setup_solution_size_bytes: SETUP_SOLUTION_SIZE_BYTES,
+            max_setup_difficulty: MAX_SETUP_DIFFICULTY,
+        },
+        protocol_versions: crate::PROTOCOL_VERSION_RANGE,
+        #[cfg(feature = "compact")]
+        compact_format_version: Some(crate::compact::COMPACT_FORMAT_VERSION),
+        #[cfg(not(feature = "compact"))]
+        compact_format_version: None,
+        schema_digest: hex::encode(crate::schema_digest()),
+    }
+}
+
+fn label_str(label: &'static [u8]) -> &'static str {
+    core::str::from_utf8(label).expect("labels are ASCII")
+}
@@ -0,0 +1,387 @@
+//
+// Copyright 2024 The Project Oak Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Model-based tests of the bidirectional protocol.
+//!
+//! Sequences of [`Operation`]s are applied both to a pure reference [`Model`]
+//! of which messages should be accepted or rejected, and to real
+//! [`ClientEncryptor`] and [`ServerEncryptor`] sessions connected by a lossy
+//! network. Every delivered message must have the outcome predicted by the
+//! model. The rules encoded in the model are:
+//!
+//! 1. Only the initial request of a session carries the encapsulated public
+//!    key, so it is the only request a server can open on its own. Later
+//!    requests are rejected.
+//! 2. There is no replay protection: a duplicated initial request is accepted
+//!    again and creates an independent server session.
+//! 3. Corrupted ciphertexts are rejected as authentication failures.
+//! 4. Failures don't consume session state: a rejected message doesn't affect
+//!    the outcome of any later message.
+//! 5. Each server session can respond at most once, and all responses can be
+//!    decrypted by the client, in any order.
+//! 6. Dropped messages don't affect other messages, since nonces are random and
+//!    there are no sequence numbers.
+
+use std::{collections::VecDeque, format, string::String, vec, vec::Vec};
+
+use crate::{
+    encryption_key::{generate_encryption_key_pair, EncryptionKey},
+    encryptor::{ClientEncryptor, ServerEncryptor},
+    error::CryptoError,
+    proto::oak::crypto::v1::{EncryptedRequest, EncryptedResponse},
+};
+
+const TEST_MESSAGE: &[u8] = b"Test message";
+const TEST_ASSOCIATED_DATA: &[u8] = b"Test associated data";
+const TEST_SEQUENCE_COUNT: u64 = 64;
+const TEST_SEQUENCE_LENGTH: usize = 48;
+
+/// Operation applied to the protocol. Operations don't carry any data, so
+/// that any subsequence of a failing sequence is still a valid sequence,
+/// which keeps shrinking trivial.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Operation {
+    /// Client encrypts a request and sends it to the network.
+    ClientEncrypt,
+    /// The oldest pending server session encrypts a response and sends it to
+    /// the network.
+    ServerRespond,
+    /// The network delivers its oldest message.
+    Deliver,
+    /// The network drops its oldest message.
+    Drop,
+    /// The network sends a copy of its oldest message.
+    Duplicate,
+    /// The network flips a bit in the ciphertext of its oldest message.
+    Corrupt,
+}
+
+impl Operation {
+    const ALL: [Operation; 6] = [
+        Operation::ClientEncrypt,
+        Operation::ServerRespond,
+        Operation::Deliver,
+        Operation::Drop,
+        Operation::Duplicate,
+        Operation::Corrupt,
+    ];
+}
+
+/// Observable outcome of delivering a message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Outcome {
+    Accepted,
+    /// Rejected with the given error class, if the error is classified.
+    Rejected(Option<CryptoError>),
+}
+
+impl<T> From<&anyhow::Result<T>> for Outcome {
+    fn from(result: &anyhow::Result<T>) -> Self {
+        match result {
+            Ok(_) => Outcome::Accepted,
+            Err(error) => Outcome::Rejected(error.downcast_ref::<CryptoError>().copied()),
+        }
+    }
+}
+
+/// What the model knows about a message in flight.
+#[derive(Clone, Copy, Debug)]
+struct ModelMessage {
+    is_request: bool,
+    is_initial: bool,
+    is_corrupted: bool,
+}
+
+/// Reference model of the protocol, which only tracks the properties of
+/// messages that determine whether they are accepted.
+#[derive(Default)]
+struct Model {
+    initial_request_sent: bool,
+    pending_server_sessions: usize,
+    network: VecDeque<ModelMessage>,
+}
+
+impl Model {
+    /// Applies `operation` and returns the expected outcome if a message was
+    /// delivered.
+    fn apply(&mut self, operation: Operation) -> Option<Outcome> {
+        match operation {
+            Operation::ClientEncrypt => {
+                self.network.push_back(ModelMessage {
+                    is_request: true,
+                    is_initial: !self.initial_request_sent,
+                    is_corrupted: false,
+                });
+                self.initial_request_sent = true;
+            }
+            Operation::ServerRespond => {
+                if self.pending_server_sessions > 0 {
+                    self.pending_server_sessions -= 1;
+                    self.network.push_back(ModelMessage {
+                        is_request: false,
+                        is_initial: false,
+                        is_corrupted: false,
+                    });
+                }
+            }
+            Operation::Deliver => {
+                let message = self.network.pop_front()?;
+                let outcome = if message.is_request && !message.is_initial {
+                    Outcome::Rejected(None)
+                } else if message.is_corrupted {
+                    Outcome::Rejected(Some(CryptoError::AuthenticationFailure))
+                } else {
+                    Outcome::Accepted
+                };
+                if message.is_request && outcome == Outcome::Accepted {
+                    self.pending_server_sessions += 1;
+                }
+                return Some(outcome);
+            }
+            Operation::Drop => {
+                self.network.pop_front();
+            }
+            Operation::Duplicate => {
+                if let Some(message) = self.network.front() {
+                    self.network.push_back(*message);
+                }
+            }
+            Operation::Corrupt => {
+                if let Some(message) = self.network.front_mut() {
+                    message.is_corrupted = true;
+                }
+            }
+        }
+        None
+    }
+}
+
+#[derive(Clone)]
+enum Message {
+    Request(EncryptedRequest),
+    Response(EncryptedResponse),
+}
+
+/// Real client and server sessions connected by a network that is controlled
+/// by the applied operations.
+struct Driver {
+    encryption_key: EncryptionKey,
+    client_encryptor: ClientEncryptor,
+    pending_server_sessions: VecDeque<ServerEncryptor>,
+    network: VecDeque<Message>,
+    corruptions: usize,
+}
+
+impl Driver {
+    fn new() -> Self {
+        let (encryption_key, public_key) = generate_encryption_key_pair();
+        let client_encryptor =
+            ClientEncryptor::create(&public_key).expect("couldn't create client encryptor");
+        Self {
+            encryption_key,
+            client_encryptor,
+            pending_server_sessions: VecDeque::new(),
+            network: VecDeque::new(),
+            corruptions: 0,
+        }
+    }
+
+    /// Applies `operation` and returns the actual outcome if a message was
+    /// delivered.
+    fn apply(&mut self, operation: Operation) -> Option<Outcome> {
+        match operation {
+            Operation::ClientEncrypt => {
+                let request = self
+                    .client_encryptor
+                    .encrypt(TEST_MESSAGE, TEST_ASSOCIATED_DATA)
+                    .expect("couldn't encrypt request");
+                self.network.push_back(Message::Request(request));
+            }
+            Operation::ServerRespond => {
+                if let Some(server_encryptor) = self.pending_server_sessions.pop_front() {
+                    let response = server_encryptor
+                        .encrypt(TEST_MESSAGE, TEST_ASSOCIATED_DATA)
+                        .expect("couldn't encrypt response");
+                    self.network.push_back(Message::Response(response));
+                }
+            }
+            Operation::Deliver => {
+                let outcome = match self.network.pop_front()? {
+                    Message::Request(request) => {
+                        let result = ServerEncryptor::decrypt(&request, &self.encryption_key);
+                        let outcome = Outcome::from(&result);
+                        if let Ok((server_encryptor, plaintext, _)) = result {
+                            assert_eq!(TEST_MESSAGE, plaintext);
+                            self.pending_server_sessions.push_back(server_encryptor);
+                        }
+                        outcome
+                    }
+                    Message::Response(response) => {
+                        let result = self.client_encryptor.decrypt(&response);
+                        if let Ok((plaintext, _)) = &result {
+                            assert_eq!(TEST_MESSAGE, plaintext);
+                        }
+                        Outcome::from(&result)
+                    }
+                };
+                return Some(outcome);
+            }
+            Operation::Drop => {
+                self.network.pop_front();
+            }
+            Operation::Duplicate => {
+                if let Some(message) = self.network.front() {
+                    self.network.push_back(message.clone());
+                }
+            }
+            Operation::Corrupt => {
+                let encrypted_message = match self.network.front_mut() {
+                    Some(Message::Request(request)) => request.encrypted_message.as_mut(),
+                    Some(Message::Response(response)) => response.encrypted_message.as_mut(),
+                    None => None,
+                };
+                // Flip a different bit each time, so that corrupting a message twice doesn't
+                // restore it.
+                if let Some(encrypted_message) = encrypted_message {
+                    let ciphertext = &mut encrypted_message.ciphertext;
+                    let index = (self.corruptions / 8) % ciphertext.len();
+                    ciphertext[index] ^= 1 << (self.corruptions % 8);
+                    self.corruptions += 1;
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Applies `operations` to both the model and real sessions, and returns a
+/// description of the first mismatch.
+fn check_operations(operations: &[Operation]) -> Result<(), String> {
+    let mut model = Model::default();
+    let mut driver = Driver::new();
+    for (index, operation) in operations.iter().enumerate() {
+        let expected = model.apply(*operation);
+        let actual = driver.apply(*operation);
+        if expected != actual {
+            return Err(format!(
+                "operation {} ({:?}): expected {:?}, got {:?}",
+                index, operation, expected, actual
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Deterministically generates a sequence of operations from `seed` with a
+/// xorshift generator, so that failures are reproducible.
+fn generate_operations(seed: u64, length: usize) -> Vec<Operation> {
+    let mut state = seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1;
+    (0..length)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            Operation::ALL[(state % Operation::ALL.len() as u64) as usize]
+        })
+        .collect()
+}
+
+/// Greedily removes operations from a failing sequence while it keeps failing.
+fn shrink<F: Fn(&[Operation]) -> bool>(operations: &[Operation], fails: F) -> Vec<Operation> {
+    let mut operations = operations.to_vec();
+    let mut index = 0;
+    while index < operations.len() {
+        let mut candidate = operations.clone();
+        candidate.remove(index);
+        if fails(&candidate) {
+            operations = candidate;
+        } else {
+            index += 1;
+        }
+    }
+    operations
+}
+
+#[test]
+fn test_protocol_model_random_sequences() {
+    for seed in 0..TEST_SEQUENCE_COUNT {
+        let operations = generate_operations(seed, TEST_SEQUENCE_LENGTH);
+        if let Err(error) = check_operations(&operations) {
+            let minimal = shrink(&operations, |candidate| check_operations(candidate).is_err());
+            panic!(
+                "seed {}: {}\nminimal failing sequence: {:?}\n{:?}",
+                seed,
+                error,
+                minimal,
+                check_operations(&minimal)
+            );
+        }
+    }
+}
+
+#[test]
+fn test_protocol_model_replayed_initial_request() {
+    let operations = [
+        Operation::ClientEncrypt,
+        Operation::Duplicate,
+        Operation::Deliver,
+        Operation::Deliver,
+        Operation::ServerRespond,
+        Operation::ServerRespond,
+        Operation::Corrupt,
+        Operation::Deliver,
+        Operation::Deliver,
+        Operation::ClientEncrypt,
+        Operation::Deliver,
+    ];
+    let mut model = Model::default();
+    let outcomes: Vec<Outcome> =
+        operations.iter().filter_map(|operation| model.apply(*operation)).collect();
+    assert_eq!(
+        vec![
+            Outcome::Accepted,
+            Outcome::Accepted,
+            Outcome::Rejected(Some(CryptoError::AuthenticationFailure)),
+            Outcome::Accepted,
+            Outcome::Rejected(None),
+        ],
+        outcomes
+    );
+    assert_eq!(Ok(()), check_operations(&operations));
+}
+
+#[test]
+fn test_protocol_model_shrink() {
+    // A sequence fails if a corrupted message is delivered.
+    let fails = |operations: &[Operation]| {
+        let mut model = Model::default();
+        operations.iter().any(|operation| {
+            matches!(
+                model.apply(*operation),
+                Some(Outcome::Rejected(Some(CryptoError::AuthenticationFailure)))
+            )
+        })
+    };
+    let operations = generate_operations(0, TEST_SEQUENCE_LENGTH);
+    let operations =
+        [&[Operation::ClientEncrypt, Operation::Corrupt, Operation::Deliver], &operations[..]]
+            .concat();
+    assert!(fails(&operations));
+    assert_eq!(
+        vec![Operation::ClientEncrypt, Operation::Corrupt, Operation::Deliver],
+        shrink(&operations, fails)
+    );
+}
@@ -14,16 +14,66 @@
 // limitations under the License.
 //
 
+use sha2::{Digest, Sha256};
+
 use crate::{
-    encryption_key::generate_encryption_key_pair,
-    encryptor::{ClientEncryptor, ServerEncryptor},
+    continuity::{ContinuitySecret, ContinuityToken, CONTINUITY_TOKEN_SIZE_BYTES},
+    dos::{
+        SetupChallenge, SetupGate, SetupSolution, MAX_SETUP_DIFFICULTY, SETUP_CHALLENGE_SIZE_BYTES,
+    },
+    encryption_key::{
+        derive_encryption_key_pairs_from_seed, generate_encryption_key_pair,
+        generate_encryption_key_pairs, ClockSkewPolicy, EncryptionKey, EncryptionKeyHandle,
+        KeyPolicy, RecipientKeyDeriver, MAX_LOCAL_SECRET_SIZE_BYTES, PRIVATE_KEY_SIZE_BYTES,
+    },
+    encryptor::{
+        read_routing_header, simplex_associated_data, verify_associated_data, AadPolicy,
+        ClientEncryptor, HashedAad, RequestOnlyClientEncryptor, RequestOnlyServerEncryptor,
+        ResponseDecryptor, ServerEncryptor, SimplexPusher, SimplexReceiver,
+        MAX_MESSAGES_PER_SESSION, MAX_ROUTING_HEADER_SIZE_BYTES, MESSAGE_TYPE_SIZE_BYTES,
+        MIN_COMBINED_MESSAGE_SIZE_BYTES, RETURN_PATH_SIZE_BYTES,
+    },
+    envelope::{EncryptedMessage, RequestEnvelope, ResponseEnvelope},
+    error::CryptoError,
     hpke::{
-        aead::{AEAD_ALGORITHM_KEY_SIZE_BYTES, AEAD_NONCE_SIZE_BYTES},
-        generate_kem_key_pair, generate_random_nonce, setup_base_recipient, setup_base_sender,
-        Serializable,
+        aead::{
+            AeadKey, AEAD_ALGORITHM_KEY_SIZE_BYTES, AEAD_NONCE_SIZE_BYTES, AEAD_TAG_SIZE_BYTES,
+        },
+        derive_key_pair, export_aead_key, generate_kem_key_pair, generate_random_nonce,
+        recipient_bound_info, setup_base_recipient, setup_base_sender,
+        setup_base_sender_shared_keys, suite_id_bytes, AeadId, HpkeMode, PublicKey,
+        RecipientContext, RequestOnlyRecipientContext, RequestOnlySenderContext, SenderContext,
+        Serializable, SuiteId, SuiteLengths, WireSuiteId, DEFAULT_SUITE, MAX_PSK_ID_SIZE_BYTES,
+        MAX_PSK_SIZE_BYTES, MIN_PSK_SIZE_BYTES, SUITE_LENGTHS,
+    },
+    key_update::{KeyPhase, KeyUpdateSchedule},
+    labels::{MESSAGE_TYPE_LABEL, ROUTING_HEADER_LABEL},
+    proto::oak::crypto::v1::{AeadEncryptedMessage, EncryptedRequest, EncryptedResponse},
+    transfer::TransferManifest,
+    util::{
+        entropy_health_check, read_encrypted_record, request_frame_associated_data,
+        require_entropy_check,
+        wire::{self, WireReader, WireWriter},
+        write_encrypted_record, LengthPrefixedReader, LengthPrefixedWriter, RequestFrameDecoder,
+        RequestFrameEncoder, DEFAULT_ENTROPY_CHECK_SAMPLES, LENGTH_PREFIX_SIZE_BYTES,
     },
 };
 
+// Guard against accidentally increasing the memory footprint of a session,
+// which matters on embedded targets. Sizes are for 64-bit targets.
+static_assertions::const_assert!(core::mem::size_of::<SenderContext>() <= 64);
+static_assertions::const_assert!(core::mem::size_of::<RecipientContext>() <= 64);
+static_assertions::const_assert!(core::mem::size_of::<ClientEncryptor>() <= 120);
+static_assertions::const_assert!(core::mem::size_of::<ServerEncryptor>() <= 64);
+static_assertions::const_assert!(core::mem::size_of::<ClientEncryptor<HashedAad>>() <= 120);
+static_assertions::const_assert!(core::mem::size_of::<ServerEncryptor<HashedAad>>() <= 64);
+static_assertions::const_assert!(core::mem::size_of::<RequestOnlySenderContext>() <= 32);
+static_assertions::const_assert!(core::mem::size_of::<RequestOnlyRecipientContext>() <= 32);
+static_assertions::const_assert!(core::mem::size_of::<RequestOnlyClientEncryptor>() <= 64);
+static_assertions::const_assert!(core::mem::size_of::<RequestOnlyServerEncryptor>() <= 32);
+static_assertions::const_assert!(core::mem::size_of::<SimplexPusher>() <= 80);
+static_assertions::const_assert!(core::mem::size_of::<SimplexReceiver>() <= 80);
+
 /// Test AES-GCM key that is only used in tests.
 /// Was generated by calling [`Hpke::setup_base_sender`].
 const TEST_AEAD_KEY: [u8; AEAD_ALGORITHM_KEY_SIZE_BYTES] = [
@@ -42,7 +92,7 @@ const TEST_RESPONSE_ASSOCIATED_DATA: &[u8] = b"Test response associated data";
 #[test]
 fn test_aead() {
     let encrypted_message = crate::hpke::aead::encrypt(
-        &TEST_AEAD_KEY,
+        &AeadKey::new(TEST_AEAD_KEY),
         &TEST_NONCE,
         TEST_REQUEST_MESSAGE,
         TEST_REQUEST_ASSOCIATED_DATA,
@@ -51,7 +101,7 @@ fn test_aead() {
     // Check that the message was encrypted.
     assert_ne!(TEST_REQUEST_MESSAGE, encrypted_message);
     let decrypted_message = crate::hpke::aead::decrypt(
-        &TEST_AEAD_KEY,
+        &AeadKey::new(TEST_AEAD_KEY),
         &TEST_NONCE,
         &encrypted_message,
         TEST_REQUEST_ASSOCIATED_DATA,
@@ -61,6 +111,289 @@ fn test_aead() {
     assert_eq!(TEST_REQUEST_MESSAGE, decrypted_message);
 }
 
+#[test]
+fn test_aead_ciphertext_size() {
+    // An empty plaintext sealed with a suite using 8-byte tags (e.g. AES-CCM-8)
+    // is shorter than the tag of the configured suite.
+    let truncated_tag_ciphertext = [0u8; 8];
+    let result = crate::hpke::aead::decrypt(
+        &AeadKey::new(TEST_AEAD_KEY),
+        &TEST_NONCE,
+        &truncated_tag_ciphertext,
+        TEST_REQUEST_ASSOCIATED_DATA,
+    );
+    assert!(
+        result.is_err_and(|error| std::format!("{}", error).contains("invalid ciphertext length"))
+    );
+
+    let mut buffer = std::vec![];
+    let result = crate::hpke::aead::decrypt_into(
+        &AeadKey::new(TEST_AEAD_KEY),
+        &TEST_NONCE,
+        &truncated_tag_ciphertext,
+        TEST_REQUEST_ASSOCIATED_DATA,
+        &mut buffer,
+    );
+    assert!(
+        result.is_err_and(|error| std::format!("{}", error).contains("invalid ciphertext length"))
+    );
+
+    // A ciphertext of exactly the tag size is a valid empty message.
+    let encrypted_message = crate::hpke::aead::encrypt(
+        &AeadKey::new(TEST_AEAD_KEY),
+        &TEST_NONCE,
+        &[],
+        TEST_REQUEST_ASSOCIATED_DATA,
+    )
+    .expect("couldn't encrypt test message");
+    assert_eq!(AEAD_TAG_SIZE_BYTES, encrypted_message.len());
+    let decrypted_message = crate::hpke::aead::decrypt(
+        &AeadKey::new(TEST_AEAD_KEY),
+        &TEST_NONCE,
+        &encrypted_message,
+        TEST_REQUEST_ASSOCIATED_DATA,
+    )
+    .expect("couldn't decrypt test message");
+    assert!(decrypted_message.is_empty());
+}
+
+#[test]
+fn test_empty_plaintext_and_associated_data() {
+    let (encryption_key, public_key) = generate_encryption_key_pair();
+    let mut client_encryptor =
+        ClientEncryptor::create(&public_key).expect("couldn't create client encryptor");
+    let encrypted_request = client_encryptor.encrypt(&[], &[]).expect("couldn't encrypt request");
+    let encrypted_message = encrypted_request.encrypted_message.as_ref().unwrap();
+    // The smallest message only consists of the tag.
+    assert_eq!(AEAD_TAG_SIZE_BYTES, encrypted_message.ciphertext.len());
+    assert!(encrypted_message.associated_data.is_empty());
+
+    // Tampering with any byte of the tag is detected.
+    for index in 0..AEAD_TAG_SIZE_BYTES {
+        let mut tampered_request = encrypted_request.clone();
+        tampered_request.encrypted_message.as_mut().unwrap().ciphertext[index] ^= 1;
+        let result = ServerEncryptor::decrypt(&tampered_request, &encryption_key);
+        assert_eq!(CryptoError::AuthenticationFailure, crypto_error(result));
+    }
+
+    let (server_encryptor, request, request_associated_data) =
+        ServerEncryptor::decrypt(&encrypted_request, &encryption_key)
+            .expect("couldn't decrypt request");
+    assert!(request.is_empty());
+    assert!(request_associated_data.is_empty());
+
+    let encrypted_response = server_encryptor.encrypt(&[], &[]).expect("couldn't encrypt response");
+    assert_eq!(
+        AEAD_TAG_SIZE_BYTES,
+        encrypted_response.encrypted_message.as_ref().unwrap().ciphertext.len()
+    );
+    let mut tampered_response = encrypted_response.clone();
+    tampered_response.encrypted_message.as_mut().unwrap().ciphertext[0] ^= 1;
+    let result = client_encryptor.decrypt(&tampered_response);
+    assert_eq!(CryptoError::AuthenticationFailure, crypto_error(result));
+    let (response, response_associated_data) =
+        client_encryptor.decrypt(&encrypted_response).expect("couldn't decrypt response");
+    assert!(response.is_empty());
+    assert!(response_associated_data.is_empty());
+}
+
+#[test]
+fn test_aead_key_debug_is_redacted() {
+    let debug_output = std::format!("{:?}", AeadKey::new(TEST_AEAD_KEY));
+    assert_eq!("AeadKey(<redacted>)", debug_output);
+}
+
+#[test]
+fn test_aead_key_is_zeroized_on_drop() {
+    let mut key = core::mem::MaybeUninit::new(AeadKey::new(TEST_AEAD_KEY));
+    // SAFETY: `key` is initialized and is not used as an `AeadKey` after being
+    // dropped. `AeadKey` is `repr(transparent)`, so its memory can be read as
+    // the underlying byte array.
+    let key_bytes = unsafe {
+        core::ptr::drop_in_place(key.as_mut_ptr());
+        *(key.as_ptr() as *const [u8; AEAD_ALGORITHM_KEY_SIZE_BYTES])
+    };
+    assert_eq!([0u8; AEAD_ALGORITHM_KEY_SIZE_BYTES], key_bytes);
+}
+
+#[test]
+fn test_export_aead_key() {
+    let key = export_aead_key(
+        |_, key| {
+            key.copy_from_slice(&TEST_AEAD_KEY);
+            Ok(())
+        },
+        b"test_key",
+    )
+    .expect("couldn't export key");
+    assert_eq!(&TEST_AEAD_KEY, key.as_bytes());
+
+    // Check that a key derivation producing an all-zero key is rejected.
+    let result = export_aead_key(
+        |_, key| {
+            key.fill(0);
+            Ok(())
+        },
+        b"test_key",
+    );
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_suite_id() {
+    // KEM: DHKEM(X25519, HKDF-SHA256), KDF: HKDF-SHA256, AEAD: AES-256-GCM.
+    // <https://www.rfc-editor.org/rfc/rfc9180.html#name-algorithm-identifiers>
+    assert_eq!(b"HPKE\x00\x20\x00\x01\x00\x02".to_vec(), suite_id_bytes());
+}
+
+#[test]
+fn test_schema_digest() {
+    let schema_digest: [u8; 32] =
+        Sha256::digest(include_bytes!("../../proto/crypto/crypto.proto")).into();
+    assert_eq!(
+        schema_digest,
+        crate::schema_digest(),
+        "generated proto code is stale, rebuild it from proto/crypto/crypto.proto"
+    );
+}
+
+#[test]
+fn test_protocol_version_range() {
+    assert_eq!(1..=1, crate::PROTOCOL_VERSION_RANGE);
+    assert!(crate::supports_version(1));
+    assert!(!crate::supports_version(0));
+    assert!(!crate::supports_version(2));
+
+    // Peers pick the highest version they have in common.
+    assert_eq!(1, crate::negotiate_version(1..=1).expect("couldn't negotiate version"));
+    assert_eq!(1, crate::negotiate_version(0..=5).expect("couldn't negotiate version"));
+    for peer_versions in [2..=5, 0..=0, core::ops::RangeInclusive::new(1, 0)] {
+        assert_eq!(
+            CryptoError::UnsupportedProtocolVersion,
+            crypto_error(crate::negotiate_version(peer_versions))
+        );
+    }
+    assert_eq!(crate::PROTOCOL_VERSION_RANGE, crate::protocol_description().protocol_versions);
+}
+
+#[test]
+fn test_protocol_description() {
+    let description = crate::protocol_description();
+    let suite_ids: std::vec::Vec<SuiteId> =
+        description.suites.iter().map(|suite| suite.id).collect();
+    assert_eq!(crate::supported_suites(), suite_ids);
+    assert!(description.labels.iter().any(|label| label.name == "OAK_HPKE_INFO"
+        && label.value.as_bytes() == crate::labels::OAK_HPKE_INFO));
+    assert_eq!(MAX_MESSAGES_PER_SESSION, description.limits.max_messages_per_session);
+}
+
+// Updating the protocol requires updating the snapshot in the same change.
+#[cfg(all(feature = "serde", feature = "compact"))]
+#[test]
+fn test_protocol_description_snapshot() {
+    let description = serde_json::to_string_pretty(&crate::protocol_description())
+        .expect("couldn't serialize protocol description");
+    assert_eq!(
+        include_str!("../testdata/protocol_description.json").trim_end(),
+        description,
+        "protocol description changed, update testdata/protocol_description.json"
+    );
+}
+
+#[test]
+fn test_supported_suites() {
+    let suites = crate::supported_suites();
+    assert!(!suites.is_empty());
+    assert_eq!(DEFAULT_SUITE, suites[0]);
+    assert_eq!(SuiteId { kem_id: 0x0020, kdf_id: 0x0001, aead: AeadId::Aes256Gcm }, DEFAULT_SUITE);
+}
+
+#[test]
+fn test_wire_suite_id() {
+    // Every supported suite has a wire id that converts back to the suite.
+    for suite in crate::supported_suites() {
+        let wire_id = WireSuiteId::try_from(*suite).expect("supported suite has no wire id");
+        assert_eq!(*suite, SuiteId::from(wire_id));
+        assert_eq!(wire_id, WireSuiteId::try_from(u16::from(wire_id)).unwrap());
+    }
+    assert_eq!(0x0001, u16::from(WireSuiteId::try_from(DEFAULT_SUITE).unwrap()));
+
+    // All other values are rejected.
+    let accepted = (0..=u16::MAX).filter(|value| WireSuiteId::try_from(*value).is_ok()).count();
+    assert_eq!(crate::supported_suites().len(), accepted);
+    assert_eq!(CryptoError::MalformedInput, crypto_error(WireSuiteId::try_from(0x0000)));
+    assert_eq!(CryptoError::MalformedInput, crypto_error(WireSuiteId::try_from(0x0002)));
+    assert_eq!(CryptoError::MalformedInput, crypto_error(WireSuiteId::try_from(0x0003)));
+    assert_eq!(CryptoError::MalformedInput, crypto_error(WireSuiteId::try_from(u16::MAX)));
+
+    let unsupported_suite = SuiteId { kem_id: 0x0010, ..DEFAULT_SUITE };
+    assert_eq!(CryptoError::MalformedInput, crypto_error(WireSuiteId::try_from(unsupported_suite)));
+}
+
+#[test]
+fn test_labels() {
+    use crate::labels::{ALL_LABELS, LABEL_PREFIX, LEGACY_LABELS};
+
+    // Every label constant declared in the module (other than `LABEL_PREFIX`)
+    // must be listed in `ALL_LABELS`.
+    let declared_labels = include_str!("labels.rs").matches(": &[u8] =").count();
+    assert_eq!(declared_labels - 1, ALL_LABELS.len(), "labels missing from ALL_LABELS");
+
+    let labels: std::vec::Vec<&[u8]> = ALL_LABELS.iter().map(|(_, label)| *label).collect();
+    for (index, label) in labels.iter().enumerate() {
+        assert!(
+            !labels[index + 1..].contains(label),
+            "duplicate label {:?}",
+            std::string::String::from_utf8_lossy(label)
+        );
+        assert!(
+            label.starts_with(LABEL_PREFIX) || LEGACY_LABELS.contains(label),
+            "label {:?} doesn't start with the common prefix",
+            std::string::String::from_utf8_lossy(label)
+        );
+    }
+}
+
+#[test]
+fn test_derive_key_pair() {
+    // RFC9180 test vector for DHKEM(X25519, HKDF-SHA256), HKDF-SHA256, AES-256-GCM
+    // in base mode, as published in the CFRG test vectors (commit 5f503c5).
+    let ikm = hex::decode("dac33b0e9db1b59dbbea58d59a14e7b5896e9bdf98fad6891e99d1686492b9ee")
+        .expect("couldn't decode ikm");
+    let (private_key, public_key) = derive_key_pair(&ikm);
+    assert_eq!(
+        "497b4502664cfea5d5af0b39934dac72242a74f8480451e1aee7d6a53320333d",
+        hex::encode(private_key.to_bytes())
+    );
+    assert_eq!(
+        "430f4b9859665145a6b1ba274024487bd66f03a2dd577d7753c68d7d7d00c00c",
+        hex::encode(public_key.to_bytes())
+    );
+}
+
+#[test]
+fn test_suite_lengths() {
+    // <https://www.rfc-editor.org/rfc/rfc9180.html#name-algorithm-identifiers>
+    assert_eq!(
+        SuiteLengths { nk: 32, nn: 12, nt: 16, nh: 32, npk: 32, nenc: 32, nsecret: 32 },
+        SUITE_LENGTHS
+    );
+
+    // Check that the sizes agree with the values produced by the implementation.
+    let (_, recipient_public_key) = generate_kem_key_pair();
+    assert_eq!(SUITE_LENGTHS.npk, PublicKey::size());
+    assert_eq!(SUITE_LENGTHS.npk, recipient_public_key.to_bytes().len());
+    let (serialized_encapsulated_public_key, sender_context) =
+        setup_base_sender(&recipient_public_key.to_bytes(), TEST_HPKE_INFO)
+            .expect("couldn't setup base sender");
+    assert_eq!(SUITE_LENGTHS.nenc, serialized_encapsulated_public_key.len());
+    assert_eq!(SUITE_LENGTHS.nn, generate_random_nonce().len());
+    let ciphertext = sender_context
+        .seal(&generate_random_nonce(), TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("sender context couldn't seal request");
+    assert_eq!(SUITE_LENGTHS.nt, ciphertext.len() - TEST_REQUEST_MESSAGE.len());
+}
+
 #[test]
 fn test_hpke() {
     let (recipient_private_key, recipient_public_key) = generate_kem_key_pair();
@@ -98,6 +431,184 @@ fn test_hpke() {
     assert_eq!(TEST_RESPONSE_MESSAGE, decrypted_response);
 }
 
+#[test]
+fn test_hpke_derived_keys() {
+    let (recipient_private_key, recipient_public_key) = generate_kem_key_pair();
+    let (serialized_encapsulated_public_key, sender_context) =
+        setup_base_sender(&recipient_public_key.to_bytes(), TEST_HPKE_INFO)
+            .expect("couldn't setup base sender");
+    let recipient_context = setup_base_recipient(
+        &serialized_encapsulated_public_key,
+        &recipient_private_key,
+        TEST_HPKE_INFO,
+    )
+    .expect("couldn't setup base recipient");
+    assert!(sender_context.has_same_keys_as(&recipient_context));
+
+    // Keys diverge if the key schedule inputs differ.
+    let recipient_context = setup_base_recipient(
+        &serialized_encapsulated_public_key,
+        &recipient_private_key,
+        b"Other HPKE info",
+    )
+    .expect("couldn't setup base recipient");
+    assert!(!sender_context.has_same_keys_as(&recipient_context));
+}
+
+#[test]
+fn test_shared_keys() {
+    let (encryption_key, encryption_public_key) = generate_encryption_key_pair();
+    let (serialized_encapsulated_public_key, sender_shared_keys) =
+        setup_base_sender_shared_keys(&encryption_public_key)
+            .expect("couldn't setup sender shared keys");
+    let recipient_shared_keys = encryption_key
+        .derive_shared_keys(&serialized_encapsulated_public_key)
+        .expect("couldn't derive recipient shared keys");
+
+    assert_eq!(sender_shared_keys.request_key(), recipient_shared_keys.request_key());
+    assert_eq!(sender_shared_keys.response_key(), recipient_shared_keys.response_key());
+    assert_eq!(sender_shared_keys.request_base_nonce(), recipient_shared_keys.request_base_nonce());
+    assert_eq!(
+        sender_shared_keys.response_base_nonce(),
+        recipient_shared_keys.response_base_nonce()
+    );
+    assert_eq!(AEAD_ALGORITHM_KEY_SIZE_BYTES, sender_shared_keys.request_key().len());
+    assert_eq!(AEAD_NONCE_SIZE_BYTES, sender_shared_keys.request_base_nonce().len());
+    assert_ne!(sender_shared_keys.request_key(), sender_shared_keys.response_key());
+    assert_ne!(sender_shared_keys.request_base_nonce(), sender_shared_keys.response_base_nonce());
+}
+
+#[test]
+fn test_key_update() {
+    let (encryption_key, encryption_public_key) = generate_encryption_key_pair();
+    let (serialized_encapsulated_public_key, client_shared_keys) =
+        setup_base_sender_shared_keys(&encryption_public_key)
+            .expect("couldn't setup sender shared keys");
+    let server_shared_keys = encryption_key
+        .derive_shared_keys(&serialized_encapsulated_public_key)
+        .expect("couldn't derive recipient shared keys");
+    let mut client_schedule =
+        KeyUpdateSchedule::for_client(&client_shared_keys).expect("couldn't create schedule");
+    let mut server_schedule =
+        KeyUpdateSchedule::for_server(&server_shared_keys).expect("couldn't create schedule");
+    assert_eq!(KeyPhase::new(false), client_schedule.phase());
+    assert_eq!(client_shared_keys.request_key(), client_schedule.current_keys().request_key());
+    // Nonces keep advancing across key updates.
+    let mut message_count = 0u64;
+    let mut next_nonce = || {
+        message_count += 1;
+        let mut nonce = [0u8; AEAD_NONCE_SIZE_BYTES];
+        nonce[AEAD_NONCE_SIZE_BYTES - 8..].copy_from_slice(&message_count.to_be_bytes());
+        nonce
+    };
+
+    let mut previous_request_key = client_schedule.current_keys().request_key().to_vec();
+    for generation in 1..=4u64 {
+        // The client initiates the update, and the server follows when it opens
+        // the first request in the new phase.
+        let (phase, keys) = client_schedule.key_update().expect("couldn't update keys");
+        assert_eq!(KeyPhase::new(generation % 2 == 1), phase);
+        assert_ne!(previous_request_key, keys.request_key());
+        previous_request_key = keys.request_key().to_vec();
+        // Another update has to wait for the server to follow.
+        assert!(client_schedule.key_update().is_err());
+
+        let nonce = next_nonce();
+        let (request_phase, request) = client_schedule
+            .seal(&nonce, TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+            .expect("couldn't seal request");
+        assert_eq!(phase, request_phase);
+        // A request that was tampered with doesn't make the server follow.
+        let mut tampered_request = request.clone();
+        tampered_request[0] ^= 1;
+        let result = server_schedule.open(
+            request_phase,
+            &nonce,
+            &tampered_request,
+            TEST_REQUEST_ASSOCIATED_DATA,
+        );
+        assert_eq!(CryptoError::AuthenticationFailure, crypto_error(result));
+        assert_ne!(phase, server_schedule.phase());
+        let decrypted_request = server_schedule
+            .open(request_phase, &nonce, &request, TEST_REQUEST_ASSOCIATED_DATA)
+            .expect("couldn't open request");
+        assert_eq!(TEST_REQUEST_MESSAGE, decrypted_request);
+        assert_eq!(phase, server_schedule.phase());
+        assert_eq!(
+            client_schedule.current_keys().response_key(),
+            server_schedule.current_keys().response_key()
+        );
+
+        // The response in the new phase confirms the update.
+        let nonce = next_nonce();
+        let (response_phase, response) = server_schedule
+            .seal(&nonce, TEST_RESPONSE_MESSAGE, TEST_RESPONSE_ASSOCIATED_DATA)
+            .expect("couldn't seal response");
+        assert_eq!(phase, response_phase);
+        let decrypted_response = client_schedule
+            .open(response_phase, &nonce, &response, TEST_RESPONSE_ASSOCIATED_DATA)
+            .expect("couldn't open response");
+        assert_eq!(TEST_RESPONSE_MESSAGE, decrypted_response);
+    }
+
+    // Delayed messages of the previous phase can still be opened, and don't
+    // make the server update its keys again.
+    let nonce = next_nonce();
+    let (delayed_phase, delayed_request) = client_schedule
+        .seal(&nonce, TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("couldn't seal request");
+    server_schedule.key_update().expect("couldn't update keys");
+    let decrypted_request = server_schedule
+        .open(delayed_phase, &nonce, &delayed_request, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("couldn't open delayed request");
+    assert_eq!(TEST_REQUEST_MESSAGE, decrypted_request);
+    assert_ne!(delayed_phase, server_schedule.phase());
+}
+
+#[test]
+fn test_can_decap() {
+    let (encryption_key, encryption_public_key) = generate_encryption_key_pair();
+    let (serialized_encapsulated_public_key, _) =
+        setup_base_sender_shared_keys(&encryption_public_key)
+            .expect("couldn't setup sender shared keys");
+    assert!(encryption_key.can_decap(&serialized_encapsulated_public_key));
+
+    assert!(!encryption_key.can_decap(&serialized_encapsulated_public_key[1..]));
+    assert!(!encryption_key.can_decap(&[]));
+    // Low-order point.
+    assert!(!encryption_key.can_decap(&[0; 32]));
+
+    // Keys whose policy doesn't allow setting up sessions are rejected.
+    let encryption_key = encryption_key
+        .with_policy(KeyPolicy { not_after: Some(0), allowed_modes: std::vec![HpkeMode::Base] });
+    assert!(!encryption_key.can_decap(&serialized_encapsulated_public_key));
+}
+
+#[test]
+fn test_channel_binding_token() {
+    let (encryption_key, encryption_public_key) = generate_encryption_key_pair();
+    let (serialized_encapsulated_public_key, sender_shared_keys) =
+        setup_base_sender_shared_keys(&encryption_public_key)
+            .expect("couldn't setup sender shared keys");
+    let recipient_shared_keys = encryption_key
+        .derive_shared_keys(&serialized_encapsulated_public_key)
+        .expect("couldn't derive recipient shared keys");
+    assert_eq!(
+        sender_shared_keys.channel_binding_token(),
+        recipient_shared_keys.channel_binding_token()
+    );
+
+    // Test that the token doesn't reuse the session keys.
+    let token = sender_shared_keys.channel_binding_token();
+    assert_ne!(&token[..], sender_shared_keys.request_key());
+    assert_ne!(&token[..], sender_shared_keys.response_key());
+
+    // Test that another session gets a different token.
+    let (_, other_shared_keys) = setup_base_sender_shared_keys(&encryption_public_key)
+        .expect("couldn't setup sender shared keys");
+    assert_ne!(token, other_shared_keys.channel_binding_token());
+}
+
 #[test]
 fn test_encryptor() {
     let (encryption_key, encryption_public_key) = generate_encryption_key_pair();
@@ -173,27 +684,3096 @@ async fn test_async_encryptor() {
     assert_eq!(TEST_RESPONSE_ASSOCIATED_DATA, response_associated_data);
 }
 
-const TEST_SIGNATURE_MESSAGE_ONE: &[u8] = b"Dogs are the best";
-const TEST_SIGNATURE_MESSAGE_TWO: &[u8] = b"Cats are even better";
+#[test]
+fn test_messages_remaining() {
+    let (_, public_key) = generate_encryption_key_pair();
+    let mut client_encryptor =
+        ClientEncryptor::create(&public_key).expect("couldn't create client encryptor");
+    assert_eq!(MAX_MESSAGES_PER_SESSION, client_encryptor.messages_remaining());
 
-use crate::{signer::Signer, verifier::Verifier};
+    client_encryptor
+        .encrypt_combined(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("couldn't encrypt request");
+    assert_eq!(MAX_MESSAGES_PER_SESSION - 1, client_encryptor.messages_remaining());
+    client_encryptor
+        .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("couldn't encrypt request");
+    assert_eq!(MAX_MESSAGES_PER_SESSION - 2, client_encryptor.messages_remaining());
+
+    // Check that the session refuses to encrypt once the limit is reached.
+    client_encryptor.set_encrypted_requests(MAX_MESSAGES_PER_SESSION - 1);
+    client_encryptor
+        .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("couldn't encrypt request");
+    assert_eq!(0, client_encryptor.messages_remaining());
+    assert!(client_encryptor.encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA).is_err());
+}
 
 #[test]
-fn test_signer_and_verifier() {
-    let signing_key_one = p256::ecdsa::SigningKey::random(&mut rand_core::OsRng);
+fn test_request_only_session() {
+    let (encryption_key, public_key) = generate_encryption_key_pair();
+    let mut client_encryptor =
+        RequestOnlyClientEncryptor::create(&public_key).expect("couldn't create client encryptor");
+    let initial_request = client_encryptor
+        .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("couldn't encrypt request");
+    let next_request = client_encryptor
+        .encrypt(TEST_RESPONSE_MESSAGE, TEST_RESPONSE_ASSOCIATED_DATA)
+        .expect("couldn't encrypt request");
+    assert_eq!(MAX_MESSAGES_PER_SESSION - 2, client_encryptor.messages_remaining());
 
-    let signature = signing_key_one.sign(TEST_SIGNATURE_MESSAGE_ONE);
+    let (server_encryptor, decrypted_request, associated_data) =
+        RequestOnlyServerEncryptor::decrypt(&initial_request, &encryption_key)
+            .expect("couldn't decrypt request");
+    assert_eq!(TEST_REQUEST_MESSAGE, decrypted_request);
+    assert_eq!(TEST_REQUEST_ASSOCIATED_DATA, associated_data);
+    let (decrypted_request, associated_data) =
+        server_encryptor.decrypt_next(&next_request).expect("couldn't decrypt request");
+    assert_eq!(TEST_RESPONSE_MESSAGE, decrypted_request);
+    assert_eq!(TEST_RESPONSE_ASSOCIATED_DATA, associated_data);
 
-    let verifying_key_one = p256::ecdsa::VerifyingKey::from(signing_key_one);
+    // Request-only sessions store half of the session keys.
+    assert_eq!(
+        core::mem::size_of::<SenderContext>(),
+        2 * core::mem::size_of::<RequestOnlySenderContext>()
+    );
+    assert_eq!(
+        core::mem::size_of::<RecipientContext>(),
+        2 * core::mem::size_of::<RequestOnlyRecipientContext>()
+    );
+}
 
-    assert!(verifying_key_one.verify(TEST_SIGNATURE_MESSAGE_ONE, &signature).is_ok());
+#[test]
+fn test_request_only_session_direction_mismatch() {
+    let (encryption_key, public_key) = generate_encryption_key_pair();
 
-    assert!(verifying_key_one.verify(TEST_SIGNATURE_MESSAGE_TWO, &signature).is_err());
+    let mut request_only_client_encryptor =
+        RequestOnlyClientEncryptor::create(&public_key).expect("couldn't create client encryptor");
+    let encrypted_request = request_only_client_encryptor
+        .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("couldn't encrypt request");
+    assert!(ServerEncryptor::decrypt(&encrypted_request, &encryption_key).is_err());
 
-    let verifying_key_two = {
-        let signing_key_two = p256::ecdsa::SigningKey::random(&mut rand_core::OsRng);
-        p256::ecdsa::VerifyingKey::from(signing_key_two)
-    };
+    let mut client_encryptor =
+        ClientEncryptor::create(&public_key).expect("couldn't create client encryptor");
+    let encrypted_request = client_encryptor
+        .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("couldn't encrypt request");
+    assert!(RequestOnlyServerEncryptor::decrypt(&encrypted_request, &encryption_key).is_err());
+}
 
-    assert!(verifying_key_two.verify(TEST_SIGNATURE_MESSAGE_TWO, &signature).is_err());
+#[test]
+fn test_client_encryptor_from_external_encapsulation() {
+    use hpke::{Deserializable, Kem as _};
+
+    let (encryption_key, public_key) = generate_encryption_key_pair();
+    // Encapsulate outside of the encryptor, as a hardware KEM would do.
+    let (shared_secret, encapsulated_public_key) = crate::hpke::Kem::encap(
+        &PublicKey::from_bytes(&public_key).expect("couldn't deserialize public key"),
+        None,
+        &mut rand_core::OsRng,
+    )
+    .expect("couldn't encapsulate");
+
+    let mut client_encryptor = ClientEncryptor::create_from_encapsulation(
+        &encapsulated_public_key.to_bytes(),
+        &shared_secret.0,
+    )
+    .expect("couldn't create client encryptor");
+    let encrypted_request = client_encryptor
+        .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("couldn't encrypt request");
+    let (server_encryptor, decrypted_request, _) =
+        ServerEncryptor::decrypt(&encrypted_request, &encryption_key)
+            .expect("couldn't decrypt request");
+    assert_eq!(TEST_REQUEST_MESSAGE, decrypted_request);
+
+    let encrypted_response = server_encryptor
+        .encrypt(TEST_RESPONSE_MESSAGE, TEST_RESPONSE_ASSOCIATED_DATA)
+        .expect("couldn't encrypt response");
+    let (decrypted_response, _) =
+        client_encryptor.decrypt(&encrypted_response).expect("couldn't decrypt response");
+    assert_eq!(TEST_RESPONSE_MESSAGE, decrypted_response);
+
+    // A wrong shared secret only surfaces as a decryption failure on the server.
+    let mut client_encryptor =
+        ClientEncryptor::create_from_encapsulation(&encapsulated_public_key.to_bytes(), &[1u8; 32])
+            .expect("couldn't create client encryptor");
+    let encrypted_request = client_encryptor
+        .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("couldn't encrypt request");
+    assert!(ServerEncryptor::decrypt(&encrypted_request, &encryption_key).is_err());
+
+    assert!(ClientEncryptor::create_from_encapsulation(
+        &encapsulated_public_key.to_bytes(),
+        &shared_secret.0[..16]
+    )
+    .is_err());
+}
+
+fn crypto_error<T>(result: anyhow::Result<T>) -> CryptoError {
+    *result
+        .err()
+        .expect("operation didn't fail")
+        .downcast_ref::<CryptoError>()
+        .expect("error doesn't have a class")
+}
+
+#[test]
+fn test_crypto_error_authentication_failure() {
+    let (encryption_key, public_key) = generate_encryption_key_pair();
+    let mut client_encryptor =
+        ClientEncryptor::create(&public_key).expect("couldn't create client encryptor");
+    let mut encrypted_request = client_encryptor
+        .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("couldn't encrypt request");
+    encrypted_request.encrypted_message.as_mut().unwrap().ciphertext[0] ^= 1;
+
+    let error = crypto_error(ServerEncryptor::decrypt(&encrypted_request, &encryption_key));
+    assert!(error.is_authentication_failure());
+    assert!(!error.is_malformed_input());
+    assert!(!error.is_nonce_exhaustion());
+}
+
+// Part of the side-channel audit in `tests/timing.rs`: an authentication
+// failure must not reveal which part of the message was tampered with.
+#[test]
+fn test_authentication_failures_are_uniform() {
+    let (encryption_key, public_key) = generate_encryption_key_pair();
+    let mut client_encryptor =
+        ClientEncryptor::create(&public_key).expect("couldn't create client encryptor");
+    let encrypted_request = client_encryptor
+        .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("couldn't encrypt request");
+    let (server_encryptor, _, _) = ServerEncryptor::decrypt(&encrypted_request, &encryption_key)
+        .expect("couldn't decrypt request");
+    let encrypted_response = server_encryptor
+        .encrypt(TEST_RESPONSE_MESSAGE, TEST_RESPONSE_ASSOCIATED_DATA)
+        .expect("couldn't encrypt response");
+    client_encryptor.decrypt(&encrypted_response).expect("couldn't decrypt response");
+
+    let encrypted_message = encrypted_response.encrypted_message.as_ref().unwrap();
+    let mut tampered_responses = std::vec::Vec::new();
+    for index in 0..encrypted_message.ciphertext.len() {
+        let mut response = encrypted_response.clone();
+        response.encrypted_message.as_mut().unwrap().ciphertext[index] ^= 1;
+        tampered_responses.push(response);
+    }
+    for index in 0..encrypted_message.nonce.len() {
+        let mut response = encrypted_response.clone();
+        response.encrypted_message.as_mut().unwrap().nonce[index] ^= 1;
+        tampered_responses.push(response);
+    }
+    for index in 0..encrypted_message.associated_data.len() {
+        let mut response = encrypted_response.clone();
+        response.encrypted_message.as_mut().unwrap().associated_data[index] ^= 1;
+        tampered_responses.push(response);
+    }
+
+    let errors: std::collections::BTreeSet<std::string::String> = tampered_responses
+        .iter()
+        .map(|response| {
+            let result = client_encryptor.decrypt(response);
+            let error = result.expect_err("decrypted tampered response");
+            assert_eq!(
+                Some(&CryptoError::AuthenticationFailure),
+                error.downcast_ref::<CryptoError>()
+            );
+            std::format!("{:#}", error)
+        })
+        .collect();
+    assert_eq!(1, errors.len(), "errors depend on the tampered location: {:?}", errors);
+}
+
+#[test]
+fn test_crypto_error_malformed_input() {
+    let (encryption_key, public_key) = generate_encryption_key_pair();
+    let mut client_encryptor =
+        ClientEncryptor::create(&public_key).expect("couldn't create client encryptor");
+    let mut encrypted_request = client_encryptor
+        .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("couldn't encrypt request");
+    encrypted_request.encrypted_message.as_mut().unwrap().nonce.pop();
+
+    let error = crypto_error(ServerEncryptor::decrypt(&encrypted_request, &encryption_key));
+    assert!(error.is_malformed_input());
+    assert!(!error.is_authentication_failure());
+
+    let error = crypto_error(ServerEncryptor::decrypt_combined(
+        &[0; MIN_COMBINED_MESSAGE_SIZE_BYTES - 1],
+        TEST_REQUEST_ASSOCIATED_DATA,
+        &encryption_key,
+    ));
+    assert!(error.is_malformed_input());
+}
+
+#[test]
+fn test_crypto_error_source() {
+    // Returns the source of the `CryptoError` in the chain of `error`. Chain
+    // entries are type-erased, so the class is found by its message.
+    fn crypto_error_source(error: &anyhow::Error) -> Option<std::string::String> {
+        let class = std::format!("{}", error.downcast_ref::<CryptoError>().unwrap());
+        let class = error
+            .chain()
+            .find(|cause| std::format!("{}", cause) == class)
+            .expect("error doesn't have a class");
+        class.source().map(|source| std::format!("{}", source))
+    }
+
+    // A malformed encapsulated public key fails decapsulation in the HPKE
+    // library.
+    let (encryption_key, public_key) = generate_encryption_key_pair();
+    let mut client_encryptor =
+        ClientEncryptor::create(&public_key).expect("couldn't create client encryptor");
+    let mut encrypted_request = client_encryptor
+        .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("couldn't encrypt request");
+    encrypted_request.serialized_encapsulated_public_key.as_mut().unwrap().pop();
+    let error = ServerEncryptor::decrypt(&encrypted_request, &encryption_key)
+        .err()
+        .expect("decapsulated a malformed point");
+    assert_eq!(Some(&CryptoError::MalformedInput), error.downcast_ref::<CryptoError>());
+    let source = crypto_error_source(&error).expect("decapsulation failure has no source");
+    assert_eq!(source, std::format!("{}", error.root_cause()));
+    // With the `std` feature, the root cause is the library error itself.
+    #[cfg(feature = "std")]
+    assert!(error.root_cause().downcast_ref::<hpke::HpkeError>().is_some());
+
+    // The AEAD library error is kept as well.
+    let mut client_encryptor =
+        ClientEncryptor::create(&public_key).expect("couldn't create client encryptor");
+    let mut encrypted_request = client_encryptor
+        .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("couldn't encrypt request");
+    encrypted_request.encrypted_message.as_mut().unwrap().ciphertext[0] ^= 1;
+    let error = ServerEncryptor::decrypt(&encrypted_request, &encryption_key)
+        .err()
+        .expect("decrypted a tampered request");
+    assert_eq!(Some(&CryptoError::AuthenticationFailure), error.downcast_ref::<CryptoError>());
+    assert!(crypto_error_source(&error).is_some());
+    #[cfg(feature = "std")]
+    assert!(error.root_cause().downcast_ref::<aes_gcm::Error>().is_some());
+
+    // Errors without an underlying cause have no source.
+    let error = ServerEncryptor::decrypt_combined(
+        &[0; MIN_COMBINED_MESSAGE_SIZE_BYTES - 1],
+        TEST_REQUEST_ASSOCIATED_DATA,
+        &encryption_key,
+    )
+    .err()
+    .expect("decrypted a truncated message");
+    assert_eq!(None, crypto_error_source(&error));
+}
+
+#[test]
+fn test_crypto_error_nonce_exhaustion() {
+    let (_, public_key) = generate_encryption_key_pair();
+    let mut client_encryptor =
+        ClientEncryptor::create(&public_key).expect("couldn't create client encryptor");
+    client_encryptor.set_encrypted_requests(MAX_MESSAGES_PER_SESSION);
+
+    let error =
+        crypto_error(client_encryptor.encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA));
+    assert!(error.is_nonce_exhaustion());
+    assert!(!error.is_authentication_failure());
+}
+
+const TEST_PUSHED_MESSAGE_COUNT: usize = 50;
+
+#[test]
+fn test_simplex_push() {
+    let (encryption_key, encryption_public_key) = generate_encryption_key_pair();
+    let mut client_encryptor =
+        ClientEncryptor::create(&encryption_public_key).expect("couldn't create client encryptor");
+    let encrypted_request = client_encryptor
+        .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("couldn't encrypt request");
+    let (server_encryptor, _, _) = ServerEncryptor::decrypt(&encrypted_request, &encryption_key)
+        .expect("couldn't decrypt request");
+
+    let mut pusher = server_encryptor.into_simplex_pusher();
+    let mut receiver = client_encryptor.into_simplex_receiver();
+    let pushed_messages: std::vec::Vec<_> = (0..TEST_PUSHED_MESSAGE_COUNT)
+        .map(|index| {
+            pusher
+                .push(std::format!("Pushed message {}", index).as_bytes(), &index.to_be_bytes())
+                .expect("couldn't push message")
+        })
+        .collect();
+
+    // Messages are only accepted in order.
+    let result = receiver.receive(&pushed_messages[1]);
+    assert_eq!(CryptoError::AuthenticationFailure, crypto_error(result));
+    for (index, pushed_message) in pushed_messages.iter().enumerate() {
+        let (plaintext, associated_data) =
+            receiver.receive(pushed_message).expect("couldn't receive message");
+        assert_eq!(std::format!("Pushed message {}", index).as_bytes(), plaintext);
+        assert_eq!(index.to_be_bytes().to_vec(), associated_data);
+    }
+    // Replayed messages are rejected.
+    let result = receiver.receive(&pushed_messages[0]);
+    assert_eq!(CryptoError::AuthenticationFailure, crypto_error(result));
+}
+
+#[test]
+fn test_simplex_push_with_duplicate_session_keys() {
+    let (encryption_key, encryption_public_key) = generate_encryption_key_pair();
+    let mut client_encryptor =
+        ClientEncryptor::create(&encryption_public_key).expect("couldn't create client encryptor");
+    let encrypted_request = client_encryptor
+        .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("couldn't encrypt request");
+
+    // Replaying the initial request yields two pushers with the same keys, which
+    // must not seal messages at the same position with the same nonce.
+    let mut pushers = [0, 1].map(|_| {
+        let (server_encryptor, _, _) =
+            ServerEncryptor::decrypt(&encrypted_request, &encryption_key)
+                .expect("couldn't decrypt request");
+        server_encryptor.into_simplex_pusher()
+    });
+    let [first_message, second_message] = [0, 1].map(|index| {
+        pushers[index]
+            .push(std::format!("Pushed message {}", index).as_bytes(), crate::EMPTY_ASSOCIATED_DATA)
+            .expect("couldn't push message")
+    });
+    assert_ne!(
+        first_message.encrypted_message.as_ref().unwrap().nonce,
+        second_message.encrypted_message.as_ref().unwrap().nonce
+    );
+    let [first_manifest, second_manifest] = pushers.map(|pusher| {
+        let (_, encrypted_manifest) = pusher
+            .into_transfer_sender(TransferManifest::new([TEST_RESPONSE_MESSAGE]))
+            .expect("couldn't create transfer sender");
+        encrypted_manifest
+    });
+    assert_ne!(
+        first_manifest.encrypted_message.as_ref().unwrap().nonce,
+        second_manifest.encrypted_message.as_ref().unwrap().nonce
+    );
+
+    // Either message is accepted at the first position of the stream.
+    let mut receiver = client_encryptor.into_simplex_receiver();
+    let (plaintext, _) = receiver.receive(&second_message).expect("couldn't receive message");
+    assert_eq!(b"Pushed message 1", plaintext.as_slice());
+}
+
+const TEST_CONVERSATION_LENGTH: usize = 5;
+
+#[test]
+fn test_server_encryptor_is_consumed() {
+    // Statically, sealing a response or turning into a pusher moves the
+    // encryptor, so it can't be used again.
+    let _: fn(ServerEncryptor, &[u8], &[u8]) -> anyhow::Result<EncryptedResponse> =
+        ServerEncryptor::encrypt;
+    let _: fn(
+        ServerEncryptor,
+        &ContinuityToken,
+        &[u8],
+        &[u8],
+    ) -> anyhow::Result<EncryptedResponse> = ServerEncryptor::encrypt_with_continuity;
+    let _: fn(ServerEncryptor) -> SimplexPusher = ServerEncryptor::into_simplex_pusher;
+    let _: fn(ClientEncryptor) -> SimplexReceiver = ClientEncryptor::into_simplex_receiver;
+
+    // Dynamically, each request of a conversation produces exactly one
+    // encryptor, which only seals the response to that request.
+    let (encryption_key, encryption_public_key) = generate_encryption_key_pair();
+    let mut exchanges = std::vec::Vec::new();
+    for index in 0..TEST_CONVERSATION_LENGTH {
+        let mut client_encryptor = ClientEncryptor::create(&encryption_public_key)
+            .expect("couldn't create client encryptor");
+        let encrypted_request = client_encryptor
+            .encrypt(std::format!("Request {}", index).as_bytes(), &index.to_be_bytes())
+            .expect("couldn't encrypt request");
+        let (server_encryptor, request, associated_data) =
+            ServerEncryptor::decrypt(&encrypted_request, &encryption_key)
+                .expect("couldn't decrypt request");
+        assert_eq!(std::format!("Request {}", index).as_bytes(), request);
+        assert_eq!(index.to_be_bytes().to_vec(), associated_data);
+        let encrypted_response = server_encryptor
+            .encrypt(std::format!("Response {}", index).as_bytes(), &index.to_be_bytes())
+            .expect("couldn't encrypt response");
+        exchanges.push((client_encryptor, encrypted_response));
+    }
+    for (index, (client_encryptor, _)) in exchanges.iter().enumerate() {
+        for (other_index, (_, encrypted_response)) in exchanges.iter().enumerate() {
+            let result = client_encryptor.decrypt(encrypted_response);
+            if index == other_index {
+                let (response, associated_data) = result.expect("couldn't decrypt response");
+                assert_eq!(std::format!("Response {}", index).as_bytes(), response);
+                assert_eq!(index.to_be_bytes().to_vec(), associated_data);
+            } else {
+                assert_eq!(CryptoError::AuthenticationFailure, crypto_error(result));
+            }
+        }
+    }
+}
+
+#[test]
+fn test_simplex_keepalive() {
+    let (encryption_key, encryption_public_key) = generate_encryption_key_pair();
+    let mut client_encryptor =
+        ClientEncryptor::create(&encryption_public_key).expect("couldn't create client encryptor");
+    let encrypted_request = client_encryptor
+        .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("couldn't encrypt request");
+    let (server_encryptor, _, _) = ServerEncryptor::decrypt(&encrypted_request, &encryption_key)
+        .expect("couldn't decrypt request");
+
+    let mut pusher = server_encryptor.into_simplex_pusher();
+    let mut receiver = client_encryptor.into_simplex_receiver();
+    let keepalive = pusher.seal_keepalive().expect("couldn't seal keepalive");
+    let data_message = pusher
+        .push(TEST_RESPONSE_MESSAGE, TEST_RESPONSE_ASSOCIATED_DATA)
+        .expect("couldn't push message");
+    // Keepalives take a position of the stream.
+    assert_eq!(MAX_MESSAGES_PER_SESSION - 2, pusher.messages_remaining());
+    let result = receiver.receive(&data_message);
+    assert_eq!(CryptoError::AuthenticationFailure, crypto_error(result));
+
+    let (plaintext, associated_data) =
+        receiver.receive(&keepalive).expect("couldn't receive keepalive");
+    assert!(plaintext.is_empty());
+    assert!(SimplexReceiver::is_keepalive(&associated_data));
+    let (plaintext, associated_data) =
+        receiver.receive(&data_message).expect("couldn't receive message");
+    assert_eq!(TEST_RESPONSE_MESSAGE, plaintext);
+    assert!(!SimplexReceiver::is_keepalive(&associated_data));
+    assert!(!SimplexReceiver::is_keepalive(crate::EMPTY_ASSOCIATED_DATA));
+
+    // Data messages can't pose as keepalives.
+    assert!(pusher.push(TEST_RESPONSE_MESSAGE, crate::labels::KEEPALIVE_LABEL).is_err());
+}
+
+#[test]
+fn test_resumable_transfer() {
+    let (encryption_key, encryption_public_key) = generate_encryption_key_pair();
+    let create_session = || {
+        let mut client_encryptor = ClientEncryptor::create(&encryption_public_key)
+            .expect("couldn't create client encryptor");
+        let encrypted_request = client_encryptor
+            .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+            .expect("couldn't encrypt request");
+        let (server_encryptor, _, _) =
+            ServerEncryptor::decrypt(&encrypted_request, &encryption_key)
+                .expect("couldn't decrypt request");
+        (server_encryptor.into_simplex_pusher(), client_encryptor.into_simplex_receiver())
+    };
+    let chunks: std::vec::Vec<std::vec::Vec<u8>> =
+        (0..5).map(|index| std::format!("Chunk {}", index).into_bytes()).collect();
+    let manifest = TransferManifest::new(chunks.iter().map(|chunk| chunk.as_slice()));
+    assert_eq!(5, manifest.chunk_count());
+    assert_eq!(chunks.iter().map(|chunk| chunk.len() as u64).sum::<u64>(), manifest.total_size());
+
+    let (mut pusher, mut receiver) = create_session();
+    // The transfer takes the positions of the stream after the pushed messages.
+    let pushed_message = pusher.push(TEST_RESPONSE_MESSAGE, &[]).expect("couldn't push message");
+    receiver.receive(&pushed_message).expect("couldn't receive message");
+    let (sender, encrypted_manifest) =
+        pusher.into_transfer_sender(manifest.clone()).expect("couldn't create transfer sender");
+    let mut receiver =
+        receiver.into_transfer_receiver(&encrypted_manifest).expect("couldn't receive manifest");
+    assert_eq!(&manifest, receiver.manifest());
+
+    // The transfer is interrupted after 3 chunks.
+    for (index, chunk) in chunks.iter().enumerate().take(3) {
+        let encrypted_chunk = sender.push_chunk(index as u64, chunk).expect("couldn't push chunk");
+        assert_eq!(*chunk, receiver.receive_chunk(&encrypted_chunk).expect("couldn't receive"));
+    }
+    let result = receiver.receive_chunk(&sender.push_chunk(4, &chunks[4]).unwrap());
+    assert_eq!(CryptoError::MalformedInput, crypto_error(result));
+
+    // Stored chunks that were tampered with can't be resumed from.
+    let mut tampered_chunk = chunks[0].clone();
+    tampered_chunk[0] ^= 1;
+    let result = receiver.resume(&[&tampered_chunk, &chunks[1]]);
+    assert_eq!(CryptoError::AuthenticationFailure, crypto_error(result));
+    // The sender only encrypts chunks that match the manifest.
+    let result = sender.push_chunk(0, &tampered_chunk);
+    assert_eq!(CryptoError::AuthenticationFailure, crypto_error(result));
+
+    // Only 2 chunks were stored, so the transfer resumes at chunk 2, which is
+    // encrypted to the same message again.
+    let next_chunk = receiver.resume(&[&chunks[0], &chunks[1]]).expect("couldn't resume");
+    assert_eq!(2, next_chunk);
+    for (index, chunk) in chunks.iter().enumerate().skip(next_chunk as usize) {
+        let encrypted_chunk = sender.push_chunk(index as u64, chunk).expect("couldn't push chunk");
+        assert_eq!(*chunk, receiver.receive_chunk(&encrypted_chunk).expect("couldn't receive"));
+    }
+    receiver.finish().expect("couldn't finish transfer");
+
+    // Manifests and chunks of another transfer are rejected, even at the same
+    // position of the stream.
+    let (pusher, receiver) = create_session();
+    let (other_sender, other_encrypted_manifest) =
+        pusher.into_transfer_sender(manifest.clone()).expect("couldn't create transfer sender");
+    let result = receiver.into_transfer_receiver(&encrypted_manifest);
+    assert_eq!(CryptoError::AuthenticationFailure, crypto_error(result));
+    let (_, receiver) = create_session();
+    let result = receiver.into_transfer_receiver(&other_encrypted_manifest);
+    assert_eq!(CryptoError::AuthenticationFailure, crypto_error(result));
+
+    // Chunks beyond the manifest can't be pushed, and a transfer with missing
+    // chunks can't be finished.
+    let result = other_sender.push_chunk(5, b"Extra chunk");
+    assert_eq!(CryptoError::MalformedInput, crypto_error(result));
+    let (pusher, receiver) = create_session();
+    let (sender, encrypted_manifest) =
+        pusher.into_transfer_sender(manifest).expect("couldn't create transfer sender");
+    let mut receiver =
+        receiver.into_transfer_receiver(&encrypted_manifest).expect("couldn't receive manifest");
+    let result = receiver.receive_chunk(&other_sender.push_chunk(0, &chunks[0]).unwrap());
+    assert_eq!(CryptoError::AuthenticationFailure, crypto_error(result));
+    for (index, chunk) in chunks.iter().enumerate().take(4) {
+        receiver
+            .receive_chunk(&sender.push_chunk(index as u64, chunk).unwrap())
+            .expect("couldn't receive chunk");
+    }
+    assert_eq!(CryptoError::MalformedInput, crypto_error(receiver.finish()));
+}
+
+#[test]
+fn test_drop_response() {
+    let (recipient_private_key, recipient_public_key) = generate_kem_key_pair();
+    let (serialized_encapsulated_public_key, mut sender_context) =
+        setup_base_sender(&recipient_public_key.to_bytes(), TEST_HPKE_INFO)
+            .expect("couldn't setup base sender");
+    let recipient_context = setup_base_recipient(
+        &serialized_encapsulated_public_key,
+        &recipient_private_key,
+        TEST_HPKE_INFO,
+    )
+    .expect("couldn't setup base recipient");
+    let test_response_nonce = generate_random_nonce();
+    let encrypted_response = recipient_context
+        .seal(&test_response_nonce, TEST_RESPONSE_MESSAGE, TEST_RESPONSE_ASSOCIATED_DATA)
+        .expect("recipient context couldn't seal response");
+
+    assert!(!sender_context.is_response_key_discarded());
+    sender_context.discard_response_key();
+    assert!(sender_context.is_response_key_discarded());
+    let result = sender_context.open(
+        &test_response_nonce,
+        &encrypted_response,
+        TEST_RESPONSE_ASSOCIATED_DATA,
+    );
+    assert!(result.is_err());
+
+    // Test that responses forged with the zeroized key are rejected.
+    let forged_response = crate::hpke::aead::encrypt(
+        &AeadKey::default(),
+        &test_response_nonce,
+        TEST_RESPONSE_MESSAGE,
+        TEST_RESPONSE_ASSOCIATED_DATA,
+    )
+    .expect("couldn't encrypt forged response");
+    let result =
+        sender_context.open(&test_response_nonce, &forged_response, TEST_RESPONSE_ASSOCIATED_DATA);
+    assert!(result.is_err());
+
+    // Test that the client can still send requests after dropping the response
+    // path.
+    let (encryption_key, encryption_public_key) = generate_encryption_key_pair();
+    let mut client_encryptor =
+        ClientEncryptor::create(&encryption_public_key).expect("couldn't create client encryptor");
+    client_encryptor.drop_response();
+    let encrypted_request = client_encryptor
+        .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("couldn't encrypt request");
+    let (server_encryptor, decrypted_request, _) =
+        ServerEncryptor::decrypt(&encrypted_request, &encryption_key)
+            .expect("couldn't decrypt request");
+    assert_eq!(TEST_REQUEST_MESSAGE, decrypted_request);
+    let encrypted_response = server_encryptor
+        .encrypt(TEST_RESPONSE_MESSAGE, TEST_RESPONSE_ASSOCIATED_DATA)
+        .expect("couldn't encrypt response");
+    assert!(client_encryptor.decrypt(&encrypted_response).is_err());
+}
+
+const TEST_SERVER_SECRET: &[u8] = b"Test server secret";
+const TEST_CHALLENGE_DIFFICULTY: u8 = 8;
+const TEST_CHALLENGE_VALIDITY_MS: u64 = 10_000;
+const TEST_CURRENT_TIME_MILLIS: u64 = 1_700_000_000_000;
+
+#[test]
+fn test_setup_challenge() {
+    let challenge = SetupChallenge::issue(
+        TEST_SERVER_SECRET,
+        TEST_CHALLENGE_DIFFICULTY,
+        b"client",
+        TEST_CURRENT_TIME_MILLIS,
+    )
+    .expect("couldn't issue challenge");
+    let solution = challenge.solve();
+    let verify = |solution: &SetupSolution, client_hint: &[u8], current_time_millis: u64| {
+        SetupChallenge::verify(
+            TEST_SERVER_SECRET,
+            client_hint,
+            solution,
+            TEST_CHALLENGE_DIFFICULTY,
+            TEST_CHALLENGE_VALIDITY_MS,
+            current_time_millis,
+        )
+    };
+    assert!(verify(&solution, b"client", TEST_CURRENT_TIME_MILLIS).is_ok());
+    let deserialized_solution =
+        SetupSolution::deserialize(&solution.serialize()).expect("couldn't deserialize solution");
+    assert_eq!(solution, deserialized_solution);
+
+    // Test that unsolved challenges are rejected. Counters are searched in order,
+    // so the counter before the solution doesn't solve the challenge. The
+    // challenge is deterministic, and its solution isn't the first counter.
+    let mut serialized_solution = solution.serialize();
+    let (_, counter) = serialized_solution.split_at_mut(SETUP_CHALLENGE_SIZE_BYTES);
+    let previous_counter =
+        u64::from_be_bytes((&*counter).try_into().expect("invalid counter size")) - 1;
+    counter.copy_from_slice(&previous_counter.to_be_bytes());
+    let unsolved =
+        SetupSolution::deserialize(&serialized_solution).expect("couldn't deserialize solution");
+    let result = verify(&unsolved, b"client", TEST_CURRENT_TIME_MILLIS);
+    assert_eq!(CryptoError::SetupChallengeFailure, crypto_error(result));
+
+    // Test that expired challenges are rejected.
+    let result =
+        verify(&solution, b"client", TEST_CURRENT_TIME_MILLIS + TEST_CHALLENGE_VALIDITY_MS);
+    assert_eq!(CryptoError::SetupChallengeFailure, crypto_error(result));
+
+    // Test that solutions can't be replayed for other clients.
+    let result = verify(&solution, b"other client", TEST_CURRENT_TIME_MILLIS);
+    assert_eq!(CryptoError::SetupChallengeFailure, crypto_error(result));
+
+    // Test that challenges can't be forged by changing the difficulty.
+    let mut serialized_solution = solution.serialize();
+    serialized_solution[8] = 0;
+    let forged =
+        SetupSolution::deserialize(&serialized_solution).expect("couldn't deserialize solution");
+    let result = verify(&forged, b"client", TEST_CURRENT_TIME_MILLIS);
+    assert_eq!(CryptoError::SetupChallengeFailure, crypto_error(result));
+
+    // Test that difficulties above the maximum are rejected, so that clients
+    // can't be made to spin forever.
+    assert!(SetupChallenge::issue(
+        TEST_SERVER_SECRET,
+        MAX_SETUP_DIFFICULTY + 1,
+        b"client",
+        TEST_CURRENT_TIME_MILLIS
+    )
+    .is_err());
+    let mut serialized_challenge = challenge.serialize();
+    serialized_challenge[8] = MAX_SETUP_DIFFICULTY + 1;
+    let result = SetupChallenge::deserialize(&serialized_challenge);
+    assert_eq!(CryptoError::MalformedInput, crypto_error(result));
+    assert!(SetupChallenge::verify(
+        TEST_SERVER_SECRET,
+        b"client",
+        &solution,
+        MAX_SETUP_DIFFICULTY + 1,
+        TEST_CHALLENGE_VALIDITY_MS,
+        TEST_CURRENT_TIME_MILLIS,
+    )
+    .is_err());
+}
+
+#[test]
+fn test_setup_gate() {
+    let (encryption_key, encryption_public_key) = generate_encryption_key_pair();
+    let mut client_encryptor =
+        ClientEncryptor::create(&encryption_public_key).expect("couldn't create client encryptor");
+    let encrypted_request = client_encryptor
+        .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("couldn't encrypt request");
+    let client_hint = encrypted_request
+        .serialized_encapsulated_public_key
+        .clone()
+        .expect("initial request doesn't contain encapsulated public key");
+    let gate = SetupGate::new(TEST_SERVER_SECRET, TEST_CHALLENGE_VALIDITY_MS);
+    let decrypt = |solution: Option<&SetupSolution>, current_time_millis: u64| {
+        ServerEncryptor::decrypt_gated(
+            &encrypted_request,
+            &encryption_key,
+            &gate,
+            solution,
+            current_time_millis,
+        )
+    };
+
+    // Test that the gate is open by default.
+    assert!(decrypt(None, TEST_CURRENT_TIME_MILLIS).is_ok());
+
+    gate.set_difficulty(TEST_CHALLENGE_DIFFICULTY);
+    let result = decrypt(None, TEST_CURRENT_TIME_MILLIS);
+    assert_eq!(CryptoError::SetupChallengeFailure, crypto_error(result));
+    let solution = gate.issue(&client_hint, TEST_CURRENT_TIME_MILLIS).solve();
+    let (_, decrypted_request, _) =
+        decrypt(Some(&solution), TEST_CURRENT_TIME_MILLIS).expect("couldn't decrypt request");
+    assert_eq!(TEST_REQUEST_MESSAGE, decrypted_request);
+
+    // Test that raising the difficulty invalidates easier solutions.
+    gate.set_difficulty(TEST_CHALLENGE_DIFFICULTY + 1);
+    let result = decrypt(Some(&solution), TEST_CURRENT_TIME_MILLIS);
+    assert_eq!(CryptoError::SetupChallengeFailure, crypto_error(result));
+
+    // Test that the difficulty is clamped to the maximum.
+    gate.set_difficulty(u8::MAX);
+    assert_eq!(MAX_SETUP_DIFFICULTY, gate.difficulty());
+    assert_eq!(
+        MAX_SETUP_DIFFICULTY,
+        gate.issue(&client_hint, TEST_CURRENT_TIME_MILLIS).difficulty()
+    );
+}
+
+const TEST_TOKEN_KEY: [u8; 32] = [7; 32];
+
+#[test]
+fn test_stateless_sender() {
+    let (encryption_key, encryption_public_key) = generate_encryption_key_pair();
+    let (request, token) = ClientEncryptor::seal_stateless(
+        &encryption_public_key,
+        TEST_REQUEST_MESSAGE,
+        TEST_REQUEST_ASSOCIATED_DATA,
+        &TEST_TOKEN_KEY,
+    )
+    .expect("couldn't seal request");
+
+    let (server_encryptor, decrypted_request) =
+        ServerEncryptor::decrypt_combined(&request, TEST_REQUEST_ASSOCIATED_DATA, &encryption_key)
+            .expect("couldn't decrypt request");
+    assert_eq!(TEST_REQUEST_MESSAGE, decrypted_request);
+    let encrypted_response = server_encryptor
+        .encrypt(TEST_RESPONSE_MESSAGE, TEST_RESPONSE_ASSOCIATED_DATA)
+        .expect("couldn't encrypt response");
+
+    // Test that the response can be decrypted by an encryptor that only has the
+    // token.
+    let client_encryptor = ClientEncryptor::resume_from_token(&token, &TEST_TOKEN_KEY)
+        .expect("couldn't resume session");
+    let (decrypted_response, associated_data) =
+        client_encryptor.decrypt(&encrypted_response).expect("couldn't decrypt response");
+    assert_eq!(TEST_RESPONSE_MESSAGE, decrypted_response);
+    assert_eq!(TEST_RESPONSE_ASSOCIATED_DATA, associated_data);
+
+    // Test that tokens are authenticated.
+    let result = ClientEncryptor::resume_from_token(&token, &[8; 32]);
+    assert_eq!(CryptoError::AuthenticationFailure, crypto_error(result));
+    let result = ClientEncryptor::resume_from_token(&token[..4], &TEST_TOKEN_KEY);
+    assert_eq!(CryptoError::MalformedInput, crypto_error(result));
+}
+
+#[test]
+fn test_stateless_sender_epoch() {
+    let (encryption_key, encryption_public_key) = generate_encryption_key_pair();
+    let seal = |epoch| {
+        ClientEncryptor::seal_stateless_with_epoch(
+            &encryption_public_key,
+            TEST_REQUEST_MESSAGE,
+            TEST_REQUEST_ASSOCIATED_DATA,
+            &TEST_TOKEN_KEY,
+            epoch,
+        )
+        .expect("couldn't seal request")
+    };
+    let (_, old_token) = seal(1);
+    let (request, token) = seal(2);
+
+    let (server_encryptor, _) =
+        ServerEncryptor::decrypt_combined(&request, TEST_REQUEST_ASSOCIATED_DATA, &encryption_key)
+            .expect("couldn't decrypt request");
+    let encrypted_response = server_encryptor
+        .encrypt(TEST_RESPONSE_MESSAGE, TEST_RESPONSE_ASSOCIATED_DATA)
+        .expect("couldn't encrypt response");
+    let (client_encryptor, epoch) =
+        ClientEncryptor::resume_from_token_with_epoch(&token, &TEST_TOKEN_KEY, 1)
+            .expect("couldn't resume session");
+    assert_eq!(2, epoch);
+    let (decrypted_response, _) =
+        client_encryptor.decrypt(&encrypted_response).expect("couldn't decrypt response");
+    assert_eq!(TEST_RESPONSE_MESSAGE, decrypted_response);
+
+    // Tokens of the last seen or older epochs are rejected.
+    for (token, last_seen_epoch) in [(&token, 2), (&old_token, 2), (&token, u64::MAX)] {
+        let result =
+            ClientEncryptor::resume_from_token_with_epoch(token, &TEST_TOKEN_KEY, last_seen_epoch);
+        assert_eq!(CryptoError::StaleEpoch, crypto_error(result));
+    }
+    // The epoch is authenticated.
+    let mut tampered_token = old_token.clone();
+    tampered_token[7] = 3;
+    let result = ClientEncryptor::resume_from_token_with_epoch(&tampered_token, &TEST_TOKEN_KEY, 2);
+    assert_eq!(CryptoError::AuthenticationFailure, crypto_error(result));
+    // Tokens with and without an epoch can't be mixed up.
+    assert!(ClientEncryptor::resume_from_token(&token, &TEST_TOKEN_KEY).is_err());
+    let result = ClientEncryptor::resume_from_token_with_epoch(&token[..4], &TEST_TOKEN_KEY, 0);
+    assert_eq!(CryptoError::MalformedInput, crypto_error(result));
+}
+
+#[test]
+fn test_offline_exchange() {
+    let (encryption_key, encryption_public_key) = generate_encryption_key_pair();
+    let (request, return_path) = ClientEncryptor::create_offline_exchange(
+        &encryption_public_key,
+        TEST_REQUEST_MESSAGE,
+        TEST_REQUEST_ASSOCIATED_DATA,
+        &TEST_TOKEN_KEY,
+    )
+    .expect("couldn't create offline exchange");
+    // The return path only has room for the response key.
+    assert_eq!(RETURN_PATH_SIZE_BYTES, return_path.len());
+    assert_eq!(
+        AEAD_ALGORITHM_KEY_SIZE_BYTES + AEAD_NONCE_SIZE_BYTES + AEAD_TAG_SIZE_BYTES,
+        RETURN_PATH_SIZE_BYTES
+    );
+
+    // The response is generated while the sender is offline.
+    let (server_encryptor, decrypted_request) =
+        ServerEncryptor::decrypt_combined(&request, TEST_REQUEST_ASSOCIATED_DATA, &encryption_key)
+            .expect("couldn't decrypt request");
+    assert_eq!(TEST_REQUEST_MESSAGE, decrypted_request);
+    let encrypted_response = server_encryptor
+        .encrypt(TEST_RESPONSE_MESSAGE, TEST_RESPONSE_ASSOCIATED_DATA)
+        .expect("couldn't encrypt response");
+
+    let response_decryptor = ResponseDecryptor::from_return_path(&return_path, &TEST_TOKEN_KEY)
+        .expect("couldn't load return path");
+    let (decrypted_response, associated_data) =
+        response_decryptor.decrypt(&encrypted_response).expect("couldn't decrypt response");
+    assert_eq!(TEST_RESPONSE_MESSAGE, decrypted_response);
+    assert_eq!(TEST_RESPONSE_ASSOCIATED_DATA, associated_data);
+
+    // Test that return paths are authenticated.
+    for index in 0..return_path.len() {
+        let mut tampered_return_path = return_path.clone();
+        tampered_return_path[index] ^= 1;
+        let result = ResponseDecryptor::from_return_path(&tampered_return_path, &TEST_TOKEN_KEY);
+        assert_eq!(CryptoError::AuthenticationFailure, crypto_error(result));
+    }
+    let result = ResponseDecryptor::from_return_path(&return_path, &[8; 32]);
+    assert_eq!(CryptoError::AuthenticationFailure, crypto_error(result));
+    let result = ResponseDecryptor::from_return_path(&return_path[1..], &TEST_TOKEN_KEY);
+    assert_eq!(CryptoError::MalformedInput, crypto_error(result));
+    // Session tokens can't be loaded as return paths.
+    let (_, token) = ClientEncryptor::seal_stateless(
+        &encryption_public_key,
+        TEST_REQUEST_MESSAGE,
+        TEST_REQUEST_ASSOCIATED_DATA,
+        &TEST_TOKEN_KEY,
+    )
+    .expect("couldn't seal request");
+    assert!(ResponseDecryptor::from_return_path(&token, &TEST_TOKEN_KEY).is_err());
+}
+
+const TEST_ROUTING_HEADER: &[u8] = b"tenant=1;priority=high";
+
+#[test]
+fn test_routing_header() {
+    let (encryption_key, encryption_public_key) = generate_encryption_key_pair();
+    let mut client_encryptor =
+        ClientEncryptor::create(&encryption_public_key).expect("couldn't create client encryptor");
+    let encrypted_request = client_encryptor
+        .encrypt_with_header(
+            TEST_ROUTING_HEADER,
+            TEST_REQUEST_MESSAGE,
+            TEST_REQUEST_ASSOCIATED_DATA,
+        )
+        .expect("couldn't encrypt request");
+
+    // Test that the header can be read without keys.
+    assert_eq!(
+        TEST_ROUTING_HEADER,
+        read_routing_header(&encrypted_request).expect("couldn't read routing header")
+    );
+
+    let (_, decrypted_request) =
+        ServerEncryptor::decrypt_with_header(&encrypted_request, &encryption_key)
+            .expect("couldn't decrypt request");
+    assert_eq!(TEST_ROUTING_HEADER, decrypted_request.header);
+    assert_eq!(TEST_REQUEST_MESSAGE, decrypted_request.plaintext);
+    assert_eq!(TEST_REQUEST_ASSOCIATED_DATA, decrypted_request.associated_data);
+    // The AAD policy applies to the caller associated data only.
+    AadPolicy::RequireExact(TEST_REQUEST_ASSOCIATED_DATA.to_vec())
+        .check(&decrypted_request.associated_data)
+        .expect("associated data violates the policy");
+
+    // Test that a tampered header is detected.
+    let mut tampered_request = encrypted_request.clone();
+    let associated_data = &mut tampered_request.encrypted_message.as_mut().unwrap().associated_data;
+    let last_header_index =
+        ROUTING_HEADER_LABEL.len() + LENGTH_PREFIX_SIZE_BYTES + TEST_ROUTING_HEADER.len() - 1;
+    associated_data[last_header_index] ^= 1;
+    let result = ServerEncryptor::decrypt_with_header(&tampered_request, &encryption_key);
+    assert_eq!(CryptoError::AuthenticationFailure, crypto_error(result));
+
+    // Test that an empty header is still framed, so that it can't be confused
+    // with a request of the plain API.
+    let mut client_encryptor =
+        ClientEncryptor::create(&encryption_public_key).expect("couldn't create client encryptor");
+    let encrypted_request = client_encryptor
+        .encrypt_with_header(b"", TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("couldn't encrypt request");
+    let (_, decrypted_request) =
+        ServerEncryptor::decrypt_with_header(&encrypted_request, &encryption_key)
+            .expect("couldn't decrypt request");
+    assert!(decrypted_request.header.is_empty());
+    assert_eq!(TEST_REQUEST_MESSAGE, decrypted_request.plaintext);
+    assert_eq!(TEST_REQUEST_ASSOCIATED_DATA, decrypted_request.associated_data);
+    let (_, _, plain_associated_data) =
+        ServerEncryptor::decrypt(&encrypted_request, &encryption_key)
+            .expect("couldn't decrypt request");
+    assert_ne!(TEST_REQUEST_ASSOCIATED_DATA, plain_associated_data);
+    let mut client_encryptor =
+        ClientEncryptor::create(&encryption_public_key).expect("couldn't create client encryptor");
+    let encrypted_request = client_encryptor
+        .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("couldn't encrypt request");
+    let result = ServerEncryptor::decrypt_with_header(&encrypted_request, &encryption_key);
+    assert_eq!(CryptoError::MalformedInput, crypto_error(result));
+
+    // Test that headers are bounded.
+    let result = client_encryptor.encrypt_with_header(
+        &[0; MAX_ROUTING_HEADER_SIZE_BYTES + 1],
+        TEST_REQUEST_MESSAGE,
+        TEST_REQUEST_ASSOCIATED_DATA,
+    );
+    assert_eq!(CryptoError::MalformedInput, crypto_error(result));
+}
+
+#[test]
+fn test_seal_with_header() {
+    let (encryption_key, encryption_public_key) = generate_encryption_key_pair();
+    let mut client_encryptor =
+        ClientEncryptor::create(&encryption_public_key).expect("couldn't create client encryptor");
+    let (header, encrypted_request) = client_encryptor
+        .seal_with_header(TEST_ROUTING_HEADER, TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("couldn't encrypt request");
+    assert_eq!(TEST_ROUTING_HEADER, header);
+    // The header is only carried next to the request.
+    assert_eq!(
+        TEST_REQUEST_ASSOCIATED_DATA,
+        encrypted_request.encrypted_message.as_ref().unwrap().associated_data
+    );
+
+    // Test that a tampered, missing or truncated header is detected.
+    let mut tampered_header = header.clone();
+    tampered_header[0] ^= 1;
+    for received_header in [&tampered_header[..], b"", &header[..header.len() - 1]] {
+        let result =
+            ServerEncryptor::open_with_header(received_header, &encrypted_request, &encryption_key);
+        assert_eq!(CryptoError::AuthenticationFailure, crypto_error(result));
+    }
+    let result = ServerEncryptor::decrypt(&encrypted_request, &encryption_key);
+    assert_eq!(CryptoError::AuthenticationFailure, crypto_error(result));
+
+    let (server_encryptor, request, request_associated_data) =
+        ServerEncryptor::open_with_header(&header, &encrypted_request, &encryption_key)
+            .expect("couldn't decrypt request");
+    assert_eq!(TEST_REQUEST_MESSAGE, request);
+    assert_eq!(TEST_REQUEST_ASSOCIATED_DATA, request_associated_data);
+
+    // Responses are unaffected.
+    let encrypted_response = server_encryptor
+        .encrypt(TEST_RESPONSE_MESSAGE, TEST_RESPONSE_ASSOCIATED_DATA)
+        .expect("couldn't encrypt response");
+    let (response, _) =
+        client_encryptor.decrypt(&encrypted_response).expect("couldn't decrypt response");
+    assert_eq!(TEST_RESPONSE_MESSAGE, response);
+
+    // Test that headers are bounded.
+    let result = client_encryptor.seal_with_header(
+        &[0; MAX_ROUTING_HEADER_SIZE_BYTES + 1],
+        TEST_REQUEST_MESSAGE,
+        TEST_REQUEST_ASSOCIATED_DATA,
+    );
+    assert_eq!(CryptoError::MalformedInput, crypto_error(result));
+}
+
+#[test]
+fn test_pre_sealed_response() {
+    let (encryption_key, public_key) = generate_encryption_key_pair();
+    let mut client_encryptor =
+        ClientEncryptor::create(&public_key).expect("couldn't create client encryptor");
+    let encrypted_request = client_encryptor
+        .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("couldn't encrypt request");
+
+    // Seal the response right after decrypting the request, before processing it.
+    let (server_encryptor, decrypted_request, _) =
+        ServerEncryptor::decrypt(&encrypted_request, &encryption_key)
+            .expect("couldn't decrypt request");
+    let prepared_response = server_encryptor
+        .encrypt(TEST_RESPONSE_MESSAGE, TEST_RESPONSE_ASSOCIATED_DATA)
+        .expect("couldn't encrypt response");
+    assert_eq!(TEST_REQUEST_MESSAGE, decrypted_request);
+
+    // Meanwhile the client keeps sending requests in the same session.
+    client_encryptor
+        .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("couldn't encrypt request");
+
+    let (decrypted_response, _) =
+        client_encryptor.decrypt(&prepared_response).expect("couldn't decrypt response");
+    assert_eq!(TEST_RESPONSE_MESSAGE, decrypted_response);
+}
+
+#[test]
+fn test_combined_encryption() {
+    let (encryption_key, public_key) = generate_encryption_key_pair();
+    let mut client_encryptor =
+        ClientEncryptor::create(&public_key).expect("couldn't create client encryptor");
+    let combined_request = client_encryptor
+        .encrypt_combined(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("couldn't encrypt request");
+    assert_eq!(
+        MIN_COMBINED_MESSAGE_SIZE_BYTES + TEST_REQUEST_MESSAGE.len(),
+        combined_request.len()
+    );
+    // Only the initial request can be combined.
+    assert!(client_encryptor
+        .encrypt_combined(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .is_err());
+
+    let (server_encryptor, decrypted_request) = ServerEncryptor::decrypt_combined(
+        &combined_request,
+        TEST_REQUEST_ASSOCIATED_DATA,
+        &encryption_key,
+    )
+    .expect("couldn't decrypt request");
+    assert_eq!(TEST_REQUEST_MESSAGE, decrypted_request);
+
+    // Check that the server encryptor is bound to the client session.
+    let encrypted_response = server_encryptor
+        .encrypt(TEST_RESPONSE_MESSAGE, TEST_RESPONSE_ASSOCIATED_DATA)
+        .expect("couldn't encrypt response");
+    let (decrypted_response, _) =
+        client_encryptor.decrypt(&encrypted_response).expect("couldn't decrypt response");
+    assert_eq!(TEST_RESPONSE_MESSAGE, decrypted_response);
+
+    // Check that the combined message matches the separate-field layout.
+    let (serialized_encapsulated_public_key, encrypted_message) =
+        combined_request.split_at(SUITE_LENGTHS.nenc);
+    let (nonce, ciphertext) = encrypted_message.split_at(SUITE_LENGTHS.nn);
+    let encrypted_request = EncryptedRequest {
+        encrypted_message: Some(AeadEncryptedMessage {
+            nonce: nonce.to_vec(),
+            ciphertext: ciphertext.to_vec(),
+            associated_data: TEST_REQUEST_ASSOCIATED_DATA.to_vec(),
+        }),
+        serialized_encapsulated_public_key: Some(serialized_encapsulated_public_key.to_vec()),
+    };
+    let (_, decrypted_request, _) = ServerEncryptor::decrypt(&encrypted_request, &encryption_key)
+        .expect("couldn't decrypt request");
+    assert_eq!(TEST_REQUEST_MESSAGE, decrypted_request);
+}
+
+#[test]
+fn test_combined_encryption_boundary_lengths() {
+    let (encryption_key, public_key) = generate_encryption_key_pair();
+    let mut client_encryptor =
+        ClientEncryptor::create(&public_key).expect("couldn't create client encryptor");
+    let combined_request = client_encryptor
+        .encrypt_combined(&[], TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("couldn't encrypt request");
+    assert_eq!(MIN_COMBINED_MESSAGE_SIZE_BYTES, combined_request.len());
+
+    let (_, decrypted_request) = ServerEncryptor::decrypt_combined(
+        &combined_request,
+        TEST_REQUEST_ASSOCIATED_DATA,
+        &encryption_key,
+    )
+    .expect("couldn't decrypt request");
+    assert!(decrypted_request.is_empty());
+
+    let result = ServerEncryptor::decrypt_combined(
+        &combined_request[..MIN_COMBINED_MESSAGE_SIZE_BYTES - 1],
+        TEST_REQUEST_ASSOCIATED_DATA,
+        &encryption_key,
+    );
+    assert!(result.is_err_and(|error| std::format!("{}", error).contains("too short")));
+}
+
+#[test]
+fn test_first_message_len() {
+    let (_, public_key) = generate_encryption_key_pair();
+    for plaintext_len in [0, 1, 15, 16, 17, 1000] {
+        let mut client_encryptor =
+            ClientEncryptor::create(&public_key).expect("couldn't create client encryptor");
+        let combined_request = client_encryptor
+            .encrypt_combined(&std::vec![0u8; plaintext_len], TEST_REQUEST_ASSOCIATED_DATA)
+            .expect("couldn't encrypt request");
+        assert_eq!(ClientEncryptor::first_message_len(plaintext_len), combined_request.len());
+    }
+}
+
+#[test]
+fn test_encrypt_into_buffer() {
+    let (encryption_key, public_key) = generate_encryption_key_pair();
+    let mut client_encryptor =
+        ClientEncryptor::create(&public_key).expect("couldn't create client encryptor");
+    let ciphertext_size = TEST_REQUEST_MESSAGE.len() + AEAD_TAG_SIZE_BYTES;
+
+    // A buffer that is too small is rejected and doesn't consume the session state.
+    let mut output = std::vec![0u8; ciphertext_size - 1];
+    let result = client_encryptor.encrypt_into(
+        TEST_REQUEST_MESSAGE,
+        TEST_REQUEST_ASSOCIATED_DATA,
+        &mut output,
+    );
+    assert_eq!(CryptoError::MalformedInput, crypto_error(result));
+    assert!(output.iter().all(|byte| *byte == 0));
+    assert_eq!(MAX_MESSAGES_PER_SESSION, client_encryptor.messages_remaining());
+
+    // An exact-fit buffer is filled completely.
+    let mut output = std::vec![0u8; ciphertext_size];
+    let header = client_encryptor
+        .encrypt_into(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA, &mut output)
+        .expect("couldn't encrypt request");
+    assert_eq!(ciphertext_size, header.ciphertext_size);
+    assert!(header.serialized_encapsulated_public_key.is_some());
+    assert_eq!(MAX_MESSAGES_PER_SESSION - 1, client_encryptor.messages_remaining());
+
+    let encrypted_request = EncryptedRequest {
+        encrypted_message: Some(AeadEncryptedMessage {
+            nonce: header.nonce.to_vec(),
+            ciphertext: output,
+            associated_data: TEST_REQUEST_ASSOCIATED_DATA.to_vec(),
+        }),
+        serialized_encapsulated_public_key: header.serialized_encapsulated_public_key,
+    };
+    let (server_encryptor, decrypted_request, _) =
+        ServerEncryptor::decrypt(&encrypted_request, &encryption_key)
+            .expect("couldn't decrypt request");
+    assert_eq!(TEST_REQUEST_MESSAGE, decrypted_request);
+
+    let encrypted_response = server_encryptor
+        .encrypt(TEST_RESPONSE_MESSAGE, TEST_RESPONSE_ASSOCIATED_DATA)
+        .expect("couldn't encrypt response");
+    let (decrypted_response, _) =
+        client_encryptor.decrypt(&encrypted_response).expect("couldn't decrypt response");
+    assert_eq!(TEST_RESPONSE_MESSAGE, decrypted_response);
+
+    // Encapsulated public key is only returned for the initial request.
+    let mut output = std::vec![0u8; ciphertext_size + 8];
+    let header = client_encryptor
+        .encrypt_into(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA, &mut output)
+        .expect("couldn't encrypt request");
+    assert_eq!(ciphertext_size, header.ciphertext_size);
+    assert!(header.serialized_encapsulated_public_key.is_none());
+}
+
+#[test]
+fn test_scoped_decryption() {
+    let (encryption_key, encryption_public_key) = generate_encryption_key_pair();
+    let mut client_encryptor =
+        ClientEncryptor::create(&encryption_public_key).expect("couldn't create client encryptor");
+    let encrypted_request = client_encryptor
+        .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("client couldn't encrypt request");
+
+    let (server_encryptor, request_length) =
+        ServerEncryptor::decrypt_scoped(&encrypted_request, &encryption_key, |plaintext| {
+            assert_eq!(TEST_REQUEST_MESSAGE, plaintext);
+            plaintext.len()
+        })
+        .expect("server couldn't decrypt request");
+    assert_eq!(TEST_REQUEST_MESSAGE.len(), request_length);
+
+    let encrypted_response = server_encryptor
+        .encrypt(TEST_RESPONSE_MESSAGE, TEST_RESPONSE_ASSOCIATED_DATA)
+        .expect("server couldn't encrypt response");
+    for _ in 0..2 {
+        let response_length = client_encryptor
+            .decrypt_scoped(&encrypted_response, |plaintext| {
+                assert_eq!(TEST_RESPONSE_MESSAGE, plaintext);
+                plaintext.len()
+            })
+            .expect("client couldn't decrypt response");
+        assert_eq!(TEST_RESPONSE_MESSAGE.len(), response_length);
+
+        // Check that the buffer is kept for reuse, but doesn't contain plaintext.
+        let buffer = client_encryptor.scoped_plaintext_buffer();
+        assert!(buffer.is_empty());
+        assert!(buffer.capacity() >= TEST_RESPONSE_MESSAGE.len());
+        // SAFETY: zeroization writes zeros to the whole capacity of the buffer, so
+        // all of its bytes are initialized.
+        let buffer_contents =
+            unsafe { core::slice::from_raw_parts(buffer.as_ptr(), buffer.capacity()) };
+        assert!(buffer_contents.iter().all(|byte| *byte == 0));
+    }
+
+    // Check that a failed decryption doesn't call the closure.
+    let mut corrupted_response = encrypted_response.clone();
+    corrupted_response.encrypted_message.as_mut().unwrap().ciphertext[0] ^= 1;
+    assert!(client_encryptor
+        .decrypt_scoped(&corrupted_response, |_| panic!("closure shouldn't be called"))
+        .is_err());
+}
+
+#[test]
+fn test_typed_messages() {
+    const REQUEST_TYPE: u64 = 1;
+    const RESPONSE_TYPE: u64 = 0x0102_0304_0506_0708;
+
+    let (encryption_key, encryption_public_key) = generate_encryption_key_pair();
+    let mut client_encryptor =
+        ClientEncryptor::create(&encryption_public_key).expect("couldn't create client encryptor");
+    let encrypted_request = client_encryptor
+        .encrypt_typed(REQUEST_TYPE, TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("client couldn't encrypt request");
+
+    // The message type is authenticated as a labeled big-endian prefix of the
+    // associated data.
+    let associated_data = &encrypted_request.encrypted_message.as_ref().unwrap().associated_data;
+    assert_eq!(
+        [MESSAGE_TYPE_LABEL, &REQUEST_TYPE.to_be_bytes(), TEST_REQUEST_ASSOCIATED_DATA].concat(),
+        *associated_data
+    );
+
+    assert_eq!(
+        CryptoError::AssociatedDataPolicyViolation,
+        crypto_error(ServerEncryptor::decrypt_typed(
+            &encrypted_request,
+            &encryption_key,
+            RESPONSE_TYPE
+        ))
+    );
+    let (server_encryptor, request, request_associated_data) =
+        ServerEncryptor::decrypt_typed(&encrypted_request, &encryption_key, REQUEST_TYPE)
+            .expect("server couldn't decrypt request");
+    assert_eq!(TEST_REQUEST_MESSAGE, request);
+    assert_eq!(TEST_REQUEST_ASSOCIATED_DATA, request_associated_data);
+
+    let encrypted_response = server_encryptor
+        .encrypt_typed(RESPONSE_TYPE, TEST_RESPONSE_MESSAGE, TEST_RESPONSE_ASSOCIATED_DATA)
+        .expect("server couldn't encrypt response");
+    assert_eq!(
+        CryptoError::AssociatedDataPolicyViolation,
+        crypto_error(client_encryptor.decrypt_typed(&encrypted_response, REQUEST_TYPE))
+    );
+    let (response, response_associated_data) = client_encryptor
+        .decrypt_typed(&encrypted_response, RESPONSE_TYPE)
+        .expect("client couldn't decrypt response");
+    assert_eq!(TEST_RESPONSE_MESSAGE, response);
+    assert_eq!(TEST_RESPONSE_ASSOCIATED_DATA, response_associated_data);
+
+    // Changing the message type in transit fails authentication.
+    let mut retyped_response = encrypted_response;
+    retyped_response.encrypted_message.as_mut().unwrap().associated_data
+        [MESSAGE_TYPE_LABEL.len() + MESSAGE_TYPE_SIZE_BYTES - 1] ^= 1;
+    assert_eq!(
+        CryptoError::AuthenticationFailure,
+        crypto_error(client_encryptor.decrypt_typed(&retyped_response, RESPONSE_TYPE ^ 1))
+    );
+
+    // Messages without a type are rejected.
+    let mut client_encryptor =
+        ClientEncryptor::create(&encryption_public_key).expect("couldn't create client encryptor");
+    let encrypted_request =
+        client_encryptor.encrypt(b"", b"short").expect("client couldn't encrypt request");
+    assert_eq!(
+        CryptoError::MalformedInput,
+        crypto_error(ServerEncryptor::decrypt_typed(&encrypted_request, &encryption_key, 0))
+    );
+}
+
+#[test]
+fn test_associated_data_framings_are_separated() {
+    let (encryption_key, encryption_public_key) = generate_encryption_key_pair();
+    // Requests whose associated data is framed by each API. Plain associated data
+    // of 8 and 4 zero bytes spells out the framing of a message type or an
+    // empty routing header without its label.
+    for name in ["plain", "unlabeled header", "header", "typed"] {
+        let mut client_encryptor = ClientEncryptor::create(&encryption_public_key)
+            .expect("couldn't create client encryptor");
+        let encrypted_request = match name {
+            "plain" => client_encryptor.encrypt(TEST_REQUEST_MESSAGE, &[0; 8]),
+            "unlabeled header" => {
+                client_encryptor.encrypt(TEST_REQUEST_MESSAGE, &[0; LENGTH_PREFIX_SIZE_BYTES])
+            }
+            "header" => client_encryptor.encrypt_with_header(b"", TEST_REQUEST_MESSAGE, b""),
+            _ => client_encryptor.encrypt_typed(0, TEST_REQUEST_MESSAGE, b""),
+        }
+        .expect("couldn't encrypt request");
+        let header_result =
+            ServerEncryptor::decrypt_with_header(&encrypted_request, &encryption_key);
+        assert_eq!(name == "header", header_result.is_ok(), "{}", name);
+        let typed_result = ServerEncryptor::decrypt_typed(&encrypted_request, &encryption_key, 0);
+        assert_eq!(name == "typed", typed_result.is_ok(), "{}", name);
+    }
+
+    // Responses with a message type and with a continuity token.
+    let client_secret = {
+        let mut client_encryptor = ClientEncryptor::create(&encryption_public_key)
+            .expect("couldn't create client encryptor");
+        client_encryptor.encrypt(b"", b"").expect("couldn't encrypt request");
+        client_encryptor.continuity_secret().expect("couldn't derive continuity secret")
+    };
+    let token = ContinuityToken::new([0; CONTINUITY_TOKEN_SIZE_BYTES]);
+    for typed in [false, true] {
+        let (mut client_encryptor, check) =
+            ClientEncryptor::reestablish_with_continuity(&encryption_public_key, &client_secret)
+                .expect("couldn't re-establish session");
+        let encrypted_request =
+            client_encryptor.encrypt(b"", b"").expect("couldn't encrypt request");
+        let (server_encryptor, _, _) =
+            ServerEncryptor::decrypt(&encrypted_request, &encryption_key)
+                .expect("couldn't decrypt request");
+        let encrypted_response = if typed {
+            server_encryptor.encrypt_typed(
+                0,
+                TEST_RESPONSE_MESSAGE,
+                &[0; CONTINUITY_TOKEN_SIZE_BYTES],
+            )
+        } else {
+            server_encryptor.encrypt_with_continuity(&token, TEST_RESPONSE_MESSAGE, b"")
+        }
+        .expect("couldn't encrypt response");
+        if typed {
+            let result = client_encryptor.decrypt_with_continuity(&encrypted_response, &check);
+            assert_eq!(CryptoError::ContinuityBroken, crypto_error(result));
+        } else {
+            let result = client_encryptor.decrypt_typed(&encrypted_response, 0);
+            assert_eq!(CryptoError::MalformedInput, crypto_error(result));
+        }
+    }
+}
+
+#[test]
+fn test_session_continuity() {
+    // Runs the first exchange of a session re-established with the previous
+    // `client_secret`, in which the server holding `encryption_key` answers
+    // with `token`, and returns the result of the continuity check.
+    fn reestablish(
+        encryption_key: &EncryptionKey,
+        encryption_public_key: &[u8],
+        client_secret: &ContinuitySecret,
+        token: Option<&ContinuityToken>,
+    ) -> anyhow::Result<(std::vec::Vec<u8>, std::vec::Vec<u8>)> {
+        let (mut client_encryptor, check) =
+            ClientEncryptor::reestablish_with_continuity(encryption_public_key, client_secret)
+                .expect("couldn't re-establish session");
+        let encrypted_request = client_encryptor
+            .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+            .expect("client couldn't encrypt request");
+        let (server_encryptor, _, _) = ServerEncryptor::decrypt(&encrypted_request, encryption_key)
+            .expect("server couldn't decrypt request");
+        let encrypted_response = match token {
+            Some(token) => server_encryptor.encrypt_with_continuity(
+                token,
+                TEST_RESPONSE_MESSAGE,
+                TEST_RESPONSE_ASSOCIATED_DATA,
+            ),
+            None => server_encryptor.encrypt(TEST_RESPONSE_MESSAGE, TEST_RESPONSE_ASSOCIATED_DATA),
+        }
+        .expect("server couldn't encrypt response");
+        client_encryptor.decrypt_with_continuity(&encrypted_response, &check)
+    }
+
+    // Healthy first session, after which both peers store the continuity
+    // secret.
+    let (old_key, old_public_key) = generate_encryption_key_pair();
+    let mut client_encryptor =
+        ClientEncryptor::create(&old_public_key).expect("couldn't create client encryptor");
+    let encrypted_request = client_encryptor
+        .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("client couldn't encrypt request");
+    let (server_encryptor, _, _) = ServerEncryptor::decrypt(&encrypted_request, &old_key)
+        .expect("server couldn't decrypt request");
+    let client_secret =
+        client_encryptor.continuity_secret().expect("couldn't derive continuity secret");
+    let server_secret = server_encryptor.continuity_secret();
+    assert_eq!(client_secret.as_bytes(), server_secret.as_bytes());
+
+    // Same key holder.
+    let (response, response_associated_data) = reestablish(
+        &old_key,
+        &old_public_key,
+        &client_secret,
+        Some(&server_secret.hand_off_token(&old_public_key)),
+    )
+    .expect("continuity check failed for the same key");
+    assert_eq!(TEST_RESPONSE_MESSAGE, response);
+    assert_eq!(TEST_RESPONSE_ASSOCIATED_DATA, response_associated_data);
+
+    // Honest rotation, in which the old key holder hands off a token for the
+    // new key.
+    let (new_key, new_public_key) = generate_encryption_key_pair();
+    let hand_off_token = server_secret.hand_off_token(&new_public_key);
+    reestablish(&new_key, &new_public_key, &client_secret, Some(&hand_off_token))
+        .expect("continuity check failed for a rotated key");
+
+    // Silent substitution, in which the attacker doesn't know the continuity
+    // secret. Neither tokens for other keys nor tokens from other sessions
+    // are accepted.
+    let (attacker_key, attacker_public_key) = generate_encryption_key_pair();
+    let mut other_client_encryptor =
+        ClientEncryptor::create(&attacker_public_key).expect("couldn't create client encryptor");
+    let other_request = other_client_encryptor
+        .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("client couldn't encrypt request");
+    let (other_server_encryptor, _, _) = ServerEncryptor::decrypt(&other_request, &attacker_key)
+        .expect("server couldn't decrypt request");
+    for token in [
+        None,
+        Some(server_secret.hand_off_token(&old_public_key)),
+        Some(hand_off_token),
+        Some(other_server_encryptor.continuity_secret().hand_off_token(&attacker_public_key)),
+        Some(ContinuityToken::new([0; CONTINUITY_TOKEN_SIZE_BYTES])),
+    ] {
+        assert_eq!(
+            CryptoError::ContinuityBroken,
+            crypto_error(reestablish(
+                &attacker_key,
+                &attacker_public_key,
+                &client_secret,
+                token.as_ref()
+            ))
+        );
+    }
+
+    // The secret needs the response key.
+    client_encryptor.drop_response();
+    assert!(client_encryptor.continuity_secret().is_err());
+}
+
+#[test]
+fn test_recipient_identity_binding() {
+    let (encryption_key, encryption_public_key) = generate_encryption_key_pair();
+    let (_, other_public_key) = generate_encryption_key_pair();
+
+    // Both peers bind the same identity.
+    let mut client_encryptor =
+        ClientEncryptor::create_with_recipient_identity(&encryption_public_key)
+            .expect("couldn't create client encryptor");
+    let encrypted_request = client_encryptor
+        .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("client couldn't encrypt request");
+    let (server_encryptor, request, _) =
+        ServerEncryptor::decrypt(&encrypted_request, &encryption_key.bind_recipient_identity())
+            .expect("server couldn't decrypt request");
+    assert_eq!(TEST_REQUEST_MESSAGE, request);
+    let encrypted_response = server_encryptor
+        .encrypt(TEST_RESPONSE_MESSAGE, TEST_RESPONSE_ASSOCIATED_DATA)
+        .expect("server couldn't encrypt response");
+    let (response, _) =
+        client_encryptor.decrypt(&encrypted_response).expect("client couldn't decrypt response");
+    assert_eq!(TEST_RESPONSE_MESSAGE, response);
+
+    // The binding has to be used by both peers.
+    assert!(ServerEncryptor::decrypt(&encrypted_request, &encryption_key).is_err());
+    let mut unbound_client_encryptor =
+        ClientEncryptor::create(&encryption_public_key).expect("couldn't create client encryptor");
+    let unbound_request = unbound_client_encryptor
+        .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("client couldn't encrypt request");
+    assert!(ServerEncryptor::decrypt(&unbound_request, &encryption_key.bind_recipient_identity())
+        .is_err());
+
+    // A client that encrypts to the server key while binding another identity
+    // can't set up a session.
+    let (serialized_encapsulated_public_key, sender_context) =
+        setup_base_sender(&encryption_public_key, &recipient_bound_info(&other_public_key))
+            .expect("couldn't set up sender");
+    let nonce = generate_random_nonce();
+    let substituted_request = EncryptedRequest {
+        encrypted_message: Some(AeadEncryptedMessage {
+            nonce: nonce.to_vec(),
+            ciphertext: sender_context
+                .seal(&nonce, TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+                .expect("couldn't encrypt request"),
+            associated_data: TEST_REQUEST_ASSOCIATED_DATA.to_vec(),
+        }),
+        serialized_encapsulated_public_key: Some(serialized_encapsulated_public_key),
+    };
+    assert!(ServerEncryptor::decrypt(
+        &substituted_request,
+        &encryption_key.bind_recipient_identity()
+    )
+    .is_err());
+}
+
+#[test]
+fn test_psk_mode() {
+    const TEST_PSK: &[u8] = &[0x42; MIN_PSK_SIZE_BYTES];
+    const TEST_PSK_ID: &[u8] = b"Test PSK ID";
+    let (encryption_key, encryption_public_key) = generate_encryption_key_pair();
+
+    // Both peers use the same PSK.
+    let mut client_encryptor =
+        ClientEncryptor::create_with_psk(&encryption_public_key, TEST_PSK, TEST_PSK_ID)
+            .expect("couldn't create client encryptor");
+    let encrypted_request = client_encryptor
+        .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("client couldn't encrypt request");
+    let (server_encryptor, request, _) = ServerEncryptor::decrypt(
+        &encrypted_request,
+        &encryption_key.with_psk(TEST_PSK, TEST_PSK_ID),
+    )
+    .expect("server couldn't decrypt request");
+    assert_eq!(TEST_REQUEST_MESSAGE, request);
+    let encrypted_response = server_encryptor
+        .encrypt(TEST_RESPONSE_MESSAGE, TEST_RESPONSE_ASSOCIATED_DATA)
+        .expect("server couldn't encrypt response");
+    let (response, _) =
+        client_encryptor.decrypt(&encrypted_response).expect("client couldn't decrypt response");
+    assert_eq!(TEST_RESPONSE_MESSAGE, response);
+
+    // Peers that disagree on the PSK, the PSK ID or the mode can't set up a
+    // session.
+    let other_psk = [0x43; MIN_PSK_SIZE_BYTES];
+    for handle in [
+        encryption_key.with_psk(&other_psk, TEST_PSK_ID),
+        encryption_key.with_psk(TEST_PSK, b"Other PSK ID"),
+    ] {
+        let result = ServerEncryptor::decrypt(&encrypted_request, &handle);
+        assert_eq!(CryptoError::AuthenticationFailure, crypto_error(result));
+    }
+    let result = ServerEncryptor::decrypt(&encrypted_request, &encryption_key);
+    assert_eq!(CryptoError::AuthenticationFailure, crypto_error(result));
+    let mut base_client_encryptor =
+        ClientEncryptor::create(&encryption_public_key).expect("couldn't create client encryptor");
+    let base_request = base_client_encryptor
+        .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("client couldn't encrypt request");
+    let result =
+        ServerEncryptor::decrypt(&base_request, &encryption_key.with_psk(TEST_PSK, TEST_PSK_ID));
+    assert_eq!(CryptoError::AuthenticationFailure, crypto_error(result));
+
+    // PSK inputs are checked on both sides.
+    let short_psk = &TEST_PSK[1..];
+    let long_psk = [0x42; MAX_PSK_SIZE_BYTES + 1];
+    let long_psk_id = [0x42; MAX_PSK_ID_SIZE_BYTES + 1];
+    for (psk, psk_id) in [
+        (short_psk, TEST_PSK_ID),
+        (TEST_PSK, b"".as_slice()),
+        (&long_psk, TEST_PSK_ID),
+        (TEST_PSK, &long_psk_id),
+    ] {
+        let result = ClientEncryptor::create_with_psk(&encryption_public_key, psk, psk_id);
+        assert_eq!(CryptoError::MalformedInput, crypto_error(result));
+        let result =
+            ServerEncryptor::decrypt(&encrypted_request, &encryption_key.with_psk(psk, psk_id));
+        assert_eq!(CryptoError::MalformedInput, crypto_error(result));
+    }
+
+    // Inputs at the maximum sizes are accepted.
+    let max_psk = &long_psk[..MAX_PSK_SIZE_BYTES];
+    let max_psk_id = &long_psk_id[..MAX_PSK_ID_SIZE_BYTES];
+    let mut max_client_encryptor =
+        ClientEncryptor::create_with_psk(&encryption_public_key, max_psk, max_psk_id)
+            .expect("couldn't create client encryptor");
+    let max_request = max_client_encryptor
+        .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("client couldn't encrypt request");
+    let (_, request, _) =
+        ServerEncryptor::decrypt(&max_request, &encryption_key.with_psk(max_psk, max_psk_id))
+            .expect("server couldn't decrypt request");
+    assert_eq!(TEST_REQUEST_MESSAGE, request);
+
+    // The key policy has to allow PSK mode.
+    let encryption_key = EncryptionKey::deserialize(&mut encryption_key.serialize())
+        .expect("couldn't restore key")
+        .with_policy(KeyPolicy { not_after: None, allowed_modes: std::vec![HpkeMode::Base] });
+    assert!(ServerEncryptor::decrypt(
+        &encrypted_request,
+        &encryption_key.with_psk(TEST_PSK, TEST_PSK_ID)
+    )
+    .is_err());
+}
+
+#[test]
+fn test_secret_material_len() {
+    let (encryption_key, encryption_public_key) = generate_encryption_key_pair();
+    let mut client_encryptor =
+        ClientEncryptor::create(&encryption_public_key).expect("couldn't create client encryptor");
+    // Request and response keys.
+    assert_eq!(2 * AEAD_ALGORITHM_KEY_SIZE_BYTES, client_encryptor.secret_material_len());
+
+    let encrypted_request = client_encryptor
+        .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("client couldn't encrypt request");
+    let (server_encryptor, _, _) = ServerEncryptor::decrypt(&encrypted_request, &encryption_key)
+        .expect("server couldn't decrypt request");
+    assert_eq!(2 * AEAD_ALGORITHM_KEY_SIZE_BYTES, server_encryptor.secret_material_len());
+
+    // The scoped plaintext buffer is counted once it has been allocated.
+    let encrypted_response = server_encryptor
+        .encrypt(TEST_RESPONSE_MESSAGE, TEST_RESPONSE_ASSOCIATED_DATA)
+        .expect("server couldn't encrypt response");
+    client_encryptor
+        .decrypt_scoped(&encrypted_response, |_| ())
+        .expect("client couldn't decrypt response");
+    assert!(
+        client_encryptor.secret_material_len()
+            >= 2 * AEAD_ALGORITHM_KEY_SIZE_BYTES + TEST_RESPONSE_MESSAGE.len()
+    );
+
+    let mut request_only_client_encryptor =
+        RequestOnlyClientEncryptor::create(&encryption_public_key)
+            .expect("couldn't create client encryptor");
+    assert_eq!(AEAD_ALGORITHM_KEY_SIZE_BYTES, request_only_client_encryptor.secret_material_len());
+    let initial_request = request_only_client_encryptor
+        .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("client couldn't encrypt request");
+    let (request_only_server_encryptor, _, _) =
+        RequestOnlyServerEncryptor::decrypt(&initial_request, &encryption_key)
+            .expect("server couldn't decrypt request");
+    assert_eq!(AEAD_ALGORITHM_KEY_SIZE_BYTES, request_only_server_encryptor.secret_material_len());
+}
+
+#[test]
+fn test_verify_associated_data() {
+    let (encryption_key, encryption_public_key) = generate_encryption_key_pair();
+    let mut client_encryptor =
+        ClientEncryptor::create(&encryption_public_key).expect("couldn't create client encryptor");
+    let encrypted_request = client_encryptor
+        .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("client couldn't encrypt request");
+    let (_, _, authenticated_associated_data) =
+        ServerEncryptor::decrypt(&encrypted_request, &encryption_key)
+            .expect("server couldn't decrypt request");
+    // Check that the authenticated associated data is echoed back.
+    assert_eq!(TEST_REQUEST_ASSOCIATED_DATA, authenticated_associated_data);
+
+    let decode = |associated_data: &[u8]| {
+        core::str::from_utf8(associated_data)
+            .map(std::string::ToString::to_string)
+            .map_err(anyhow::Error::msg)
+    };
+    let expected_associated_data = std::string::String::from("Test request associated data");
+    assert_eq!(
+        expected_associated_data,
+        verify_associated_data(&authenticated_associated_data, decode, &expected_associated_data)
+            .expect("couldn't verify associated data")
+    );
+    assert!(verify_associated_data(
+        &authenticated_associated_data,
+        decode,
+        &std::string::String::from("Other associated data")
+    )
+    .is_err());
+    assert!(verify_associated_data(b"\xff", decode, &expected_associated_data).is_err());
+}
+
+// Sessions are kept low because X25519 is slow in unoptimized test builds.
+const TEST_CONCURRENT_THREADS: usize = 64;
+const TEST_SESSIONS_PER_THREAD: usize = 8;
+
+#[test]
+fn test_encryption_key_concurrent_sessions() {
+    let (encryption_key, encryption_public_key) = generate_encryption_key_pair();
+    let done = core::sync::atomic::AtomicBool::new(false);
+    std::thread::scope(|scope| {
+        // Control thread that keeps using the key while sessions are set up.
+        let control = scope.spawn(|| {
+            let serialized_encapsulated_public_key =
+                ClientEncryptor::create(&encryption_public_key)
+                    .expect("couldn't create client encryptor")
+                    .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+                    .expect("couldn't encrypt request")
+                    .serialized_encapsulated_public_key
+                    .expect("initial request doesn't contain encapsulated public key");
+            let expected = encryption_key
+                .derive_shared_keys(&serialized_encapsulated_public_key)
+                .expect("couldn't derive shared keys");
+            while !done.load(core::sync::atomic::Ordering::Relaxed) {
+                let shared_keys = encryption_key
+                    .derive_shared_keys(&serialized_encapsulated_public_key)
+                    .expect("couldn't derive shared keys");
+                assert_eq!(expected.request_key(), shared_keys.request_key());
+                std::thread::sleep(std::time::Duration::from_millis(1));
+            }
+        });
+
+        let workers: std::vec::Vec<_> = (0..TEST_CONCURRENT_THREADS)
+            .map(|_| {
+                scope.spawn(|| {
+                    for _ in 0..TEST_SESSIONS_PER_THREAD {
+                        let mut client_encryptor = ClientEncryptor::create(&encryption_public_key)
+                            .expect("couldn't create client encryptor");
+                        let encrypted_request = client_encryptor
+                            .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+                            .expect("couldn't encrypt request");
+                        let (server_encryptor, plaintext, _) =
+                            ServerEncryptor::decrypt(&encrypted_request, &encryption_key)
+                                .expect("couldn't decrypt request");
+                        assert_eq!(TEST_REQUEST_MESSAGE, plaintext);
+                        let encrypted_response = server_encryptor
+                            .encrypt(TEST_RESPONSE_MESSAGE, TEST_RESPONSE_ASSOCIATED_DATA)
+                            .expect("couldn't encrypt response");
+                        let (plaintext, _) = client_encryptor
+                            .decrypt(&encrypted_response)
+                            .expect("couldn't decrypt response");
+                        assert_eq!(TEST_RESPONSE_MESSAGE, plaintext);
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().expect("worker thread panicked");
+        }
+        done.store(true, core::sync::atomic::Ordering::Relaxed);
+        control.join().expect("control thread panicked");
+    });
+}
+
+#[test]
+fn test_hashed_associated_data() {
+    let (encryption_key, encryption_public_key) = generate_encryption_key_pair();
+    let header = std::vec![0x42u8; 64 * 1024];
+    let mut client_encryptor = ClientEncryptor::create(&encryption_public_key)
+        .expect("couldn't create client encryptor")
+        .with_hashed_aad();
+    let encrypted_request =
+        client_encryptor.encrypt(TEST_REQUEST_MESSAGE, &header).expect("couldn't encrypt request");
+
+    // The server has to hash the associated data too.
+    let result = ServerEncryptor::decrypt(&encrypted_request, &encryption_key);
+    assert_eq!(CryptoError::AuthenticationFailure, crypto_error(result));
+
+    // Changing the header fails verification.
+    let mut tampered_request = encrypted_request.clone();
+    tampered_request.encrypted_message.as_mut().unwrap().associated_data[1000] ^= 1;
+    let result = ServerEncryptor::decrypt_with_hashed_aad(&tampered_request, &encryption_key);
+    assert_eq!(CryptoError::AuthenticationFailure, crypto_error(result));
+
+    let (server_encryptor, plaintext, associated_data) =
+        ServerEncryptor::decrypt_with_hashed_aad(&encrypted_request, &encryption_key)
+            .expect("couldn't decrypt request");
+    assert_eq!(TEST_REQUEST_MESSAGE, plaintext);
+    assert_eq!(header, associated_data);
+
+    let encrypted_response = server_encryptor
+        .encrypt(TEST_RESPONSE_MESSAGE, &header)
+        .expect("couldn't encrypt response");
+    let (plaintext, associated_data) =
+        client_encryptor.decrypt(&encrypted_response).expect("couldn't decrypt response");
+    assert_eq!(TEST_RESPONSE_MESSAGE, plaintext);
+    assert_eq!(header, associated_data);
+}
+
+#[test]
+fn test_hashed_associated_data_is_separated() {
+    let (encryption_key, encryption_public_key) = generate_encryption_key_pair();
+
+    // A hashed request is rejected by a plain server, also if its associated
+    // data is replaced by the digest.
+    let mut client_encryptor = ClientEncryptor::create(&encryption_public_key)
+        .expect("couldn't create client encryptor")
+        .with_hashed_aad();
+    let mut encrypted_request = client_encryptor
+        .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("couldn't encrypt request");
+    let result = ServerEncryptor::decrypt(&encrypted_request, &encryption_key);
+    assert_eq!(CryptoError::AuthenticationFailure, crypto_error(result));
+    encrypted_request.encrypted_message.as_mut().unwrap().associated_data =
+        Sha256::digest(TEST_REQUEST_ASSOCIATED_DATA).to_vec();
+    let result = ServerEncryptor::decrypt(&encrypted_request, &encryption_key);
+    assert_eq!(CryptoError::AuthenticationFailure, crypto_error(result));
+
+    // A plain request whose associated data is a digest is rejected by a hashed
+    // server, whatever associated data it is claimed to be the digest of.
+    let mut client_encryptor =
+        ClientEncryptor::create(&encryption_public_key).expect("couldn't create client encryptor");
+    let encrypted_request = client_encryptor
+        .encrypt(TEST_REQUEST_MESSAGE, &Sha256::digest(TEST_REQUEST_ASSOCIATED_DATA))
+        .expect("couldn't encrypt request");
+    let result = ServerEncryptor::decrypt_with_hashed_aad(&encrypted_request, &encryption_key);
+    assert_eq!(CryptoError::AuthenticationFailure, crypto_error(result));
+    let mut relabeled_request = encrypted_request.clone();
+    relabeled_request.encrypted_message.as_mut().unwrap().associated_data =
+        TEST_REQUEST_ASSOCIATED_DATA.to_vec();
+    let result = ServerEncryptor::decrypt_with_hashed_aad(&relabeled_request, &encryption_key);
+    assert_eq!(CryptoError::AuthenticationFailure, crypto_error(result));
+}
+
+#[test]
+fn test_aad_policy() {
+    let policies = [
+        (AadPolicy::RequireEmpty, false),
+        (AadPolicy::RequireExact(TEST_REQUEST_ASSOCIATED_DATA.to_vec()), true),
+        (AadPolicy::RequireExact(b"Test request".to_vec()), false),
+        (AadPolicy::RequirePrefix(b"Test request".to_vec()), true),
+        (AadPolicy::RequirePrefix(b"Test response".to_vec()), false),
+        (AadPolicy::Validator(|associated_data| associated_data.is_ascii()), true),
+        (AadPolicy::Validator(|associated_data| associated_data.len() < 4), false),
+    ];
+    let (encryption_key, encryption_public_key) = generate_encryption_key_pair();
+    for (policy, satisfied) in policies {
+        let mut client_encryptor = ClientEncryptor::create(&encryption_public_key)
+            .expect("couldn't create client encryptor");
+        let encrypted_request = client_encryptor
+            .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+            .expect("client couldn't encrypt request");
+        let result =
+            ServerEncryptor::decrypt_with_policy(&encrypted_request, &encryption_key, &policy);
+        if satisfied {
+            let (_, plaintext, _) = result.expect("server couldn't decrypt request");
+            assert_eq!(TEST_REQUEST_MESSAGE, plaintext);
+        } else {
+            assert_eq!(CryptoError::AssociatedDataPolicyViolation, crypto_error(result));
+        }
+    }
+    assert!(AadPolicy::RequireEmpty.check(&[]).is_ok());
+
+    // Response associated data is checked by the client.
+    let mut client_encryptor =
+        ClientEncryptor::create(&encryption_public_key).expect("couldn't create client encryptor");
+    let encrypted_request = client_encryptor
+        .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("client couldn't encrypt request");
+    let (server_encryptor, _, associated_data) =
+        ServerEncryptor::decrypt(&encrypted_request, &encryption_key)
+            .expect("server couldn't decrypt request");
+    // The server echoes the request associated data back.
+    let encrypted_response = server_encryptor
+        .encrypt(TEST_RESPONSE_MESSAGE, &associated_data)
+        .expect("server couldn't encrypt response");
+    let policy = AadPolicy::RequirePrefix(b"Test response".to_vec());
+    let result = client_encryptor.decrypt_with_policy(&encrypted_response, &policy);
+    assert_eq!(CryptoError::AssociatedDataPolicyViolation, crypto_error(result));
+    let policy = AadPolicy::RequireExact(TEST_REQUEST_ASSOCIATED_DATA.to_vec());
+    let (plaintext, _) = client_encryptor
+        .decrypt_with_policy(&encrypted_response, &policy)
+        .expect("client couldn't decrypt response");
+    assert_eq!(TEST_RESPONSE_MESSAGE, plaintext);
+
+    // Authentication failures are reported before the policy is checked.
+    let mut tampered_response = encrypted_response.clone();
+    tampered_response.encrypted_message.as_mut().unwrap().associated_data = b"".to_vec();
+    let result = client_encryptor.decrypt_with_policy(&tampered_response, &AadPolicy::RequireEmpty);
+    assert_eq!(CryptoError::AuthenticationFailure, crypto_error(result));
+}
+
+const TEST_KEY_NOT_AFTER_MILLIS: u64 = 1_700_000_000_000;
+
+#[test]
+fn test_key_policy() {
+    let (encryption_key, encryption_public_key) = generate_encryption_key_pair();
+    let encryption_key = encryption_key.with_policy(KeyPolicy {
+        not_after: Some(TEST_KEY_NOT_AFTER_MILLIS),
+        allowed_modes: std::vec![HpkeMode::Base],
+    });
+
+    let mut client_encryptor =
+        ClientEncryptor::create(&encryption_public_key).expect("couldn't create client encryptor");
+    let encrypted_request = client_encryptor
+        .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("client couldn't encrypt request");
+
+    // Check that a non-expired key can be used.
+    let (_, decrypted_request, _) = ServerEncryptor::decrypt(
+        &encrypted_request,
+        &encryption_key.at_time(TEST_KEY_NOT_AFTER_MILLIS),
+    )
+    .expect("server couldn't decrypt request");
+    assert_eq!(TEST_REQUEST_MESSAGE, decrypted_request);
+
+    // Check that an expired key is rejected.
+    assert!(ServerEncryptor::decrypt(
+        &encrypted_request,
+        &encryption_key.at_time(TEST_KEY_NOT_AFTER_MILLIS + 1)
+    )
+    .is_err());
+
+    // Check that an expiring key can't be used without providing the current time.
+    assert!(ServerEncryptor::decrypt(&encrypted_request, &encryption_key).is_err());
+}
+
+#[test]
+fn test_key_policy_clock_skew() {
+    const TEST_LEEWAY_MS: u64 = 3_000;
+    let clock_skew_policy = ClockSkewPolicy { leeway_ms: TEST_LEEWAY_MS };
+    let (encryption_key, encryption_public_key) = generate_encryption_key_pair();
+    let encryption_key = encryption_key.with_policy(KeyPolicy {
+        not_after: Some(TEST_KEY_NOT_AFTER_MILLIS),
+        allowed_modes: std::vec![HpkeMode::Base],
+    });
+
+    let mut client_encryptor =
+        ClientEncryptor::create(&encryption_public_key).expect("couldn't create client encryptor");
+    let encrypted_request = client_encryptor
+        .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("client couldn't encrypt request");
+
+    let decrypt_at = |current_time_millis, clock_skew_policy| {
+        ServerEncryptor::decrypt(
+            &encrypted_request,
+            &encryption_key.at_time(current_time_millis).with_clock_skew_policy(clock_skew_policy),
+        )
+    };
+    assert!(decrypt_at(TEST_KEY_NOT_AFTER_MILLIS, clock_skew_policy).is_ok());
+    assert!(decrypt_at(TEST_KEY_NOT_AFTER_MILLIS + TEST_LEEWAY_MS, clock_skew_policy).is_ok());
+    assert!(decrypt_at(TEST_KEY_NOT_AFTER_MILLIS + TEST_LEEWAY_MS + 1, clock_skew_policy).is_err());
+    // Check that there is no leeway by default.
+    assert!(decrypt_at(TEST_KEY_NOT_AFTER_MILLIS + 1, ClockSkewPolicy::default()).is_err());
+}
+
+#[test]
+fn test_key_policy_at_time_for_all_key_uses() {
+    const TEST_PSK: &[u8] = &[0x42; MIN_PSK_SIZE_BYTES];
+    const TEST_PSK_ID: &[u8] = b"Test PSK ID";
+    let (encryption_key, encryption_public_key) = generate_encryption_key_pair();
+    let encryption_key = encryption_key.with_policy(KeyPolicy {
+        not_after: Some(TEST_KEY_NOT_AFTER_MILLIS),
+        allowed_modes: std::vec![HpkeMode::Base, HpkeMode::Psk],
+    });
+    let valid_key = encryption_key.at_time(TEST_KEY_NOT_AFTER_MILLIS);
+    let expired_key = encryption_key.at_time(TEST_KEY_NOT_AFTER_MILLIS + 1);
+
+    // Shared keys and decapsulation checks.
+    let (encapsulated_public_key, _) =
+        setup_base_sender_shared_keys(&encryption_public_key).expect("couldn't set up shared keys");
+    assert!(valid_key.derive_shared_keys(&encapsulated_public_key).is_ok());
+    assert!(expired_key.derive_shared_keys(&encapsulated_public_key).is_err());
+    assert!(encryption_key.derive_shared_keys(&encapsulated_public_key).is_err());
+    assert!(valid_key.can_decap(&encapsulated_public_key));
+    assert!(!expired_key.can_decap(&encapsulated_public_key));
+    assert!(!encryption_key.can_decap(&encapsulated_public_key));
+
+    // Request-only sessions, including request frames.
+    let mut client_encryptor = RequestOnlyClientEncryptor::create(&encryption_public_key)
+        .expect("couldn't create client encryptor");
+    let encrypted_request = client_encryptor
+        .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("couldn't encrypt request");
+    assert!(RequestOnlyServerEncryptor::decrypt(&encrypted_request, &valid_key).is_ok());
+    assert!(RequestOnlyServerEncryptor::decrypt(&encrypted_request, &expired_key).is_err());
+    assert!(RequestOnlyServerEncryptor::decrypt(&encrypted_request, &encryption_key).is_err());
+    let mut buffer = [0u8; 256];
+    let mut writer = LengthPrefixedWriter::new(&mut buffer, TEST_MAX_RECORD_LENGTH);
+    RequestFrameEncoder::create(&encryption_public_key)
+        .expect("couldn't create frame encoder")
+        .encode(&mut writer, TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("couldn't encode frame");
+    let written = writer.written().to_vec();
+    for (key, valid) in [(valid_key, true), (expired_key, false)] {
+        let mut reader = LengthPrefixedReader::new(&written, TEST_MAX_RECORD_LENGTH);
+        assert_eq!(valid, RequestFrameDecoder::new().decode(&mut reader, &key).is_ok());
+    }
+
+    // Sessions that bind the recipient identity or a PSK.
+    let mut client_encryptor =
+        ClientEncryptor::create_with_recipient_identity(&encryption_public_key)
+            .expect("couldn't create client encryptor");
+    let encrypted_request = client_encryptor
+        .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("couldn't encrypt request");
+    let result = ServerEncryptor::decrypt(&encrypted_request, &valid_key.bind_recipient_identity());
+    assert!(result.is_ok());
+    let result =
+        ServerEncryptor::decrypt(&encrypted_request, &expired_key.bind_recipient_identity());
+    assert!(result.is_err());
+    let result =
+        ServerEncryptor::decrypt(&encrypted_request, &encryption_key.bind_recipient_identity());
+    assert!(result.is_err());
+    let mut client_encryptor =
+        ClientEncryptor::create_with_psk(&encryption_public_key, TEST_PSK, TEST_PSK_ID)
+            .expect("couldn't create client encryptor");
+    let encrypted_request = client_encryptor
+        .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("couldn't encrypt request");
+    let result =
+        ServerEncryptor::decrypt(&encrypted_request, &valid_key.with_psk(TEST_PSK, TEST_PSK_ID));
+    assert!(result.is_ok());
+    let result =
+        ServerEncryptor::decrypt(&encrypted_request, &expired_key.with_psk(TEST_PSK, TEST_PSK_ID));
+    assert!(result.is_err());
+    let result = ServerEncryptor::decrypt(
+        &encrypted_request,
+        &encryption_key.with_psk(TEST_PSK, TEST_PSK_ID),
+    );
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_key_policy_allowed_modes() {
+    let (encryption_key, encryption_public_key) = generate_encryption_key_pair();
+    let encryption_key =
+        encryption_key.with_policy(KeyPolicy { not_after: None, allowed_modes: std::vec![] });
+
+    let mut client_encryptor =
+        ClientEncryptor::create(&encryption_public_key).expect("couldn't create client encryptor");
+    let encrypted_request = client_encryptor
+        .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("client couldn't encrypt request");
+
+    assert!(ServerEncryptor::decrypt(&encrypted_request, &encryption_key).is_err());
+}
+
+const TEST_MASTER_IKM: &[u8] = b"Test master input keying material";
+const TEST_TENANT_ID_ONE: &[u8] = b"Test tenant one";
+const TEST_TENANT_ID_TWO: &[u8] = b"Test tenant two";
+
+#[test]
+fn test_tenant_key_derivation() {
+    let deriver = RecipientKeyDeriver::new(TEST_MASTER_IKM).expect("couldn't create key deriver");
+
+    let (_, public_key_one) = deriver.derive_tenant_key_pair(TEST_TENANT_ID_ONE);
+    let (_, public_key_one_again) = deriver.derive_tenant_key_pair(TEST_TENANT_ID_ONE);
+    let (_, public_key_two) = deriver.derive_tenant_key_pair(TEST_TENANT_ID_TWO);
+    // Check that derivation is deterministic and separates tenants.
+    assert_eq!(public_key_one, public_key_one_again);
+    assert_ne!(public_key_one, public_key_two);
+
+    // Check that a different master secret results in different tenant keys.
+    let other_deriver = RecipientKeyDeriver::new(b"Other master input keying material")
+        .expect("couldn't create key deriver");
+    let (_, other_public_key_one) = other_deriver.derive_tenant_key_pair(TEST_TENANT_ID_ONE);
+    assert_ne!(public_key_one, other_public_key_one);
+
+    assert!(RecipientKeyDeriver::new(b"Too short").is_err());
+}
+
+#[test]
+fn test_tenant_key_provider() {
+    let mut deriver =
+        RecipientKeyDeriver::new(TEST_MASTER_IKM).expect("couldn't create key deriver");
+    let (_, public_key_one) = deriver.derive_tenant_key_pair(TEST_TENANT_ID_ONE);
+    let (_, public_key_two) = deriver.derive_tenant_key_pair(TEST_TENANT_ID_TWO);
+
+    let mut client_encryptor =
+        ClientEncryptor::create(&public_key_one).expect("couldn't create client encryptor");
+    let encrypted_request = client_encryptor
+        .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("client couldn't encrypt request");
+
+    let (_, decrypted_request, _) = ServerEncryptor::decrypt(
+        &encrypted_request,
+        deriver.provider_for_tenant(TEST_TENANT_ID_ONE),
+    )
+    .expect("server couldn't decrypt request");
+    assert_eq!(TEST_REQUEST_MESSAGE, decrypted_request);
+
+    // Check that another tenant's key can't decrypt the request.
+    assert!(ServerEncryptor::decrypt(
+        &encrypted_request,
+        deriver.provider_for_tenant(TEST_TENANT_ID_TWO)
+    )
+    .is_err());
+
+    let mut client_encryptor =
+        ClientEncryptor::create(&public_key_two).expect("couldn't create client encryptor");
+    let encrypted_request = client_encryptor
+        .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("client couldn't encrypt request");
+    assert!(ServerEncryptor::decrypt(
+        &encrypted_request,
+        deriver.provider_for_tenant(TEST_TENANT_ID_TWO)
+    )
+    .is_ok());
+}
+
+#[test]
+fn test_tenant_key_cache_eviction() {
+    let tenant_id_three: &[u8] = b"Test tenant three";
+    let mut deriver = RecipientKeyDeriver::with_cache_capacity(TEST_MASTER_IKM, 2)
+        .expect("couldn't create key deriver");
+
+    deriver.provider_for_tenant(TEST_TENANT_ID_ONE);
+    deriver.provider_for_tenant(TEST_TENANT_ID_TWO);
+    // Use the first tenant again, so that the second tenant becomes the least
+    // recently used one.
+    deriver.provider_for_tenant(TEST_TENANT_ID_ONE);
+    deriver.provider_for_tenant(tenant_id_three);
+
+    assert!(deriver.is_cached(TEST_TENANT_ID_ONE));
+    assert!(!deriver.is_cached(TEST_TENANT_ID_TWO));
+    assert!(deriver.is_cached(tenant_id_three));
+}
+
+#[test]
+fn test_sessions_outlive_encryption_key() {
+    let (encryption_key, public_key) = generate_encryption_key_pair();
+    let mut deriver =
+        RecipientKeyDeriver::new(TEST_MASTER_IKM).expect("couldn't create key deriver");
+    let (_, tenant_public_key) = deriver.derive_tenant_key_pair(TEST_TENANT_ID_ONE);
+
+    let mut client_encryptors = std::vec::Vec::new();
+    let mut server_encryptors = std::vec::Vec::new();
+    for (public_key, handle) in [
+        (&public_key, &encryption_key.at_time(0) as &dyn EncryptionKeyHandle),
+        (&tenant_public_key, deriver.provider_for_tenant(TEST_TENANT_ID_ONE)),
+    ] {
+        let mut client_encryptor =
+            ClientEncryptor::create(public_key).expect("couldn't create client encryptor");
+        let encrypted_request = client_encryptor
+            .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+            .expect("couldn't encrypt request");
+        let (server_encryptor, _, _) =
+            ServerEncryptor::decrypt(&encrypted_request, handle).expect("couldn't decrypt request");
+        client_encryptors.push(client_encryptor);
+        server_encryptors.push(server_encryptor);
+    }
+    // Sessions don't borrow the key, including the keys cached by the deriver.
+    drop(encryption_key);
+    drop(deriver);
+
+    for (client_encryptor, server_encryptor) in client_encryptors.into_iter().zip(server_encryptors)
+    {
+        assert_eq!(2 * AEAD_ALGORITHM_KEY_SIZE_BYTES, server_encryptor.secret_material_len());
+        let mut pusher = server_encryptor.into_simplex_pusher();
+        let mut receiver = client_encryptor.into_simplex_receiver();
+        for _ in 0..3 {
+            let pushed_message = pusher
+                .push(TEST_RESPONSE_MESSAGE, TEST_RESPONSE_ASSOCIATED_DATA)
+                .expect("couldn't push message");
+            let (plaintext, _) =
+                receiver.receive(&pushed_message).expect("couldn't receive message");
+            assert_eq!(TEST_RESPONSE_MESSAGE, plaintext);
+        }
+    }
+}
+
+const TEST_BATCH_SIZE: usize = 16;
+
+#[test]
+fn test_encryption_key_serialization() {
+    let (encryption_key, public_key) = generate_encryption_key_pair();
+    let serialized_private_key = encryption_key.serialize();
+    assert_eq!(PRIVATE_KEY_SIZE_BYTES, serialized_private_key.len());
+
+    // The restored key has the same public key, and the input is zeroized.
+    let mut input = serialized_private_key.clone();
+    let encryption_key = EncryptionKey::deserialize(&mut input).expect("couldn't restore key");
+    assert_eq!(public_key, encryption_key.serialized_public_key());
+    assert_eq!(std::vec![0; PRIVATE_KEY_SIZE_BYTES], input);
+    let mut client_encryptor =
+        ClientEncryptor::create(&public_key).expect("couldn't create client encryptor");
+    let encrypted_request = client_encryptor
+        .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("couldn't encrypt request");
+    assert!(ServerEncryptor::decrypt(&encrypted_request, &encryption_key).is_ok());
+
+    for size in [0, PRIVATE_KEY_SIZE_BYTES - 1, PRIVATE_KEY_SIZE_BYTES + 1] {
+        let mut input = std::vec![1; size];
+        let result = EncryptionKey::deserialize(&mut input);
+        assert_eq!(CryptoError::MalformedInput, crypto_error(result));
+        assert!(input.iter().all(|byte| *byte == 0));
+    }
+}
+
+#[test]
+fn test_encryption_key_from_private_and_public() {
+    let (encryption_key, public_key) = generate_encryption_key_pair();
+    let (_, other_public_key) = generate_encryption_key_pair();
+    let serialized_private_key = encryption_key.serialize();
+
+    let encryption_key =
+        EncryptionKey::from_private_and_public(&mut serialized_private_key.clone(), &public_key)
+            .expect("couldn't load key pair");
+    let mut client_encryptor =
+        ClientEncryptor::create(&public_key).expect("couldn't create client encryptor");
+    let encrypted_request = client_encryptor
+        .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("couldn't encrypt request");
+    let (_, plaintext, _) = ServerEncryptor::decrypt(&encrypted_request, &encryption_key)
+        .expect("couldn't decrypt request");
+    assert_eq!(TEST_REQUEST_MESSAGE, plaintext);
+
+    let result = EncryptionKey::from_private_and_public(
+        &mut serialized_private_key.clone(),
+        &other_public_key,
+    );
+    assert_eq!(CryptoError::KeyMismatch, crypto_error(result));
+    let result = EncryptionKey::from_private_and_public(
+        &mut serialized_private_key.clone(),
+        &public_key[1..],
+    );
+    assert_eq!(CryptoError::KeyMismatch, crypto_error(result));
+}
+
+#[test]
+fn test_generate_many_encryption_key_pairs() {
+    const PROVISIONING_BATCH_SIZE: usize = 1000;
+    let key_pairs =
+        generate_encryption_key_pairs(PROVISIONING_BATCH_SIZE).expect("couldn't generate keys");
+    let public_keys: std::collections::BTreeSet<&std::vec::Vec<u8>> =
+        key_pairs.iter().map(|(_, public_key)| public_key).collect();
+    assert_eq!(PROVISIONING_BATCH_SIZE, public_keys.len());
+}
+
+#[test]
+fn test_generate_encryption_key_pairs() {
+    let key_pairs = generate_encryption_key_pairs(TEST_BATCH_SIZE).expect("couldn't generate keys");
+    assert_eq!(TEST_BATCH_SIZE, key_pairs.len());
+
+    // Check that all generated keys are independent.
+    let public_keys: std::collections::BTreeSet<&std::vec::Vec<u8>> =
+        key_pairs.iter().map(|(_, public_key)| public_key).collect();
+    assert_eq!(TEST_BATCH_SIZE, public_keys.len());
+
+    // Check that every generated private key matches its public key.
+    for (encryption_key, public_key) in key_pairs.iter() {
+        let mut client_encryptor =
+            ClientEncryptor::create(public_key).expect("couldn't create client encryptor");
+        let request = client_encryptor
+            .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+            .expect("couldn't encrypt request");
+        let (_, decrypted_message, _) =
+            ServerEncryptor::decrypt(&request, encryption_key).expect("couldn't decrypt request");
+        assert_eq!(TEST_REQUEST_MESSAGE, decrypted_message);
+    }
+
+    // Check that counts whose entropy size overflows are rejected.
+    assert!(generate_encryption_key_pairs(usize::MAX).is_err());
+}
+
+#[test]
+fn test_derive_encryption_key_pairs_from_seed() {
+    let public_keys =
+        |key_pairs: std::vec::Vec<(_, std::vec::Vec<u8>)>| -> std::vec::Vec<std::vec::Vec<u8>> {
+            key_pairs.into_iter().map(|(_, public_key)| public_key).collect()
+        };
+    let first = derive_encryption_key_pairs_from_seed(TEST_MASTER_IKM, TEST_BATCH_SIZE)
+        .expect("couldn't derive key pairs");
+    let second = derive_encryption_key_pairs_from_seed(TEST_MASTER_IKM, TEST_BATCH_SIZE)
+        .expect("couldn't derive key pairs");
+    let first = public_keys(first);
+    assert_eq!(first, public_keys(second));
+
+    let distinct: std::collections::BTreeSet<&std::vec::Vec<u8>> = first.iter().collect();
+    assert_eq!(TEST_BATCH_SIZE, distinct.len());
+
+    // A smaller batch is a prefix of a larger one.
+    let prefix = derive_encryption_key_pairs_from_seed(TEST_MASTER_IKM, 2)
+        .expect("couldn't derive key pairs");
+    assert_eq!(first[..2], public_keys(prefix)[..]);
+
+    assert!(derive_encryption_key_pairs_from_seed(&TEST_MASTER_IKM[..16], 1).is_err());
+}
+
+#[test]
+fn test_encryption_key_from_seed() {
+    let (encryption_key, public_key) =
+        EncryptionKey::from_seed(TEST_MASTER_IKM).expect("couldn't derive key pair");
+    let (_, same_public_key) =
+        EncryptionKey::from_seed(TEST_MASTER_IKM).expect("couldn't derive key pair");
+    assert_eq!(public_key, same_public_key);
+    assert_eq!(public_key, derive_key_pair(TEST_MASTER_IKM).1.to_bytes().to_vec());
+
+    let other_seed = [TEST_MASTER_IKM, b"!"].concat();
+    let (_, other_public_key) =
+        EncryptionKey::from_seed(&other_seed).expect("couldn't derive key pair");
+    assert_ne!(public_key, other_public_key);
+
+    let mut client_encryptor =
+        ClientEncryptor::create(&public_key).expect("couldn't create client encryptor");
+    let encrypted_request = client_encryptor
+        .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("couldn't encrypt request");
+    let (_, decrypted_request, _) = ServerEncryptor::decrypt(&encrypted_request, &encryption_key)
+        .expect("couldn't decrypt request");
+    assert_eq!(TEST_REQUEST_MESSAGE, decrypted_request);
+
+    assert!(EncryptionKey::from_seed(&TEST_MASTER_IKM[..16]).is_err());
+}
+
+#[test]
+fn test_encryption_key_public_key() {
+    let (encryption_key, public_key) = generate_encryption_key_pair();
+    assert_eq!(
+        encryption_key.public_key().to_bytes().as_slice(),
+        encryption_key.serialized_public_key()
+    );
+    assert_eq!(public_key, encryption_key.serialized_public_key());
+
+    let (other_encryption_key, _) = generate_encryption_key_pair();
+    assert_ne!(encryption_key.public_key(), other_encryption_key.public_key());
+}
+
+#[test]
+fn test_derive_local_secret() {
+    const STORAGE_PURPOSE: &str = "storage sealing key";
+    const CACHE_PURPOSE: &str = "cache sealing key";
+
+    let (encryption_key, public_key) =
+        EncryptionKey::from_seed(TEST_MASTER_IKM).expect("couldn't derive key pair");
+    let secret =
+        encryption_key.derive_local_secret(STORAGE_PURPOSE, 32).expect("couldn't derive secret");
+    assert_eq!(
+        "a518e88f1a579425014fb4ff1f2b6a6d0e1484d34efc01a467e3ecd403104846",
+        hex::encode(&secret)
+    );
+    // Shorter secrets are prefixes of longer ones for the same purpose.
+    let long_secret =
+        encryption_key.derive_local_secret(STORAGE_PURPOSE, 64).expect("couldn't derive secret");
+    assert_eq!(secret[..], long_secret[..32]);
+    assert_ne!(
+        secret,
+        encryption_key.derive_local_secret(CACHE_PURPOSE, 32).expect("couldn't derive secret")
+    );
+
+    // Restored keys derive the same secrets, whether they were serialized or
+    // wrapped for another recipient.
+    let mut serialized_private_key =
+        EncryptionKey::from_seed(TEST_MASTER_IKM).expect("couldn't derive key pair").0.serialize();
+    let restored_key =
+        EncryptionKey::from_private_and_public(&mut serialized_private_key, &public_key)
+            .expect("couldn't restore key");
+    assert_eq!(
+        secret,
+        restored_key.derive_local_secret(STORAGE_PURPOSE, 32).expect("couldn't derive secret")
+    );
+    let (peer_key, peer_public_key) = generate_encryption_key_pair();
+    let wrapped_private_key =
+        encryption_key.encrypted_private_key(&peer_public_key).expect("couldn't wrap key");
+    let (_, mut unwrapped_private_key, _) =
+        ServerEncryptor::decrypt(&wrapped_private_key, &peer_key).expect("couldn't unwrap key");
+    let unwrapped_key =
+        EncryptionKey::deserialize(&mut unwrapped_private_key).expect("couldn't restore key");
+    assert_eq!(
+        secret,
+        unwrapped_key.derive_local_secret(STORAGE_PURPOSE, 32).expect("couldn't derive secret")
+    );
+
+    // Rotating the key pair rotates the secrets.
+    assert_ne!(
+        secret,
+        peer_key.derive_local_secret(STORAGE_PURPOSE, 32).expect("couldn't derive secret")
+    );
+
+    assert!(encryption_key.derive_local_secret("", 32).is_err());
+    assert!(encryption_key.derive_local_secret(STORAGE_PURPOSE, 0).is_err());
+    assert!(encryption_key
+        .derive_local_secret(STORAGE_PURPOSE, MAX_LOCAL_SECRET_SIZE_BYTES + 1)
+        .is_err());
+    encryption_key
+        .derive_local_secret(STORAGE_PURPOSE, MAX_LOCAL_SECRET_SIZE_BYTES)
+        .expect("couldn't derive secret");
+}
+
+const TEST_MAX_RECORD_LENGTH: usize = 1024;
+
+#[test]
+fn test_length_prefixed_records() {
+    let mut buffer = [0u8; 64];
+    let mut writer = LengthPrefixedWriter::new(&mut buffer, TEST_MAX_RECORD_LENGTH);
+    writer.write(TEST_REQUEST_MESSAGE).expect("couldn't write record");
+    writer.write(&[]).expect("couldn't write record");
+    writer.write(TEST_RESPONSE_MESSAGE).expect("couldn't write record");
+    assert_eq!(
+        3 * LENGTH_PREFIX_SIZE_BYTES + TEST_REQUEST_MESSAGE.len() + TEST_RESPONSE_MESSAGE.len(),
+        writer.position()
+    );
+    // Records that don't fit into the buffer are not written.
+    let position = writer.position();
+    assert!(writer.write(&[0u8; 64]).is_err());
+    assert_eq!(position, writer.position());
+    let written = writer.written().to_vec();
+
+    let mut reader = LengthPrefixedReader::new(&written, TEST_MAX_RECORD_LENGTH);
+    assert_eq!(TEST_REQUEST_MESSAGE, reader.read().expect("couldn't read record"));
+    assert!(reader.read().expect("couldn't read record").is_empty());
+    assert_eq!(TEST_RESPONSE_MESSAGE, reader.read().expect("couldn't read record"));
+    assert!(reader.is_empty());
+    assert!(reader.read().is_err());
+
+    // Records longer than the maximum length are rejected on both sides.
+    let mut writer = LengthPrefixedWriter::new(&mut buffer, 4);
+    assert!(writer.write(TEST_REQUEST_MESSAGE).is_err());
+    let mut reader = LengthPrefixedReader::new(&written, 4);
+    assert!(crypto_error(reader.read()).is_malformed_input());
+    assert_eq!(0, reader.position());
+}
+
+#[test]
+fn test_length_prefixed_reader_corrupted_input() {
+    let mut buffer = [0u8; 64];
+    let mut writer = LengthPrefixedWriter::new(&mut buffer, TEST_MAX_RECORD_LENGTH);
+    writer.write(TEST_REQUEST_MESSAGE).expect("couldn't write record");
+    let written = writer.written().to_vec();
+
+    // Every truncation of the input is rejected without reading past its end.
+    for length in 0..written.len() {
+        let mut reader = LengthPrefixedReader::new(&written[..length], TEST_MAX_RECORD_LENGTH);
+        assert!(crypto_error(reader.read()).is_malformed_input());
+        assert_eq!(0, reader.position());
+    }
+
+    // Lengths pointing past the end of the input are rejected, including the
+    // largest representable one.
+    for length in [written.len() as u32, u32::MAX] {
+        let mut corrupted = written.clone();
+        corrupted[..LENGTH_PREFIX_SIZE_BYTES].copy_from_slice(&length.to_be_bytes());
+        let mut reader = LengthPrefixedReader::new(&corrupted, usize::MAX);
+        assert!(crypto_error(reader.read()).is_malformed_input());
+    }
+
+    // Padding after the last record is read as records, and is rejected once it
+    // no longer forms a valid one.
+    let mut padded = written.clone();
+    padded.extend_from_slice(&[0u8; LENGTH_PREFIX_SIZE_BYTES + 1]);
+    let mut reader = LengthPrefixedReader::new(&padded, TEST_MAX_RECORD_LENGTH);
+    assert_eq!(TEST_REQUEST_MESSAGE, reader.read().expect("couldn't read record"));
+    assert!(reader.read().expect("couldn't read record").is_empty());
+    assert!(reader.read().is_err());
+    assert!(!reader.is_empty());
+}
+
+#[test]
+fn test_wire_integers() {
+    let mut encoded = std::vec::Vec::new();
+    for value in [0, 1, u16::MAX] {
+        wire::put_u16(&mut encoded, value);
+    }
+    for value in [0, 1, u32::MAX] {
+        wire::put_u32(&mut encoded, value);
+    }
+    for value in [0, 1, u64::MAX] {
+        wire::put_u64(&mut encoded, value);
+    }
+    for value in [0, 1, 127, 128, u64::MAX] {
+        wire::put_varint(&mut encoded, value);
+    }
+
+    let mut reader = WireReader::new(&encoded);
+    for value in [0, 1, u16::MAX] {
+        assert_eq!(value, reader.get_u16().expect("couldn't read u16"));
+    }
+    for value in [0, 1, u32::MAX] {
+        assert_eq!(value, reader.get_u32().expect("couldn't read u32"));
+    }
+    for value in [0, 1, u64::MAX] {
+        assert_eq!(value, reader.get_u64().expect("couldn't read u64"));
+    }
+    for value in [0, 1, 127, 128, u64::MAX] {
+        assert_eq!(value, reader.get_varint().expect("couldn't read varint"));
+    }
+    assert!(reader.is_empty());
+
+    // Short input is rejected without advancing the reader.
+    let mut reader = WireReader::new(&[1, 2, 3]);
+    assert_eq!(CryptoError::MalformedInput, crypto_error(reader.get_u32()));
+    assert_eq!(CryptoError::MalformedInput, crypto_error(reader.get_bytes(4)));
+    assert_eq!(0, reader.position());
+    assert_eq!(0x0102, reader.get_u16().expect("couldn't read u16"));
+
+    // Truncated, overflowing and non-minimal varints are rejected.
+    let overflowing = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x02];
+    for invalid in [&[][..], &[0x80], &[0x80, 0x00], &[0x81, 0x80, 0x00], &overflowing] {
+        let mut reader = WireReader::new(invalid);
+        assert_eq!(CryptoError::MalformedInput, crypto_error(reader.get_varint()));
+        assert_eq!(0, reader.position());
+    }
+
+    // Writes that don't fit are rejected without writing anything.
+    let mut buffer = [0u8; 3];
+    let mut writer = WireWriter::new(&mut buffer);
+    assert_eq!(CryptoError::MalformedInput, crypto_error(writer.put_u32(u32::MAX)));
+    assert_eq!(CryptoError::MalformedInput, crypto_error(writer.put_varint(1 << 21)));
+    writer.put_u16(u16::MAX).expect("couldn't write u16");
+    writer.put_varint(127).expect("couldn't write varint");
+    assert_eq!(0, writer.remaining());
+    assert_eq!([0xff, 0xff, 0x7f], buffer);
+}
+
+// Encodings of boundary values in every binary format of the crate. Changing
+// an encoding breaks compatibility, so it requires updating the expectations
+// in the same change.
+#[test]
+fn test_wire_encodings_snapshot() {
+    let mut encodings: std::vec::Vec<(std::string::String, std::vec::Vec<u8>)> =
+        std::vec::Vec::new();
+    for value in [0, 1, u16::MAX] {
+        let mut encoded = std::vec::Vec::new();
+        wire::put_u16(&mut encoded, value);
+        encodings.push((std::format!("u16 {}", value), encoded));
+    }
+    for value in [0, 1, u32::MAX] {
+        let mut encoded = std::vec::Vec::new();
+        wire::put_u32(&mut encoded, value);
+        encodings.push((std::format!("u32 {}", value), encoded));
+    }
+    for value in [0, 1, u64::MAX] {
+        let mut encoded = std::vec::Vec::new();
+        wire::put_u64(&mut encoded, value);
+        encodings.push((std::format!("u64 {}", value), encoded));
+    }
+    for value in [0, 1, 127, 128, u64::MAX] {
+        let mut encoded = std::vec::Vec::new();
+        wire::put_varint(&mut encoded, value);
+        encodings.push((std::format!("varint {}", value), encoded));
+    }
+
+    // Records can't be as long as the largest length prefix, which is covered
+    // by `u32` above.
+    for record in [&b""[..], b"\xff"] {
+        let mut buffer = [0u8; LENGTH_PREFIX_SIZE_BYTES + 1];
+        let mut writer = LengthPrefixedWriter::new(&mut buffer, TEST_MAX_RECORD_LENGTH);
+        writer.write(record).expect("couldn't write record");
+        encodings.push((std::format!("record {}", record.len()), writer.written().to_vec()));
+    }
+
+    let (_, public_key) = generate_encryption_key_pair();
+    let mut client_encryptor =
+        ClientEncryptor::create(&public_key).expect("couldn't create client encryptor");
+    for header_length in [0, 1, MAX_ROUTING_HEADER_SIZE_BYTES] {
+        let encrypted_request = client_encryptor
+            .encrypt_with_header(&std::vec![0xff; header_length], b"", b"")
+            .expect("couldn't encrypt request");
+        encodings.push((
+            std::format!("routing_header {}", header_length),
+            encrypted_request.encrypted_message.unwrap().associated_data,
+        ));
+    }
+    for msg_type in [0, 1, u64::MAX] {
+        let encrypted_request =
+            client_encryptor.encrypt_typed(msg_type, b"", b"").expect("couldn't encrypt request");
+        encodings.push((
+            std::format!("message_type {}", msg_type),
+            encrypted_request.encrypted_message.unwrap().associated_data,
+        ));
+    }
+
+    for (issued_at_millis, difficulty) in [(0, 0), (1, 1), (u64::MAX, MAX_SETUP_DIFFICULTY)] {
+        let challenge =
+            SetupChallenge::issue(b"server secret", difficulty, b"client hint", issued_at_millis)
+                .expect("couldn't issue challenge");
+        encodings.push((
+            std::format!("setup_challenge {} {}", issued_at_millis, difficulty),
+            challenge.serialize().to_vec(),
+        ));
+    }
+    // Solutions can only be constructed by solving or deserializing, so
+    // counters other than the first one are round-tripped.
+    let challenge = SetupChallenge::issue(b"server secret", 0, b"client hint", 0)
+        .expect("couldn't issue challenge");
+    for counter in [0, 1, u64::MAX] {
+        let mut serialized = challenge.serialize().to_vec();
+        wire::put_u64(&mut serialized, counter);
+        let solution =
+            SetupSolution::deserialize(&serialized).expect("couldn't deserialize solution");
+        assert_eq!(serialized, solution.serialize());
+        encodings.push((std::format!("setup_solution {}", counter), serialized));
+    }
+    assert_eq!(challenge.solve().serialize()[SETUP_CHALLENGE_SIZE_BYTES..], [0; 8]);
+
+    for sequence_number in [0, 1, u64::MAX] {
+        encodings.push((
+            std::format!("simplex_associated_data {}", sequence_number),
+            simplex_associated_data(sequence_number, b"ad", false),
+        ));
+    }
+    for index in [0, 1, u64::MAX] {
+        encodings.push((
+            std::format!("request_frame_associated_data {}", index),
+            request_frame_associated_data(index, b"ad"),
+        ));
+    }
+
+    let snapshot: std::string::String = encodings
+        .iter()
+        .map(|(name, encoded)| std::format!("{}: {}\n", name, hex::encode(encoded)))
+        .collect();
+    assert_eq!(
+        include_str!("../testdata/wire_encodings.txt"),
+        snapshot,
+        "wire encodings changed, update testdata/wire_encodings.txt"
+    );
+}
+
+#[test]
+fn test_encrypted_records() {
+    let (encryption_key, public_key) = generate_encryption_key_pair();
+    let mut buffer = [0u8; 256];
+    let mut writer = LengthPrefixedWriter::new(&mut buffer, TEST_MAX_RECORD_LENGTH);
+    for message in [TEST_REQUEST_MESSAGE, TEST_RESPONSE_MESSAGE] {
+        let mut client_encryptor =
+            ClientEncryptor::create(&public_key).expect("couldn't create client encryptor");
+        write_encrypted_record(
+            &mut writer,
+            &mut client_encryptor,
+            message,
+            TEST_REQUEST_ASSOCIATED_DATA,
+        )
+        .expect("couldn't write record");
+    }
+
+    // A record that doesn't fit is rejected before the session is used, so the
+    // initial request can still be written into a larger buffer.
+    let mut client_encryptor =
+        ClientEncryptor::create(&public_key).expect("couldn't create client encryptor");
+    let position = writer.position();
+    assert!(write_encrypted_record(
+        &mut writer,
+        &mut client_encryptor,
+        &[0u8; 256],
+        TEST_REQUEST_ASSOCIATED_DATA
+    )
+    .is_err());
+    assert_eq!(position, writer.position());
+    assert_eq!(MAX_MESSAGES_PER_SESSION, client_encryptor.messages_remaining());
+    let written = writer.written().to_vec();
+
+    let mut reader = LengthPrefixedReader::new(&written, TEST_MAX_RECORD_LENGTH);
+    for message in [TEST_REQUEST_MESSAGE, TEST_RESPONSE_MESSAGE] {
+        let (_, plaintext) =
+            read_encrypted_record(&mut reader, &encryption_key, TEST_REQUEST_ASSOCIATED_DATA)
+                .expect("couldn't read record");
+        assert_eq!(message, plaintext);
+    }
+    assert!(reader.is_empty());
+
+    let mut reader = LengthPrefixedReader::new(&written, TEST_MAX_RECORD_LENGTH);
+    let error = crypto_error(read_encrypted_record(
+        &mut reader,
+        &encryption_key,
+        TEST_RESPONSE_ASSOCIATED_DATA,
+    ));
+    assert!(error.is_authentication_failure());
+}
+
+#[test]
+fn test_request_frames() {
+    let (encryption_key, public_key) = generate_encryption_key_pair();
+    let frames: [(&[u8], &[u8]); 3] = [
+        (TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA),
+        (TEST_RESPONSE_MESSAGE, b""),
+        (b"", TEST_RESPONSE_ASSOCIATED_DATA),
+    ];
+    let mut buffer = [0u8; 512];
+    let mut writer = LengthPrefixedWriter::new(&mut buffer, TEST_MAX_RECORD_LENGTH);
+    let mut encoder =
+        RequestFrameEncoder::create(&public_key).expect("couldn't create frame encoder");
+    for (plaintext, associated_data) in frames {
+        encoder.encode(&mut writer, plaintext, associated_data).expect("couldn't encode frame");
+    }
+
+    // A frame that doesn't fit is rejected without writing anything.
+    let position = writer.position();
+    assert!(encoder.encode(&mut writer, &[0u8; 256], TEST_REQUEST_ASSOCIATED_DATA).is_err());
+    assert_eq!(position, writer.position());
+    let written = writer.written().to_vec();
+
+    let mut reader = LengthPrefixedReader::new(&written, TEST_MAX_RECORD_LENGTH);
+    let mut decoder = RequestFrameDecoder::new();
+    for (plaintext, associated_data) in frames {
+        let (decoded_associated_data, decoded_plaintext) =
+            decoder.decode(&mut reader, &encryption_key).expect("couldn't decode frame");
+        assert_eq!(associated_data, decoded_associated_data);
+        assert_eq!(plaintext, decoded_plaintext);
+    }
+    assert!(reader.is_empty());
+
+    // Frames can't be decoded out of order.
+    let mut reader = LengthPrefixedReader::new(&written, TEST_MAX_RECORD_LENGTH);
+    reader.read().expect("couldn't read associated data");
+    reader.read().expect("couldn't read payload");
+    let position = reader.position();
+    assert!(RequestFrameDecoder::new().decode(&mut reader, &encryption_key).is_err());
+    assert_eq!(position, reader.position());
+
+    // Frames after the initial one can't be skipped or replayed, and a
+    // rejected frame doesn't advance the decoder.
+    let mut reader = LengthPrefixedReader::new(&written, TEST_MAX_RECORD_LENGTH);
+    let mut decoder = RequestFrameDecoder::new();
+    decoder.decode(&mut reader, &encryption_key).expect("couldn't decode frame");
+    let mut skipping_reader = LengthPrefixedReader::new(&written, TEST_MAX_RECORD_LENGTH);
+    for _ in 0..4 {
+        skipping_reader.read().expect("couldn't read record");
+    }
+    let error = crypto_error(decoder.decode(&mut skipping_reader, &encryption_key));
+    assert!(error.is_authentication_failure());
+    decoder.decode(&mut reader, &encryption_key).expect("couldn't decode frame");
+    let mut replaying_reader = LengthPrefixedReader::new(&written, TEST_MAX_RECORD_LENGTH);
+    for _ in 0..2 {
+        replaying_reader.read().expect("couldn't read record");
+    }
+    let error = crypto_error(decoder.decode(&mut replaying_reader, &encryption_key));
+    assert!(error.is_authentication_failure());
+    decoder.decode(&mut reader, &encryption_key).expect("couldn't decode frame");
+    assert!(reader.is_empty());
+}
+
+const TEST_SIGNATURE_MESSAGE_ONE: &[u8] = b"Dogs are the best";
+const TEST_SIGNATURE_MESSAGE_TWO: &[u8] = b"Cats are even better";
+
+use crate::{signer::Signer, verifier::Verifier};
+
+#[test]
+fn test_signer_and_verifier() {
+    let signing_key_one = p256::ecdsa::SigningKey::random(&mut rand_core::OsRng);
+
+    let signature = signing_key_one.sign(TEST_SIGNATURE_MESSAGE_ONE);
+
+    let verifying_key_one = p256::ecdsa::VerifyingKey::from(signing_key_one);
+
+    assert!(verifying_key_one.verify(TEST_SIGNATURE_MESSAGE_ONE, &signature).is_ok());
+
+    assert!(verifying_key_one.verify(TEST_SIGNATURE_MESSAGE_TWO, &signature).is_err());
+
+    let verifying_key_two = {
+        let signing_key_two = p256::ecdsa::SigningKey::random(&mut rand_core::OsRng);
+        p256::ecdsa::VerifyingKey::from(signing_key_two)
+    };
+
+    assert!(verifying_key_two.verify(TEST_SIGNATURE_MESSAGE_TWO, &signature).is_err());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_proto_serde() {
+    let (encryption_key, public_key) = generate_encryption_key_pair();
+    let mut client_encryptor =
+        ClientEncryptor::create(&public_key).expect("couldn't create client encryptor");
+    let encrypted_request = client_encryptor
+        .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("couldn't encrypt request");
+
+    let json = serde_json::to_string(&encrypted_request).expect("couldn't serialize to JSON");
+    // Bytes fields are base64 encoded in JSON.
+    let encoded_associated_data =
+        base64::Engine::encode(&base64::prelude::BASE64_STANDARD, TEST_REQUEST_ASSOCIATED_DATA);
+    assert!(json.contains(&std::format!("\"associatedData\":\"{}\"", encoded_associated_data)));
+    let json_request: EncryptedRequest =
+        serde_json::from_str(&json).expect("couldn't deserialize from JSON");
+    assert_eq!(encrypted_request, json_request);
+
+    let mut cbor = std::vec![];
+    ciborium::into_writer(&encrypted_request, &mut cbor).expect("couldn't serialize to CBOR");
+    let cbor_request: EncryptedRequest =
+        ciborium::from_reader(cbor.as_slice()).expect("couldn't deserialize from CBOR");
+    assert_eq!(encrypted_request, cbor_request);
+
+    let (server_encryptor, decrypted_request, _) =
+        ServerEncryptor::decrypt(&cbor_request, &encryption_key).expect("couldn't decrypt request");
+    assert_eq!(TEST_REQUEST_MESSAGE, decrypted_request);
+
+    let encrypted_response = server_encryptor
+        .encrypt(TEST_RESPONSE_MESSAGE, TEST_RESPONSE_ASSOCIATED_DATA)
+        .expect("couldn't encrypt response");
+    let json = serde_json::to_string(&encrypted_response).expect("couldn't serialize to JSON");
+    let json_response: crate::proto::oak::crypto::v1::EncryptedResponse =
+        serde_json::from_str(&json).expect("couldn't deserialize from JSON");
+    let (decrypted_response, _) =
+        client_encryptor.decrypt(&json_response).expect("couldn't decrypt response");
+    assert_eq!(TEST_RESPONSE_MESSAGE, decrypted_response);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_proto_serde_rejects_oversized_byte_arrays() {
+    // CBOR map with a single entry, whose ciphertext is an array header that
+    // declares 2^60 elements, without any elements.
+    let mut cbor = std::vec![0xa1, 0x6a];
+    cbor.extend_from_slice(b"ciphertext");
+    cbor.push(0x9b);
+    cbor.extend_from_slice(&(1u64 << 60).to_be_bytes());
+    // Fails for the missing elements instead of aborting the process when
+    // preallocating the declared length.
+    let result = ciborium::from_reader::<AeadEncryptedMessage, _>(cbor.as_slice());
+    assert!(result.is_err());
+}
+
+#[cfg(feature = "compact")]
+#[test]
+fn test_compact_envelope() {
+    use prost::Message;
+
+    let (encryption_key, public_key) = generate_encryption_key_pair();
+    let mut client_encryptor =
+        ClientEncryptor::create(&public_key).expect("couldn't create client encryptor");
+
+    // Initial compact request, answered with a proto response.
+    let compact_request = client_encryptor
+        .encrypt_to_compact(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("couldn't encrypt request");
+    assert_eq!(Some(&crate::compact::COMPACT_FORMAT_VERSION), compact_request.first());
+    let (server_encryptor, decrypted_request, associated_data) =
+        ServerEncryptor::decrypt_any(&compact_request, &encryption_key)
+            .expect("couldn't decrypt request");
+    assert_eq!(TEST_REQUEST_MESSAGE, decrypted_request);
+    assert_eq!(TEST_REQUEST_ASSOCIATED_DATA, associated_data);
+
+    let proto_response = server_encryptor
+        .encrypt(TEST_RESPONSE_MESSAGE, TEST_RESPONSE_ASSOCIATED_DATA)
+        .expect("couldn't encrypt response")
+        .encode_to_vec();
+    let (decrypted_response, _) =
+        client_encryptor.decrypt_any(&proto_response).expect("couldn't decrypt response");
+    assert_eq!(TEST_RESPONSE_MESSAGE, decrypted_response);
+
+    // Proto request answered with a compact response.
+    let mut client_encryptor =
+        ClientEncryptor::create(&public_key).expect("couldn't create client encryptor");
+    let proto_request = client_encryptor
+        .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("couldn't encrypt request");
+    let (server_encryptor, decrypted_request, _) =
+        ServerEncryptor::decrypt_any(&proto_request.encode_to_vec(), &encryption_key)
+            .expect("couldn't decrypt request");
+    assert_eq!(TEST_REQUEST_MESSAGE, decrypted_request);
+
+    let compact_response = server_encryptor
+        .encrypt_to_compact(TEST_RESPONSE_MESSAGE, TEST_RESPONSE_ASSOCIATED_DATA)
+        .expect("couldn't encrypt response");
+    let (decrypted_response, _) =
+        client_encryptor.decrypt_any(&compact_response).expect("couldn't decrypt response");
+    assert_eq!(TEST_RESPONSE_MESSAGE, decrypted_response);
+
+    // The compact envelope is smaller than the proto encoding of the same message.
+    let compact_request =
+        crate::compact::encode_request(&proto_request).expect("couldn't encode request");
+    assert!(compact_request.len() + 6 <= proto_request.encoded_len());
+    assert_eq!(
+        proto_request,
+        crate::compact::decode_request(&compact_request).expect("couldn't decode request")
+    );
+
+    // Unknown versions are rejected.
+    let mut invalid_request = compact_request.clone();
+    invalid_request[0] = crate::compact::COMPACT_FORMAT_VERSION + 1;
+    assert_eq!(
+        CryptoError::UnsupportedProtocolVersion,
+        crypto_error(crate::compact::decode_request(&invalid_request))
+    );
+}
+
+#[cfg(feature = "compact")]
+#[test]
+fn test_compact_envelope_rejects_malformed_envelopes() {
+    let (_, public_key) = generate_encryption_key_pair();
+    let mut client_encryptor =
+        ClientEncryptor::create(&public_key).expect("couldn't create client encryptor");
+    let compact_request = client_encryptor
+        .encrypt_to_compact(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("couldn't encrypt request");
+
+    let truncated_request = &compact_request[..compact_request.len() - 1];
+    let mut trailing_request = compact_request.clone();
+    trailing_request.push(0);
+    for request in [&compact_request[..1], truncated_request, &trailing_request] {
+        assert_eq!(
+            CryptoError::MalformedInput,
+            crypto_error(crate::compact::decode_request(request))
+        );
+        assert_eq!(
+            CryptoError::MalformedInput,
+            crypto_error(crate::compact::decode_any_request(request))
+        );
+    }
+    assert_eq!(CryptoError::MalformedInput, crypto_error(crate::compact::decode_request(&[])));
+
+    // Encapsulated public keys of the wrong length can't be encoded.
+    let mut proto_request =
+        crate::compact::decode_request(&compact_request).expect("couldn't decode request");
+    proto_request.serialized_encapsulated_public_key.as_mut().unwrap().pop();
+    assert_eq!(
+        CryptoError::MalformedInput,
+        crypto_error(crate::compact::encode_request(&proto_request))
+    );
+}
+
+#[cfg(feature = "compact")]
+#[test]
+fn test_envelope_version() {
+    use crate::compact::{EnvelopeVersion, COMPACT_FORMAT_VERSION};
+
+    assert_eq!(COMPACT_FORMAT_VERSION, u8::from(EnvelopeVersion::CURRENT));
+    for value in 0..=u8::MAX {
+        let result = EnvelopeVersion::try_from(value);
+        if value == COMPACT_FORMAT_VERSION {
+            assert_eq!(value, u8::from(result.expect("known version is rejected")));
+        } else {
+            assert_eq!(CryptoError::UnsupportedProtocolVersion, crypto_error(result));
+        }
+    }
+}
+
+#[cfg(feature = "compact")]
+#[test]
+fn test_compact_envelope_rejects_unsupported_versions() {
+    let (encryption_key, public_key) = generate_encryption_key_pair();
+    let mut client_encryptor =
+        ClientEncryptor::create(&public_key).expect("couldn't create client encryptor");
+    let compact_request = client_encryptor
+        .encrypt_to_compact(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("couldn't encrypt request");
+    let (server_encryptor, _, _) = ServerEncryptor::decrypt_any(&compact_request, &encryption_key)
+        .expect("couldn't decrypt request");
+    let compact_response = server_encryptor
+        .encrypt_to_compact(TEST_RESPONSE_MESSAGE, TEST_RESPONSE_ASSOCIATED_DATA)
+        .expect("couldn't encrypt response");
+    // Producers stamp the highest supported version.
+    assert_eq!(Some(crate::PROTOCOL_VERSION_RANGE.end()), compact_request.first());
+    assert_eq!(Some(crate::PROTOCOL_VERSION_RANGE.end()), compact_response.first());
+
+    for version in (0..8).filter(|version| !crate::supports_version(*version)) {
+        let mut request = compact_request.clone();
+        request[0] = version;
+        let mut response = compact_response.clone();
+        response[0] = version;
+        assert_eq!(
+            CryptoError::UnsupportedProtocolVersion,
+            crypto_error(crate::compact::decode_request(&request))
+        );
+        assert_eq!(
+            CryptoError::UnsupportedProtocolVersion,
+            crypto_error(crate::compact::decode_any_request(&request))
+        );
+        assert_eq!(
+            CryptoError::UnsupportedProtocolVersion,
+            crypto_error(ServerEncryptor::decrypt_any(&request, &encryption_key))
+        );
+        assert_eq!(
+            CryptoError::UnsupportedProtocolVersion,
+            crypto_error(crate::compact::decode_response(&response))
+        );
+        assert_eq!(
+            CryptoError::UnsupportedProtocolVersion,
+            crypto_error(crate::compact::decode_any_response(&response))
+        );
+        assert_eq!(
+            CryptoError::UnsupportedProtocolVersion,
+            crypto_error(client_encryptor.decrypt_any(&response))
+        );
+    }
+}
+
+const TEST_ENVELOPE_ROUND_TRIPS: usize = 64;
+
+fn random_bytes(max_length: usize) -> std::vec::Vec<u8> {
+    use rand_core::RngCore;
+    let mut bytes = std::vec![0u8; rand_core::OsRng.next_u32() as usize % (max_length + 1)];
+    rand_core::OsRng.fill_bytes(&mut bytes);
+    bytes
+}
+
+fn random_encrypted_message() -> EncryptedMessage {
+    use rand_core::RngCore;
+    let mut nonce = [0u8; SUITE_LENGTHS.nn];
+    rand_core::OsRng.fill_bytes(&mut nonce);
+    EncryptedMessage {
+        nonce,
+        ciphertext: [random_bytes(64), std::vec![0u8; SUITE_LENGTHS.nt]].concat(),
+        associated_data: random_bytes(64),
+    }
+}
+
+#[test]
+fn test_envelope_round_trip() {
+    for index in 0..TEST_ENVELOPE_ROUND_TRIPS {
+        let request_envelope = RequestEnvelope {
+            encapsulated_public_key: (index % 2 == 0).then(|| {
+                let (_, public_key) = generate_encryption_key_pair();
+                public_key.try_into().expect("public key has the encapsulated key size")
+            }),
+            encrypted_message: random_encrypted_message(),
+        };
+        let encrypted_request = EncryptedRequest::from(request_envelope.clone());
+        assert_eq!(
+            Some(request_envelope.clone()),
+            RequestEnvelope::try_from(encrypted_request).ok()
+        );
+
+        let response_envelope = ResponseEnvelope { encrypted_message: random_encrypted_message() };
+        let encrypted_response = EncryptedResponse::from(response_envelope.clone());
+        assert_eq!(Some(response_envelope), ResponseEnvelope::try_from(encrypted_response).ok());
+    }
+
+    // Envelopes of real messages can still be decrypted after a round trip.
+    let (encryption_key, public_key) = generate_encryption_key_pair();
+    let mut client_encryptor =
+        ClientEncryptor::create(&public_key).expect("couldn't create client encryptor");
+    let encrypted_request = client_encryptor
+        .encrypt(TEST_REQUEST_MESSAGE, TEST_REQUEST_ASSOCIATED_DATA)
+        .expect("couldn't encrypt request");
+    let request_envelope =
+        RequestEnvelope::try_from(encrypted_request).expect("couldn't convert request");
+    let (_, decrypted_request, _) =
+        ServerEncryptor::decrypt(&request_envelope.into(), &encryption_key)
+            .expect("couldn't decrypt request");
+    assert_eq!(TEST_REQUEST_MESSAGE, decrypted_request);
+}
+
+#[test]
+fn test_envelope_validation() {
+    let valid_request = EncryptedRequest::from(RequestEnvelope {
+        encapsulated_public_key: Some([1u8; SUITE_LENGTHS.nenc]),
+        encrypted_message: random_encrypted_message(),
+    });
+    let check = |request: EncryptedRequest, field: &str| {
+        let error = RequestEnvelope::try_from(request).expect_err("invalid request was accepted");
+        assert_eq!(Some(&CryptoError::MalformedInput), error.downcast_ref::<CryptoError>());
+        assert!(std::format!("{:#}", error).contains(field), "{:#} doesn't name {}", error, field);
+    };
+
+    check(
+        EncryptedRequest { encrypted_message: None, ..valid_request.clone() },
+        "EncryptedRequest.encrypted_message",
+    );
+    check(
+        EncryptedRequest {
+            serialized_encapsulated_public_key: Some(std::vec![1u8; SUITE_LENGTHS.nenc - 1]),
+            ..valid_request.clone()
+        },
+        "EncryptedRequest.serialized_encapsulated_public_key",
+    );
+    let mut request = valid_request.clone();
+    request.encrypted_message.as_mut().unwrap().nonce.push(0);
+    check(request, "EncryptedRequest.encrypted_message.nonce");
+    let mut request = valid_request.clone();
+    request.encrypted_message.as_mut().unwrap().ciphertext.truncate(SUITE_LENGTHS.nt - 1);
+    check(request, "EncryptedRequest.encrypted_message.ciphertext");
+
+    let error = ResponseEnvelope::try_from(EncryptedResponse { encrypted_message: None })
+        .expect_err("invalid response was accepted");
+    assert_eq!(Some(&CryptoError::MalformedInput), error.downcast_ref::<CryptoError>());
+    assert!(std::format!("{:#}", error).contains("EncryptedResponse.encrypted_message"));
+}
+
+/// Fake RNG that repeats `pattern`.
+struct PatternRng {
+    pattern: &'static [u8],
+    position: usize,
+}
+
+impl rand_core::RngCore for PatternRng {
+    fn next_u32(&mut self) -> u32 {
+        rand_core::impls::next_u32_via_fill(self)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        rand_core::impls::next_u64_via_fill(self)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for byte in dest {
+            *byte = self.pattern[self.position % self.pattern.len()];
+            self.position += 1;
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+#[test]
+fn test_entropy_health_check() {
+    let mut stuck_rng = PatternRng { pattern: &[0], position: 0 };
+    let result = entropy_health_check(&mut stuck_rng, DEFAULT_ENTROPY_CHECK_SAMPLES);
+    assert_eq!(CryptoError::EntropySuspect, crypto_error(result));
+
+    let mut alternating_rng = PatternRng { pattern: &[0x00, 0xFF], position: 0 };
+    let result = entropy_health_check(&mut alternating_rng, DEFAULT_ENTROPY_CHECK_SAMPLES);
+    assert_eq!(CryptoError::EntropySuspect, crypto_error(result));
+
+    assert!(entropy_health_check(&mut rand_core::OsRng, DEFAULT_ENTROPY_CHECK_SAMPLES).is_ok());
+
+    // Sessions can still be created once the check is required, since the OS RNG
+    // passes it.
+    require_entropy_check();
+    let (_, public_key) = generate_encryption_key_pair();
+    assert!(ClientEncryptor::create(&public_key).is_ok());
+    assert!(RequestOnlyClientEncryptor::create(&public_key).is_ok());
+}
+
+#[cfg(feature = "debug")]
+#[test]
+fn test_hexdump() {
+    assert_eq!("", crate::util::hexdump(&[]));
+    assert_eq!(
+        "00017f80abcdefff",
+        crate::util::hexdump(&[0x00, 0x01, 0x7F, 0x80, 0xAB, 0xCD, 0xEF, 0xFF])
+    );
 }
@@ -0,0 +1,375 @@
+//
+// Copyright 2024 The Project Oak Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Resumable transfers of large payloads as a stream of chunks pushed over a
+//! simplex session, see [`SimplexPusher::into_transfer_sender`].
+//!
+//! Before the chunks, the server pushes an encrypted [`TransferManifest`] with
+//! the number of chunks, the total size and the SHA-256 hash of every chunk.
+//! The chunks and the manifest of a transfer occupy consecutive positions in
+//! the stream, are sealed with random nonces, and are bound to a transfer
+//! id, computed as `HKDF-SHA256(salt = TRANSFER_ID_LABEL, ikm = request_key ||
+//! response_key, info = I2OSP(first_sequence_number, 8))`, where the session
+//! keys are the HPKE exporter outputs and the first sequence number is the
+//! position of the manifest in the stream. Chunks of another session or
+//! another transfer of the same session therefore fail authentication.
+//!
+//! If the transfer is interrupted, the client checks the chunks it already
+//! stored against the manifest with [`TransferReceiver::resume`], and the
+//! server pushes the remaining chunks again, starting at the returned index.
+//! The sender only seals chunks that match the manifest, so a chunk that is
+//! pushed again has the same plaintext. The transfer is only
+//! complete once [`TransferReceiver::finish`] checked all chunks against the
+//! manifest.
+//!
+//! [`SimplexPusher::into_transfer_sender`]: crate::encryptor::SimplexPusher::into_transfer_sender
+
+use alloc::{format, vec::Vec};
+
+use anyhow::Context;
+use hkdf::Hkdf;
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+use crate::{
+    encryptor::MAX_MESSAGES_PER_SESSION,
+    error::CryptoError,
+    hpke::{
+        aead::AeadKey, deserialize_nonce, generate_random_nonce, RecipientContext, SenderContext,
+    },
+    labels::TRANSFER_ID_LABEL,
+    proto::oak::crypto::v1::{AeadEncryptedMessage, EncryptedResponse},
+    util::wire::{self, WireReader},
+};
+
+/// Size of the id that binds the chunks of a transfer to its manifest.
+pub const TRANSFER_ID_SIZE_BYTES: usize = 32;
+/// Size of the SHA-256 hash of a chunk in a [`TransferManifest`].
+pub const CHUNK_HASH_SIZE_BYTES: usize = 32;
+
+/// Number of chunks, total size and hashes of the chunks of a transfer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TransferManifest {
+    total_size: u64,
+    chunk_hashes: Vec<[u8; CHUNK_HASH_SIZE_BYTES]>,
+}
+
+impl TransferManifest {
+    /// Creates the manifest of a transfer of `chunks`, in order.
+    pub fn new<'a>(chunks: impl IntoIterator<Item = &'a [u8]>) -> Self {
+        let mut total_size = 0u64;
+        let chunk_hashes = chunks
+            .into_iter()
+            .map(|chunk| {
+                total_size += chunk.len() as u64;
+                hash_chunk(chunk)
+            })
+            .collect();
+        Self { total_size, chunk_hashes }
+    }
+
+    pub fn chunk_count(&self) -> u64 {
+        self.chunk_hashes.len() as u64
+    }
+
+    /// Total size of the chunks in bytes.
+    pub fn total_size(&self) -> u64 {
+        self.total_size
+    }
+
+    /// Checks that `chunk` is the chunk of the transfer at `index`.
+    fn check_chunk(&self, index: u64, chunk: &[u8]) -> anyhow::Result<()> {
+        let expected_hash = usize::try_from(index)
+            .ok()
+            .and_then(|index| self.chunk_hashes.get(index))
+            .ok_or_else(|| {
+                CryptoError::MalformedInput.context(format!(
+                    "chunk index {} is out of range, transfer has {} chunks",
+                    index,
+                    self.chunk_count()
+                ))
+            })?;
+        if hash_chunk(chunk) != *expected_hash {
+            return Err(CryptoError::AuthenticationFailure
+                .context(format!("chunk {} doesn't match the manifest", index)));
+        }
+        Ok(())
+    }
+
+    /// Serializes the manifest as `I2OSP(chunk_count, 8) || I2OSP(total_size,
+    /// 8) || chunk_hashes`.
+    fn serialize(&self) -> Vec<u8> {
+        let mut serialized =
+            Vec::with_capacity(16 + self.chunk_hashes.len() * CHUNK_HASH_SIZE_BYTES);
+        wire::put_u64(&mut serialized, self.chunk_count());
+        wire::put_u64(&mut serialized, self.total_size);
+        for chunk_hash in &self.chunk_hashes {
+            serialized.extend_from_slice(chunk_hash);
+        }
+        serialized
+    }
+
+    fn deserialize(serialized: &[u8]) -> anyhow::Result<Self> {
+        let mut reader = WireReader::new(serialized);
+        let chunk_count = reader.get_u64()?;
+        let total_size = reader.get_u64()?;
+        let hashes_size = usize::try_from(chunk_count)
+            .ok()
+            .and_then(|chunk_count| chunk_count.checked_mul(CHUNK_HASH_SIZE_BYTES));
+        if hashes_size != Some(reader.remaining().len()) {
+            return Err(CryptoError::MalformedInput.context(format!(
+                "manifest declares {} chunks, but has {} bytes of chunk hashes",
+                chunk_count,
+                reader.remaining().len()
+            )));
+        }
+        let chunk_hashes =
+            (0..chunk_count).map(|_| reader.get_array()).collect::<anyhow::Result<_>>()?;
+        Ok(Self { total_size, chunk_hashes })
+    }
+}
+
+/// Sender of a transfer, created with
+/// [`SimplexPusher::into_transfer_sender`](crate::encryptor::SimplexPusher::into_transfer_sender).
+pub struct TransferSender {
+    recipient_context: RecipientContext,
+    transfer_id: [u8; TRANSFER_ID_SIZE_BYTES],
+    manifest: TransferManifest,
+}
+
+impl TransferSender {
+    /// Creates the sender of a transfer whose manifest is pushed as the message
+    /// at `first_sequence_number`. Returns the sender and the encrypted
+    /// manifest.
+    pub(crate) fn create(
+        recipient_context: RecipientContext,
+        first_sequence_number: u64,
+        manifest: TransferManifest,
+    ) -> anyhow::Result<(Self, EncryptedResponse)> {
+        check_stream_capacity(first_sequence_number, manifest.chunk_count())?;
+        let transfer_id = recipient_context.transfer_id(first_sequence_number);
+        let sender = Self { recipient_context, transfer_id, manifest };
+        let nonce = generate_random_nonce();
+        let ciphertext = sender
+            .recipient_context
+            .seal(&nonce, &sender.manifest.serialize(), &sender.transfer_id)
+            .context("couldn't encrypt transfer manifest")?;
+        let encrypted_manifest = EncryptedResponse {
+            encrypted_message: Some(AeadEncryptedMessage {
+                nonce: nonce.to_vec(),
+                ciphertext,
+                associated_data: Vec::new(),
+            }),
+        };
+        Ok((sender, encrypted_manifest))
+    }
+
+    pub fn manifest(&self) -> &TransferManifest {
+        &self.manifest
+    }
+
+    /// Encrypts the chunk of the transfer at `index`. Chunks that don't match
+    /// the manifest are rejected, so pushing a chunk again after an
+    /// interruption yields a message with the same plaintext.
+    pub fn push_chunk(&self, index: u64, chunk: &[u8]) -> anyhow::Result<EncryptedResponse> {
+        self.manifest.check_chunk(index, chunk).context("couldn't push chunk")?;
+        let nonce = generate_random_nonce();
+        let ciphertext = self
+            .recipient_context
+            .seal(&nonce, chunk, &chunk_associated_data(&self.transfer_id, index))
+            .context("couldn't encrypt chunk")?;
+        let mut associated_data = Vec::with_capacity(8);
+        wire::put_u64(&mut associated_data, index);
+        Ok(EncryptedResponse {
+            encrypted_message: Some(AeadEncryptedMessage {
+                nonce: nonce.to_vec(),
+                ciphertext,
+                associated_data,
+            }),
+        })
+    }
+}
+
+/// Receiver of a transfer, created with
+/// [`SimplexReceiver::into_transfer_receiver`](crate::encryptor::SimplexReceiver::into_transfer_receiver).
+pub struct TransferReceiver {
+    sender_context: SenderContext,
+    transfer_id: [u8; TRANSFER_ID_SIZE_BYTES],
+    manifest: TransferManifest,
+    /// Hashes of the chunks received so far, in order.
+    received_hashes: Vec<[u8; CHUNK_HASH_SIZE_BYTES]>,
+    /// Total size of the chunks received so far.
+    received_size: u64,
+}
+
+impl TransferReceiver {
+    /// Decrypts the manifest of a transfer that was pushed as the message at
+    /// `first_sequence_number`.
+    pub(crate) fn create(
+        sender_context: SenderContext,
+        first_sequence_number: u64,
+        encrypted_manifest: &EncryptedResponse,
+    ) -> anyhow::Result<Self> {
+        let encrypted_message = encrypted_manifest
+            .encrypted_message
+            .as_ref()
+            .context("manifest doesn't contain encrypted message")?;
+        let transfer_id = sender_context.transfer_id(first_sequence_number)?;
+        let nonce =
+            deserialize_nonce(&encrypted_message.nonce).context("couldn't deserialize nonce")?;
+        let serialized_manifest = sender_context
+            .open(&nonce, &encrypted_message.ciphertext, &transfer_id)
+            .context("couldn't decrypt transfer manifest")?;
+        let manifest = TransferManifest::deserialize(&serialized_manifest)
+            .context("couldn't deserialize transfer manifest")?;
+        check_stream_capacity(first_sequence_number, manifest.chunk_count())?;
+        Ok(Self {
+            sender_context,
+            transfer_id,
+            manifest,
+            received_hashes: Vec::new(),
+            received_size: 0,
+        })
+    }
+
+    pub fn manifest(&self) -> &TransferManifest {
+        &self.manifest
+    }
+
+    /// Returns the index of the next chunk of the transfer.
+    pub fn next_chunk(&self) -> u64 {
+        self.received_hashes.len() as u64
+    }
+
+    /// Resumes an interrupted transfer from `received_chunks`, the chunks that
+    /// were stored before the interruption. Fails if any of them doesn't match
+    /// the manifest. Returns the index of the chunk at which the sender has to
+    /// resume the transfer.
+    pub fn resume(&mut self, received_chunks: &[&[u8]]) -> anyhow::Result<u64> {
+        let mut received_hashes = Vec::with_capacity(received_chunks.len());
+        let mut received_size = 0u64;
+        for (index, chunk) in received_chunks.iter().enumerate() {
+            self.manifest
+                .check_chunk(index as u64, chunk)
+                .context("couldn't resume transfer from received chunks")?;
+            received_hashes.push(hash_chunk(chunk));
+            received_size += chunk.len() as u64;
+        }
+        self.received_hashes = received_hashes;
+        self.received_size = received_size;
+        Ok(self.next_chunk())
+    }
+
+    /// Decrypts the next chunk of the transfer. Returns the chunk.
+    ///
+    /// Chunks that don't match the manifest, belong to another transfer or
+    /// don't arrive in order are rejected, and don't advance the transfer.
+    pub fn receive_chunk(
+        &mut self,
+        encrypted_chunk: &EncryptedResponse,
+    ) -> anyhow::Result<Vec<u8>> {
+        let encrypted_message = encrypted_chunk
+            .encrypted_message
+            .as_ref()
+            .context("chunk doesn't contain encrypted message")?;
+        let mut reader = WireReader::new(&encrypted_message.associated_data);
+        let index = reader.get_u64().context("couldn't read chunk index")?;
+        if !reader.is_empty() || index != self.next_chunk() {
+            return Err(CryptoError::MalformedInput
+                .context(format!("expected chunk {} of the transfer", self.next_chunk())));
+        }
+        if index >= self.manifest.chunk_count() {
+            return Err(CryptoError::MalformedInput
+                .context(format!("transfer only has {} chunks", self.manifest.chunk_count())));
+        }
+        let nonce =
+            deserialize_nonce(&encrypted_message.nonce).context("couldn't deserialize nonce")?;
+        let mut chunk = Zeroizing::new(
+            self.sender_context
+                .open(
+                    &nonce,
+                    &encrypted_message.ciphertext,
+                    &chunk_associated_data(&self.transfer_id, index),
+                )
+                .context("couldn't decrypt chunk")?,
+        );
+        self.manifest.check_chunk(index, &chunk)?;
+        self.received_hashes.push(hash_chunk(&chunk));
+        self.received_size += chunk.len() as u64;
+        Ok(core::mem::take(&mut *chunk))
+    }
+
+    /// Completes the transfer, checking that all chunks were received and
+    /// match the manifest.
+    pub fn finish(self) -> anyhow::Result<()> {
+        if self.next_chunk() != self.manifest.chunk_count() {
+            return Err(CryptoError::MalformedInput.context(format!(
+                "transfer is incomplete, received {} of {} chunks",
+                self.next_chunk(),
+                self.manifest.chunk_count()
+            )));
+        }
+        if self.received_hashes != self.manifest.chunk_hashes
+            || self.received_size != self.manifest.total_size
+        {
+            return Err(CryptoError::AuthenticationFailure
+                .context("received chunks don't match the manifest"));
+        }
+        Ok(())
+    }
+}
+
+/// Derives the id of the transfer whose manifest is the message at
+/// `first_sequence_number` of the stream.
+pub(crate) fn derive_transfer_id(
+    request_key: &AeadKey,
+    response_key: &AeadKey,
+    first_sequence_number: u64,
+) -> [u8; TRANSFER_ID_SIZE_BYTES] {
+    let ikm = Zeroizing::new([&request_key.as_bytes()[..], &response_key.as_bytes()[..]].concat());
+    let mut info = Vec::with_capacity(8);
+    wire::put_u64(&mut info, first_sequence_number);
+    let mut transfer_id = [0u8; TRANSFER_ID_SIZE_BYTES];
+    Hkdf::<Sha256>::new(Some(TRANSFER_ID_LABEL), &ikm)
+        .expand(&info, &mut transfer_id)
+        .expect("transfer id size is a valid HKDF-SHA256 output length");
+    transfer_id
+}
+
+fn hash_chunk(chunk: &[u8]) -> [u8; CHUNK_HASH_SIZE_BYTES] {
+    Sha256::digest(chunk).into()
+}
+
+/// Returns the associated data of the chunk at `index`, which is
+/// `transfer_id || I2OSP(index, 8)`.
+fn chunk_associated_data(transfer_id: &[u8; TRANSFER_ID_SIZE_BYTES], index: u64) -> Vec<u8> {
+    let mut associated_data = Vec::with_capacity(TRANSFER_ID_SIZE_BYTES + 8);
+    associated_data.extend_from_slice(transfer_id);
+    wire::put_u64(&mut associated_data, index);
+    associated_data
+}
+
+/// Checks that the manifest and chunks of a transfer fit into the remaining
+/// messages of the session.
+fn check_stream_capacity(first_sequence_number: u64, chunk_count: u64) -> anyhow::Result<()> {
+    if chunk_count >= MAX_MESSAGES_PER_SESSION - first_sequence_number {
+        return Err(CryptoError::NonceExhaustion.context(format!(
+            "transfer of {} chunks exceeds the remaining messages of the session",
+            chunk_count
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,463 @@
+//
+// Copyright 2024 The Project Oak Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Utilities for embedding encrypted messages in custom containers.
+
+use alloc::{format, vec::Vec};
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use anyhow::Context;
+use rand_core::{OsRng, RngCore};
+
+pub mod wire;
+
+#[cfg(feature = "response-direction")]
+use crate::{
+    encryption_key::EncryptionKeyHandle,
+    encryptor::{ClientEncryptor, ServerEncryptor},
+};
+use crate::{
+    encryption_key::RequestOnlyEncryptionKeyHandle,
+    encryptor::{RequestOnlyClientEncryptor, RequestOnlyServerEncryptor},
+    error::CryptoError,
+    hpke::SUITE_LENGTHS,
+    labels::REQUEST_FRAME_LABEL,
+    proto::oak::crypto::v1::{AeadEncryptedMessage, EncryptedRequest},
+    util::wire::{WireReader, WireWriter},
+};
+
+/// Cutoff of the repetition count test: a run of this many identical samples
+/// fails the health check. Computed as `1 + ceil(20 / H)` for a false positive
+/// probability of 2^-20 per sample, assuming a min-entropy `H` of 4 bits per
+/// byte, which any working RNG exceeds.
+const REPETITION_COUNT_CUTOFF: usize = 6;
+/// Window size of the adaptive proportion test for non-binary samples.
+const ADAPTIVE_PROPORTION_WINDOW: usize = 512;
+/// Cutoff of the adaptive proportion test: a window in which the first sample
+/// occurs this many times fails the health check. Taken from SP 800-90B for a
+/// window of 512 samples and a min-entropy of 4 bits per byte.
+const ADAPTIVE_PROPORTION_CUTOFF: usize = 62;
+
+/// Number of bytes drawn by the entropy check enabled with
+/// [`require_entropy_check`].
+pub const DEFAULT_ENTROPY_CHECK_SAMPLES: usize = 4 * ADAPTIVE_PROPORTION_WINDOW;
+
+const ENTROPY_CHECK_NOT_REQUIRED: u8 = 0;
+const ENTROPY_CHECK_REQUIRED: u8 = 1;
+const ENTROPY_CHECK_PASSED: u8 = 2;
+
+static ENTROPY_CHECK_STATE: AtomicU8 = AtomicU8::new(ENTROPY_CHECK_NOT_REQUIRED);
+
+/// Runs the repetition count and adaptive proportion health tests of
+/// SP 800-90B over `samples` bytes drawn from `rng`, to catch RNGs that are
+/// stuck or produce a repeating pattern, e.g. a hardware RNG that returns zeros
+/// right after power-on. Returns a [`CryptoError::EntropySuspect`] error if a
+/// test fails.
+///
+/// The tests only catch gross failures. Passing them doesn't prove that the
+/// output is random.
+/// <https://nvlpubs.nist.gov/nistpubs/SpecialPublications/NIST.SP.800-90B.pdf>
+pub fn entropy_health_check<R: RngCore>(rng: &mut R, samples: usize) -> anyhow::Result<()> {
+    let mut buffer = alloc::vec![0u8; samples];
+    rng.try_fill_bytes(&mut buffer)
+        .map_err(|error| anyhow::anyhow!("couldn't draw random samples: {}", error))?;
+
+    let mut run_length = 0;
+    for (index, sample) in buffer.iter().enumerate() {
+        run_length = if index > 0 && buffer[index - 1] == *sample { run_length + 1 } else { 1 };
+        if run_length >= REPETITION_COUNT_CUTOFF {
+            return Err(CryptoError::EntropySuspect.context(format!(
+                "repetition count test failed: {} consecutive samples of {:#04x}",
+                run_length, sample
+            )));
+        }
+    }
+    for window in buffer.chunks(ADAPTIVE_PROPORTION_WINDOW) {
+        let occurrences = window.iter().filter(|sample| **sample == window[0]).count();
+        if occurrences >= ADAPTIVE_PROPORTION_CUTOFF {
+            return Err(CryptoError::EntropySuspect.context(format!(
+                "adaptive proportion test failed: {} of {} samples are {:#04x}",
+                occurrences,
+                window.len(),
+                window[0]
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Makes the first ephemeral key generation of a client session run
+/// [`entropy_health_check`] on the OS RNG with
+/// [`DEFAULT_ENTROPY_CHECK_SAMPLES`], e.g. on boards whose RNG needs time to
+/// start up. Once the check passes, its result is cached for the lifetime of
+/// the process. A failed check fails session creation and is run again on the
+/// next attempt.
+///
+/// Infallible key generation such as
+/// [`generate_encryption_key_pair`](crate::encryption_key::generate_encryption_key_pair)
+/// isn't covered, and callers should run [`entropy_health_check`] themselves
+/// before it.
+pub fn require_entropy_check() {
+    let _ = ENTROPY_CHECK_STATE.compare_exchange(
+        ENTROPY_CHECK_NOT_REQUIRED,
+        ENTROPY_CHECK_REQUIRED,
+        Ordering::Relaxed,
+        Ordering::Relaxed,
+    );
+}
+
+/// Runs the entropy check if [`require_entropy_check`] was called and the check
+/// hasn't passed yet.
+pub(crate) fn check_entropy_if_required() -> anyhow::Result<()> {
+    if ENTROPY_CHECK_STATE.load(Ordering::Relaxed) != ENTROPY_CHECK_REQUIRED {
+        return Ok(());
+    }
+    entropy_health_check(&mut OsRng, DEFAULT_ENTROPY_CHECK_SAMPLES)?;
+    ENTROPY_CHECK_STATE.store(ENTROPY_CHECK_PASSED, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Returns `bytes` as a lowercase hex string without separators, e.g. for
+/// logging ciphertexts and encapsulated public keys when debugging interop.
+///
+/// This crate never logs, so it is up to the caller to keep keys, plaintexts
+/// and other secrets out of its logs.
+#[cfg(feature = "debug")]
+pub fn hexdump(bytes: &[u8]) -> alloc::string::String {
+    hex::encode(bytes)
+}
+
+/// Size of the length prefix written before each record. Lengths are encoded as
+/// big-endian `u32` values.
+pub const LENGTH_PREFIX_SIZE_BYTES: usize = 4;
+
+/// Writes length-prefixed records into a caller-provided buffer.
+pub struct LengthPrefixedWriter<'a> {
+    buffer: &'a mut [u8],
+    position: usize,
+    max_record_length: usize,
+}
+
+impl<'a> LengthPrefixedWriter<'a> {
+    /// Creates a writer that starts at the beginning of `buffer` and rejects
+    /// records longer than `max_record_length` bytes.
+    pub fn new(buffer: &'a mut [u8], max_record_length: usize) -> Self {
+        Self { buffer, position: 0, max_record_length }
+    }
+
+    /// Writes `record` prefixed with its length. Nothing is written if the
+    /// record doesn't fit into the remaining buffer.
+    pub fn write(&mut self, record: &[u8]) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            record.len() <= self.max_record_length,
+            "record is too long, expected at most {} bytes, got {}",
+            self.max_record_length,
+            record.len()
+        );
+        let length = u32::try_from(record.len())
+            .map_err(|_| anyhow::anyhow!("record length doesn't fit into u32"))?;
+        let remaining = self.remaining();
+        anyhow::ensure!(
+            LENGTH_PREFIX_SIZE_BYTES + record.len() <= remaining,
+            "buffer is too small, {} bytes remaining, {} bytes needed",
+            remaining,
+            LENGTH_PREFIX_SIZE_BYTES + record.len()
+        );
+
+        let mut writer = WireWriter::new(&mut self.buffer[self.position..]);
+        writer.put_u32(length)?;
+        writer.put_bytes(record)?;
+        self.position += writer.position();
+        Ok(())
+    }
+
+    /// Number of bytes written so far.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Number of bytes left in the buffer, including space for length
+    /// prefixes.
+    pub fn remaining(&self) -> usize {
+        self.buffer.len() - self.position
+    }
+
+    /// Returns the part of the buffer that has been written.
+    pub fn written(&self) -> &[u8] {
+        &self.buffer[..self.position]
+    }
+}
+
+/// Reads length-prefixed records written by [`LengthPrefixedWriter`].
+///
+/// Lengths are validated against both the maximum record length and the
+/// remaining input before any data is read, so corrupted lengths never lead to
+/// reading past the end of the input.
+pub struct LengthPrefixedReader<'a> {
+    buffer: &'a [u8],
+    position: usize,
+    max_record_length: usize,
+}
+
+impl<'a> LengthPrefixedReader<'a> {
+    /// Creates a reader that starts at the beginning of `buffer` and rejects
+    /// records longer than `max_record_length` bytes.
+    pub fn new(buffer: &'a [u8], max_record_length: usize) -> Self {
+        Self { buffer, position: 0, max_record_length }
+    }
+
+    /// Reads the next record. The position isn't advanced if the record is
+    /// invalid.
+    pub fn read(&mut self) -> anyhow::Result<&'a [u8]> {
+        let mut reader = WireReader::new(&self.buffer[self.position..]);
+        let length = reader.get_u32().context("truncated length prefix")?;
+        let length = usize::try_from(length).unwrap_or(usize::MAX);
+        if length > self.max_record_length {
+            return Err(CryptoError::MalformedInput.context(format!(
+                "record is too long, expected at most {} bytes, got {}",
+                self.max_record_length, length
+            )));
+        }
+        let record = reader.get_bytes(length).context("truncated record")?;
+
+        self.position += reader.position();
+        Ok(record)
+    }
+
+    /// Number of bytes read so far.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Returns whether all of the input has been read.
+    pub fn is_empty(&self) -> bool {
+        self.position == self.buffer.len()
+    }
+}
+
+/// Encrypts `plaintext` as the initial request of the `encryptor` session and
+/// writes it as a single record, using the layout of
+/// [`ClientEncryptor::encrypt_combined`]. The associated data isn't written and
+/// has to be provided to [`read_encrypted_record`].
+///
+/// Nothing is written and the session isn't advanced if the record doesn't fit
+/// into the remaining buffer, so the initial request isn't spent on a record
+/// that is never written.
+#[cfg(feature = "response-direction")]
+pub fn write_encrypted_record(
+    writer: &mut LengthPrefixedWriter,
+    encryptor: &mut ClientEncryptor,
+    plaintext: &[u8],
+    associated_data: &[u8],
+) -> anyhow::Result<()> {
+    let record_size = SUITE_LENGTHS.nenc + SUITE_LENGTHS.nn + plaintext.len() + SUITE_LENGTHS.nt;
+    anyhow::ensure!(
+        LENGTH_PREFIX_SIZE_BYTES + record_size <= writer.remaining(),
+        "buffer is too small, {} bytes remaining, {} bytes needed",
+        writer.remaining(),
+        LENGTH_PREFIX_SIZE_BYTES + record_size
+    );
+    anyhow::ensure!(
+        record_size <= writer.max_record_length,
+        "record is too long, expected at most {} bytes, got {}",
+        writer.max_record_length,
+        record_size
+    );
+
+    let record = encryptor
+        .encrypt_combined(plaintext, associated_data)
+        .context("couldn't encrypt record")?;
+    writer.write(&record).context("couldn't write record")
+}
+
+/// Reads a record written by [`write_encrypted_record`] and decrypts it,
+/// authenticating `associated_data`. Returns a response encryptor and the
+/// record plaintext.
+#[cfg(feature = "response-direction")]
+pub fn read_encrypted_record<E: EncryptionKeyHandle + ?Sized>(
+    reader: &mut LengthPrefixedReader,
+    encryption_key_handle: &E,
+    associated_data: &[u8],
+) -> anyhow::Result<(ServerEncryptor, Vec<u8>)> {
+    let record = reader.read().context("couldn't read record")?;
+    ServerEncryptor::decrypt_combined(record, associated_data, encryption_key_handle)
+        .context("couldn't decrypt record")
+}
+
+/// Encodes a stream of request frames that each carry their own associated
+/// data, e.g. for routing. Each frame is written as two records: the associated
+/// data and the encrypted payload, so that a frame is laid out as `aad_len ||
+/// aad || ct_len || ct`.
+///
+/// Frames are encrypted with a single request-only session, and the payload of
+/// the initial frame is `encapsulated_public_key || nonce || ciphertext`, while
+/// following payloads are `nonce || ciphertext`. The index of each frame in the
+/// stream is authenticated as part of its AEAD associated data, which is
+/// `REQUEST_FRAME_LABEL || I2OSP(index, 8) || associated_data`, so a
+/// [`RequestFrameDecoder`] rejects
+/// frames that are reordered, replayed or skipped. Frames dropped from the end
+/// of the stream can't be detected, so the caller has to delimit the stream if
+/// it needs to know that all frames were received.
+pub struct RequestFrameEncoder {
+    encryptor: RequestOnlyClientEncryptor,
+    /// Number of frames encoded so far, which is the index of the next frame.
+    encoded_frames: u64,
+}
+
+impl RequestFrameEncoder {
+    /// Creates an encoder for a new session with the server.
+    pub fn create(serialized_server_public_key: &[u8]) -> anyhow::Result<Self> {
+        let encryptor = RequestOnlyClientEncryptor::create(serialized_server_public_key)
+            .context("couldn't create request encryptor")?;
+        Ok(Self { encryptor, encoded_frames: 0 })
+    }
+
+    /// Encrypts `plaintext`, authenticating `associated_data`, and writes them
+    /// as a frame. Nothing is written and the session isn't advanced if the
+    /// frame doesn't fit into the remaining buffer.
+    pub fn encode(
+        &mut self,
+        writer: &mut LengthPrefixedWriter,
+        plaintext: &[u8],
+        associated_data: &[u8],
+    ) -> anyhow::Result<()> {
+        let encapsulated_public_key_size =
+            if self.encoded_frames == 0 { SUITE_LENGTHS.nenc } else { 0 };
+        let payload_size =
+            encapsulated_public_key_size + SUITE_LENGTHS.nn + plaintext.len() + SUITE_LENGTHS.nt;
+        let frame_size = 2 * LENGTH_PREFIX_SIZE_BYTES + associated_data.len() + payload_size;
+        anyhow::ensure!(
+            frame_size <= writer.remaining(),
+            "buffer is too small, {} bytes remaining, {} bytes needed",
+            writer.remaining(),
+            frame_size
+        );
+        anyhow::ensure!(
+            associated_data.len().max(payload_size) <= writer.max_record_length,
+            "frame is too long, expected records of at most {} bytes",
+            writer.max_record_length
+        );
+
+        let encrypted_request = self
+            .encryptor
+            .encrypt(
+                plaintext,
+                &request_frame_associated_data(self.encoded_frames, associated_data),
+            )
+            .context("couldn't encrypt frame")?;
+        let encrypted_message = encrypted_request
+            .encrypted_message
+            .context("request doesn't contain encrypted message")?;
+        let payload = [
+            encrypted_request.serialized_encapsulated_public_key.unwrap_or_default().as_slice(),
+            &encrypted_message.nonce,
+            &encrypted_message.ciphertext,
+        ]
+        .concat();
+        self.encoded_frames += 1;
+
+        writer.write(associated_data).context("couldn't write associated data")?;
+        writer.write(&payload).context("couldn't write payload")
+    }
+}
+
+/// Decodes frames written by a [`RequestFrameEncoder`], in order.
+#[derive(Default)]
+pub struct RequestFrameDecoder {
+    encryptor: Option<RequestOnlyServerEncryptor>,
+    /// Number of frames decoded so far, which is the index of the next frame.
+    decoded_frames: u64,
+}
+
+impl RequestFrameDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads and decrypts the next frame. Returns the authenticated associated
+    /// data and the plaintext. The reader isn't advanced if the frame is
+    /// invalid, e.g. if it isn't the next frame of the stream.
+    pub fn decode<E: RequestOnlyEncryptionKeyHandle + ?Sized>(
+        &mut self,
+        reader: &mut LengthPrefixedReader,
+        encryption_key_handle: &E,
+    ) -> anyhow::Result<(Vec<u8>, Vec<u8>)> {
+        let position = reader.position;
+        let result = self.decode_frame(reader, encryption_key_handle);
+        if result.is_err() {
+            reader.position = position;
+        }
+        result
+    }
+
+    fn decode_frame<E: RequestOnlyEncryptionKeyHandle + ?Sized>(
+        &mut self,
+        reader: &mut LengthPrefixedReader,
+        encryption_key_handle: &E,
+    ) -> anyhow::Result<(Vec<u8>, Vec<u8>)> {
+        let associated_data = reader.read().context("couldn't read associated data")?;
+        let payload = reader.read().context("couldn't read payload")?;
+
+        let encapsulated_public_key_size =
+            if self.encryptor.is_none() { SUITE_LENGTHS.nenc } else { 0 };
+        if payload.len() < encapsulated_public_key_size + SUITE_LENGTHS.nn + SUITE_LENGTHS.nt {
+            return Err(CryptoError::MalformedInput
+                .context(format!("frame payload is too short, got {} bytes", payload.len())));
+        }
+        let (serialized_encapsulated_public_key, payload) =
+            payload.split_at(encapsulated_public_key_size);
+        let (nonce, ciphertext) = payload.split_at(SUITE_LENGTHS.nn);
+        let encrypted_request = EncryptedRequest {
+            encrypted_message: Some(AeadEncryptedMessage {
+                nonce: nonce.to_vec(),
+                ciphertext: ciphertext.to_vec(),
+                associated_data: request_frame_associated_data(
+                    self.decoded_frames,
+                    associated_data,
+                ),
+            }),
+            serialized_encapsulated_public_key: (!serialized_encapsulated_public_key.is_empty())
+                .then(|| serialized_encapsulated_public_key.to_vec()),
+        };
+
+        let (plaintext, _) = match &self.encryptor {
+            Some(encryptor) => {
+                encryptor.decrypt_next(&encrypted_request).context("couldn't decrypt frame")?
+            }
+            None => {
+                let (encryptor, plaintext, associated_data) =
+                    RequestOnlyServerEncryptor::decrypt(&encrypted_request, encryption_key_handle)
+                        .context("couldn't decrypt initial frame")?;
+                self.encryptor = Some(encryptor);
+                (plaintext, associated_data)
+            }
+        };
+        self.decoded_frames += 1;
+        Ok((associated_data.to_vec(), plaintext))
+    }
+}
+
+/// Returns the AEAD associated data of the request frame with the given
+/// `index`, which is `REQUEST_FRAME_LABEL || I2OSP(index, 8) ||
+/// associated_data`.
+pub(crate) fn request_frame_associated_data(index: u64, associated_data: &[u8]) -> Vec<u8> {
+    let mut frame_associated_data =
+        Vec::with_capacity(REQUEST_FRAME_LABEL.len() + 8 + associated_data.len());
+    frame_associated_data.extend_from_slice(REQUEST_FRAME_LABEL);
+    wire::put_u64(&mut frame_associated_data, index);
+    frame_associated_data.extend_from_slice(associated_data);
+    frame_associated_data
+}
@@ -0,0 +1,211 @@
+//
+// Copyright 2024 The Project Oak Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Integer encodings of the binary formats defined by this crate, i.e.
+//! length-prefixed records and request frames, routing headers, message type
+//! tags, setup challenges and solutions, and simplex nonces. Every format uses
+//! these helpers instead of encoding integers itself:
+//!
+//! - Fixed-size integers are big-endian, as `I2OSP` in HPKE.
+//! - Varints are unsigned LEB128, and only their shortest encoding is accepted.
+//!
+//! Reads are bounds-checked and never panic: short or invalid input fails with
+//! a [`CryptoError::MalformedInput`] error. Compact envelopes are encoded with
+//! postcard and don't use these helpers.
+//! <https://www.rfc-editor.org/rfc/rfc9180.html#name-notation>
+
+use alloc::{format, vec::Vec};
+
+use crate::error::CryptoError;
+
+/// Maximum size of an encoded `u64` varint.
+pub const MAX_VARINT_SIZE_BYTES: usize = 10;
+
+pub fn put_u16(output: &mut Vec<u8>, value: u16) {
+    output.extend_from_slice(&value.to_be_bytes());
+}
+
+pub fn put_u32(output: &mut Vec<u8>, value: u32) {
+    output.extend_from_slice(&value.to_be_bytes());
+}
+
+pub fn put_u64(output: &mut Vec<u8>, value: u64) {
+    output.extend_from_slice(&value.to_be_bytes());
+}
+
+pub fn put_varint(output: &mut Vec<u8>, value: u64) {
+    let (encoded, size) = encode_varint(value);
+    output.extend_from_slice(&encoded[..size]);
+}
+
+fn encode_varint(mut value: u64) -> ([u8; MAX_VARINT_SIZE_BYTES], usize) {
+    let mut encoded = [0u8; MAX_VARINT_SIZE_BYTES];
+    let mut size = 0;
+    while value >= 0x80 {
+        encoded[size] = (value as u8) | 0x80;
+        value >>= 7;
+        size += 1;
+    }
+    encoded[size] = value as u8;
+    (encoded, size + 1)
+}
+
+/// Bounds-checked cursor that reads integers from `input`.
+pub struct WireReader<'a> {
+    input: &'a [u8],
+    position: usize,
+}
+
+impl<'a> WireReader<'a> {
+    pub fn new(input: &'a [u8]) -> Self {
+        Self { input, position: 0 }
+    }
+
+    /// Number of bytes read so far.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Returns the part of the input that hasn't been read.
+    pub fn remaining(&self) -> &'a [u8] {
+        &self.input[self.position..]
+    }
+
+    /// Returns whether all of the input has been read.
+    pub fn is_empty(&self) -> bool {
+        self.position == self.input.len()
+    }
+
+    /// Reads the next `length` bytes. The position isn't advanced if there
+    /// aren't enough of them.
+    pub fn get_bytes(&mut self, length: usize) -> anyhow::Result<&'a [u8]> {
+        let remaining = self.remaining();
+        if length > remaining.len() {
+            return Err(CryptoError::MalformedInput.context(format!(
+                "truncated input, expected {} bytes, got {}",
+                length,
+                remaining.len()
+            )));
+        }
+        self.position += length;
+        Ok(&remaining[..length])
+    }
+
+    pub fn get_array<const N: usize>(&mut self) -> anyhow::Result<[u8; N]> {
+        let bytes = self.get_bytes(N)?;
+        let mut array = [0u8; N];
+        array.copy_from_slice(bytes);
+        Ok(array)
+    }
+
+    pub fn get_u8(&mut self) -> anyhow::Result<u8> {
+        Ok(self.get_array::<1>()?[0])
+    }
+
+    pub fn get_u16(&mut self) -> anyhow::Result<u16> {
+        Ok(u16::from_be_bytes(self.get_array()?))
+    }
+
+    pub fn get_u32(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_be_bytes(self.get_array()?))
+    }
+
+    pub fn get_u64(&mut self) -> anyhow::Result<u64> {
+        Ok(u64::from_be_bytes(self.get_array()?))
+    }
+
+    /// Reads a varint written by [`put_varint`]. The position isn't advanced
+    /// if the varint is truncated, overflows a `u64` or isn't minimally
+    /// encoded.
+    pub fn get_varint(&mut self) -> anyhow::Result<u64> {
+        let remaining = self.remaining();
+        let mut value = 0u64;
+        for (index, byte) in remaining.iter().take(MAX_VARINT_SIZE_BYTES).enumerate() {
+            let bits = u64::from(byte & 0x7f);
+            // The last byte of a `u64` only carries its most significant bit.
+            if index == MAX_VARINT_SIZE_BYTES - 1 && *byte > 1 {
+                return Err(CryptoError::MalformedInput.context("varint overflows u64"));
+            }
+            value |= bits << (7 * index);
+            if byte & 0x80 == 0 {
+                if index > 0 && *byte == 0 {
+                    return Err(CryptoError::MalformedInput.context("varint isn't minimal"));
+                }
+                self.position += index + 1;
+                return Ok(value);
+            }
+        }
+        Err(CryptoError::MalformedInput.context("truncated varint"))
+    }
+}
+
+/// Bounds-checked cursor that writes integers into a caller-provided buffer.
+pub struct WireWriter<'a> {
+    output: &'a mut [u8],
+    position: usize,
+}
+
+impl<'a> WireWriter<'a> {
+    pub fn new(output: &'a mut [u8]) -> Self {
+        Self { output, position: 0 }
+    }
+
+    /// Number of bytes written so far.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Number of bytes left in the buffer.
+    pub fn remaining(&self) -> usize {
+        self.output.len() - self.position
+    }
+
+    /// Writes `bytes`. Nothing is written if they don't fit into the remaining
+    /// buffer.
+    pub fn put_bytes(&mut self, bytes: &[u8]) -> anyhow::Result<()> {
+        if bytes.len() > self.remaining() {
+            return Err(CryptoError::MalformedInput.context(format!(
+                "buffer is too small, {} bytes remaining, {} bytes needed",
+                self.remaining(),
+                bytes.len()
+            )));
+        }
+        self.output[self.position..self.position + bytes.len()].copy_from_slice(bytes);
+        self.position += bytes.len();
+        Ok(())
+    }
+
+    pub fn put_u8(&mut self, value: u8) -> anyhow::Result<()> {
+        self.put_bytes(&[value])
+    }
+
+    pub fn put_u16(&mut self, value: u16) -> anyhow::Result<()> {
+        self.put_bytes(&value.to_be_bytes())
+    }
+
+    pub fn put_u32(&mut self, value: u32) -> anyhow::Result<()> {
+        self.put_bytes(&value.to_be_bytes())
+    }
+
+    pub fn put_u64(&mut self, value: u64) -> anyhow::Result<()> {
+        self.put_bytes(&value.to_be_bytes())
+    }
+
+    pub fn put_varint(&mut self, value: u64) -> anyhow::Result<()> {
+        let (encoded, size) = encode_varint(value);
+        self.put_bytes(&encoded[..size])
+    }
+}
@@ -0,0 +1,142 @@
+//
+// Copyright 2024 The Project Oak Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Checks that failing to allocate the buffer of a large message surfaces as
+//! [`CryptoError::OutOfMemory`] instead of aborting, and leaves the session
+//! usable.
+//!
+//! The global allocator of this test binary fails all allocations above a
+//! threshold, so the file only contains a single test, which can't race with
+//! other tests that allocate.
+
+#![cfg(feature = "response-direction")]
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use oak_crypto::{
+    encryption_key::generate_encryption_key_pair,
+    encryptor::{ClientEncryptor, ServerEncryptor},
+    error::CryptoError,
+    transfer::TransferManifest,
+};
+
+/// Allocator that fails allocations larger than the threshold.
+struct FailingAllocator {
+    threshold: AtomicUsize,
+}
+
+// SAFETY: Allocations are delegated to the system allocator, or fail by
+// returning a null pointer.
+unsafe impl GlobalAlloc for FailingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if layout.size() > self.threshold.load(Ordering::SeqCst) {
+            return std::ptr::null_mut();
+        }
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if new_size > self.threshold.load(Ordering::SeqCst) {
+            return std::ptr::null_mut();
+        }
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: FailingAllocator = FailingAllocator { threshold: AtomicUsize::new(usize::MAX) };
+
+const LARGE_MESSAGE_SIZE_BYTES: usize = 1 << 20;
+const SMALL_MESSAGE: &[u8] = b"Small message";
+
+/// Runs `f` while allocations of a large message fail.
+fn with_failing_allocations<T>(f: impl FnOnce() -> T) -> T {
+    ALLOCATOR.threshold.store(LARGE_MESSAGE_SIZE_BYTES / 2, Ordering::SeqCst);
+    let result = f();
+    ALLOCATOR.threshold.store(usize::MAX, Ordering::SeqCst);
+    result
+}
+
+fn assert_out_of_memory<T>(result: anyhow::Result<T>) {
+    let error = result.err().expect("allocation didn't fail");
+    assert_eq!(
+        Some(&CryptoError::OutOfMemory),
+        error.downcast_ref::<CryptoError>(),
+        "unexpected error: {:#}",
+        error
+    );
+}
+
+#[test]
+fn large_messages_fail_without_aborting() {
+    let large_message = vec![7u8; LARGE_MESSAGE_SIZE_BYTES];
+    let (encryption_key, public_key) = generate_encryption_key_pair();
+    let mut client_encryptor =
+        ClientEncryptor::create(&public_key).expect("couldn't create client encryptor");
+
+    // Encryption fails without using up a message of the session.
+    assert_out_of_memory(with_failing_allocations(|| {
+        client_encryptor.encrypt(&large_message, b"")
+    }));
+    let messages_remaining = client_encryptor.messages_remaining();
+    let encrypted_request =
+        client_encryptor.encrypt(&large_message, b"").expect("couldn't encrypt request");
+    assert_eq!(messages_remaining - 1, client_encryptor.messages_remaining());
+
+    // Decryption fails, and can be retried.
+    assert_out_of_memory(with_failing_allocations(|| {
+        ServerEncryptor::decrypt(&encrypted_request, &encryption_key)
+    }));
+    let (server_encryptor, request, _) =
+        ServerEncryptor::decrypt(&encrypted_request, &encryption_key)
+            .expect("couldn't decrypt request");
+    assert_eq!(large_message, request);
+
+    // Pushed messages that fail to decrypt don't advance the stream.
+    let mut pusher = server_encryptor.into_simplex_pusher();
+    let mut receiver = client_encryptor.into_simplex_receiver();
+    let pushed_message = pusher.push(&large_message, b"").expect("couldn't push message");
+    assert_out_of_memory(with_failing_allocations(|| receiver.receive(&pushed_message)));
+    let (plaintext, _) = receiver.receive(&pushed_message).expect("couldn't receive message");
+    assert_eq!(large_message, plaintext);
+    let pushed_message = pusher.push(SMALL_MESSAGE, b"").expect("couldn't push message");
+    let (plaintext, _) = with_failing_allocations(|| receiver.receive(&pushed_message))
+        .expect("couldn't receive small message");
+    assert_eq!(SMALL_MESSAGE, plaintext);
+
+    // Chunks of a transfer that fail to decrypt can be received again.
+    let chunks = [large_message.as_slice(), SMALL_MESSAGE];
+    let (sender, encrypted_manifest) = pusher
+        .into_transfer_sender(TransferManifest::new(chunks.iter().copied()))
+        .expect("couldn't create transfer sender");
+    let mut receiver =
+        receiver.into_transfer_receiver(&encrypted_manifest).expect("couldn't receive manifest");
+    let encrypted_chunk = sender.push_chunk(0, chunks[0]).expect("couldn't push chunk");
+    assert_out_of_memory(with_failing_allocations(|| receiver.receive_chunk(&encrypted_chunk)));
+    assert_eq!(0, receiver.next_chunk());
+    let chunk = receiver.receive_chunk(&encrypted_chunk).expect("couldn't receive chunk");
+    assert_eq!(large_message, chunk);
+    let encrypted_chunk = sender.push_chunk(1, chunks[1]).expect("couldn't push chunk");
+    receiver.receive_chunk(&encrypted_chunk).expect("couldn't receive chunk");
+    receiver.finish().expect("couldn't finish transfer");
+}
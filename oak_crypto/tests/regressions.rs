@@ -0,0 +1,159 @@
+//
+// Copyright 2024 The Project Oak Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Runs every input in `testdata/regressions/` through the entry point named by
+//! the prefix of its file name, and checks that the input is rejected with an
+//! error instead of a panic:
+//!
+//! - `decryptor-setup-*`: encapsulated public key of a new session.
+//! - `aead-open-*`: `nonce || ciphertext` of a response in an established
+//!   session.
+//! - `envelope-parse-*`: serialized `EncryptedRequest` proto message.
+//! - `framing-*`: length-prefixed records.
+//!
+//! If an input has a `<name>.expected` sidecar file, it contains the
+//! [`CryptoError`] class (e.g. `MalformedInput`) that the error must carry.
+//! Inputs found by fuzzing should be added here with a descriptive name.
+
+#![cfg(feature = "response-direction")]
+
+use std::{fs, panic, path::Path};
+
+use oak_crypto::{
+    encryption_key::{generate_encryption_key_pair, EncryptionKey},
+    encryptor::{ClientEncryptor, ServerEncryptor},
+    error::CryptoError,
+    proto::oak::crypto::v1::{AeadEncryptedMessage, EncryptedRequest, EncryptedResponse},
+    util::LengthPrefixedReader,
+};
+use prost::Message;
+
+const REGRESSIONS_PATH: &str = "testdata/regressions";
+const EXPECTED_ERROR_EXTENSION: &str = "expected";
+// Records of any length are allowed, so that corrupted length prefixes are only
+// caught by the bounds checks against the input.
+const MAX_RECORD_LENGTH: usize = usize::MAX;
+const NONCE_SIZE_BYTES: usize = 12;
+
+/// Server key pair that inputs are checked against.
+struct Fixture {
+    encryption_key: EncryptionKey,
+    public_key: Vec<u8>,
+}
+
+fn decryptor_setup(fixture: &Fixture, input: &[u8]) -> anyhow::Result<()> {
+    fixture.encryption_key.derive_shared_keys(input).map(|_| ())
+}
+
+fn aead_open(fixture: &Fixture, input: &[u8]) -> anyhow::Result<()> {
+    let mut client_encryptor = ClientEncryptor::create(&fixture.public_key)?;
+    client_encryptor.encrypt(b"", b"")?;
+    let (nonce, ciphertext) = input.split_at(input.len().min(NONCE_SIZE_BYTES));
+    let encrypted_response = EncryptedResponse {
+        encrypted_message: Some(AeadEncryptedMessage {
+            ciphertext: ciphertext.to_vec(),
+            associated_data: Vec::new(),
+            nonce: nonce.to_vec(),
+        }),
+    };
+    client_encryptor.decrypt(&encrypted_response).map(|_| ())
+}
+
+fn envelope_parse(fixture: &Fixture, input: &[u8]) -> anyhow::Result<()> {
+    let encrypted_request = EncryptedRequest::decode(input)
+        .map_err(|error| anyhow::anyhow!("couldn't decode request proto: {}", error))?;
+    ServerEncryptor::decrypt(&encrypted_request, &fixture.encryption_key).map(|_| ())
+}
+
+fn framing(_fixture: &Fixture, input: &[u8]) -> anyhow::Result<()> {
+    let mut reader = LengthPrefixedReader::new(input, MAX_RECORD_LENGTH);
+    while !reader.is_empty() {
+        reader.read()?;
+    }
+    Ok(())
+}
+
+type EntryPoint = fn(&Fixture, &[u8]) -> anyhow::Result<()>;
+
+const ENTRY_POINTS: &[(&str, EntryPoint)] = &[
+    ("decryptor-setup-", decryptor_setup),
+    ("aead-open-", aead_open),
+    ("envelope-parse-", envelope_parse),
+    ("framing-", framing),
+];
+
+/// Runs a single regression input, and returns a description of the failure if
+/// the input wasn't rejected as expected.
+fn check_regression(fixture: &Fixture, path: &Path) -> Result<(), String> {
+    let name = path.file_name().and_then(|name| name.to_str()).expect("invalid file name");
+    let (_, entry_point) = ENTRY_POINTS
+        .iter()
+        .find(|(prefix, _)| name.starts_with(prefix))
+        .ok_or_else(|| format!("{}: unknown entry point", name))?;
+    let input = fs::read(path).expect("couldn't read regression input");
+    let expected_error = fs::read_to_string(path.with_extension(EXPECTED_ERROR_EXTENSION))
+        .ok()
+        .map(|expected_error| expected_error.trim().to_string());
+
+    let result = panic::catch_unwind(|| entry_point(fixture, &input))
+        .map_err(|_| format!("{}: panicked", name))?;
+    let error = match result {
+        Ok(()) => return Err(format!("{}: input was accepted", name)),
+        Err(error) => error,
+    };
+    if let Some(expected_error) = expected_error {
+        let error_class = error.downcast_ref::<CryptoError>().map(|class| format!("{:?}", class));
+        if error_class.as_deref() != Some(expected_error.as_str()) {
+            return Err(format!(
+                "{}: expected {} error, got {:?}: {:#}",
+                name, expected_error, error_class, error
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_regressions() {
+    let (encryption_key, public_key) = generate_encryption_key_pair();
+    let fixture = Fixture { encryption_key, public_key };
+    let mut paths: Vec<_> = fs::read_dir(REGRESSIONS_PATH)
+        .expect("couldn't read regressions directory")
+        .map(|entry| entry.expect("couldn't read directory entry").path())
+        .filter(|path| {
+            path.extension().and_then(|extension| extension.to_str())
+                != Some(EXPECTED_ERROR_EXTENSION)
+        })
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "no regression inputs found");
+
+    // Every entry point has to be covered by at least one input.
+    for (prefix, _) in ENTRY_POINTS {
+        assert!(
+            paths.iter().any(|path| path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(prefix))),
+            "no regression inputs for {}",
+            prefix
+        );
+    }
+
+    let failures: Vec<String> =
+        paths.iter().filter_map(|path| check_regression(&fixture, path).err()).collect();
+    assert!(failures.is_empty(), "regressions failed:\n{}", failures.join("\n"));
+}
@@ -0,0 +1,198 @@
+//
+// Copyright 2024 The Project Oak Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Soak test that pushes millions of messages of random sizes through a
+//! sequence of simplex sessions, to catch bugs in sequence counters and
+//! session re-establishment that only show up after long runtimes.
+//!
+//! Sessions are re-established after a fixed number of messages, far below
+//! [`MAX_MESSAGES_PER_SESSION`], so that many sessions are set up. Some
+//! messages are corrupted or replayed before delivery, and have to fail
+//! authentication without advancing the stream. Invariants are checked after
+//! every message.
+//!
+//! The test is too slow for regular test runs, especially in debug builds, so
+//! it is ignored by default. Run it in release mode with `just
+//! oak_crypto_soak`, or manually with:
+//!
+//! ```shell
+//! cargo test --release --test soak -- --ignored
+//! ```
+//!
+//! The run can be configured with environment variables:
+//!
+//! - `OAK_CRYPTO_SOAK_MESSAGES`: number of messages, 5 million by default.
+//! - `OAK_CRYPTO_SOAK_MESSAGES_PER_SESSION`: number of messages after which the
+//!   session is re-established, 100 000 by default.
+//! - `OAK_CRYPTO_SOAK_SEED`: seed of message sizes and corruptions, so that a
+//!   failing run can be reproduced.
+
+#![cfg(feature = "response-direction")]
+
+use std::env;
+
+use oak_crypto::{
+    encryption_key::generate_encryption_key_pair,
+    encryptor::{ClientEncryptor, ServerEncryptor, MAX_MESSAGES_PER_SESSION},
+    error::CryptoError,
+    hpke::SUITE_LENGTHS,
+    proto::oak::crypto::v1::{AeadEncryptedMessage, EncryptedRequest, EncryptedResponse},
+};
+
+const DEFAULT_MESSAGES: u64 = 5_000_000;
+const DEFAULT_MESSAGES_PER_SESSION: u64 = 100_000;
+const DEFAULT_SEED: u64 = 0x9E3779B97F4A7C15;
+const MAX_MESSAGE_SIZE_BYTES: usize = 4096;
+/// On average, one in this many messages is corrupted or replayed before it is
+/// delivered.
+const CORRUPTION_INTERVAL: u64 = 1000;
+
+fn env_or_default(name: &str, default: u64) -> u64 {
+    env::var(name).map_or(default, |value| {
+        value.parse().unwrap_or_else(|error| panic!("invalid {}={}: {}", name, value, error))
+    })
+}
+
+/// xorshift64, so that runs are reproducible from the seed.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound
+    }
+}
+
+#[test]
+#[ignore = "soak test, run with `just oak_crypto_soak`"]
+fn soak_simplex_sessions() {
+    let total_messages = env_or_default("OAK_CRYPTO_SOAK_MESSAGES", DEFAULT_MESSAGES);
+    let messages_per_session =
+        env_or_default("OAK_CRYPTO_SOAK_MESSAGES_PER_SESSION", DEFAULT_MESSAGES_PER_SESSION);
+    let seed = env_or_default("OAK_CRYPTO_SOAK_SEED", DEFAULT_SEED);
+    assert!(messages_per_session > 0 && messages_per_session < MAX_MESSAGES_PER_SESSION);
+    assert_ne!(0, seed, "xorshift needs a non-zero seed");
+    eprintln!(
+        "soaking {} messages, {} per session, seed {:#x}",
+        total_messages, messages_per_session, seed
+    );
+
+    let mut rng = Rng(seed);
+    // Plaintexts are slices of a single random buffer, so that messages don't
+    // allocate their plaintext.
+    let source: Vec<u8> = (0..2 * MAX_MESSAGE_SIZE_BYTES).map(|_| rng.next() as u8).collect();
+    let mut request_buffer = vec![0u8; SUITE_LENGTHS.nt + 8];
+    let (encryption_key, public_key) = generate_encryption_key_pair();
+
+    let mut sessions = 0u64;
+    let mut corruptions = 0u64;
+    let mut delivered = 0u64;
+    while delivered < total_messages {
+        // Re-establish the session, with a request that carries the number of
+        // the session.
+        let mut client_encryptor =
+            ClientEncryptor::create(&public_key).expect("couldn't create client encryptor");
+        let header = client_encryptor
+            .encrypt_into(&sessions.to_be_bytes(), b"", &mut request_buffer)
+            .expect("couldn't encrypt request");
+        assert_eq!(MAX_MESSAGES_PER_SESSION - 1, client_encryptor.messages_remaining());
+        let encrypted_request = EncryptedRequest {
+            encrypted_message: Some(AeadEncryptedMessage {
+                ciphertext: request_buffer[..header.ciphertext_size].to_vec(),
+                associated_data: Vec::new(),
+                nonce: header.nonce.to_vec(),
+            }),
+            serialized_encapsulated_public_key: header.serialized_encapsulated_public_key,
+        };
+        let (server_encryptor, request, _) =
+            ServerEncryptor::decrypt(&encrypted_request, &encryption_key)
+                .expect("couldn't decrypt request");
+        assert_eq!(sessions.to_be_bytes().as_slice(), request);
+        let mut pusher = server_encryptor.into_simplex_pusher();
+        let mut receiver = client_encryptor.into_simplex_receiver();
+        sessions += 1;
+
+        let session_messages = messages_per_session.min(total_messages - delivered);
+        let mut previous_message: Option<EncryptedResponse> = None;
+        for index in 0..session_messages {
+            let size = rng.below(MAX_MESSAGE_SIZE_BYTES as u64 + 1) as usize;
+            let offset = rng.below(MAX_MESSAGE_SIZE_BYTES as u64) as usize;
+            let plaintext = &source[offset..offset + size];
+            let associated_data = delivered.to_be_bytes();
+            let pushed_message =
+                pusher.push(plaintext, &associated_data).expect("couldn't push message");
+            // Sequence numbers increase by one with every message of a session.
+            assert_eq!(MAX_MESSAGES_PER_SESSION - index - 1, pusher.messages_remaining());
+
+            if rng.below(CORRUPTION_INTERVAL) == 0 {
+                // Replays the previous message, or flips a bit of the
+                // associated data or the ciphertext, which always contains
+                // the tag.
+                let corrupted_message = match (rng.below(3), &previous_message) {
+                    (0, Some(previous_message)) => previous_message.clone(),
+                    (choice, _) => {
+                        let mut corrupted_message = pushed_message.clone();
+                        let corrupted = corrupted_message.encrypted_message.as_mut().unwrap();
+                        let field = if choice == 1 {
+                            &mut corrupted.associated_data
+                        } else {
+                            &mut corrupted.ciphertext
+                        };
+                        let position = rng.below(field.len() as u64) as usize;
+                        field[position] ^= 1 << rng.below(8);
+                        corrupted_message
+                    }
+                };
+                let error = receiver
+                    .receive(&corrupted_message)
+                    .expect_err("corrupted message was accepted");
+                assert_eq!(
+                    Some(&CryptoError::AuthenticationFailure),
+                    error.downcast_ref::<CryptoError>(),
+                    "unexpected error for corrupted message: {:#}",
+                    error
+                );
+                corruptions += 1;
+            }
+
+            let (received_plaintext, received_associated_data) =
+                receiver.receive(&pushed_message).unwrap_or_else(|error| {
+                    panic!(
+                        "couldn't receive message {} of session {}: {:#}",
+                        index, sessions, error
+                    )
+                });
+            assert_eq!(plaintext, received_plaintext);
+            assert_eq!(associated_data.as_slice(), received_associated_data);
+            previous_message = Some(pushed_message);
+            delivered += 1;
+        }
+    }
+
+    // Sessions are re-established exactly after every `messages_per_session`
+    // messages.
+    assert_eq!(total_messages.div_ceil(messages_per_session), sessions);
+    eprintln!(
+        "delivered {} messages in {} sessions, rejected {} corrupted messages",
+        delivered, sessions, corruptions
+    );
+}
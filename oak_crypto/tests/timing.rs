@@ -0,0 +1,200 @@
+//
+// Copyright 2024 The Project Oak Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Statistical timing tests in the style of dudect
+//! (<https://eprint.iacr.org/2016/1123.pdf>), which measure an operation on two
+//! classes of secret inputs and flag the operation if Welch's t-test shows that
+//! the classes take different amounts of time.
+//!
+//! Whether a tag is valid is revealed by the result anyway, and the error path
+//! allocates, so tag checks are measured on invalid tags that differ from the
+//! valid tag in their first vs last byte, which only take different times if
+//! the tag is compared with an early exit. Decapsulation is measured on a fixed
+//! vs random encapsulated public key.
+//!
+//! The tests only catch gross differences, and are not a proof of constant-time
+//! behavior. Timings are noisy, so the tests are flaky on loaded machines and
+//! in debug builds, and are only built with the `timing_tests` feature. They
+//! run in release mode in an advisory CI job that doesn't block submission
+//! (`kokoro/run_timing_audit.sh`), with `just oak_crypto_timing_audit`, or
+//! manually with:
+//!
+//! ```shell
+//! cargo test --release --features timing_tests --test timing
+//! ```
+//!
+//! They complement a manual audit of secret-dependent branches, which has to
+//! be repeated when any of these change:
+//!
+//! - AEAD tags are checked by the `aes-gcm` crate, which compares them in
+//!   constant time.
+//! - HMAC tags of setup challenges and continuity tokens are checked with
+//!   `verify_slice`, which compares in constant time.
+//! - Discarded response keys are detected by folding all key bytes, without an
+//!   early exit.
+//! - Authentication failures carry the same error regardless of which byte of
+//!   the message was tampered with, see
+//!   `test_authentication_failures_are_uniform` in `src/tests.rs`.
+
+#![cfg(feature = "timing_tests")]
+
+use std::{hint::black_box, time::Instant};
+
+use oak_crypto::{
+    encryption_key::generate_encryption_key_pair,
+    encryptor::{ClientEncryptor, ServerEncryptor},
+    proto::oak::crypto::v1::EncryptedResponse,
+};
+
+/// Threshold of the t statistic above which the classes are considered to take
+/// different times. dudect reports a leak above 4.5, this threshold only flags
+/// gross differences.
+const T_THRESHOLD: f64 = 10.0;
+/// Fraction of the slowest measurements that are discarded, since they are
+/// dominated by interrupts and scheduling.
+const OUTLIER_FRACTION: f64 = 0.1;
+const OPEN_MEASUREMENTS_PER_CLASS: usize = 20_000;
+const DECAP_MEASUREMENTS_PER_CLASS: usize = 500;
+
+/// Measures `operation` on both classes, interleaving the classes in a
+/// pseudo-random order so that drift affects them equally, and returns the
+/// t statistic of the measured times.
+fn measure_t_statistic<F: FnMut(bool)>(measurements_per_class: usize, mut operation: F) -> f64 {
+    let mut times = [Vec::new(), Vec::new()];
+    let mut state = 0x9E3779B97F4A7C15u64;
+    while times.iter().any(|class_times| class_times.len() < measurements_per_class) {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        let class = (state & 1) as usize;
+        if times[class].len() == measurements_per_class {
+            continue;
+        }
+        let start = Instant::now();
+        operation(class == 1);
+        times[class].push(start.elapsed().as_nanos() as f64);
+    }
+    let [first, second] = times.map(crop_outliers);
+    welch_t_statistic(&first, &second)
+}
+
+fn crop_outliers(mut times: Vec<f64>) -> Vec<f64> {
+    times.sort_by(f64::total_cmp);
+    times.truncate(times.len() - (times.len() as f64 * OUTLIER_FRACTION) as usize);
+    times
+}
+
+fn welch_t_statistic(first: &[f64], second: &[f64]) -> f64 {
+    let mean_and_variance = |values: &[f64]| {
+        let count = values.len() as f64;
+        let mean = values.iter().sum::<f64>() / count;
+        let variance =
+            values.iter().map(|value| (value - mean).powi(2)).sum::<f64>() / (count - 1.0);
+        (mean, variance / count)
+    };
+    let (first_mean, first_variance) = mean_and_variance(first);
+    let (second_mean, second_variance) = mean_and_variance(second);
+    (first_mean - second_mean) / (first_variance + second_variance).sqrt()
+}
+
+#[test]
+fn test_open_tag_check_timing() {
+    let (encryption_key, public_key) = generate_encryption_key_pair();
+    let mut client_encryptor =
+        ClientEncryptor::create(&public_key).expect("couldn't create client encryptor");
+    let encrypted_request =
+        client_encryptor.encrypt(b"request", b"").expect("couldn't encrypt request");
+    let (server_encryptor, _, _) = ServerEncryptor::decrypt(&encrypted_request, &encryption_key)
+        .expect("couldn't decrypt request");
+    let encrypted_response =
+        server_encryptor.encrypt(&[0u8; 64], b"").expect("couldn't encrypt response");
+
+    // Responses whose tags differ from the valid tag in the first and in the last
+    // byte.
+    let corrupt = |index_from_end: usize| {
+        let mut response = encrypted_response.clone();
+        let ciphertext =
+            &mut response.encrypted_message.as_mut().expect("no encrypted message").ciphertext;
+        let index = ciphertext.len() - 1 - index_from_end;
+        ciphertext[index] ^= 1;
+        response
+    };
+    let responses: [EncryptedResponse; 2] = [corrupt(15), corrupt(0)];
+
+    let t = measure_t_statistic(OPEN_MEASUREMENTS_PER_CLASS, |class| {
+        let result = client_encryptor.decrypt(black_box(&responses[usize::from(class)]));
+        assert!(black_box(result).is_err());
+    });
+    assert!(t.abs() < T_THRESHOLD, "tag check time depends on the tag, t = {}", t);
+}
+
+#[test]
+fn test_open_tamper_location_timing() {
+    let (encryption_key, public_key) = generate_encryption_key_pair();
+    let mut client_encryptor =
+        ClientEncryptor::create(&public_key).expect("couldn't create client encryptor");
+    let encrypted_request =
+        client_encryptor.encrypt(b"request", b"").expect("couldn't encrypt request");
+    let (server_encryptor, _, _) = ServerEncryptor::decrypt(&encrypted_request, &encryption_key)
+        .expect("couldn't decrypt request");
+    let encrypted_response =
+        server_encryptor.encrypt(&[0u8; 64], b"").expect("couldn't encrypt response");
+
+    // Responses tampered with in the first byte of the encrypted plaintext and
+    // in the last byte of the tag, which both have to fail the full tag check.
+    let corrupt = |index: usize| {
+        let mut response = encrypted_response.clone();
+        let ciphertext =
+            &mut response.encrypted_message.as_mut().expect("no encrypted message").ciphertext;
+        let index = index.min(ciphertext.len() - 1);
+        ciphertext[index] ^= 1;
+        response
+    };
+    let responses: [EncryptedResponse; 2] = [corrupt(0), corrupt(usize::MAX)];
+    let errors = responses.each_ref().map(|response| {
+        format!(
+            "{:#}",
+            client_encryptor.decrypt(response).expect_err("decrypted tampered response")
+        )
+    });
+    assert_eq!(errors[0], errors[1], "error depends on the tampered location");
+
+    let t = measure_t_statistic(OPEN_MEASUREMENTS_PER_CLASS, |class| {
+        let result = client_encryptor.decrypt(black_box(&responses[usize::from(class)]));
+        assert!(black_box(result).is_err());
+    });
+    assert!(t.abs() < T_THRESHOLD, "open time depends on the tampered location, t = {}", t);
+}
+
+#[test]
+fn test_decapsulation_timing() {
+    let (encryption_key, _) = generate_encryption_key_pair();
+    let (_, fixed_encapsulated_public_key) = generate_encryption_key_pair();
+    let random_encapsulated_public_keys: Vec<Vec<u8>> =
+        (0..DECAP_MEASUREMENTS_PER_CLASS).map(|_| generate_encryption_key_pair().1).collect();
+    let mut random_keys = random_encapsulated_public_keys.iter();
+
+    let t = measure_t_statistic(DECAP_MEASUREMENTS_PER_CLASS, |class| {
+        let encapsulated_public_key = if class {
+            random_keys.next().expect("ran out of random keys")
+        } else {
+            &fixed_encapsulated_public_key
+        };
+        let result = encryption_key.derive_shared_keys(black_box(encapsulated_public_key));
+        assert!(black_box(result).is_ok());
+    });
+    assert!(t.abs() < T_THRESHOLD, "decapsulation time depends on the input, t = {}", t);
+}